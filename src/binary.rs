@@ -0,0 +1,172 @@
+//! Byte strings and UUIDs, for BYTEA and UUID columns: `X'..'` literals,
+//! GEN_RANDOM_UUID, and ENCODE / DECODE between bytes and hex or base64 text.
+//!
+//! Byte strings compare byte by byte, a prefix sorting first. UUIDs compare
+//! by their sixteen bytes, which is also the order of their text, and text
+//! compared with a UUID is read as one.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{DataType, Value};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    /// Reads the usual form `a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11`, in
+    /// either case, with or without the hyphens or surrounding braces.
+    pub fn parse(text: &str) -> Result<Uuid, String> {
+        let trimmed = text.trim();
+        let inner = trimmed.strip_prefix('{').and_then(|t| t.strip_suffix('}')).unwrap_or(trimmed);
+        let hyphens: Vec<usize> = inner.match_indices('-').map(|(i, _)| i).collect();
+        let digits = inner.replace('-', "");
+        if !(hyphens.is_empty() || hyphens == [8, 13, 18, 23]) || digits.len() != 32 {
+            return Err(format!("Invalid UUID: {}", text));
+        }
+        let bytes = parse_hex(&digits).map_err(|_| format!("Invalid UUID: {}", text))?;
+        Ok(Uuid(bytes.try_into().unwrap()))
+    }
+
+    /// A version 4 (random) UUID. The bits come from the standard library's
+    /// randomly keyed hasher, which is unpredictable but not a
+    /// cryptographic generator.
+    pub fn random() -> Uuid {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let mut bytes = [0u8; 16];
+        for half in bytes.chunks_mut(8) {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(COUNTER.fetch_add(1, AtomicOrdering::Relaxed));
+            hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos()));
+            half.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        bytes[6] = bytes[6] & 0x0f | 0x40;
+        bytes[8] = bytes[8] & 0x3f | 0x80;
+        Uuid(bytes)
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = hex(&self.0);
+        write!(f, "{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+    }
+}
+
+/// Lowercase hex digits of `bytes`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Bytes from pairs of hex digits, as in `X'DEADBEEF'`.
+pub(crate) fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex string: {}", text));
+    }
+    Ok((0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect())
+}
+
+/// Text stored as BYTEA: `\x` and hex digits, the form BYTEA prints as, or
+/// otherwise the text's own UTF-8 bytes.
+pub(crate) fn from_text(text: &str) -> Result<Vec<u8>, String> {
+    match text.strip_prefix("\\x") {
+        Some(digits) => parse_hex(digits),
+        None => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() { BASE64[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+    out
+}
+
+fn parse_base64(text: &str) -> Option<Vec<u8>> {
+    let text: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for (i, chunk) in text.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && i + 1 < text.len() / 4) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &b in &chunk[..4 - padding] {
+            n = n << 6 | BASE64.iter().position(|&c| c == b)? as u32;
+        }
+        n <<= 6 * padding as u32;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+/// ENCODE(bytes, format): the bytes as `hex` or `base64` text. Text is
+/// encoded as its UTF-8 bytes.
+pub(crate) fn encode(v: &Value, format: &str) -> Option<Value> {
+    let bytes = match v {
+        Value::Bytes(b) => b.as_slice(),
+        Value::String(s) => s.as_bytes(),
+        _ => return None,
+    };
+    match format.to_lowercase().as_str() {
+        "hex" => Some(Value::String(hex(bytes))),
+        "base64" => Some(Value::String(base64(bytes))),
+        _ => None,
+    }
+}
+
+/// DECODE(text, format): the bytes `hex` or `base64` text stands for.
+pub(crate) fn decode(text: &str, format: &str) -> Option<Value> {
+    match format.to_lowercase().as_str() {
+        "hex" => parse_hex(text).ok().map(Value::Bytes),
+        "base64" => parse_base64(text).map(Value::Bytes),
+        _ => None,
+    }
+}
+
+/// A text constant compared with a UUID column, read as a UUID the way
+/// comparisons read it.
+pub(crate) fn align(ty: &DataType, value: Value) -> Value {
+    match (ty, &value) {
+        (DataType::Uuid, Value::String(s)) => Uuid::parse(s).map_or(value, Value::Uuid),
+        _ => value,
+    }
+}
+
+/// Order of two byte strings or UUIDs, `None` unless they are comparable.
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Bytes(x), Value::Bytes(y)) => Some(x.cmp(y)),
+        (Value::Uuid(x), Value::Uuid(y)) => Some(x.cmp(y)),
+        (Value::Uuid(x), Value::String(s)) => Some(x.cmp(&Uuid::parse(s).ok()?)),
+        (Value::String(s), Value::Uuid(y)) => Some(Uuid::parse(s).ok()?.cmp(y)),
+        _ => None,
+    }
+}
+
+/// Comparison of byte strings or UUIDs; `None` for any other operator.
+pub(crate) fn binop(left: &Value, op: &str, right: &Value) -> Option<Value> {
+    let ord = compare(left, right)?;
+    Some(Value::Bool(match op {
+        "=" => ord == Ordering::Equal,
+        "!=" => ord != Ordering::Equal,
+        "<" => ord == Ordering::Less,
+        ">" => ord == Ordering::Greater,
+        "<=" => ord != Ordering::Greater,
+        ">=" => ord != Ordering::Less,
+        _ => return None,
+    }))
+}
@@ -0,0 +1,280 @@
+//! Collations: how text compares, sorts, groups and matches LIKE patterns.
+//!
+//! - `BINARY`, the default, compares text by code point.
+//! - `UNICODE` compares the canonical decomposition of text, so text with
+//!   precomposed letters equals the same text with combining marks.
+//! - `NOCASE` compares like `UNICODE` after lowercasing.
+//!
+//! A column can declare a collation (`name TEXT COLLATE NOCASE`) and any
+//! expression can be given one (`name COLLATE BINARY`). A comparison or LIKE
+//! uses a collation given with COLLATE on its left, else on its right, else
+//! one a column operand declares. ORDER BY, GROUP BY and DISTINCT use the
+//! collation of what they sort or group by, hash joins that of their
+//! equality, and indexes their columns' collations.
+//!
+//! Under `UNICODE` and `NOCASE` text is replaced by its collation key, the
+//! decomposed (and lowercased) text, before it is compared, hashed or
+//! matched. Values with equal keys are equal: they group together and the
+//! first one read stands for the group.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{unicode, Column, Expr, GroupByItem, Json, Query, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    #[default]
+    Binary,
+    NoCase,
+    Unicode,
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Result<Collation, String> {
+        match name.to_uppercase().as_str() {
+            "BINARY" => Ok(Collation::Binary),
+            "NOCASE" => Ok(Collation::NoCase),
+            "UNICODE" => Ok(Collation::Unicode),
+            _ => Err(format!("Unknown collation: {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Collation::Binary => "BINARY",
+            Collation::NoCase => "NOCASE",
+            Collation::Unicode => "UNICODE",
+        }
+    }
+
+    /// The key `text` compares as.
+    pub fn fold(self, text: &str) -> Cow<'_, str> {
+        match self {
+            Collation::Binary => Cow::Borrowed(text),
+            Collation::Unicode => unicode::normalize(text),
+            Collation::NoCase => Cow::Owned(unicode::normalize(&text.to_lowercase()).into_owned()),
+        }
+    }
+
+    /// The value `v` compares as: text, including JSON strings and text in
+    /// arrays and structs, replaced by its key.
+    pub(crate) fn key(self, v: &Value) -> Cow<'_, Value> {
+        match (self, v) {
+            (Collation::Binary, _) => Cow::Borrowed(v),
+            (_, Value::String(s) | Value::Json(Json::String(s))) => Cow::Owned(Value::String(self.fold(s).into_owned())),
+            (_, Value::Array(items)) => Cow::Owned(Value::Array(items.iter().map(|v| self.key(v).into_owned()).collect())),
+            (_, Value::Struct(fields)) => Cow::Owned(Value::Struct(fields.iter().map(|(n, v)| (n.clone(), self.key(v).into_owned())).collect())),
+            _ => Cow::Borrowed(v),
+        }
+    }
+
+    /// `key`, for a value that is no longer needed.
+    pub(crate) fn into_key(self, v: Value) -> Value {
+        match self {
+            Collation::Binary => v,
+            _ => self.key(&v).into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `expr COLLATE name`.
+pub(crate) fn collate(expr: Expr, collation: Collation) -> Expr {
+    Expr::FuncCall("COLLATE".into(), vec![expr, Expr::Literal(Value::String(collation.name().into()))])
+}
+
+/// The collation `expr` is given with COLLATE, if it is.
+pub(crate) fn explicit(expr: &Expr) -> Option<Collation> {
+    match expr {
+        Expr::FuncCall(name, args) if name == "COLLATE" => match args.as_slice() {
+            [_, Expr::Literal(Value::String(c))] => Collation::from_name(c).ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `op` compares its operands, and so follows their collation.
+pub(crate) fn is_comparison(op: &str) -> bool {
+    matches!(op, "=" | "!=" | "<" | ">" | "<=" | ">=" | "LIKE")
+}
+
+/// The collation `left op right` compares under, if it is a comparison and
+/// either operand is given one.
+pub(crate) fn of_comparison(left: &Expr, op: &str, right: &Expr) -> Option<Collation> {
+    if !is_comparison(op) {
+        return None;
+    }
+    explicit(left).or_else(|| explicit(right))
+}
+
+/// Collations declared by the columns a statement reads, by plain and
+/// qualified name. Only those other than BINARY are kept.
+#[derive(Default)]
+pub(crate) struct Collations {
+    columns: HashMap<String, Collation>,
+    /// Every plain name read so far, with the collation it was declared with
+    plain: HashMap<String, Collation>,
+    /// Plain names that tables read together declare with different collations
+    ambiguous: HashSet<String>,
+}
+
+impl Collations {
+    pub(crate) fn add(&mut self, table: &str, columns: &[Column]) {
+        for c in columns {
+            if self.plain.insert(c.name.clone(), c.collation).is_some_and(|other| other != c.collation) {
+                self.ambiguous.insert(c.name.clone());
+            }
+            if c.collation != Collation::Binary {
+                self.columns.entry(c.name.clone()).or_insert(c.collation);
+                self.columns.insert(format!("{}.{}", table, c.name), c.collation);
+            }
+        }
+    }
+
+    /// Fails for a plain `name` whose collation depends on which table it
+    /// is read from.
+    pub(crate) fn check(&self, name: &str) -> Result<(), String> {
+        match self.ambiguous.contains(name) {
+            true => Err(format!("Ambiguous collation for column {}: qualify it with its table", name)),
+            false => Ok(()),
+        }
+    }
+
+    /// The collation of a column by name.
+    pub(crate) fn column(&self, name: &str) -> Collation {
+        self.columns.get(name).copied().unwrap_or_default()
+    }
+
+    /// The collation of `expr`: the one it is given with COLLATE, or that
+    /// of the column it reads.
+    pub(crate) fn of(&self, expr: &Expr) -> Collation {
+        match expr {
+            Expr::Column(name) => self.column(name),
+            e => explicit(e).unwrap_or_default(),
+        }
+    }
+
+    /// `query` with its columns' collations given explicitly in its
+    /// comparisons, so evaluating them needs no schema: over a NOCASE
+    /// column `name = 'x'` becomes `name COLLATE NOCASE = 'x'`.
+    pub(crate) fn resolve<'a>(&self, query: &'a Query) -> Cow<'a, Query> {
+        if self.columns.is_empty() {
+            return Cow::Borrowed(query);
+        }
+        let mut query = query.clone();
+        let exprs = query.select_exprs.iter_mut().chain(&mut query.where_clause);
+        let joins = query.joins.iter_mut().flat_map(|j| std::iter::once(&mut j.on).chain(j.unnest.as_mut().map(|u| &mut u.expr)));
+        let unnest = query.from_unnest.as_mut().map(|u| &mut u.expr);
+        for expr in exprs.chain(joins).chain(unnest) {
+            self.resolve_expr(expr);
+        }
+        query.group_by.iter_mut().for_each(|item| self.resolve_item(item));
+        Cow::Owned(query)
+    }
+
+    pub(crate) fn resolve_expr(&self, expr: &mut Expr) {
+        match expr {
+            Expr::BinOp(l, op, r) => {
+                self.resolve_expr(l);
+                self.resolve_expr(r);
+                if is_comparison(op) && of_comparison(l, op, r).is_none() {
+                    for side in [l, r] {
+                        if let Expr::Column(name) = &**side {
+                            if let Some(&c) = self.columns.get(name) {
+                                **side = collate(Expr::Column(name.clone()), c);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Expr::UnaryOp(_, e) => self.resolve_expr(e),
+            Expr::FuncCall(_, args) => args.iter_mut().for_each(|a| self.resolve_expr(a)),
+            Expr::Column(_) | Expr::Literal(_) => {}
+        }
+    }
+
+    fn resolve_item(&self, item: &mut GroupByItem) {
+        match item {
+            GroupByItem::Expr(e) => self.resolve_expr(e),
+            GroupByItem::Set(exprs) | GroupByItem::Rollup(exprs) | GroupByItem::Cube(exprs) => exprs.iter_mut().for_each(|e| self.resolve_expr(e)),
+            GroupByItem::GroupingSets(items) => items.iter_mut().for_each(|i| self.resolve_item(i)),
+        }
+    }
+}
+
+/// `text LIKE pattern`: `%` matches any run of characters and `_` one
+/// character along with the combining marks after it; `\` makes the
+/// character after it match itself.
+pub(crate) fn like(text: &str, pattern: &str) -> bool {
+    enum Piece<'a> {
+        Any,
+        One,
+        Exact(&'a str),
+    }
+    let mut pieces = Vec::new();
+    let mut escaped = false;
+    for unit in units(pattern) {
+        pieces.push(match unit {
+            _ if escaped => Piece::Exact(unit),
+            "\\" => {
+                escaped = true;
+                continue;
+            }
+            "%" => Piece::Any,
+            "_" => Piece::One,
+            _ => Piece::Exact(unit),
+        });
+        escaped = false;
+    }
+    if escaped {
+        pieces.push(Piece::Exact("\\"));
+    }
+
+    // Each `%` first matches nothing, then one more unit on every retry
+    let text = units(text);
+    let (mut t, mut p) = (0, 0);
+    let mut retry: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pieces.get(p) {
+            Some(Piece::Any) => {
+                retry = Some((p, t));
+                p += 1;
+            }
+            Some(Piece::One) => (t, p) = (t + 1, p + 1),
+            Some(Piece::Exact(s)) if *s == text[t] => (t, p) = (t + 1, p + 1),
+            _ => match retry {
+                Some((any, from)) => {
+                    retry = Some((any, from + 1));
+                    (t, p) = (from + 1, any + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    pieces[p..].iter().all(|piece| matches!(piece, Piece::Any))
+}
+
+/// Characters of `text`, each with the combining marks that follow it.
+fn units(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if i > start && !unicode::is_mark(c) {
+            out.push(&text[start..i]);
+            start = i;
+        }
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use crate::{Column, Constraint, DataType, Row, Table, Value};
+
+/// One validity bit per value; a clear bit marks NULL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bitmap {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        i < self.len && self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn push(&mut self, valid: bool) {
+        if self.len.is_multiple_of(64) {
+            self.bits.push(0);
+        }
+        self.len += 1;
+        self.set(self.len - 1, valid);
+    }
+
+    pub fn set(&mut self, i: usize, valid: bool) {
+        if valid {
+            self.bits[i / 64] |= 1 << (i % 64);
+        } else {
+            self.bits[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    pub fn count_valid(&self) -> usize {
+        self.bits.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+/// Values of one column. Typed columns store a placeholder where the
+/// validity bitmap marks NULL; `Any` columns keep whole `Value`s.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnData {
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+    String(Vec<String>),
+    Bool(Vec<bool>),
+    Any(Vec<Value>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnVector {
+    pub data: ColumnData,
+    pub validity: Bitmap,
+}
+
+impl ColumnVector {
+    pub fn new(data_type: &DataType) -> Self {
+        let data = match data_type {
+            DataType::Int => ColumnData::Int(Vec::new()),
+            DataType::Float => ColumnData::Float(Vec::new()),
+            DataType::String => ColumnData::String(Vec::new()),
+            DataType::Bool => ColumnData::Bool(Vec::new()),
+            // Decimal, date, time, JSON, composite, BYTEA and UUID columns keep whole values
+            DataType::Any
+            | DataType::Decimal(..)
+            | DataType::Date
+            | DataType::Time
+            | DataType::Timestamp
+            | DataType::TimestampTz
+            | DataType::Interval
+            | DataType::Json
+            | DataType::Array(_)
+            | DataType::Struct(_)
+            | DataType::Bytes
+            | DataType::Uuid => ColumnData::Any(Vec::new()),
+        };
+        ColumnVector { data, validity: Bitmap::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validity.is_empty()
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.len() - self.validity.count_valid()
+    }
+
+    pub fn get(&self, i: usize) -> Value {
+        if !self.validity.get(i) {
+            return Value::Null;
+        }
+        match &self.data {
+            ColumnData::Int(v) => Value::Int(v[i]),
+            ColumnData::Float(v) => Value::Float(v[i]),
+            ColumnData::String(v) => Value::String(v[i].clone()),
+            ColumnData::Bool(v) => Value::Bool(v[i]),
+            ColumnData::Any(v) => v[i].clone(),
+        }
+    }
+
+    /// Appends a value that has already been coerced to the column type.
+    pub fn push(&mut self, value: Value) -> Result<(), String> {
+        let valid = self.store(None, value)?;
+        self.validity.push(valid);
+        Ok(())
+    }
+
+    /// Replaces value `i` with one already coerced to the column type.
+    pub fn set(&mut self, i: usize, value: Value) -> Result<(), String> {
+        let valid = self.store(Some(i), value)?;
+        self.validity.set(i, valid);
+        Ok(())
+    }
+
+    /// Keeps the values whose `keep` flag is set.
+    pub fn retain(&mut self, keep: &[bool]) {
+        fn retain<T>(v: &mut Vec<T>, keep: &[bool]) {
+            let mut flags = keep.iter();
+            v.retain(|_| *flags.next().unwrap());
+        }
+        match &mut self.data {
+            ColumnData::Int(v) => retain(v, keep),
+            ColumnData::Float(v) => retain(v, keep),
+            ColumnData::String(v) => retain(v, keep),
+            ColumnData::Bool(v) => retain(v, keep),
+            ColumnData::Any(v) => retain(v, keep),
+        }
+        let mut validity = Bitmap::default();
+        for i in (0..keep.len()).filter(|&i| keep[i]) {
+            validity.push(self.validity.get(i));
+        }
+        self.validity = validity;
+    }
+
+    /// Writes `value` at `at`, or appends it, and returns whether it is
+    /// valid. A typed vector holds a placeholder for NULL.
+    fn store(&mut self, at: Option<usize>, value: Value) -> Result<bool, String> {
+        fn put<T>(v: &mut Vec<T>, at: Option<usize>, x: T) {
+            match at {
+                Some(i) => v[i] = x,
+                None => v.push(x),
+            }
+        }
+        let valid = value != Value::Null;
+        match (&mut self.data, value) {
+            (ColumnData::Any(v), value) => put(v, at, value),
+            (ColumnData::Int(v), Value::Int(x)) => put(v, at, x),
+            (ColumnData::Int(v), Value::Null) => put(v, at, 0),
+            (ColumnData::Float(v), Value::Float(x)) => put(v, at, x),
+            (ColumnData::Float(v), Value::Null) => put(v, at, 0.0),
+            (ColumnData::String(v), Value::String(x)) => put(v, at, x),
+            (ColumnData::String(v), Value::Null) => put(v, at, String::new()),
+            (ColumnData::Bool(v), Value::Bool(x)) => put(v, at, x),
+            (ColumnData::Bool(v), Value::Null) => put(v, at, false),
+            (_, value) => return Err(format!("Cannot store {} in a typed column vector", value)),
+        }
+        Ok(valid)
+    }
+}
+
+/// Column-oriented counterpart of `Table`: the same schema and constraints,
+/// with one typed vector per column instead of a map per row.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnarTable {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub constraints: Vec<Constraint>,
+    vectors: Vec<ColumnVector>,
+    len: usize,
+}
+
+impl ColumnarTable {
+    pub fn new(name: &str, columns: Vec<Column>) -> Self {
+        let vectors = columns.iter().map(|c| ColumnVector::new(&c.data_type)).collect();
+        ColumnarTable { name: name.to_string(), columns, constraints: Vec::new(), vectors, len: 0 }
+    }
+
+    /// Converts a row table, coercing each value to its declared column type.
+    pub fn from_table(table: &Table) -> Result<Self, String> {
+        let mut columnar = ColumnarTable::new(&table.name, table.columns.clone());
+        columnar.constraints = table.constraints.clone();
+        for row in &table.rows {
+            columnar.push_row(row)?;
+        }
+        Ok(columnar)
+    }
+
+    pub fn to_table(&self) -> Table {
+        Table {
+            name: self.name.clone(),
+            columns: self.columns.clone(),
+            rows: (0..self.len).map(|i| self.row(i)).collect(),
+            constraints: self.constraints.clone(),
+            indexes: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    pub fn vector(&self, col: usize) -> &ColumnVector {
+        &self.vectors[col]
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Value {
+        self.vectors[col].get(row)
+    }
+
+    /// Materializes one row in the row-table form.
+    pub fn row(&self, i: usize) -> Row {
+        let data: HashMap<String, Value> = self.columns.iter().zip(&self.vectors).map(|(c, v)| (c.name.clone(), v.get(i))).collect();
+        Row { data }
+    }
+
+    /// Appends a row; columns missing from `row` are NULL.
+    pub fn push_row(&mut self, row: &Row) -> Result<(), String> {
+        self.append(std::slice::from_ref(row))
+    }
+
+    /// Appends rows, coercing them all before storing any, so a value that
+    /// does not fit its column leaves the table unchanged.
+    pub fn append(&mut self, rows: &[Row]) -> Result<(), String> {
+        let rows = rows.iter().map(|r| self.values(r)).collect::<Result<Vec<_>, _>>()?;
+        for values in rows {
+            for (vector, v) in self.vectors.iter_mut().zip(values) {
+                vector.push(v)?;
+            }
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Replaces row `i`; columns missing from `row` become NULL.
+    pub fn set_row(&mut self, i: usize, row: &Row) -> Result<(), String> {
+        let values = self.values(row)?;
+        for (vector, v) in self.vectors.iter_mut().zip(values) {
+            vector.set(i, v)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the rows whose `keep` flag is set.
+    pub fn retain(&mut self, keep: &[bool]) {
+        for vector in &mut self.vectors {
+            vector.retain(keep);
+        }
+        self.len = keep.iter().filter(|&&k| k).count();
+    }
+
+    /// The values of `row` in column order, coerced to the column types.
+    fn values(&self, row: &Row) -> Result<Vec<Value>, String> {
+        self.columns
+            .iter()
+            .map(|c| {
+                let v = row.data.get(&c.name).cloned().unwrap_or(Value::Null);
+                c.data_type.coerce(v).map_err(|e| format!("Column {}.{}: {}", self.name, c.name, e))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,121 @@
+//! Arrays and structs: element and field access, comparisons, the array
+//! functions and UNNEST.
+//!
+//! Arrays are indexed from 1, and a negative index counts from the end.
+//! Two arrays or two structs with the same field names compare element by
+//! element, left to right, the first unequal pair deciding; a NULL in that
+//! pair makes the comparison NULL, and a shorter array that is a prefix of
+//! the other sorts first.
+
+use std::cmp::Ordering;
+
+use crate::{Database, DataType, Json, Value};
+
+/// The type of an array of `items`: arrays of the first non-NULL item's type.
+pub(crate) fn array_type(items: &[Value]) -> DataType {
+    let element = items.iter().find(|v| **v != Value::Null).map_or(DataType::Any, Value::data_type);
+    DataType::Array(Box::new(element))
+}
+
+/// Pairs to compare for two arrays, or two structs with the same fields.
+fn pairs<'a>(left: &'a Value, right: &'a Value) -> Option<(Vec<(&'a Value, &'a Value)>, Ordering)> {
+    match (left, right) {
+        (Value::Array(a), Value::Array(b)) => Some((a.iter().zip(b).collect(), a.len().cmp(&b.len()))),
+        (Value::Struct(a), Value::Struct(b)) if a.len() == b.len() && a.iter().zip(b).all(|((x, _), (y, _))| x == y) => {
+            Some((a.iter().zip(b).map(|((_, x), (_, y))| (x, y)).collect(), Ordering::Equal))
+        }
+        _ => None,
+    }
+}
+
+/// Comparison of two arrays or structs; arithmetic on them is NULL.
+pub(crate) fn binop(db: &Database, left: &Value, op: &str, right: &Value) -> Option<Value> {
+    let (pairs, lengths) = pairs(left, right)?;
+    if !matches!(op, "=" | "!=" | "<" | ">" | "<=" | ">=") {
+        return None;
+    }
+    for (a, b) in pairs {
+        match db.apply_binop(a, "=", b) {
+            Value::Bool(true) => {}
+            Value::Bool(false) => return Some(db.apply_binop(a, op, b)),
+            _ => return Some(Value::Null),
+        }
+    }
+    let holds = match op {
+        "=" => lengths == Ordering::Equal,
+        "!=" => lengths != Ordering::Equal,
+        "<" => lengths == Ordering::Less,
+        ">" => lengths == Ordering::Greater,
+        "<=" => lengths != Ordering::Greater,
+        _ => lengths != Ordering::Less,
+    };
+    Some(Value::Bool(holds))
+}
+
+/// `a[i]`: the element at a 1-based position of an array, or the field
+/// of a struct named by text.
+pub(crate) fn element_at(v: &Value, index: &Value) -> Option<Value> {
+    match (v, index) {
+        (Value::Array(items), Value::Int(i)) => {
+            let i = match *i {
+                0 => return None,
+                i if i < 0 => i.checked_add(items.len() as i64)?,
+                i => i - 1,
+            };
+            items.get(usize::try_from(i).ok()?).cloned()
+        }
+        (Value::Struct(_), Value::String(name)) => field(v, name).cloned(),
+        _ => None,
+    }
+}
+
+/// A field of a struct.
+pub(crate) fn field<'a>(v: &'a Value, name: &str) -> Option<&'a Value> {
+    match v {
+        Value::Struct(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+/// STRUCT(name, value, ...), as `{'name': value, ...}` builds it.
+pub(crate) fn build_struct(args: &[Value]) -> Option<Value> {
+    if !args.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut fields: Vec<(String, Value)> = Vec::new();
+    for pair in args.chunks(2) {
+        let Value::String(name) = &pair[0] else { return None };
+        if fields.iter().any(|(n, _)| n == name) {
+            return None;
+        }
+        fields.push((name.clone(), pair[1].clone()));
+    }
+    Some(Value::Struct(fields))
+}
+
+/// ARRAY_CONTAINS(array, value): whether an element equals `value`.
+pub(crate) fn contains(db: &Database, array: &Value, value: &Value) -> Option<Value> {
+    let Value::Array(items) = array else { return None };
+    if *value == Value::Null {
+        return None;
+    }
+    Some(Value::Bool(items.iter().any(|item| db.apply_binop(item, "=", value).is_true())))
+}
+
+/// CARDINALITY(array): the number of elements.
+pub(crate) fn cardinality(array: &Value) -> Option<Value> {
+    match array {
+        Value::Array(items) => Some(Value::Int(items.len() as i64)),
+        _ => None,
+    }
+}
+
+/// The rows UNNEST produces from a value: the elements of an array or of a
+/// JSON array, and none for anything else.
+pub(crate) fn elements(v: Value) -> Vec<Value> {
+    match v {
+        Value::Array(items) => items,
+        Value::Json(Json::Array(items)) => items.into_iter().map(Value::Json).collect(),
+        _ => Vec::new(),
+    }
+}
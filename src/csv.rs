@@ -0,0 +1,245 @@
+//! CSV import and export: `Database::load_csv` and `COPY t FROM 'file'`
+//! read a file into a table, `Database::write_csv` and
+//! `COPY t | (query) TO 'file'` write one out.
+//!
+//! Fields are separated by the delimiter and records by `\n` or `\r\n`;
+//! blank lines are skipped. A field in quotes may hold delimiters, quotes
+//! and line breaks: a quote inside one is written twice, or after the
+//! escape character when one is set. An unquoted field equal to the NULL
+//! text (by default the empty field) is NULL, so `""` is the empty string.
+//!
+//! Loading into an existing table converts each field to its column's type
+//! as CAST does. A new table is created with one column per field, typed
+//! by its values: INT, FLOAT, BOOL (`true` or `false`), DATE, TIMESTAMP or
+//! TIMESTAMPTZ when every non-NULL value reads as one, in that order, and
+//! TEXT otherwise.
+//!
+//! Unless told, a first record is taken for a header when it names the
+//! existing table's columns or, for a new table, when its fields are
+//! distinct, non-empty and all text.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{temporal, Column, Database, DataType, Expr, Insert, InsertSource, Query, ResultSet, Row, Statement, Table, Value};
+
+/// How CSV text is read and written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// Whether the first record names the columns; `None` detects it when
+    /// reading and writes one.
+    pub header: Option<bool>,
+    pub delimiter: char,
+    pub quote: char,
+    /// Makes a quote inside a quoted field literal; the quote itself, for
+    /// doubled quotes, by default.
+    pub escape: char,
+    /// Unquoted text that stands for NULL.
+    pub null: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions { header: None, delimiter: ',', quote: '"', escape: '"', null: String::new() }
+    }
+}
+
+/// A record and the line it starts on; `None` fields are NULL.
+type Record = (usize, Vec<Option<String>>);
+
+impl Database {
+    /// Loads the CSV file at `path` into table `name`, creating the table
+    /// when there is none. Returns the number of rows loaded. The rows go
+    /// in as one INSERT, or with the new table, so a field that does not
+    /// fit its column or a violated constraint loads nothing and creates no
+    /// table.
+    pub fn load_csv(&mut self, name: &str, path: impl AsRef<Path>, options: &CsvOptions) -> Result<usize, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let mut records = parse(&text, options)?;
+        let existing = self.tables.get(name).map(|t| &t.columns).or_else(|| self.columnar.get(name).map(|t| &t.columns)).cloned();
+
+        let first = records.first().map(|(_, fields)| fields.as_slice()).unwrap_or_default();
+        let header = options.header.unwrap_or_else(|| match &existing {
+            Some(columns) => first.iter().all(|f| f.as_ref().is_some_and(|f| columns.iter().any(|c| c.name == *f))),
+            None => {
+                let names: Vec<&str> = first.iter().flatten().map(String::as_str).collect();
+                names.len() == first.len() && names.iter().enumerate().all(|(i, n)| !n.is_empty() && !names[..i].contains(n) && infer(&[n]) == DataType::String)
+            }
+        });
+        let names: Vec<String> = match (header, &existing) {
+            (true, _) if records.is_empty() => return Err(format!("CSV file {} has no header", path.display())),
+            (true, _) => records.remove(0).1.into_iter().map(Option::unwrap_or_default).collect(),
+            (false, Some(columns)) => columns.iter().map(|c| c.name.clone()).collect(),
+            (false, None) => (1..=first.len()).map(|i| format!("column{}", i)).collect(),
+        };
+        for (i, n) in names.iter().enumerate() {
+            if names[..i].contains(n) {
+                return Err(format!("Duplicate column in CSV header: {}", n));
+            }
+        }
+        if let Some((line, fields)) = records.iter().find(|(_, fields)| fields.len() != names.len()) {
+            return Err(format!("CSV line {}: expected {} fields, found {}", line, names.len(), fields.len()));
+        }
+
+        let types: Vec<DataType> = match &existing {
+            Some(columns) => {
+                let column = |n: &String| columns.iter().find(|c| c.name == *n).ok_or(format!("Column not found: {}.{}", name, n));
+                names.iter().map(|n| column(n).map(|c| c.data_type.clone())).collect::<Result<_, _>>()?
+            }
+            None => (0..names.len()).map(|i| infer(&records.iter().filter_map(|(_, fields)| fields[i].as_deref()).collect::<Vec<_>>())).collect(),
+        };
+        let mut rows = Vec::with_capacity(records.len());
+        for (line, fields) in records {
+            let mut row = Vec::with_capacity(fields.len());
+            for ((field, ty), n) in fields.into_iter().zip(&types).zip(&names) {
+                row.push(match field {
+                    None => Value::Null,
+                    Some(text) => convert(ty, text).map_err(|e| format!("CSV line {}: column {}: {}", line, n, e))?,
+                });
+            }
+            rows.push(row);
+        }
+
+        if existing.is_none() {
+            // The new table arrives with its rows, so a failure leaves none behind
+            let mut table = Table::new(name, names.iter().zip(types).map(|(n, ty)| Column::new(n, ty)).collect());
+            table.rows = rows.into_iter().map(|row| Row { data: names.iter().cloned().zip(row).collect() }).collect();
+            let count = table.rows.len();
+            self.add_table(table)?;
+            return Ok(count);
+        }
+        let rows = rows.into_iter().map(|row| row.into_iter().map(Expr::Literal).collect()).collect();
+        let insert = Insert { table: name.into(), columns: names, source: InsertSource::Values(rows), returning: Vec::new() };
+        Ok(self.execute_statement(&Statement::Insert(insert))?.rows_affected)
+    }
+
+    /// Writes the result of `query` to a CSV file at `path`, replacing any
+    /// file there. Returns the number of rows written.
+    pub fn write_csv(&self, query: &Query, path: impl AsRef<Path>, options: &CsvOptions) -> Result<usize, String> {
+        let path = path.as_ref();
+        let result = self.execute(query)?;
+        fs::write(path, write(&result, options)).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        Ok(result.len())
+    }
+}
+
+/// Records of CSV `text`, each with the line it starts on.
+pub(crate) fn parse(text: &str, options: &CsvOptions) -> Result<Vec<Record>, String> {
+    let CsvOptions { delimiter, quote, escape, .. } = *options;
+    let mut chars = text.strip_prefix('\u{feff}').unwrap_or(text).chars().peekable();
+    let mut records = Vec::new();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        if c == '\r' || c == '\n' {
+            chars.next();
+            line += usize::from(c == '\n');
+            continue;
+        }
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            let quoted = chars.peek() == Some(&quote);
+            if quoted {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        None => return Err(format!("CSV line {}: unterminated quoted field", start)),
+                        Some(c) if c == escape && escape != quote && matches!(chars.peek(), Some(&n) if n == quote || n == escape) => field.push(chars.next().unwrap()),
+                        Some(c) if c == quote && escape == quote && chars.peek() == Some(&quote) => field.push(chars.next().unwrap()),
+                        Some(c) if c == quote => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            field.push(c);
+                        }
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek().filter(|&&c| c != delimiter && c != '\n' && c != '\r') {
+                    field.push(c);
+                    chars.next();
+                }
+            }
+            fields.push(if !quoted && field == options.null { None } else { Some(field) });
+            match chars.next() {
+                Some(c) if c == delimiter => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                    line += 1;
+                    break;
+                }
+                Some('\r') => break,
+                Some('\n') => {
+                    line += 1;
+                    break;
+                }
+                None => break,
+                Some(c) => return Err(format!("CSV line {}: unexpected {:?} after a quoted field", line, c)),
+            }
+        }
+        records.push((start, fields));
+    }
+    Ok(records)
+}
+
+/// `result` as CSV text, with a header unless `options` says not to.
+pub(crate) fn write(result: &ResultSet, options: &CsvOptions) -> String {
+    let mut out = String::new();
+    let mut record = |fields: Vec<Option<String>>| {
+        let fields: Vec<String> = fields.into_iter().map(|f| f.map_or_else(|| options.null.clone(), |f| field(&f, options))).collect();
+        out.push_str(&fields.join(&options.delimiter.to_string()));
+        out.push('\n');
+    };
+    if options.header != Some(false) {
+        record(result.columns.iter().map(|c| Some(c.name.clone())).collect());
+    }
+    for row in &result.rows {
+        record(row.iter().map(|v| (*v != Value::Null).then(|| v.text())).collect());
+    }
+    out
+}
+
+/// A non-NULL field, quoted when it must be to read back as itself.
+fn field(text: &str, options: &CsvOptions) -> String {
+    let CsvOptions { delimiter, quote, escape, .. } = *options;
+    let special = |c: char| c == delimiter || c == quote || c == escape || c == '\n' || c == '\r';
+    if !text.contains(special) && text != options.null {
+        return text.to_string();
+    }
+    let mut out = String::from(quote);
+    for c in text.chars() {
+        if c == quote || (c == escape && escape != quote) {
+            out.push(escape);
+        }
+        out.push(c);
+    }
+    out.push(quote);
+    out
+}
+
+/// The narrowest type every one of `values` reads as.
+fn infer(values: &[&str]) -> DataType {
+    let reads_as = |ty: &DataType| {
+        values.iter().all(|v| match ty {
+            DataType::Int => v.trim().parse::<i64>().is_ok(),
+            DataType::Float => v.trim().parse::<f64>().is_ok(),
+            DataType::Bool => v.trim().eq_ignore_ascii_case("true") || v.trim().eq_ignore_ascii_case("false"),
+            ty => temporal::parse(ty, v.trim()).is_ok(),
+        })
+    };
+    let candidates = [DataType::Int, DataType::Float, DataType::Bool, DataType::Date, DataType::Timestamp, DataType::TimestampTz];
+    match candidates.into_iter().find(reads_as) {
+        Some(ty) if !values.is_empty() => ty,
+        _ => DataType::String,
+    }
+}
+
+/// A field read as a value of type `ty`: as CAST reads text, or for an
+/// untyped column as the narrowest type it reads as.
+fn convert(ty: &DataType, text: String) -> Result<Value, String> {
+    match ty {
+        DataType::Any => convert(&infer(&[&text]), text),
+        ty => ty.cast(Value::String(text)),
+    }
+}
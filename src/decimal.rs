@@ -0,0 +1,361 @@
+//! Exact decimal numbers, for DECIMAL(p, s) columns and money.
+//!
+//! A `Decimal` is an integer of up to `MAX_PRECISION` digits and a scale,
+//! the number of those digits after the point. Addition, subtraction,
+//! multiplication and remainders are exact; division keeps
+//! `DIVISION_SCALE` more digits than its operands and rounds the last.
+//! Results too large for 38 digits give up digits after the point, and
+//! NULL when they cannot.
+//!
+//! Arithmetic and comparisons with an INT are exact. A FLOAT mixed with a
+//! DECIMAL is read as the shortest decimal that converts back to it, so
+//! `price * 1.1` multiplies by exactly 1.1.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::{DataType, Value};
+
+/// Most digits a decimal holds.
+pub const MAX_PRECISION: u8 = 38;
+
+/// Digits a quotient keeps beyond the larger scale of its operands.
+pub const DIVISION_SCALE: u8 = 6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    digits: i128,
+    scale: u8,
+}
+
+/// How digits dropped by rounding move the last one kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// To the nearest, halves away from zero.
+    #[default]
+    HalfUp,
+    /// To the nearest, halves to the even neighbour (banker's rounding).
+    HalfEven,
+    /// Toward zero (truncation).
+    Down,
+    /// Away from zero.
+    Up,
+    /// Toward positive infinity.
+    Ceiling,
+    /// Toward negative infinity.
+    Floor,
+}
+
+impl RoundingMode {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_uppercase().as_str() {
+            "HALF_UP" => Ok(RoundingMode::HalfUp),
+            "HALF_EVEN" => Ok(RoundingMode::HalfEven),
+            "DOWN" | "TRUNCATE" => Ok(RoundingMode::Down),
+            "UP" => Ok(RoundingMode::Up),
+            "CEILING" => Ok(RoundingMode::Ceiling),
+            "FLOOR" => Ok(RoundingMode::Floor),
+            other => Err(format!("Unknown rounding mode: {}", other)),
+        }
+    }
+}
+
+impl Decimal {
+    /// `digits / 10^scale`; `None` past `MAX_PRECISION` digits.
+    pub fn new(digits: i128, scale: u8) -> Option<Decimal> {
+        (scale <= MAX_PRECISION && digits.unsigned_abs() < limit(MAX_PRECISION)).then_some(Decimal { digits, scale })
+    }
+
+    pub fn digits(self) -> i128 {
+        self.digits
+    }
+
+    pub fn scale(self) -> u8 {
+        self.scale
+    }
+
+    /// Number of digits, not counting leading zeros (at least 1).
+    pub fn precision(self) -> u8 {
+        let mut n = 1;
+        while n < MAX_PRECISION && self.digits.unsigned_abs() >= limit(n) {
+            n += 1;
+        }
+        n
+    }
+
+    /// Parses `[-+]digits[.digits][e[-+]digits]`, keeping the scale written.
+    pub fn parse(text: &str) -> Result<Decimal, String> {
+        parse(text.trim()).ok_or_else(|| format!("Invalid decimal: '{}'", text))
+    }
+
+    /// The shortest decimal that converts back to `f`; `None` for NaN, the
+    /// infinities and magnitudes beyond 38 digits.
+    pub fn from_f64(f: f64) -> Option<Decimal> {
+        if f.is_finite() { parse(&f.to_string()) } else { None }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    /// The value as an integer, if it has no fraction and fits.
+    pub fn to_i64(self) -> Option<i64> {
+        let divisor = 10i128.pow(self.scale as u32);
+        if self.digits % divisor != 0 { None } else { i64::try_from(self.digits / divisor).ok() }
+    }
+
+    /// Rounds to `scale` digits after the point; a negative scale rounds to
+    /// tens, hundreds and so on. `None` if the result does not fit.
+    pub fn round(self, scale: i32, mode: RoundingMode) -> Option<Decimal> {
+        let d = self.rounded(scale, mode)?;
+        Decimal::new(d.digits, d.scale)
+    }
+
+    /// `round` without the precision limit.
+    fn rounded(self, scale: i32, mode: RoundingMode) -> Option<Decimal> {
+        let target = scale.clamp(0, MAX_PRECISION as i32) as u8;
+        if scale >= self.scale as i32 {
+            return Some(Decimal { digits: self.digits.checked_mul(pow10(target - self.scale)?)?, scale: target });
+        }
+        let dropped = (self.scale as i32 - scale) as u32;
+        let Some(divisor) = 10i128.checked_pow(dropped) else { return Some(Decimal { digits: 0, scale: target }) };
+        let (q, r) = (self.digits / divisor, self.digits % divisor);
+        let sign = self.digits.signum();
+        let half = r.abs().cmp(&(divisor - r.abs()));
+        let away = match mode {
+            RoundingMode::HalfUp => half != Ordering::Less,
+            RoundingMode::HalfEven => half == Ordering::Greater || (half == Ordering::Equal && q % 2 != 0),
+            RoundingMode::Down => false,
+            RoundingMode::Up => r != 0,
+            RoundingMode::Ceiling => r > 0,
+            RoundingMode::Floor => r < 0,
+        };
+        let q = if away { q + sign } else { q };
+        // A negative scale puts back the zeros it rounded away
+        Some(Decimal { digits: q.checked_mul(pow10((target as i32 - scale) as u8)?)?, scale: target })
+    }
+
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let sum = self.upscale(scale)?.checked_add(other.upscale(scale)?)?;
+        fit(sum, scale)
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        self.checked_add(other.checked_neg()?)
+    }
+
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        fit(self.digits.checked_mul(other.digits)?, self.scale + other.scale)
+    }
+
+    /// `None` when dividing by zero.
+    pub fn checked_div(self, other: Decimal) -> Option<Decimal> {
+        if other.digits == 0 {
+            return None;
+        }
+        let scale = (self.scale.max(other.scale) + DIVISION_SCALE).min(MAX_PRECISION);
+        // self.digits * 10^(scale - self.scale + other.scale) / other.digits
+        let n = self.digits.checked_mul(pow10(scale + other.scale - self.scale)?)?;
+        let (q, r) = (n / other.digits, n % other.digits);
+        let q = if r.abs() >= other.digits.abs() - r.abs() { q + n.signum() * other.digits.signum() } else { q };
+        fit(q, scale)
+    }
+
+    pub fn checked_rem(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        fit(self.upscale(scale)?.checked_rem(other.upscale(scale)?)?, scale)
+    }
+
+    pub fn checked_neg(self) -> Option<Decimal> {
+        Some(Decimal { digits: -self.digits, scale: self.scale })
+    }
+
+    pub fn abs(self) -> Decimal {
+        Decimal { digits: self.digits.abs(), scale: self.scale }
+    }
+
+    /// Digits at a larger scale, `None` on overflow.
+    fn upscale(self, scale: u8) -> Option<i128> {
+        self.digits.checked_mul(pow10(scale - self.scale)?)
+    }
+
+    /// Without trailing zeros after the point, so equal values match.
+    fn normalized(self) -> (i128, u8) {
+        let (mut digits, mut scale) = (self.digits, self.scale);
+        while scale > 0 && digits % 10 == 0 {
+            digits /= 10;
+            scale -= 1;
+        }
+        (digits, scale)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Decimal { digits: i as i128, scale: 0 }
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+/// Hashes the value, so 1.5 and 1.50 hash alike.
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized().hash(state);
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        // Only the smaller scale is raised; if that overflows, its side is
+        // the larger in magnitude
+        match (self.upscale(scale), other.upscale(scale)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (None, _) => self.digits.cmp(&0),
+            (_, None) => 0.cmp(&other.digits),
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.digits.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let digits = if digits.len() <= scale { format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits) } else { digits };
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        let sign = if self.digits < 0 { "-" } else { "" };
+        if frac.is_empty() { write!(f, "{}{}", sign, int) } else { write!(f, "{}{}.{}", sign, int, frac) }
+    }
+}
+
+fn limit(digits: u8) -> u128 {
+    10u128.pow(digits as u32)
+}
+
+fn pow10(exp: u8) -> Option<i128> {
+    10i128.checked_pow(exp as u32)
+}
+
+/// A result kept within `MAX_PRECISION` digits, rounding off digits after
+/// the point as needed.
+fn fit(digits: i128, scale: u8) -> Option<Decimal> {
+    let mut d = Decimal { digits, scale };
+    if scale > MAX_PRECISION {
+        d = d.rounded(MAX_PRECISION as i32, RoundingMode::HalfUp)?;
+    }
+    while d.digits.unsigned_abs() >= limit(MAX_PRECISION) && d.scale > 0 {
+        d = d.rounded(d.scale as i32 - 1, RoundingMode::HalfUp)?;
+    }
+    Decimal::new(d.digits, d.scale)
+}
+
+fn parse(s: &str) -> Option<Decimal> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let all = format!("{}{}", int, frac);
+    let all = all.trim_start_matches('0');
+    if all.len() > MAX_PRECISION as usize {
+        return None;
+    }
+    let digits: i128 = if all.is_empty() { 0 } else { all.parse().ok()? };
+    let digits = if negative { -digits } else { digits };
+    let scale = frac.len() as i32 - exponent;
+    if scale < 0 {
+        Decimal::new(digits.checked_mul(pow10(u8::try_from(-scale).ok()?)?)?, 0)
+    } else {
+        fit(digits, u8::try_from(scale).ok()?)
+    }
+}
+
+/// An INT, FLOAT or DECIMAL as a decimal.
+pub(crate) fn to_decimal(v: &Value) -> Option<Decimal> {
+    match v {
+        Value::Decimal(d) => Some(*d),
+        Value::Int(i) => Some(Decimal::from(*i)),
+        Value::Float(f) => Decimal::from_f64(*f),
+        _ => None,
+    }
+}
+
+/// Comparison or arithmetic on numbers of which at least one is a DECIMAL;
+/// `None` for other operators, division by zero and overflow.
+pub(crate) fn binop(left: &Value, op: &str, right: &Value) -> Option<Value> {
+    let (a, b) = (to_decimal(left)?, to_decimal(right)?);
+    let result = match op {
+        "=" => return Some(Value::Bool(a == b)),
+        "!=" => return Some(Value::Bool(a != b)),
+        "<" => return Some(Value::Bool(a < b)),
+        ">" => return Some(Value::Bool(a > b)),
+        "<=" => return Some(Value::Bool(a <= b)),
+        ">=" => return Some(Value::Bool(a >= b)),
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        _ => None,
+    };
+    result.map(Value::Decimal)
+}
+
+/// Type of `left op right` when either side is a DECIMAL.
+pub(crate) fn result_type(left: &DataType, op: &str, right: &DataType) -> Option<DataType> {
+    let scale = |t: &DataType| match t {
+        DataType::Decimal(_, s) => Some(*s),
+        DataType::Int | DataType::Float => Some(0),
+        _ => None,
+    };
+    if !matches!((left, right), (DataType::Decimal(..), _) | (_, DataType::Decimal(..))) {
+        return None;
+    }
+    let (l, r) = (scale(left)?, scale(right)?);
+    let scale = match op {
+        "+" | "-" | "%" => l.max(r),
+        "*" => (l + r).min(MAX_PRECISION),
+        "/" => (l.max(r) + DIVISION_SCALE).min(MAX_PRECISION),
+        _ => return None,
+    };
+    Some(DataType::Decimal(MAX_PRECISION, scale))
+}
+
+/// `ROUND(x [, places [, mode]])` and `TRUNC(x [, places])` of an INT,
+/// FLOAT or DECIMAL, keeping its type. A FLOAT is rounded as the shortest
+/// decimal that converts back to it.
+pub(crate) fn round(x: &Value, places: Option<&Value>, mode: RoundingMode) -> Option<Value> {
+    let places = match places {
+        None => 0,
+        Some(Value::Int(n)) => i32::try_from(*n).ok()?,
+        Some(_) => return None,
+    };
+    let rounded = to_decimal(x)?.round(places, mode)?;
+    Some(match x {
+        Value::Int(_) => Value::Int(rounded.to_i64()?),
+        Value::Float(_) => Value::Float(rounded.to_f64()),
+        _ => Value::Decimal(rounded),
+    })
+}
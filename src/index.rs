@@ -0,0 +1,501 @@
+//! Secondary indexes.
+//!
+//! An index maps the values of its columns to the positions of the rows
+//! holding them, in a B-tree (`BTreeMap`) or, for hash indexes, a hash table
+//! that only answers equality on every column. Keys order as `Value` does:
+//! NULL first, then booleans, numbers and strings, with numbers of every
+//! type compared by value as SQL compares them. Every value a comparison
+//! with a number, string or boolean can match therefore lies in one
+//! contiguous key range.
+//!
+//! Text is keyed under its column's collation, so an index over a NOCASE
+//! column serves comparisons and ORDER BY made under NOCASE, and only
+//! those.
+//!
+//! Each index also keeps a copy of its key and INCLUDE columns for every
+//! row, so a query reading only those columns is answered from the index
+//! alone (an index-only scan).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Bound;
+
+use crate::collation::{self, Collation};
+use crate::{binary, conjuncts, contains_aggregate, expr_columns, field_path, query_columns, temporal, Database, DataType, Expr, Query, Row, Table, Value};
+
+#[derive(Clone)]
+pub struct Index {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    pub kind: IndexKind,
+    /// Extra columns stored with each entry but not part of the key.
+    pub include: Vec<String>,
+    tree: BTreeMap<IndexKey, Vec<usize>>,
+    hash: HashMap<IndexKey, Vec<usize>>,
+    /// Values of `columns` then `include`, by row position.
+    covered: Vec<Vec<Value>>,
+    /// Collations of `columns`, which keys are folded by.
+    collations: Vec<Collation>,
+}
+
+/// How an index stores its keys (`USING BTREE` or `USING HASH`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IndexKind {
+    /// Ordered: serves equality, ranges and ORDER BY.
+    #[default]
+    BTree,
+    /// Serves equality on every key column only.
+    Hash,
+}
+
+/// Values of an index's columns, ordered column by column as `Value`
+/// orders them; a key sorts before the longer keys it is a prefix of.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct IndexKey(Vec<Value>);
+
+impl Index {
+    /// An empty B-tree index without INCLUDE columns; `Table::reindex`
+    /// fills it.
+    pub fn new(name: &str, columns: Vec<String>, unique: bool) -> Self {
+        Index {
+            name: name.to_string(),
+            columns,
+            unique,
+            kind: IndexKind::BTree,
+            include: Vec::new(),
+            tree: BTreeMap::new(),
+            hash: HashMap::new(),
+            covered: Vec::new(),
+            collations: Vec::new(),
+        }
+    }
+
+    /// Whether every one of `columns` can be read from the index.
+    pub fn covers(&self, columns: &[String]) -> bool {
+        columns.iter().all(|c| self.columns.contains(c) || self.include.contains(c))
+    }
+
+    /// Rebuilds the index from every row of the table, whose key columns
+    /// have `collations`.
+    pub(crate) fn build(&mut self, rows: &[Row], collations: Vec<Collation>) {
+        self.collations = collations;
+        self.tree.clear();
+        self.hash.clear();
+        self.covered.clear();
+        for (position, row) in rows.iter().enumerate() {
+            self.insert(row, position);
+        }
+    }
+
+    /// Adds the row at `position`, which must follow every indexed row.
+    pub(crate) fn insert(&mut self, row: &Row, position: usize) {
+        debug_assert_eq!(position, self.covered.len());
+        let (key, covered) = self.entry(row);
+        self.covered.push(covered);
+        self.attach(key, position);
+    }
+
+    /// Moves the row at `position` from the entry of `old` to that of `new`.
+    pub(crate) fn update(&mut self, position: usize, old: &Row, new: &Row) {
+        let (old_key, _) = self.entry(old);
+        let (key, covered) = self.entry(new);
+        self.covered[position] = covered;
+        if key != old_key {
+            self.detach(&old_key, position);
+            self.attach(key, position);
+        }
+    }
+
+    /// Drops the entries of `rows`, held at the ascending `positions`, and
+    /// renumbers the rows after them, which move up to close the gaps.
+    pub(crate) fn remove(&mut self, positions: &[usize], rows: &[&Row]) {
+        for (&position, row) in positions.iter().zip(rows) {
+            let (key, _) = self.entry(row);
+            self.detach(&key, position);
+        }
+        let Some(&first) = positions.first() else { return };
+        let shift = |held: &mut Vec<usize>| {
+            for p in held.iter_mut().filter(|p| **p > first) {
+                *p -= positions.partition_point(|&d| d < *p);
+            }
+        };
+        self.tree.values_mut().for_each(shift);
+        self.hash.values_mut().for_each(shift);
+        let mut position = 0;
+        self.covered.retain(|_| {
+            position += 1;
+            positions.binary_search(&(position - 1)).is_err()
+        });
+    }
+
+    /// The key of `row` and the values of its key and INCLUDE columns.
+    fn entry(&self, row: &Row) -> (IndexKey, Vec<Value>) {
+        let value = |c: &String| row.data.get(c).cloned().unwrap_or(Value::Null);
+        let values: Vec<Value> = self.columns.iter().map(value).collect();
+        let key = IndexKey(values.iter().zip(&self.collations).map(|(v, c)| c.key(v).into_owned()).collect());
+        (key, values.into_iter().chain(self.include.iter().map(value)).collect())
+    }
+
+    /// Files `position` under `key`, keeping each key's positions in table
+    /// order.
+    fn attach(&mut self, key: IndexKey, position: usize) {
+        let held = match self.kind {
+            IndexKind::BTree => self.tree.entry(key).or_default(),
+            IndexKind::Hash => self.hash.entry(key).or_default(),
+        };
+        let at = held.partition_point(|&p| p < position);
+        held.insert(at, position);
+    }
+
+    fn detach(&mut self, key: &IndexKey, position: usize) {
+        let held = match self.kind {
+            IndexKind::BTree => self.tree.get_mut(key),
+            IndexKind::Hash => self.hash.get_mut(key),
+        };
+        let Some(held) = held else { return };
+        held.retain(|&p| p != position);
+        if held.is_empty() {
+            match self.kind {
+                IndexKind::BTree => self.tree.remove(key),
+                IndexKind::Hash => self.hash.remove(key),
+            };
+        }
+    }
+
+    /// The key and INCLUDE columns of the row at `position`, read from the
+    /// index.
+    pub(crate) fn covered_row(&self, position: usize) -> Row {
+        let names = self.columns.iter().chain(&self.include);
+        Row { data: names.cloned().zip(self.covered[position].iter().cloned()).collect() }
+    }
+
+    /// Positions, in table order, of the rows whose whole key is the
+    /// already collated `key`.
+    pub(crate) fn get(&self, key: &[Value]) -> &[usize] {
+        let key = IndexKey(key.to_vec());
+        let held = match self.kind {
+            IndexKind::BTree => self.tree.get(&key),
+            IndexKind::Hash => self.hash.get(&key),
+        };
+        held.map_or(&[], Vec::as_slice)
+    }
+
+    /// Rows whose leading columns equal `prefix` and whose next column lies
+    /// between `lower` and `upper`, grouped by key in key order. Each group
+    /// holds row positions in table order. A hash index only answers a
+    /// `prefix` of every column.
+    pub(crate) fn range(&self, prefix: &[Value], lower: Bound<&Value>, upper: Bound<&Value>) -> Vec<&[usize]> {
+        if self.kind == IndexKind::Hash {
+            debug_assert!(prefix.len() == self.columns.len());
+            return self.hash.get(&IndexKey(prefix.to_vec())).map(|p| vec![p.as_slice()]).unwrap_or_default();
+        }
+        let mut start = prefix.to_vec();
+        if let Bound::Included(v) | Bound::Excluded(v) = lower {
+            start.push(v.clone());
+        }
+        let n = prefix.len();
+        let bounded = !matches!((lower, upper), (Bound::Unbounded, Bound::Unbounded));
+        let mut groups = Vec::new();
+        for (key, positions) in self.tree.range(IndexKey(start)..) {
+            if key.0[..n] != *prefix {
+                break;
+            }
+            if bounded {
+                let v = &key.0[n];
+                if matches!(lower, Bound::Excluded(l) if v == l) {
+                    continue;
+                }
+                match upper {
+                    Bound::Included(u) if v > u => break,
+                    Bound::Excluded(u) if v >= u => break,
+                    _ => {}
+                }
+            }
+            groups.push(positions.as_slice());
+        }
+        groups
+    }
+}
+
+impl fmt::Debug for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Index")
+            .field("name", &self.name)
+            .field("columns", &self.columns)
+            .field("unique", &self.unique)
+            .field("kind", &self.kind)
+            .field("include", &self.include)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Indexes are equal when their definitions are.
+impl PartialEq for Index {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.columns == other.columns
+            && self.unique == other.unique
+            && self.kind == other.kind
+            && self.include == other.include
+    }
+}
+
+/// A WHERE clause and ORDER BY served by one index: rows whose leading
+/// columns equal `prefix` and whose next column lies within the bounds.
+pub(crate) struct IndexScan<'a> {
+    pub index: &'a Index,
+    prefix: Vec<Value>,
+    lower: Bound<Value>,
+    upper: Bound<Value>,
+    /// `Some(descending)` when the rows come out in ORDER BY order.
+    pub order: Option<bool>,
+    /// Every column the query reads is in the index: the table itself is
+    /// not read (an index-only scan).
+    pub covering: bool,
+    /// A comparison with NULL: no row can pass.
+    empty: bool,
+}
+
+impl IndexScan<'_> {
+    /// Positions of the candidate rows: in ORDER BY order when `order` is
+    /// set, otherwise in table order.
+    pub fn positions(&self) -> Vec<usize> {
+        if self.empty {
+            return Vec::new();
+        }
+        let bounded = !matches!((&self.lower, &self.upper), (Bound::Unbounded, Bound::Unbounded));
+        if self.prefix.is_empty() && !bounded && self.order.is_none() {
+            return (0..self.index.covered.len()).collect();
+        }
+        let groups = self.index.range(&self.prefix, self.lower.as_ref(), self.upper.as_ref());
+        match self.order {
+            Some(false) => groups.concat(),
+            Some(true) => groups.into_iter().rev().flatten().copied().collect(),
+            None => {
+                let mut positions = groups.concat();
+                positions.sort_unstable();
+                positions
+            }
+        }
+    }
+
+    /// The key conditions the scan narrows to, as SQL text, if any.
+    pub fn condition(&self) -> Option<String> {
+        let mut terms: Vec<String> = self.index.columns.iter().zip(&self.prefix).map(|(c, v)| format!("{} = {}", c, v)).collect();
+        let next = self.index.columns.get(self.prefix.len());
+        for (bound, strict, loose) in [(&self.lower, ">", ">="), (&self.upper, "<", "<=")] {
+            match bound {
+                Bound::Excluded(v) => terms.push(format!("{} {} {}", next.unwrap(), strict, v)),
+                Bound::Included(v) => terms.push(format!("{} {} {}", next.unwrap(), loose, v)),
+                Bound::Unbounded => {}
+            }
+        }
+        (!terms.is_empty()).then(|| terms.join(" AND "))
+    }
+}
+
+/// Picks the index that best narrows `query`'s WHERE clause over `table`:
+/// equality on the most leading columns, then a range on the next one. An
+/// index that also yields the ORDER BY order wins ties, and is used on its
+/// own when nothing narrows the scan; `sortable` says whether the rows are
+/// sorted straight after the scan. Next an index holding every column the
+/// query reads wins, and serves a full scan by itself. `None` means a full
+/// scan of the table.
+pub(crate) fn plan_scan<'a>(db: &Database, table: &'a Table, query: &Query, sortable: bool) -> Option<IndexScan<'a>> {
+    let mut predicates: Vec<(String, &str, Value)> = Vec::new();
+    let mut terms = Vec::new();
+    if let Some(filter) = &query.where_clause {
+        conjuncts(filter, &mut terms);
+    }
+    let empty = Row { data: HashMap::new() };
+    for term in terms {
+        let Expr::BinOp(l, op, r) = term else { continue };
+        let flipped = match op.as_str() {
+            "=" => "=",
+            "<" => ">",
+            ">" => "<",
+            "<=" => ">=",
+            ">=" => "<=",
+            _ => continue,
+        };
+        let (col, op, constant) = match (column_of(table, l), column_of(table, r)) {
+            (Some(col), None) if is_constant(r) => (col, op.as_str(), r),
+            (None, Some(col)) if is_constant(l) => (col, flipped, l),
+            _ => continue,
+        };
+        // The index keys text under the column's collation only
+        let column = table.column(&col).unwrap();
+        if collation::of_comparison(l, op, r).unwrap_or_default() != column.collation {
+            continue;
+        }
+        // Text compared with a date, time or UUID column is read as its type
+        let ty = &column.data_type;
+        let value = binary::align(ty, temporal::align(ty, db.eval_expr(constant, &empty)));
+        predicates.push((col, op, column.collation.into_key(value)));
+    }
+
+    // ORDER BY can come from an index over NOT NULL columns whose values
+    // sort exactly as the executor sorts them
+    let order = query.order_by.first().filter(|_| sortable).and_then(|(_, asc, _)| {
+        let pk = table.primary_key().unwrap_or(&[]);
+        let cols: Option<Vec<String>> = query
+            .order_by
+            .iter()
+            .map(|(name, is_asc, collation)| {
+                let col = column_of(table, &Expr::Column(name.clone()))?;
+                let column = table.column(&col)?;
+                if collation.is_some_and(|c| c != column.collation) {
+                    return None;
+                }
+                let sortable_type = matches!(
+                    column.data_type,
+                    DataType::Int | DataType::Float | DataType::String | DataType::Date | DataType::Time | DataType::Timestamp | DataType::TimestampTz | DataType::Bytes | DataType::Uuid
+                );
+                (is_asc == asc && sortable_type && (column.not_null || pk.contains(&col))).then_some(col)
+            })
+            .collect();
+        cols.map(|cols| (cols, !asc))
+    });
+
+    // Columns the query reads, by their names in the table
+    let read: Vec<String> = match query_columns(query) {
+        Some(names) => {
+            let cols = names.iter().filter_map(|n| {
+                let field = || table.columns.iter().find(|c| field_path(n, &table.name, &c.name).is_some());
+                table.column(n).or_else(field).or_else(|| table.column(n.rsplit_once('.')?.1))
+            });
+            cols.map(|c| c.name.clone()).collect()
+        }
+        None => table.columns.iter().map(|c| c.name.clone()).collect(),
+    };
+
+    let mut best: Option<((usize, bool, bool), IndexScan)> = None;
+    for index in &table.indexes {
+        let mut scan = IndexScan {
+            index,
+            prefix: Vec::new(),
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+            order: None,
+            covering: index.covers(&read),
+            empty: false,
+        };
+        for col in &index.columns {
+            let Some((_, _, value)) = predicates.iter().find(|(c, op, _)| c == col && *op == "=") else { break };
+            scan.empty |= *value == Value::Null;
+            scan.prefix.push(value.clone());
+        }
+        if index.kind == IndexKind::Hash {
+            // Only a lookup of the whole key, or a full covering scan
+            if scan.prefix.len() < index.columns.len() {
+                scan.prefix.clear();
+                scan.empty = false;
+            }
+            let rank = (2 * scan.prefix.len(), false, scan.covering);
+            if rank != (0, false, false) && best.as_ref().is_none_or(|(r, _)| rank > *r) {
+                best = Some((rank, scan));
+            }
+            continue;
+        }
+        let eq = scan.prefix.len();
+        if let Some(next) = index.columns.get(eq) {
+            for (_, op, value) in predicates.iter().filter(|(c, _, _)| c == next) {
+                let unset = |b: &Bound<Value>| matches!(b, Bound::Unbounded);
+                match *op {
+                    ">" if unset(&scan.lower) => scan.lower = Bound::Excluded(value.clone()),
+                    ">=" if unset(&scan.lower) => scan.lower = Bound::Included(value.clone()),
+                    "<" if unset(&scan.upper) => scan.upper = Bound::Excluded(value.clone()),
+                    "<=" if unset(&scan.upper) => scan.upper = Bound::Included(value.clone()),
+                    _ => continue,
+                }
+                scan.empty |= *value == Value::Null;
+            }
+        }
+        // Ties must be rows with equal keys, which stay in table order as a
+        // stable sort leaves them, so the ORDER BY columns not fixed by
+        // equality must be exactly the rest of the index
+        if let Some((cols, descending)) = &order {
+            let rest: Vec<&String> = cols.iter().filter(|c| !index.columns[..eq].contains(c)).collect();
+            if !rest.is_empty() && rest.iter().copied().eq(index.columns[eq..].iter()) {
+                scan.order = Some(*descending);
+            }
+        }
+        let ranged = !matches!((&scan.lower, &scan.upper), (Bound::Unbounded, Bound::Unbounded));
+        let rank = (2 * eq + usize::from(ranged), scan.order.is_some(), scan.covering);
+        if rank != (0, false, false) && best.as_ref().is_none_or(|(r, _)| rank > *r) {
+            best = Some((rank, scan));
+        }
+    }
+    best.map(|(_, scan)| scan)
+}
+
+/// An index of a joined table whose leading columns are matched by
+/// equalities with expressions over the earlier tables.
+pub(crate) struct IndexLookup<'a> {
+    pub index: &'a Index,
+    /// Expressions the leading columns equal, and the collation each
+    /// equality is made under.
+    keys: Vec<(Expr, Collation)>,
+}
+
+impl IndexLookup<'_> {
+    /// Positions, in table order, of the rows matching `row`'s key values.
+    pub fn positions(&self, db: &Database, row: &Row) -> Vec<usize> {
+        let values: Vec<Value> = self.keys.iter().map(|(e, c)| c.into_key(db.eval_expr(e, row))).collect();
+        if values.contains(&Value::Null) {
+            return Vec::new();
+        }
+        let mut positions = self.index.range(&values, Bound::Unbounded, Bound::Unbounded).concat();
+        if values.len() < self.index.columns.len() {
+            positions.sort_unstable();
+        }
+        positions
+    }
+}
+
+/// Picks the index of `table` covering the most equi-join `keys`, given as
+/// `(earlier side, joined side)` pairs. A hash index needs all its columns.
+pub(crate) fn plan_lookup<'a>(table: &'a Table, keys: &[(Expr, Expr)]) -> Option<IndexLookup<'a>> {
+    let mut best: Option<IndexLookup> = None;
+    for index in &table.indexes {
+        let mut lookup = IndexLookup { index, keys: Vec::new() };
+        for col in &index.columns {
+            let Some((left, right)) = keys.iter().find(|(_, right)| column_of(table, right).as_ref() == Some(col)) else { break };
+            let collation = collation::of_comparison(left, "=", right).unwrap_or_default();
+            if collation != table.column(col).unwrap().collation {
+                break;
+            }
+            lookup.keys.push((left.clone(), collation));
+        }
+        if index.kind == IndexKind::Hash && lookup.keys.len() < index.columns.len() {
+            continue;
+        }
+        if !lookup.keys.is_empty() && best.as_ref().is_none_or(|b| lookup.keys.len() > b.keys.len()) {
+            best = Some(lookup);
+        }
+    }
+    best
+}
+
+/// The column of `table` that `expr` reads, if it is a bare column
+/// reference, plain or qualified with the table name, perhaps given a
+/// collation.
+fn column_of(table: &Table, expr: &Expr) -> Option<String> {
+    let name = match expr {
+        Expr::Column(name) => name,
+        Expr::FuncCall(f, args) if f == "COLLATE" => return column_of(table, &args[0]),
+        _ => return None,
+    };
+    let col = match name.rsplit_once('.') {
+        Some((t, c)) if t == table.name => c,
+        Some(_) => return None,
+        None => name.as_str(),
+    };
+    table.column(col).map(|c| c.name.clone())
+}
+
+pub(crate) fn is_constant(expr: &Expr) -> bool {
+    expr_columns(expr).is_empty() && !contains_aggregate(expr)
+}
@@ -0,0 +1,463 @@
+//! JSON documents, for JSON columns: parsing, printing, path access and the
+//! JSON functions.
+//!
+//! A document keeps its object keys in the order written, with a repeated
+//! key keeping its last value, and is printed compactly. `->` and `->>`
+//! step into an object by key or an array by position (negative positions
+//! count from the end); `#>` and `#>>` follow a text path such as
+//! `'{a,b,0}'`, and JSON_EXTRACT a path such as `'$.a.b[0]'`. The `->`
+//! forms give JSON and the `->>` forms text.
+//!
+//! JSON numbers, strings and booleans compare, group and join as the SQL
+//! value they hold, and JSON null as NULL, so `doc->'age' > 30` needs no
+//! cast. Arrays and objects are only equal to identical documents.
+
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+use crate::Value;
+
+/// Deepest nesting of arrays and objects a document may have.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { chars: text.char_indices().peekable(), text, depth: 0 };
+        let json = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(json),
+            Some((i, _)) => Err(format!("Invalid JSON: unexpected text at offset {}", i)),
+        }
+    }
+
+    /// Converts a SQL value: text becomes a JSON string (use `parse` to read
+    /// it as a document), dates and times their text, and a FLOAT that is
+    /// not finite null.
+    pub fn from_value(v: &Value) -> Json {
+        match v {
+            Value::Null => Json::Null,
+            Value::Bool(b) => Json::Bool(*b),
+            Value::Int(i) => Json::Int(*i),
+            Value::Float(f) if f.is_finite() => Json::Float(*f),
+            Value::Float(_) => Json::Null,
+            Value::Decimal(d) => Json::parse(&d.to_string()).unwrap_or(Json::Null),
+            Value::String(s) => Json::String(s.clone()),
+            Value::Json(j) => j.clone(),
+            other => Json::String(other.text()),
+        }
+    }
+
+    /// The SQL value a scalar holds; JSON null is NULL. Arrays and objects
+    /// stay JSON.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Json::Null => Value::Null,
+            Json::Bool(b) => Value::Bool(*b),
+            Json::Int(i) => Value::Int(*i),
+            Json::Float(f) => Value::Float(*f),
+            Json::String(s) => Value::String(s.clone()),
+            doc => Value::Json(doc.clone()),
+        }
+    }
+
+    pub fn is_scalar(&self) -> bool {
+        !matches!(self, Json::Array(_) | Json::Object(_))
+    }
+
+    /// The member for an object key, or the element at an array position.
+    pub fn get(&self, step: &Value) -> Option<&Json> {
+        match (self, step) {
+            (Json::Object(members), Value::String(key)) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            (Json::Array(items), Value::Int(i)) => {
+                let i = if *i < 0 { i.checked_add(items.len() as i64)? } else { *i };
+                items.get(usize::try_from(i).ok()?)
+            }
+            _ => None,
+        }
+    }
+
+    /// Follows `path` from this document; array positions are given as text.
+    pub fn pointer(&self, path: &[Value]) -> Option<&Json> {
+        path.iter().try_fold(self, |doc, step| match (doc, step) {
+            (Json::Array(_), Value::String(s)) => doc.get(&Value::Int(s.parse().ok()?)),
+            _ => doc.get(step),
+        })
+    }
+
+    /// Text as `->>` gives it: strings unquoted, null as NULL.
+    fn text(&self) -> Option<String> {
+        match self {
+            Json::Null => None,
+            Json::String(s) => Some(s.clone()),
+            doc => Some(doc.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(i) => write!(f, "{}", i),
+            // A float keeps its point, so it reads back as a float
+            Json::Float(x) if x.fract() == 0.0 => write!(f, "{:.1}", x),
+            Json::Float(x) => write!(f, "{}", x),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn error(&mut self) -> String {
+        match self.chars.peek() {
+            Some((i, c)) => format!("Invalid JSON: unexpected '{}' at offset {}", c, i),
+            None => "Invalid JSON: unexpected end of text".into(),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some((_, c)) if *c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some('{') | Some('[') if self.depth == MAX_DEPTH => Err(format!("Invalid JSON: nested deeper than {} levels", MAX_DEPTH)),
+            Some('{') => {
+                self.chars.next();
+                self.depth += 1;
+                let mut members: Vec<(String, Json)> = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == '}').is_none() {
+                    loop {
+                        self.expect('"')?;
+                        let key = self.string()?;
+                        self.expect(':')?;
+                        let value = self.value()?;
+                        match members.iter_mut().find(|(k, _)| *k == key) {
+                            Some(member) => member.1 = value,
+                            None => members.push((key, value)),
+                        }
+                        self.skip_whitespace();
+                        match self.chars.next() {
+                            Some((_, ',')) => {}
+                            Some((_, '}')) => break,
+                            Some((i, c)) => return Err(format!("Invalid JSON: unexpected '{}' at offset {}", c, i)),
+                            None => return Err("Invalid JSON: unexpected end of text".into()),
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Object(members))
+            }
+            Some('[') => {
+                self.chars.next();
+                self.depth += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == ']').is_none() {
+                    loop {
+                        items.push(self.value()?);
+                        self.skip_whitespace();
+                        match self.chars.next() {
+                            Some((_, ',')) => {}
+                            Some((_, ']')) => break,
+                            Some((i, c)) => return Err(format!("Invalid JSON: unexpected '{}' at offset {}", c, i)),
+                            None => return Err("Invalid JSON: unexpected end of text".into()),
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Array(items))
+            }
+            Some('"') => {
+                self.chars.next();
+                self.string().map(Json::String)
+            }
+            Some('-' | '0'..='9') => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.chars.peek().unwrap().0;
+                let mut end = start;
+                while let Some((i, c)) = self.chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+                    end = i + c.len_utf8();
+                }
+                match &self.text[start..end] {
+                    "null" => Ok(Json::Null),
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    word => Err(format!("Invalid JSON: unexpected '{}' at offset {}", word, start)),
+                }
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    /// The rest of a string whose opening quote was read.
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => {
+                    let c = match self.chars.next() {
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/')) => '/',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((i, 'u')) => {
+                            let high = self.hex4()?;
+                            let code = if (0xD800..0xDC00).contains(&high) && self.chars.next_if(|(_, c)| *c == '\\').is_some() {
+                                if self.chars.next_if(|(_, c)| *c == 'u').is_none() {
+                                    return Err(format!("Invalid JSON: bad \\u escape at offset {}", i));
+                                }
+                                let low = self.hex4()?;
+                                0x10000 + ((high - 0xD800) << 10) + low.wrapping_sub(0xDC00)
+                            } else {
+                                high
+                            };
+                            char::from_u32(code).ok_or(format!("Invalid JSON: bad \\u escape at offset {}", i))?
+                        }
+                        Some((i, c)) => return Err(format!("Invalid JSON: bad escape '\\{}' at offset {}", c, i)),
+                        None => return Err("Invalid JSON: unterminated string".into()),
+                    };
+                    s.push(c);
+                }
+                Some((i, c)) if (c as u32) < 0x20 => return Err(format!("Invalid JSON: control character in string at offset {}", i)),
+                Some((_, c)) => s.push(c),
+                None => return Err("Invalid JSON: unterminated string".into()),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                Some(d) => code = code * 16 + d,
+                None => return Err("Invalid JSON: bad \\u escape".into()),
+            }
+        }
+        Ok(code)
+    }
+
+    /// A number; integers that fit an INT stay integers.
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.chars.peek().unwrap().0;
+        let mut end = start;
+        while let Some((i, _)) = self.chars.next_if(|(_, c)| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            end = i + 1;
+        }
+        let text = &self.text[start..end];
+        match (is_number(text), text.parse::<i64>(), text.parse::<f64>()) {
+            (true, Ok(i), _) => Ok(Json::Int(i)),
+            (true, _, Ok(f)) if f.is_finite() => Ok(Json::Float(f)),
+            _ => Err(format!("Invalid JSON: bad number '{}' at offset {}", text, start)),
+        }
+    }
+}
+
+/// JSON's number grammar: `-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?`.
+fn is_number(text: &str) -> bool {
+    let digits = |s: &str| s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let mut rest = text.strip_prefix('-').unwrap_or(text);
+    let n = digits(rest);
+    if n == 0 || (n > 1 && rest.starts_with('0')) {
+        return false;
+    }
+    rest = &rest[n..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let n = digits(fraction);
+        if n == 0 {
+            return false;
+        }
+        rest = &fraction[n..];
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let n = digits(exponent);
+        if n == 0 {
+            return false;
+        }
+        rest = &exponent[n..];
+    }
+    rest.is_empty()
+}
+
+/// True for the path operators `->`, `->>`, `#>` and `#>>`.
+pub(crate) fn is_access(op: &str) -> bool {
+    matches!(op, "->" | "->>" | "#>" | "#>>")
+}
+
+/// Applies a path operator. Text on the left is read as a document.
+pub(crate) fn access(left: &Value, op: &str, right: &Value) -> Option<Value> {
+    let parsed;
+    let doc = match left {
+        Value::Json(doc) => doc,
+        Value::String(s) => {
+            parsed = Json::parse(s).ok()?;
+            &parsed
+        }
+        _ => return None,
+    };
+    let found = match (op, right) {
+        ("->" | "->>", step) => doc.get(step)?,
+        ("#>" | "#>>", Value::String(path)) => doc.pointer(&text_path(path)?)?,
+        _ => return None,
+    };
+    Some(if op.ends_with(">>") { found.text().map_or(Value::Null, Value::String) } else { Value::Json(found.clone()) })
+}
+
+/// The steps of a `#>` path such as `{a,b,0}`.
+fn text_path(path: &str) -> Option<Vec<Value>> {
+    let inner = path.trim().strip_prefix('{')?.strip_suffix('}')?;
+    if inner.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    Some(inner.split(',').map(|s| Value::String(s.trim().trim_matches('"').to_string())).collect())
+}
+
+/// The steps of a JSON_EXTRACT path such as `$.a."b c"[0]`.
+fn dollar_path(path: &str) -> Option<Vec<Value>> {
+    let mut rest = path.trim().strip_prefix('$')?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let (index, tail) = after.split_once(']')?;
+            steps.push(Value::Int(index.trim().parse().ok()?));
+            rest = tail;
+        } else if let Some(after) = rest.strip_prefix(".\"") {
+            let (key, tail) = after.split_once('"')?;
+            steps.push(Value::String(key.to_string()));
+            rest = tail;
+        } else {
+            let after = rest.strip_prefix('.')?;
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            steps.push(Value::String(after[..end].to_string()));
+            rest = &after[end..];
+        }
+    }
+    Some(steps)
+}
+
+fn document(v: &Value) -> Option<Cow<'_, Json>> {
+    match v {
+        Value::Json(doc) => Some(Cow::Borrowed(doc)),
+        Value::String(s) => Json::parse(s).ok().map(Cow::Owned),
+        _ => None,
+    }
+}
+
+/// JSON_EXTRACT(doc, path): the part of `doc` at a `$` path.
+pub(crate) fn extract(doc: &Value, path: &str) -> Option<Value> {
+    let doc = document(doc)?;
+    doc.pointer(&dollar_path(path)?).map(|found| Value::Json(found.clone()))
+}
+
+/// JSON_ARRAY_LENGTH(doc[, path]): elements of the array at `path`.
+pub(crate) fn array_length(doc: &Value, path: Option<&str>) -> Option<Value> {
+    let doc = document(doc)?;
+    let steps = match path {
+        Some(path) => dollar_path(path)?,
+        None => Vec::new(),
+    };
+    match doc.pointer(&steps)? {
+        Json::Array(items) => Some(Value::Int(items.len() as i64)),
+        _ => None,
+    }
+}
+
+/// JSON_OBJECT(key, value, ...): keys must be text.
+pub(crate) fn object(args: &[Value]) -> Option<Value> {
+    if !args.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut members: Vec<(String, Json)> = Vec::new();
+    for pair in args.chunks(2) {
+        let Value::String(key) = &pair[0] else { return None };
+        let value = Json::from_value(&pair[1]);
+        match members.iter_mut().find(|(k, _)| k == key) {
+            Some(member) => member.1 = value,
+            None => members.push((key.clone(), value)),
+        }
+    }
+    Some(Value::Json(Json::Object(members)))
+}
+
+/// A JSON scalar as the SQL value it compares as; anything else as is.
+pub(crate) fn unwrap(v: &Value) -> Cow<'_, Value> {
+    match v {
+        Value::Json(doc) if doc.is_scalar() => Cow::Owned(doc.to_value()),
+        v => Cow::Borrowed(v),
+    }
+}
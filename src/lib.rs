@@ -1,16 +1,81 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use regex::Regex;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::hash::Hash;
+use std::sync::Arc;
+
+mod binary;
+mod collation;
+mod columnar;
+mod composite;
+mod csv;
+mod decimal;
+mod explain;
+mod index;
+mod json;
+mod mvcc;
+mod ndjson;
+mod optimizer;
+mod order;
+mod parallel;
+mod result;
+mod stats;
+mod storage;
+mod temporal;
+mod transaction;
+mod unicode;
+mod vectorized;
+mod wal;
+
+pub use binary::Uuid;
+pub use collation::Collation;
+pub use columnar::{Bitmap, ColumnData, ColumnVector, ColumnarTable};
+pub use csv::CsvOptions;
+pub use decimal::{Decimal, RoundingMode, DIVISION_SCALE, MAX_PRECISION};
+pub use index::{Index, IndexKind};
+pub use json::Json;
+pub use mvcc::{IsolationLevel, SharedDatabase, SharedTransaction};
+pub use ndjson::JsonOptions;
+pub use optimizer::MAX_DP_TABLES;
+pub use parallel::MORSEL_SIZE;
+pub use result::{ResultColumn, ResultRow, ResultSet};
+pub use stats::{ColumnStats, TableStats, HISTOGRAM_BUCKETS};
+pub use storage::{FORMAT_VERSION, PAGE_SIZE};
+pub use temporal::{Date, Interval, Time, Timestamp};
+pub use transaction::Transaction;
+pub use vectorized::BATCH_SIZE;
+pub use wal::SyncPolicy;
+
+use collation::Collations;
+use index::{IndexLookup, IndexScan};
+use vectorized::Kernel;
+use wal::{Effect, Record, Wal};
 
 // ============================================================================
 // AST & Types
 // ============================================================================
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Value {
     Int(i64),
     Float(f64),
+    Decimal(Decimal),
     String(String),
     Bool(bool),
+    Date(Date),
+    Time(Time),
+    Timestamp(Timestamp),
+    /// An instant, kept in UTC.
+    TimestampTz(Timestamp),
+    Interval(Interval),
+    Json(Json),
+    /// Elements in order; they need not share a type.
+    Array(Vec<Value>),
+    /// Named fields in order.
+    Struct(Vec<(String, Value)>),
+    Bytes(Vec<u8>),
+    Uuid(Uuid),
     Null,
 }
 
@@ -19,38 +84,603 @@ pub struct Row {
     pub data: HashMap<String, Value>,
 }
 
-#[derive(Debug, Clone)]
-pub struct Table {
+/// Declared type of a column. `Any` is the untyped column produced when a
+/// table is built from bare names, and accepts every value unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataType {
+    Int,
+    Float,
+    /// Precision (total digits) and scale (digits after the point).
+    Decimal(u8, u8),
+    String,
+    Bool,
+    Date,
+    Time,
+    Timestamp,
+    TimestampTz,
+    Interval,
+    Json,
+    /// Elements of the given type, `INT[]`.
+    Array(Box<DataType>),
+    /// Named, typed fields, `STRUCT(name TEXT, age INT)`.
+    Struct(Vec<(String, DataType)>),
+    Bytes,
+    Uuid,
+    Any,
+}
+
+/// How an identity column gets its values. Both kinds assign the next value
+/// after the largest one in the table when the column is omitted from INSERT.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identity {
+    Always,    // GENERATED ALWAYS AS IDENTITY: explicit values are rejected
+    ByDefault, // GENERATED BY DEFAULT AS IDENTITY or AUTOINCREMENT
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
     pub name: String,
+    pub data_type: DataType,
+    pub not_null: bool,
+    pub default: Option<Expr>,
+    pub identity: Option<Identity>,
+    /// How the column's text compares (`COLLATE name`).
+    pub collation: Collation,
+}
+
+/// What happens to referencing rows when the referenced key is deleted or
+/// updated. NO ACTION only fails if a dangling reference remains at the end
+/// of the statement; RESTRICT fails as soon as a referenced key is touched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefAction {
+    NoAction,
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
     pub columns: Vec<String>,
+    pub ref_table: String,
+    pub ref_columns: Vec<String>, // empty until resolved to the parent's primary key
+    pub on_delete: RefAction,
+    pub on_update: RefAction,
+    /// INITIALLY DEFERRED: checked at commit rather than after each statement.
+    /// Without an open transaction every statement commits on its own.
+    pub deferred: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintKind {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    Check(Expr),
+    ForeignKey(ForeignKey),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub name: String,
+    pub kind: ConstraintKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
     pub rows: Vec<Row>,
+    pub constraints: Vec<Constraint>,
+    /// Secondary indexes. They are kept up to date by statements; after
+    /// editing `rows` directly, call `reindex`.
+    pub indexes: Vec<Index>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Column(String),
     Literal(Value),
+    UnaryOp(String, Box<Expr>),          // op, expr
     BinOp(Box<Expr>, String, Box<Expr>), // expr, op, expr
-    FuncCall(String, Vec<Expr>),           // func_name, args
+    FuncCall(String, Vec<Expr>),         // func_name, args
 }
 
 #[derive(Debug, Clone)]
 pub struct Join {
-    pub table: String,
+    pub table: String, // the table, or the name of an UNNEST
     pub on: Expr,
+    pub unnest: Option<Unnest>,
+}
+
+/// `UNNEST(expr) AS name`: a row per element of `expr`, held in a column
+/// named after the UNNEST. In a JOIN, `expr` reads the rows joined so far.
+#[derive(Debug, Clone)]
+pub struct Unnest {
+    pub expr: Expr,
+    pub column: Column,
+}
+
+/// One element of a GROUP BY clause. The grouping sets of the whole clause
+/// are the cross product of the sets produced by each element.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupByItem {
+    Expr(Expr),                      // a, age / 10, or an ordinal like 1
+    Set(Vec<Expr>),                  // (a, b) or ()
+    Rollup(Vec<Expr>),               // ROLLUP(a, b) => (a, b), (a), ()
+    Cube(Vec<Expr>),                 // CUBE(a, b) => every subset of (a, b)
+    GroupingSets(Vec<GroupByItem>),  // GROUPING SETS((a, b), a, ())
 }
 
 #[derive(Debug, Clone)]
 pub struct Query {
-    pub select_cols: Vec<String>,
+    pub select_cols: Vec<String>, // output names ("*" for the wildcard)
+    pub select_exprs: Vec<Expr>,  // expression producing each output column
+    pub distinct: bool,           // SELECT DISTINCT
     pub from_table: String,
+    pub from_unnest: Option<Unnest>, // FROM UNNEST(...) AS from_table
     pub joins: Vec<Join>,
     pub where_clause: Option<Expr>,
-    pub group_by: Vec<String>,
-    pub order_by: Vec<(String, bool)>, // (col, is_asc)
+    pub group_by: Vec<GroupByItem>,
+    pub order_by: Vec<(String, bool, Option<Collation>)>, // (col, is_asc, COLLATE)
     pub limit: Option<usize>,
 }
 
+#[derive(Debug, Clone)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Query(Box<Query>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Insert {
+    pub table: String,
+    pub columns: Vec<String>, // empty means every table column in order
+    pub source: InsertSource,
+    pub returning: Vec<(String, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub table: String,
+    pub assignments: Vec<(String, Expr)>, // (col, new value)
+    pub where_clause: Option<Expr>,
+    pub returning: Vec<(String, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Delete {
+    pub table: String,
+    pub where_clause: Option<Expr>,
+    pub returning: Vec<(String, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AlterAction {
+    AddColumn(Column, Vec<Constraint>),
+    DropColumn(String),
+    RenameColumn(String, String), // from, to
+    RenameTable(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Select(Query),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    CreateTable { name: String, columns: Vec<Column>, constraints: Vec<Constraint>, if_not_exists: bool, columnar: bool },
+    DropTable { name: String, if_exists: bool },
+    AlterTable { name: String, action: AlterAction },
+    CreateIndex { name: String, table: String, columns: Vec<String>, unique: bool, kind: IndexKind, include: Vec<String>, if_not_exists: bool },
+    DropIndex { name: String, if_exists: bool },
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(String),
+    RollbackTo(String),
+    Release(String),
+    /// The plan `execute` would run for a query, one step per row.
+    Explain(Query),
+    /// Gathers statistics for one table, or all of them.
+    Analyze(Option<String>),
+    /// `COPY table FROM 'file'`: loads a CSV file into a table.
+    CopyFrom { table: String, path: String, options: CsvOptions },
+    /// `COPY table TO 'file'` or `COPY (query) TO 'file'`: writes a query's
+    /// result to a CSV file.
+    CopyTo { query: Query, path: String, options: CsvOptions },
+}
+
+/// Outcome of `Database::execute_statement`. For DML `rows` holds the
+/// RETURNING output (no columns without RETURNING); for SELECT it holds the
+/// result.
+#[derive(Debug, Clone)]
+pub struct StatementResult {
+    pub rows_affected: usize,
+    pub rows: ResultSet,
+}
+
+impl Column {
+    pub fn new(name: &str, data_type: DataType) -> Self {
+        Column { name: name.to_string(), data_type, not_null: false, default: None, identity: None, collation: Collation::Binary }
+    }
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::new(name, DataType::Any)
+    }
+}
+
+impl From<String> for Column {
+    fn from(name: String) -> Self {
+        Column::new(&name, DataType::Any)
+    }
+}
+
+impl Constraint {
+    /// Postgres-style generated name, e.g. `users_pkey` or `users_email_key`.
+    fn default_name(table: &str, kind: &ConstraintKind) -> String {
+        match kind {
+            ConstraintKind::PrimaryKey(_) => format!("{}_pkey", table),
+            ConstraintKind::Unique(cols) => format!("{}_{}_key", table, cols.join("_")),
+            ConstraintKind::Check(expr) => match expr_columns(expr).first() {
+                Some(col) => format!("{}_{}_check", table, col),
+                None => format!("{}_check", table),
+            },
+            ConstraintKind::ForeignKey(fk) => format!("{}_{}_fkey", table, fk.columns.join("_")),
+        }
+    }
+
+    fn columns(&self) -> Vec<String> {
+        match &self.kind {
+            ConstraintKind::PrimaryKey(cols) | ConstraintKind::Unique(cols) => cols.clone(),
+            ConstraintKind::Check(expr) => expr_columns(expr),
+            ConstraintKind::ForeignKey(fk) => fk.columns.clone(),
+        }
+    }
+}
+
+impl DataType {
+    /// Maps a SQL type name (with any length arguments already stripped).
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_uppercase().as_str() {
+            "INT" | "INTEGER" | "BIGINT" | "SMALLINT" => Ok(DataType::Int),
+            "FLOAT" | "REAL" | "DOUBLE" => Ok(DataType::Float),
+            "DECIMAL" | "NUMERIC" | "DEC" => Ok(DataType::Decimal(MAX_PRECISION, 0)),
+            "TEXT" | "VARCHAR" | "CHAR" | "STRING" => Ok(DataType::String),
+            "BOOL" | "BOOLEAN" => Ok(DataType::Bool),
+            "DATE" => Ok(DataType::Date),
+            "TIME" => Ok(DataType::Time),
+            "TIMESTAMP" | "DATETIME" => Ok(DataType::Timestamp),
+            "TIMESTAMPTZ" => Ok(DataType::TimestampTz),
+            "INTERVAL" => Ok(DataType::Interval),
+            "JSON" | "JSONB" => Ok(DataType::Json),
+            "ARRAY" => Ok(DataType::Array(Box::new(DataType::Any))),
+            "STRUCT" => Ok(DataType::Struct(Vec::new())),
+            "BYTEA" | "BLOB" | "BYTES" | "BINARY" | "VARBINARY" => Ok(DataType::Bytes),
+            "UUID" => Ok(DataType::Uuid),
+            "ANY" => Ok(DataType::Any),
+            other => Err(format!("Unknown type: {}", other)),
+        }
+    }
+
+    /// Coerces `value` for storage in a column of this type.
+    pub fn coerce(&self, value: Value) -> Result<Value, String> {
+        match (self, value) {
+            // NULL fits any type
+            (DataType::Any, v) | (_, v @ Value::Null) => Ok(v),
+            // INT and FLOAT take each other's numbers and decimals, INT only
+            // integral ones
+            (DataType::Int, v @ Value::Int(_)) => Ok(v),
+            (DataType::Int, Value::Float(f)) if f.fract() == 0.0 && f.abs() < 9.2e18 => Ok(Value::Int(f as i64)),
+            (DataType::Float, Value::Int(i)) => Ok(Value::Float(i as f64)),
+            (DataType::Float, v @ Value::Float(_)) => Ok(v),
+            (DataType::Int, Value::Decimal(d)) if d.to_i64().is_some() => Ok(Value::Int(d.to_i64().unwrap())),
+            (DataType::Float, Value::Decimal(d)) => Ok(Value::Float(d.to_f64())),
+            // Numbers round halves away from zero to a DECIMAL's scale and must
+            // fit its precision; a FLOAT reads as the shortest decimal that
+            // converts back to it
+            (ty @ DataType::Decimal(precision, scale), v @ (Value::Int(_) | Value::Float(_) | Value::Decimal(_))) => {
+                let d = decimal::to_decimal(&v).ok_or_else(|| format!("Expected {}, got {}", ty, v))?;
+                match d.round(*scale as i32, RoundingMode::HalfUp) {
+                    Some(d) if d.precision() <= *precision => Ok(Value::Decimal(d)),
+                    _ => Err(format!("Value {} out of range for {}", v, ty)),
+                }
+            }
+            (DataType::String, v @ Value::String(_)) => Ok(v),
+            (DataType::Bool, v @ Value::Bool(_)) => Ok(v),
+            (DataType::Date, v @ Value::Date(_)) | (DataType::Time, v @ Value::Time(_)) | (DataType::Interval, v @ Value::Interval(_)) => Ok(v),
+            (DataType::Timestamp, v @ Value::Timestamp(_)) | (DataType::TimestampTz, v @ Value::TimestampTz(_)) => Ok(v),
+            // Dates widen to timestamps, and a TIMESTAMP is taken as UTC
+            (DataType::Timestamp | DataType::TimestampTz, Value::Date(d)) => {
+                let t = Timestamp::new(d, Time(0)).ok_or(format!("Date {} out of range for {}", d, self))?;
+                Ok(if *self == DataType::Timestamp { Value::Timestamp(t) } else { Value::TimestampTz(t) })
+            }
+            (DataType::Timestamp, Value::TimestampTz(t)) => Ok(Value::Timestamp(t)),
+            (DataType::TimestampTz, Value::Timestamp(t)) => Ok(Value::TimestampTz(t)),
+            // Text is read as a date or time type
+            (ty, Value::String(s)) if ty.is_temporal() => temporal::parse(ty, &s),
+            (DataType::Bytes, v @ Value::Bytes(_)) | (DataType::Uuid, v @ Value::Uuid(_)) => Ok(v),
+            // Text is read as BYTEA from `\x` and hex digits or else as its
+            // UTF-8 bytes, and as a UUID in its usual form
+            (DataType::Bytes, Value::String(s)) => binary::from_text(&s).map(Value::Bytes),
+            (DataType::Uuid, Value::String(s)) => Uuid::parse(&s).map(Value::Uuid),
+            // Text is parsed into a JSON column and other values converted; a
+            // JSON scalar stored elsewhere is the SQL value it holds
+            (DataType::Json, Value::String(s)) => Json::parse(&s).map(Value::Json),
+            (DataType::Json, v) => Ok(Value::Json(Json::from_value(&v))),
+            (ty, Value::Json(doc)) if doc.is_scalar() => ty.coerce(doc.to_value()),
+            // Elements are coerced to the element type, and a JSON array reads
+            // as an array
+            (DataType::Array(element), Value::Array(items)) => items.into_iter().map(|v| element.coerce(v)).collect::<Result<_, _>>().map(Value::Array),
+            (ty @ DataType::Array(_), Value::Json(doc @ Json::Array(_))) => ty.coerce(doc.to_value()),
+            // Struct fields match by name, missing ones being NULL; a bare
+            // STRUCT takes any struct
+            (DataType::Struct(fields), v @ Value::Struct(_)) if fields.is_empty() => Ok(v),
+            (DataType::Struct(fields), Value::Struct(mut values)) => {
+                if let Some((name, _)) = values.iter().find(|(n, _)| !fields.iter().any(|(f, _)| f == n)) {
+                    return Err(format!("Unknown field {} for {}", name, self));
+                }
+                let coerced = fields.iter().map(|(name, ty)| {
+                    let v = values.iter().position(|(n, _)| n == name).map_or(Value::Null, |i| values.swap_remove(i).1);
+                    ty.coerce(v).map(|v| (name.clone(), v))
+                });
+                coerced.collect::<Result<_, _>>().map(Value::Struct)
+            }
+            (ty, v) => Err(format!("Expected {}, got {}", ty, v)),
+        }
+    }
+
+    /// Converts `value` as CAST does: like `coerce`, but text is also read as
+    /// a number or boolean, FLOAT and DECIMAL round to INT, a UUID and its
+    /// sixteen bytes convert both ways, and every value converts to text.
+    pub fn cast(&self, value: Value) -> Result<Value, String> {
+        let value = match (self, value) {
+            (DataType::Json, v) => return self.coerce(v),
+            (_, Value::Json(doc)) if doc.is_scalar() => doc.to_value(),
+            (_, v) => v,
+        };
+        match (self, value) {
+            (_, Value::Null) => Ok(Value::Null),
+            (DataType::String, v) => Ok(Value::String(v.text())),
+            (DataType::Int, Value::Float(f)) => self.coerce(Value::Float(f.round())),
+            (DataType::Int, Value::Decimal(d)) => self.coerce(Value::Decimal(d.round(0, RoundingMode::HalfUp).unwrap_or(d))),
+            (DataType::Int, Value::Bool(b)) => Ok(Value::Int(i64::from(b))),
+            (DataType::Bytes, Value::Uuid(u)) => Ok(Value::Bytes(u.0.to_vec())),
+            (DataType::Uuid, Value::Bytes(b)) => b.as_slice().try_into().map(|b| Value::Uuid(Uuid(b))).map_err(|_| format!("Invalid UUID: {} bytes", b.len())),
+            (DataType::Int, Value::String(s)) => s.trim().parse().map(Value::Int).map_err(|_| format!("Invalid INT: {}", s)),
+            (DataType::Float, Value::String(s)) => s.trim().parse().map(Value::Float).map_err(|_| format!("Invalid FLOAT: {}", s)),
+            (DataType::Decimal(..), Value::String(s)) => self.coerce(Value::Decimal(Decimal::parse(s.trim())?)),
+            (DataType::Bool, Value::Int(i)) => Ok(Value::Bool(i != 0)),
+            (DataType::Bool, Value::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "1" => Ok(Value::Bool(true)),
+                "false" | "f" | "no" | "n" | "0" => Ok(Value::Bool(false)),
+                _ => Err(format!("Invalid BOOL: {}", s)),
+            },
+            (ty, v) => ty.coerce(v),
+        }
+    }
+
+    /// DECIMAL(precision, scale) from a type's arguments: none, the
+    /// precision, or both.
+    fn decimal(args: &[u32]) -> Result<DataType, String> {
+        let (precision, scale) = match args {
+            [] => (MAX_PRECISION as u32, 0),
+            [p] => (*p, 0),
+            [p, s] => (*p, *s),
+            _ => return Err("DECIMAL takes a precision and a scale".into()),
+        };
+        if precision == 0 || precision > MAX_PRECISION as u32 || scale > precision {
+            return Err(format!("Invalid DECIMAL({}, {}): precision must be 1 to {} and scale at most the precision", precision, scale, MAX_PRECISION));
+        }
+        Ok(DataType::Decimal(precision as u8, scale as u8))
+    }
+
+    fn is_temporal(&self) -> bool {
+        matches!(self, DataType::Date | DataType::Time | DataType::Timestamp | DataType::TimestampTz | DataType::Interval)
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DataType::Decimal(precision, scale) => return write!(f, "DECIMAL({},{})", precision, scale),
+            DataType::Int => "INT",
+            DataType::Float => "FLOAT",
+            DataType::String => "TEXT",
+            DataType::Bool => "BOOL",
+            DataType::Date => "DATE",
+            DataType::Time => "TIME",
+            DataType::Timestamp => "TIMESTAMP",
+            DataType::TimestampTz => "TIMESTAMPTZ",
+            DataType::Interval => "INTERVAL",
+            DataType::Json => "JSON",
+            DataType::Bytes => "BYTEA",
+            DataType::Uuid => "UUID",
+            DataType::Array(element) => return write!(f, "{}[]", element),
+            DataType::Struct(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, ty)| format!("{} {}", name, ty)).collect();
+                return write!(f, "STRUCT({})", fields.join(", "));
+            }
+            DataType::Any => "ANY",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Table {
+    pub fn new(name: &str, columns: Vec<Column>) -> Self {
+        Table { name: name.to_string(), columns, ..Default::default() }
+    }
+
+    pub fn foreign_keys(&self) -> impl Iterator<Item = (&Constraint, &ForeignKey)> {
+        foreign_keys(&self.constraints)
+    }
+
+    /// True if `cols` is exactly the primary key or a UNIQUE constraint.
+    fn is_unique_key(&self, cols: &[String]) -> bool {
+        self.constraints.iter().any(|c| match &c.kind {
+            ConstraintKind::PrimaryKey(k) | ConstraintKind::Unique(k) => k == cols,
+            _ => false,
+        })
+    }
+
+    pub fn primary_key(&self) -> Option<&[String]> {
+        self.constraints.iter().find_map(|c| match &c.kind {
+            ConstraintKind::PrimaryKey(cols) => Some(cols.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Next value for an identity column: one past the largest present.
+    fn next_identity(&self, col: &str) -> i64 {
+        self.rows
+            .iter()
+            .filter_map(|r| match r.data.get(col) {
+                Some(Value::Int(i)) => Some(*i),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// Rebuilds every index from the current rows.
+    pub fn reindex(&mut self) {
+        for index in &mut self.indexes {
+            let collations = index.columns.iter().map(|name| self.columns.iter().find(|c| c.name == *name).map_or(Collation::Binary, |c| c.collation)).collect();
+            index.build(&self.rows, collations);
+        }
+    }
+
+    /// Appends rows, adding them to every index.
+    fn append(&mut self, rows: Vec<Row>) {
+        for row in rows {
+            for index in &mut self.indexes {
+                index.insert(&row, self.rows.len());
+            }
+            self.rows.push(row);
+        }
+    }
+
+    /// Replaces the rows at the given positions, moving their index entries.
+    fn update(&mut self, updated: HashMap<usize, Row>) {
+        for (i, row) in updated {
+            for index in &mut self.indexes {
+                index.update(i, &self.rows[i], &row);
+            }
+            self.rows[i] = row;
+        }
+    }
+
+    /// Removes the rows at `deleted`, dropping their index entries; the rows
+    /// after them move up.
+    fn delete(&mut self, deleted: &HashSet<usize>) {
+        let mut positions: Vec<usize> = deleted.iter().copied().collect();
+        positions.sort_unstable();
+        let removed: Vec<&Row> = positions.iter().map(|&i| &self.rows[i]).collect();
+        for index in &mut self.indexes {
+            index.remove(&positions, &removed);
+        }
+        let rows = std::mem::take(&mut self.rows);
+        self.rows = rows.into_iter().enumerate().filter(|(i, _)| !deleted.contains(i)).map(|(_, r)| r).collect();
+    }
+
+    /// `expr` over this table with its columns' collations given
+    /// explicitly, as `Collations::resolve` gives them in a query.
+    fn collated(&self, expr: &Expr) -> Expr {
+        let mut collations = Collations::default();
+        collations.add(&self.name, &self.columns);
+        let mut expr = expr.clone();
+        collations.resolve_expr(&mut expr);
+        expr
+    }
+
+    /// `key` over `cols` as UNIQUE compares it: text under its column's
+    /// collation.
+    fn collated_key(&self, cols: &[String], GroupKey(_, key): GroupKey) -> GroupKey {
+        GroupKey(0, key.into_iter().zip(cols).map(|(v, c)| self.column(c).map_or(Collation::Binary, |c| c.collation).into_key(v)).collect())
+    }
+
+    /// Coerces `value` to the declared type of `col`.
+    fn coerce(&self, col: &str, value: Value) -> Result<Value, String> {
+        let column = self.column(col).ok_or(format!("Column not found: {}.{}", self.name, col))?;
+        column.data_type.coerce(value).map_err(|e| format!("Column {}.{}: {}", self.name, col, e))
+    }
+}
+
+impl StatementResult {
+    fn empty() -> Self {
+        StatementResult { rows_affected: 0, rows: ResultSet::default() }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Decimal(d) => write!(f, "DECIMAL '{}'", d),
+            Value::String(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Value::Bool(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Value::Date(d) => write!(f, "DATE '{}'", d),
+            Value::Time(t) => write!(f, "TIME '{}'", t),
+            Value::Timestamp(t) => write!(f, "TIMESTAMP '{}'", t),
+            Value::TimestampTz(t) => write!(f, "TIMESTAMPTZ '{}+00'", t),
+            Value::Interval(i) => write!(f, "INTERVAL '{}'", i),
+            Value::Json(j) => write!(f, "JSON '{}'", j.to_string().replace('\'', "''")),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_string()).collect();
+                write!(f, "ARRAY[{}]", items.join(", "))
+            }
+            Value::Struct(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, v)| format!("'{}': {}", name.replace('\'', "''"), v)).collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            Value::Bytes(b) => write!(f, "X'{}'", binary::hex(b).to_uppercase()),
+            Value::Uuid(u) => write!(f, "UUID '{}'", u),
+            Value::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Column(name) => write!(f, "{}", name),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::UnaryOp(op, e) if op == "NOT" => write!(f, "NOT {}", e),
+            Expr::UnaryOp(op, e) => write!(f, "{}{}", op, e),
+            Expr::BinOp(l, op, r) => write!(f, "{} {} {}", l, op, r),
+            Expr::FuncCall(name, args) if name == "CAST" && args.len() == 2 => match &args[1] {
+                Expr::Literal(Value::String(ty)) => write!(f, "CAST({} AS {})", args[0], ty),
+                ty => write!(f, "CAST({}, {})", args[0], ty),
+            },
+            Expr::FuncCall(name, args) if name == "ELEMENT_AT" && args.len() == 2 => write!(f, "{}[{}]", args[0], args[1]),
+            Expr::FuncCall(name, args) if name == "COLLATE" && args.len() == 2 => match &args[1] {
+                Expr::Literal(Value::String(c)) => write!(f, "{} COLLATE {}", args[0], c),
+                c => write!(f, "COLLATE({}, {})", args[0], c),
+            },
+            Expr::FuncCall(name, args) if name == "ARRAY" => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "ARRAY[{}]", args.join(", "))
+            }
+            Expr::FuncCall(name, args) if name == "STRUCT" && args.len().is_multiple_of(2) => {
+                let fields: Vec<String> = args.chunks(2).map(|pair| format!("{}: {}", pair[0], pair[1])).collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            Expr::FuncCall(name, args) => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
 // ============================================================================
 // Lexer
 // ============================================================================
@@ -64,15 +694,34 @@ enum Token {
     On,
     GroupBy,
     OrderBy,
+    By,
     Limit,
     And,
     Or,
+    Not,
+    As,
     Asc,
     Desc,
+    Insert,
+    Into,
+    Values,
+    Update,
+    Set,
+    Delete,
+    Returning,
+    Create,
+    Drop,
+    Alter,
+    Table,
     Comma,
     Star,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Colon,
     Ident(String),
     Number(String),
     String(String),
@@ -80,7 +729,6 @@ enum Token {
 }
 
 fn tokenize(input: &str) -> Vec<Token> {
-    let input = input.to_uppercase();
     let mut tokens = Vec::new();
     let mut chars = input.chars().peekable();
 
@@ -99,17 +747,54 @@ fn tokenize(input: &str) -> Vec<Token> {
         } else if ch == ')' {
             tokens.push(Token::RParen);
             chars.next();
-        } else if ch == '\'' {
+        } else if let Some(token) = match ch {
+            '[' => Some(Token::LBracket),
+            ']' => Some(Token::RBracket),
+            '{' => Some(Token::LBrace),
+            '}' => Some(Token::RBrace),
+            ':' => Some(Token::Colon),
+            _ => None,
+        } {
+            tokens.push(token);
             chars.next();
-            let s: String = chars.by_ref().take_while(|&c| c != '\'').collect();
+        } else if ch == '\'' {
             chars.next();
+            let mut s = String::new();
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    // '' inside a literal is an escaped quote
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                        s.push('\'');
+                        continue;
+                    }
+                    break;
+                }
+                s.push(c);
+            }
             tokens.push(Token::String(s));
-        } else if ch.is_numeric() {
-            let num: String = chars.by_ref().take_while(|c| c.is_numeric() || *c == '.').collect();
+        } else if ch.is_ascii_digit() {
+            let mut num = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() || (c == '.' && !num.contains('.')) {
+                    num.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
             tokens.push(Token::Number(num));
         } else if ch.is_alphabetic() || ch == '_' {
-            let word: String = chars.by_ref().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
-            let token = match word.as_str() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' || c == '.' {
+                    word.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let token = match word.to_uppercase().as_str() {
                 "SELECT" => Token::Select,
                 "FROM" => Token::From,
                 "WHERE" => Token::Where,
@@ -117,18 +802,50 @@ fn tokenize(input: &str) -> Vec<Token> {
                 "ON" => Token::On,
                 "GROUP" => Token::GroupBy,
                 "ORDER" => Token::OrderBy,
+                "BY" => Token::By,
                 "LIMIT" => Token::Limit,
                 "AND" => Token::And,
                 "OR" => Token::Or,
+                "NOT" => Token::Not,
+                "AS" => Token::As,
                 "ASC" => Token::Asc,
                 "DESC" => Token::Desc,
-                "BY" => Token::Ident("BY".into()),
+                "INSERT" => Token::Insert,
+                "INTO" => Token::Into,
+                "VALUES" => Token::Values,
+                "UPDATE" => Token::Update,
+                "SET" => Token::Set,
+                "DELETE" => Token::Delete,
+                "RETURNING" => Token::Returning,
+                "CREATE" => Token::Create,
+                "DROP" => Token::Drop,
+                "ALTER" => Token::Alter,
+                "TABLE" => Token::Table,
                 _ => Token::Ident(word),
             };
             tokens.push(token);
         } else if "=<>!".contains(ch) {
-            let op: String = chars.by_ref().take_while(|&c| "=<>!".contains(c)).collect();
+            chars.next();
+            let mut op = ch.to_string();
+            if let Some(&next) = chars.peek() {
+                if next == '=' || (ch == '<' && next == '>') {
+                    op.push(next);
+                    chars.next();
+                }
+            }
+            tokens.push(Token::Op(if op == "<>" { "!=".into() } else { op }));
+        } else if (ch == '-' || ch == '#') && chars.clone().nth(1) == Some('>') {
+            // JSON path operators: ->, ->>, #> and #>>
+            chars.next();
+            chars.next();
+            let mut op = format!("{}>", ch);
+            if chars.next_if_eq(&'>').is_some() {
+                op.push('>');
+            }
             tokens.push(Token::Op(op));
+        } else if "+-/%".contains(ch) {
+            chars.next();
+            tokens.push(Token::Op(ch.to_string()));
         } else {
             chars.next();
         }
@@ -171,14 +888,43 @@ impl Parser {
         }
     }
 
+    fn expect_ident(&mut self, what: &str) -> Result<String, String> {
+        match self.current() {
+            Some(Token::Ident(name)) => {
+                let n = name.clone();
+                self.advance();
+                Ok(n)
+            }
+            _ => Err(format!("Expected {}", what)),
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), String> {
+        match self.current() {
+            Some(tok) => Err(format!("Unexpected token {:?}", tok)),
+            None => Ok(()),
+        }
+    }
+
+    /// Non-reserved keywords (ROLLUP, CUBE, ...) lex as identifiers.
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.current(), Some(Token::Ident(s)) if s.eq_ignore_ascii_case(word))
+    }
+
     fn parse_query(&mut self) -> Result<Query, String> {
         self.expect(Token::Select)?;
 
-        let select_cols = self.parse_select_list()?;
+        let distinct = self.is_word("DISTINCT");
+        if distinct {
+            self.advance();
+        }
+        let (select_cols, select_exprs) = self.parse_select_list()?;
         self.expect(Token::From)?;
 
-        let from_table = match self.current() {
-            Some(Token::Ident(name)) => {
+        let from_unnest = self.parse_unnest()?;
+        let from_table = match (&from_unnest, self.current()) {
+            (Some(unnest), _) => unnest.column.name.clone(),
+            (None, Some(Token::Ident(name))) => {
                 let n = name.clone();
                 self.advance();
                 n
@@ -189,17 +935,24 @@ impl Parser {
         let mut joins = Vec::new();
         while matches!(self.current(), Some(Token::Join)) {
             self.advance();
-            let join_table = match self.current() {
-                Some(Token::Ident(name)) => {
+            let unnest = self.parse_unnest()?;
+            let join_table = match (&unnest, self.current()) {
+                (Some(unnest), _) => unnest.column.name.clone(),
+                (None, Some(Token::Ident(name))) => {
                     let n = name.clone();
                     self.advance();
                     n
                 }
                 _ => return Err("Expected table name in JOIN".into()),
             };
-            self.expect(Token::On)?;
-            let on_expr = self.parse_expr()?;
-            joins.push(Join { table: join_table, on: on_expr });
+            // Every element row joins its source row unless ON says otherwise
+            let on_expr = if unnest.is_some() && !matches!(self.current(), Some(Token::On)) {
+                Expr::Literal(Value::Bool(true))
+            } else {
+                self.expect(Token::On)?;
+                self.parse_expr()?
+            };
+            joins.push(Join { table: join_table, on: on_expr, unnest });
         }
 
         let where_clause = if matches!(self.current(), Some(Token::Where)) {
@@ -211,19 +964,15 @@ impl Parser {
 
         let group_by = if matches!(self.current(), Some(Token::GroupBy)) {
             self.advance();
-            if matches!(self.current(), Some(Token::Ident(s)) if s == "BY") {
-                self.advance();
-            }
-            self.parse_column_list()?
+            self.expect(Token::By)?;
+            self.parse_group_by_list()?
         } else {
             Vec::new()
         };
 
         let order_by = if matches!(self.current(), Some(Token::OrderBy)) {
             self.advance();
-            if matches!(self.current(), Some(Token::Ident(s)) if s == "BY") {
-                self.advance();
-            }
+            self.expect(Token::By)?;
             let mut cols = Vec::new();
             loop {
                 let col = match self.current() {
@@ -234,6 +983,7 @@ impl Parser {
                     }
                     _ => return Err("Expected column name".into()),
                 };
+                let collation = if self.accept_word("COLLATE") { Some(Collation::from_name(&self.expect_ident("collation name")?)?) } else { None };
                 let is_asc = match self.current() {
                     Some(Token::Asc) => {
                         self.advance();
@@ -245,7 +995,7 @@ impl Parser {
                     }
                     _ => true,
                 };
-                cols.push((col, is_asc));
+                cols.push((col, is_asc, collation));
                 if !matches!(self.current(), Some(Token::Comma)) {
                     break;
                 }
@@ -270,284 +1020,3757 @@ impl Parser {
             None
         };
 
-        Ok(Query { select_cols, from_table, joins, where_clause, group_by, order_by, limit })
+        Ok(Query { select_cols, select_exprs, distinct, from_table, from_unnest, joins, where_clause, group_by, order_by, limit })
     }
 
-    fn parse_select_list(&mut self) -> Result<Vec<String>, String> {
-        let mut cols = Vec::new();
-        if matches!(self.current(), Some(Token::Star)) {
-            self.advance();
-            cols.push("*".into());
-        } else {
-            loop {
-                match self.current() {
-                    Some(Token::Ident(name)) => {
-                        cols.push(name.clone());
-                        self.advance();
-                    }
-                    _ => return Err("Expected column name".into()),
-                }
-                if !matches!(self.current(), Some(Token::Comma)) {
-                    break;
-                }
+    fn parse_statement(&mut self) -> Result<Statement, String> {
+        match self.current() {
+            Some(Token::Select) => Ok(Statement::Select(self.parse_query()?)),
+            Some(Token::Insert) => self.parse_insert(),
+            Some(Token::Update) => self.parse_update(),
+            Some(Token::Delete) => self.parse_delete(),
+            Some(Token::Create) if self.peek(1) == Some(&Token::Table) => self.parse_create_table(),
+            Some(Token::Create) => self.parse_create_index(),
+            Some(Token::Drop) if self.peek(1) == Some(&Token::Table) => self.parse_drop_table(),
+            Some(Token::Drop) => self.parse_drop_index(),
+            Some(Token::Alter) => self.parse_alter_table(),
+            Some(Token::Ident(_)) if self.is_word("EXPLAIN") => {
+                self.advance();
+                Ok(Statement::Explain(self.parse_query()?))
+            }
+            Some(Token::Ident(_)) if self.is_word("COPY") => self.parse_copy(),
+            Some(Token::Ident(_)) if self.is_word("ANALYZE") => {
                 self.advance();
+                let table = if self.current().is_some() { Some(self.expect_ident("table name")?) } else { None };
+                Ok(Statement::Analyze(table))
             }
+            Some(Token::Ident(_)) => self.parse_transaction_control(),
+            _ => Err("Expected SELECT, INSERT, UPDATE, DELETE, CREATE, DROP or ALTER".into()),
         }
-        Ok(cols)
     }
 
-    fn parse_column_list(&mut self) -> Result<Vec<String>, String> {
-        let mut cols = Vec::new();
-        loop {
-            match self.current() {
-                Some(Token::Ident(name)) => {
-                    cols.push(name.clone());
-                    self.advance();
-                }
-                _ => return Err("Expected column name".into()),
-            }
-            if !matches!(self.current(), Some(Token::Comma)) {
-                break;
+    /// BEGIN / START TRANSACTION, COMMIT / END, ROLLBACK [TO [SAVEPOINT] name],
+    /// SAVEPOINT name and RELEASE [SAVEPOINT] name.
+    fn parse_transaction_control(&mut self) -> Result<Statement, String> {
+        let skip_noise = |p: &mut Self| p.accept_word("TRANSACTION") || p.accept_word("WORK");
+        if self.accept_word("BEGIN") {
+            skip_noise(self);
+            Ok(Statement::Begin)
+        } else if self.accept_word("START") {
+            self.expect_word("TRANSACTION")?;
+            Ok(Statement::Begin)
+        } else if self.accept_word("COMMIT") || self.accept_word("END") {
+            skip_noise(self);
+            Ok(Statement::Commit)
+        } else if self.accept_word("ROLLBACK") {
+            skip_noise(self);
+            if self.accept_word("TO") {
+                self.accept_word("SAVEPOINT");
+                Ok(Statement::RollbackTo(self.expect_ident("savepoint name")?))
+            } else {
+                Ok(Statement::Rollback)
             }
-            self.advance();
+        } else if self.accept_word("SAVEPOINT") {
+            Ok(Statement::Savepoint(self.expect_ident("savepoint name")?))
+        } else if self.accept_word("RELEASE") {
+            self.accept_word("SAVEPOINT");
+            Ok(Statement::Release(self.expect_ident("savepoint name")?))
+        } else {
+            Err("Expected SELECT, INSERT, UPDATE, DELETE, CREATE, DROP, ALTER or a transaction command".into())
         }
-        Ok(cols)
-    }
-
-    fn parse_expr(&mut self) -> Result<Expr, String> {
-        self.parse_or_expr()
     }
 
-    fn parse_or_expr(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_and_expr()?;
-        while matches!(self.current(), Some(Token::Or)) {
+    /// Consumes a non-reserved keyword if it is next.
+    fn accept_word(&mut self, word: &str) -> bool {
+        if self.is_word(word) {
             self.advance();
-            let right = self.parse_and_expr()?;
-            left = Expr::BinOp(Box::new(left), "OR".into(), Box::new(right));
+            true
+        } else {
+            false
         }
-        Ok(left)
     }
 
-    fn parse_and_expr(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_comparison()?;
-        while matches!(self.current(), Some(Token::And)) {
-            self.advance();
-            let right = self.parse_comparison()?;
-            left = Expr::BinOp(Box::new(left), "AND".into(), Box::new(right));
-        }
-        Ok(left)
+    fn expect_word(&mut self, word: &str) -> Result<(), String> {
+        if self.accept_word(word) { Ok(()) } else { Err(format!("Expected {}", word)) }
     }
 
-    fn parse_comparison(&mut self) -> Result<Expr, String> {
-        let left = self.parse_primary()?;
-        if let Some(Token::Op(op)) = self.current() {
-            let op = op.clone();
-            self.advance();
-            let right = self.parse_primary()?;
-            Ok(Expr::BinOp(Box::new(left), op, Box::new(right)))
-        } else {
-            Ok(left)
+    fn parse_create_table(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Create)?;
+        self.expect(Token::Table)?;
+        let if_not_exists = self.accept_word("IF");
+        if if_not_exists {
+            self.expect(Token::Not)?;
+            self.expect_word("EXISTS")?;
         }
-    }
+        let name = self.expect_ident("table name")?;
+        self.expect(Token::LParen)?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            if let Some(constraint) = self.parse_table_constraint(&name)? {
+                constraints.push(constraint);
+            } else {
+                let (column, column_constraints) = self.parse_column_def(&name)?;
+                columns.push(column);
+                constraints.extend(column_constraints);
+            }
+            if !matches!(self.current(), Some(Token::Comma)) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(Token::RParen)?;
+        let columnar = if self.accept_word("USING") {
+            if self.accept_word("COLUMNAR") {
+                true
+            } else {
+                self.expect_word("ROWS")?;
+                false
+            }
+        } else {
+            false
+        };
+        Ok(Statement::CreateTable { name, columns, constraints, if_not_exists, columnar })
+    }
 
-    fn parse_primary(&mut self) -> Result<Expr, String> {
-        match self.current() {
+    /// `[CONSTRAINT name] PRIMARY KEY (..) | UNIQUE (..) | CHECK (..)`, or
+    /// `None` if the next element is a column definition.
+    fn parse_table_constraint(&mut self, table: &str) -> Result<Option<Constraint>, String> {
+        let name = if self.accept_word("CONSTRAINT") { Some(self.expect_ident("constraint name")?) } else { None };
+        let kind = if self.accept_word("PRIMARY") {
+            self.expect_word("KEY")?;
+            ConstraintKind::PrimaryKey(self.parse_ident_list()?)
+        } else if self.accept_word("UNIQUE") {
+            ConstraintKind::Unique(self.parse_ident_list()?)
+        } else if self.accept_word("CHECK") {
+            ConstraintKind::Check(self.parse_paren_expr()?)
+        } else if self.accept_word("FOREIGN") {
+            self.expect_word("KEY")?;
+            let columns = self.parse_ident_list()?;
+            self.expect_word("REFERENCES")?;
+            ConstraintKind::ForeignKey(self.parse_references(columns)?)
+        } else if name.is_some() {
+            return Err("Expected PRIMARY KEY, UNIQUE, CHECK or FOREIGN KEY".into());
+        } else {
+            return Ok(None);
+        };
+        let name = name.unwrap_or_else(|| Constraint::default_name(table, &kind));
+        Ok(Some(Constraint { name, kind }))
+    }
+
+    /// `parent [(cols)] [ON DELETE action] [ON UPDATE action] [DEFERRABLE ...]`
+    /// after the REFERENCES keyword.
+    fn parse_references(&mut self, columns: Vec<String>) -> Result<ForeignKey, String> {
+        let ref_table = self.expect_ident("referenced table name")?;
+        let ref_columns = if matches!(self.current(), Some(Token::LParen)) { self.parse_ident_list()? } else { Vec::new() };
+        let mut fk = ForeignKey {
+            columns,
+            ref_table,
+            ref_columns,
+            on_delete: RefAction::NoAction,
+            on_update: RefAction::NoAction,
+            deferred: false,
+        };
+        loop {
+            if matches!(self.current(), Some(Token::On)) {
+                self.advance();
+                let is_delete = match self.current() {
+                    Some(Token::Delete) => true,
+                    Some(Token::Update) => false,
+                    _ => return Err("Expected DELETE or UPDATE after ON".into()),
+                };
+                self.advance();
+                let action = self.parse_ref_action()?;
+                if is_delete { fk.on_delete = action } else { fk.on_update = action }
+            } else if matches!(self.current(), Some(Token::Not)) && matches!(self.peek(1), Some(Token::Ident(w)) if w.eq_ignore_ascii_case("DEFERRABLE")) {
+                self.advance();
+                self.advance();
+            } else if self.accept_word("DEFERRABLE") {
+                if self.accept_word("INITIALLY") {
+                    fk.deferred = self.accept_word("DEFERRED");
+                    if !fk.deferred {
+                        self.expect_word("IMMEDIATE")?;
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(fk)
+    }
+
+    fn parse_ref_action(&mut self) -> Result<RefAction, String> {
+        if self.accept_word("CASCADE") {
+            Ok(RefAction::Cascade)
+        } else if self.accept_word("RESTRICT") {
+            Ok(RefAction::Restrict)
+        } else if matches!(self.current(), Some(Token::Set)) {
+            self.advance();
+            self.expect_word("NULL")?;
+            Ok(RefAction::SetNull)
+        } else if self.accept_word("NO") {
+            self.expect_word("ACTION")?;
+            Ok(RefAction::NoAction)
+        } else {
+            Err("Expected CASCADE, SET NULL, RESTRICT or NO ACTION".into())
+        }
+    }
+
+    fn parse_ident_list(&mut self) -> Result<Vec<String>, String> {
+        self.expect(Token::LParen)?;
+        let mut names = Vec::new();
+        loop {
+            names.push(self.expect_ident("column name")?);
+            if !matches!(self.current(), Some(Token::Comma)) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(Token::RParen)?;
+        Ok(names)
+    }
+
+    fn parse_paren_expr(&mut self) -> Result<Expr, String> {
+        self.expect(Token::LParen)?;
+        let expr = self.parse_expr()?;
+        self.expect(Token::RParen)?;
+        Ok(expr)
+    }
+
+    /// A column definition and the table constraints declared inline on it.
+    fn parse_column_def(&mut self, table: &str) -> Result<(Column, Vec<Constraint>), String> {
+        let name = self.expect_ident("column name")?;
+        let mut column = Column::new(&name, self.parse_data_type()?);
+        let mut constraints = Vec::new();
+        loop {
+            let constraint_name = if self.accept_word("CONSTRAINT") { Some(self.expect_ident("constraint name")?) } else { None };
+            let kind = if matches!(self.current(), Some(Token::Not)) {
+                self.advance();
+                self.expect_word("NULL")?;
+                column.not_null = true;
+                None
+            } else if self.accept_word("NULL") {
+                None
+            } else if self.accept_word("PRIMARY") {
+                self.expect_word("KEY")?;
+                Some(ConstraintKind::PrimaryKey(vec![name.clone()]))
+            } else if self.accept_word("UNIQUE") {
+                Some(ConstraintKind::Unique(vec![name.clone()]))
+            } else if self.accept_word("CHECK") {
+                Some(ConstraintKind::Check(self.parse_paren_expr()?))
+            } else if self.accept_word("REFERENCES") {
+                Some(ConstraintKind::ForeignKey(self.parse_references(vec![name.clone()])?))
+            } else if self.accept_word("DEFAULT") {
+                column.default = Some(self.parse_unary()?);
+                None
+            } else if self.accept_word("COLLATE") {
+                column.collation = Collation::from_name(&self.expect_ident("collation name")?)?;
+                None
+            } else if self.accept_word("AUTOINCREMENT") || self.accept_word("AUTO_INCREMENT") {
+                column.identity = Some(Identity::ByDefault);
+                None
+            } else if self.accept_word("GENERATED") {
+                let identity = if self.accept_word("ALWAYS") {
+                    Identity::Always
+                } else {
+                    self.expect(Token::By)?;
+                    self.expect_word("DEFAULT")?;
+                    Identity::ByDefault
+                };
+                self.expect(Token::As)?;
+                self.expect_word("IDENTITY")?;
+                column.identity = Some(identity);
+                None
+            } else if constraint_name.is_some() {
+                return Err("Expected column constraint".into());
+            } else {
+                break;
+            };
+            if let Some(kind) = kind {
+                let cname = constraint_name.unwrap_or_else(|| Constraint::default_name(table, &kind));
+                constraints.push(Constraint { name: cname, kind });
+            }
+        }
+        if column.identity.is_some() && column.data_type != DataType::Int {
+            return Err(format!("Identity column {} must be INT", name));
+        }
+        if column.collation != Collation::Binary && !matches!(column.data_type, DataType::String | DataType::Any) {
+            return Err(format!("Column {} has a collation but is not TEXT", name));
+        }
+        Ok((column, constraints))
+    }
+
+    /// `UNNEST(expr) [AS name]`, if the next tokens are one; the name
+    /// defaults to `unnest`.
+    fn parse_unnest(&mut self) -> Result<Option<Unnest>, String> {
+        if !(self.is_word("UNNEST") && matches!(self.peek(1), Some(Token::LParen))) {
+            return Ok(None);
+        }
+        self.advance();
+        self.advance();
+        let expr = self.parse_expr()?;
+        self.expect(Token::RParen)?;
+        let name = match self.current() {
+            Some(Token::As) => {
+                self.advance();
+                self.expect_ident("UNNEST name")?
+            }
             Some(Token::Ident(name)) => {
                 let n = name.clone();
                 self.advance();
-                Ok(Expr::Column(n))
+                n
             }
-            Some(Token::Number(n)) => {
-                let num = n.clone();
+            _ => "unnest".into(),
+        };
+        Ok(Some(Unnest { expr, column: Column::new(&name, DataType::Any) }))
+    }
+
+    /// A type name with optional length arguments, e.g. `VARCHAR(255)`.
+    fn parse_data_type(&mut self) -> Result<DataType, String> {
+        let type_name = self.expect_ident("column type")?;
+        let mut data_type = DataType::from_name(&type_name)?;
+        let mut args = Vec::new();
+        if let (DataType::Struct(fields), Some(Token::LParen)) = (&mut data_type, self.current()) {
+            self.advance();
+            loop {
+                let name = self.expect_ident("field name")?;
+                if fields.iter().any(|(n, _)| *n == name) {
+                    return Err(format!("Duplicate field: {}", name));
+                }
+                fields.push((name, self.parse_data_type()?));
+                if !matches!(self.current(), Some(Token::Comma)) {
+                    break;
+                }
                 self.advance();
-                if num.contains('.') {
-                    Ok(Expr::Literal(Value::Float(num.parse().unwrap())))
-                } else {
-                    Ok(Expr::Literal(Value::Int(num.parse().unwrap())))
+            }
+            self.expect(Token::RParen)?;
+        } else if matches!(self.current(), Some(Token::LParen)) {
+            self.advance();
+            while let Some(token @ (Token::Number(_) | Token::Comma)) = self.current() {
+                if let Token::Number(n) = token {
+                    args.push(n.parse::<u32>().map_err(|_| format!("Invalid type argument: {}", n))?);
                 }
+                self.advance();
             }
-            Some(Token::String(s)) => {
-                let s = s.clone();
+            self.expect(Token::RParen)?;
+        }
+        if let DataType::Decimal(..) = data_type {
+            data_type = DataType::decimal(&args)?;
+        }
+        // INT[], INT[][], ...
+        while matches!(self.current(), Some(Token::LBracket)) && matches!(self.peek(1), Some(Token::RBracket)) {
+            self.advance();
+            self.advance();
+            data_type = DataType::Array(Box::new(data_type));
+        }
+        Ok(data_type)
+    }
+
+    fn parse_drop_table(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Drop)?;
+        self.expect(Token::Table)?;
+        let if_exists = self.accept_word("IF");
+        if if_exists {
+            self.expect_word("EXISTS")?;
+        }
+        let name = self.expect_ident("table name")?;
+        Ok(Statement::DropTable { name, if_exists })
+    }
+
+    /// CREATE [UNIQUE] INDEX [IF NOT EXISTS] name ON table (col, ...)
+    fn parse_create_index(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Create)?;
+        let unique = self.accept_word("UNIQUE");
+        self.expect_word("INDEX")?;
+        let if_not_exists = self.accept_word("IF");
+        if if_not_exists {
+            self.expect(Token::Not)?;
+            self.expect_word("EXISTS")?;
+        }
+        let name = self.expect_ident("index name")?;
+        self.expect(Token::On)?;
+        let table = self.expect_ident("table name")?;
+        let kind = if self.accept_word("USING") {
+            if self.accept_word("HASH") {
+                IndexKind::Hash
+            } else {
+                self.expect_word("BTREE")?;
+                IndexKind::BTree
+            }
+        } else {
+            IndexKind::BTree
+        };
+        let columns = self.parse_ident_list()?;
+        let include = if self.accept_word("INCLUDE") { self.parse_ident_list()? } else { Vec::new() };
+        Ok(Statement::CreateIndex { name, table, columns, unique, kind, include, if_not_exists })
+    }
+
+    fn parse_drop_index(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Drop)?;
+        self.expect_word("INDEX")?;
+        let if_exists = self.accept_word("IF");
+        if if_exists {
+            self.expect_word("EXISTS")?;
+        }
+        let name = self.expect_ident("index name")?;
+        Ok(Statement::DropIndex { name, if_exists })
+    }
+
+    fn parse_alter_table(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Alter)?;
+        self.expect(Token::Table)?;
+        let name = self.expect_ident("table name")?;
+        let action = if self.accept_word("ADD") {
+            self.accept_word("COLUMN");
+            let (column, constraints) = self.parse_column_def(&name)?;
+            AlterAction::AddColumn(column, constraints)
+        } else if matches!(self.current(), Some(Token::Drop)) {
+            self.advance();
+            self.accept_word("COLUMN");
+            AlterAction::DropColumn(self.expect_ident("column name")?)
+        } else if self.accept_word("RENAME") {
+            if self.accept_word("TO") {
+                AlterAction::RenameTable(self.expect_ident("table name")?)
+            } else {
+                self.accept_word("COLUMN");
+                let from = self.expect_ident("column name")?;
+                self.expect_word("TO")?;
+                AlterAction::RenameColumn(from, self.expect_ident("column name")?)
+            }
+        } else {
+            return Err("Expected ADD, DROP or RENAME".into());
+        };
+        Ok(Statement::AlterTable { name, action })
+    }
+
+    fn parse_insert(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Insert)?;
+        self.expect(Token::Into)?;
+        let table = self.expect_ident("table name")?;
+
+        let mut columns = Vec::new();
+        if matches!(self.current(), Some(Token::LParen)) {
+            self.advance();
+            loop {
+                columns.push(self.expect_ident("column name")?);
+                if !matches!(self.current(), Some(Token::Comma)) {
+                    break;
+                }
                 self.advance();
-                Ok(Expr::Literal(Value::String(s)))
             }
-            Some(Token::LParen) => {
+            self.expect(Token::RParen)?;
+        }
+
+        let source = match self.current() {
+            Some(Token::Values) => {
+                self.advance();
+                let mut rows = Vec::new();
+                loop {
+                    self.expect(Token::LParen)?;
+                    rows.push(self.parse_expr_list()?);
+                    self.expect(Token::RParen)?;
+                    if !matches!(self.current(), Some(Token::Comma)) {
+                        break;
+                    }
+                    self.advance();
+                }
+                InsertSource::Values(rows)
+            }
+            Some(Token::Select) => InsertSource::Query(Box::new(self.parse_query()?)),
+            _ => return Err("Expected VALUES or SELECT".into()),
+        };
+
+        let returning = self.parse_returning()?;
+        Ok(Statement::Insert(Insert { table, columns, source, returning }))
+    }
+
+    fn parse_update(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Update)?;
+        let table = self.expect_ident("table name")?;
+        self.expect(Token::Set)?;
+
+        let mut assignments = Vec::new();
+        loop {
+            let col = self.expect_ident("column name")?;
+            self.expect(Token::Op("=".into()))?;
+            assignments.push((col, self.parse_expr()?));
+            if !matches!(self.current(), Some(Token::Comma)) {
+                break;
+            }
+            self.advance();
+        }
+
+        let where_clause = self.parse_where()?;
+        let returning = self.parse_returning()?;
+        Ok(Statement::Update(Update { table, assignments, where_clause, returning }))
+    }
+
+    fn parse_delete(&mut self) -> Result<Statement, String> {
+        self.expect(Token::Delete)?;
+        self.expect(Token::From)?;
+        let table = self.expect_ident("table name")?;
+        let where_clause = self.parse_where()?;
+        let returning = self.parse_returning()?;
+        Ok(Statement::Delete(Delete { table, where_clause, returning }))
+    }
+
+    /// COPY table FROM 'file', COPY table TO 'file' or COPY (query) TO
+    /// 'file', then options, in parentheses and separated by commas or not:
+    /// `[WITH] (FORMAT CSV, HEADER [TRUE | FALSE], DELIMITER 'c', QUOTE 'c',
+    /// ESCAPE 'c', NULL 'text')`.
+    fn parse_copy(&mut self) -> Result<Statement, String> {
+        self.expect_word("COPY")?;
+        let (table, query) = if matches!(self.current(), Some(Token::LParen)) {
+            self.advance();
+            let query = self.parse_query()?;
+            self.expect(Token::RParen)?;
+            (None, query)
+        } else {
+            let table = self.expect_ident("table name")?;
+            let query = Query {
+                select_cols: vec!["*".into()],
+                select_exprs: vec![Expr::Column("*".into())],
+                distinct: false,
+                from_table: table.clone(),
+                from_unnest: None,
+                joins: Vec::new(),
+                where_clause: None,
+                group_by: Vec::new(),
+                order_by: Vec::new(),
+                limit: None,
+            };
+            (Some(table), query)
+        };
+        let from = match (self.current(), &table) {
+            (Some(Token::From), Some(_)) => true,
+            (Some(Token::Ident(w)), _) if w.eq_ignore_ascii_case("TO") => false,
+            _ => return Err(format!("Expected {}TO", if table.is_some() { "FROM or " } else { "" })),
+        };
+        self.advance();
+        let path = match self.current() {
+            Some(Token::String(path)) => path.clone(),
+            _ => return Err("Expected file name".into()),
+        };
+        self.advance();
+
+        let mut options = CsvOptions::default();
+        self.accept_word("WITH");
+        let parenthesized = matches!(self.current(), Some(Token::LParen));
+        if parenthesized {
+            self.advance();
+        }
+        while let Some(Token::Ident(option)) = self.current() {
+            let option = option.to_uppercase();
+            self.advance();
+            let text = |p: &mut Self| match p.current() {
+                Some(Token::String(s)) => {
+                    let s = s.clone();
+                    p.advance();
+                    Ok(s)
+                }
+                _ => Err(format!("Expected text after {}", option)),
+            };
+            match option.as_str() {
+                "CSV" => {}
+                "FORMAT" => {
+                    let format = self.expect_ident("format")?;
+                    if !format.eq_ignore_ascii_case("CSV") {
+                        return Err(format!("Unsupported COPY format: {}", format));
+                    }
+                }
+                "HEADER" if self.accept_word("FALSE") => options.header = Some(false),
+                "HEADER" => {
+                    self.accept_word("TRUE");
+                    options.header = Some(true);
+                }
+                "DELIMITER" => options.delimiter = copy_char("DELIMITER", &text(self)?)?,
+                "QUOTE" => options.quote = copy_char("QUOTE", &text(self)?)?,
+                "ESCAPE" => options.escape = copy_char("ESCAPE", &text(self)?)?,
+                "NULL" => options.null = text(self)?,
+                _ => return Err(format!("Unknown COPY option: {}", option)),
+            }
+            if parenthesized && matches!(self.current(), Some(Token::Comma)) {
                 self.advance();
+            }
+        }
+        if parenthesized {
+            self.expect(Token::RParen)?;
+        }
+        Ok(match table {
+            Some(table) if from => Statement::CopyFrom { table, path, options },
+            _ => Statement::CopyTo { query, path, options },
+        })
+    }
+
+    fn parse_where(&mut self) -> Result<Option<Expr>, String> {
+        if matches!(self.current(), Some(Token::Where)) {
+            self.advance();
+            Ok(Some(self.parse_expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_returning(&mut self) -> Result<Vec<(String, Expr)>, String> {
+        if !matches!(self.current(), Some(Token::Returning)) {
+            return Ok(Vec::new());
+        }
+        self.advance();
+        let (cols, exprs) = self.parse_select_list()?;
+        Ok(cols.into_iter().zip(exprs).collect())
+    }
+
+    fn parse_select_list(&mut self) -> Result<(Vec<String>, Vec<Expr>), String> {
+        let mut cols = Vec::new();
+        let mut exprs = Vec::new();
+        if matches!(self.current(), Some(Token::Star)) {
+            self.advance();
+            cols.push("*".into());
+            exprs.push(Expr::Column("*".into()));
+        } else {
+            loop {
                 let expr = self.parse_expr()?;
-                self.expect(Token::RParen)?;
-                Ok(expr)
+                let name = match self.current() {
+                    Some(Token::As) => {
+                        self.advance();
+                        match self.current() {
+                            Some(Token::Ident(alias)) => {
+                                let a = alias.clone();
+                                self.advance();
+                                a
+                            }
+                            _ => return Err("Expected alias after AS".into()),
+                        }
+                    }
+                    Some(Token::Ident(alias)) => {
+                        let a = alias.clone();
+                        self.advance();
+                        a
+                    }
+                    _ => expr.to_string(),
+                };
+                cols.push(name);
+                exprs.push(expr);
+                if !matches!(self.current(), Some(Token::Comma)) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        Ok((cols, exprs))
+    }
+
+    fn parse_expr_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut exprs = Vec::new();
+        if matches!(self.current(), Some(Token::RParen)) {
+            return Ok(exprs);
+        }
+        loop {
+            exprs.push(self.parse_expr()?);
+            if !matches!(self.current(), Some(Token::Comma)) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(exprs)
+    }
+
+    fn parse_group_by_list(&mut self) -> Result<Vec<GroupByItem>, String> {
+        let mut items = Vec::new();
+        loop {
+            items.push(self.parse_group_by_item(false)?);
+            if !matches!(self.current(), Some(Token::Comma)) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(items)
+    }
+
+    /// Inside GROUPING SETS a parenthesized list is a composite set rather
+    /// than a parenthesized expression.
+    fn parse_group_by_item(&mut self, in_sets: bool) -> Result<GroupByItem, String> {
+        let is_call = matches!(self.peek(1), Some(Token::LParen));
+        if self.is_word("ROLLUP") && is_call {
+            self.advance();
+            self.advance();
+            let exprs = self.parse_expr_list()?;
+            self.expect(Token::RParen)?;
+            Ok(GroupByItem::Rollup(exprs))
+        } else if self.is_word("CUBE") && is_call {
+            self.advance();
+            self.advance();
+            let exprs = self.parse_expr_list()?;
+            self.expect(Token::RParen)?;
+            Ok(GroupByItem::Cube(exprs))
+        } else if self.is_word("GROUPING") && matches!(self.peek(1), Some(Token::Ident(s)) if s.eq_ignore_ascii_case("SETS")) {
+            self.advance();
+            self.advance();
+            self.expect(Token::LParen)?;
+            let mut elems = Vec::new();
+            loop {
+                elems.push(self.parse_group_by_item(true)?);
+                if !matches!(self.current(), Some(Token::Comma)) {
+                    break;
+                }
+                self.advance();
+            }
+            self.expect(Token::RParen)?;
+            Ok(GroupByItem::GroupingSets(elems))
+        } else if matches!(self.current(), Some(Token::LParen))
+            && (in_sets || matches!(self.peek(1), Some(Token::RParen)))
+        {
+            self.advance();
+            let exprs = self.parse_expr_list()?;
+            self.expect(Token::RParen)?;
+            Ok(GroupByItem::Set(exprs))
+        } else {
+            Ok(GroupByItem::Expr(self.parse_expr()?))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        self.parse_or_expr()
+    }
+
+    fn parse_or_expr(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and_expr()?;
+        while matches!(self.current(), Some(Token::Or)) {
+            self.advance();
+            let right = self.parse_and_expr()?;
+            left = Expr::BinOp(Box::new(left), "OR".into(), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and_expr(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not_expr()?;
+        while matches!(self.current(), Some(Token::And)) {
+            self.advance();
+            let right = self.parse_not_expr()?;
+            left = Expr::BinOp(Box::new(left), "AND".into(), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not_expr(&mut self) -> Result<Expr, String> {
+        if matches!(self.current(), Some(Token::Not)) {
+            self.advance();
+            let expr = self.parse_not_expr()?;
+            Ok(Expr::UnaryOp("NOT".into(), Box::new(expr)))
+        } else {
+            self.parse_comparison()
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_additive()?;
+        if self.accept_word("LIKE") {
+            let right = self.parse_additive()?;
+            return Ok(Expr::BinOp(Box::new(left), "LIKE".into(), Box::new(right)));
+        }
+        match self.current() {
+            Some(Token::Op(op)) if matches!(op.as_str(), "=" | "!=" | "<" | ">" | "<=" | ">=") => {
+                let op = op.clone();
+                self.advance();
+                let right = self.parse_additive()?;
+                Ok(Expr::BinOp(Box::new(left), op, Box::new(right)))
+            }
+            // a LIKE p and a NOT LIKE p
+            Some(Token::Not) if matches!(self.peek(1), Some(Token::Ident(w)) if w.eq_ignore_ascii_case("LIKE")) => {
+                self.advance();
+                self.advance();
+                let right = self.parse_additive()?;
+                Ok(Expr::UnaryOp("NOT".into(), Box::new(Expr::BinOp(Box::new(left), "LIKE".into(), Box::new(right)))))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_multiplicative()?;
+        while let Some(Token::Op(op)) = self.current() {
+            if op != "+" && op != "-" {
+                break;
+            }
+            let op = op.clone();
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            left = Expr::BinOp(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.current() {
+                Some(Token::Star) => "*".to_string(),
+                Some(Token::Op(op)) if op == "/" || op == "%" => op.clone(),
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_unary()?;
+            left = Expr::BinOp(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if matches!(self.current(), Some(Token::Op(op)) if op == "-") {
+            self.advance();
+            return match self.parse_unary()? {
+                Expr::Literal(Value::Int(i)) => Ok(Expr::Literal(Value::Int(-i))),
+                Expr::Literal(Value::Float(f)) => Ok(Expr::Literal(Value::Float(-f))),
+                expr => Ok(Expr::UnaryOp("-".into(), Box::new(expr))),
+            };
+        }
+        self.parse_access()
+    }
+
+    /// JSON path operators, subscripts and COLLATE bind tighter than any
+    /// other: `-doc->'n'` negates the member and `doc->'a'->0` and `a[1][2]`
+    /// read left to right.
+    fn parse_access(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_primary()?;
+        loop {
+            if self.accept_word("COLLATE") {
+                let collation = Collation::from_name(&self.expect_ident("collation name")?)?;
+                left = collation::collate(left, collation);
+                continue;
+            }
+            let op = match self.current() {
+                Some(Token::LBracket) => {
+                    self.advance();
+                    let index = self.parse_expr()?;
+                    self.expect(Token::RBracket)?;
+                    left = Expr::FuncCall("ELEMENT_AT".into(), vec![left, index]);
+                    continue;
+                }
+                Some(Token::Op(op)) if json::is_access(op) => op.clone(),
+                _ => break,
+            };
+            self.advance();
+            // A negative position counts from the end: doc->-1
+            let negative = matches!(self.current(), Some(Token::Op(minus)) if minus == "-");
+            if negative {
+                self.advance();
+            }
+            let right = match self.parse_primary()? {
+                Expr::Literal(Value::Int(i)) if negative => Expr::Literal(Value::Int(-i)),
+                expr if negative => Expr::UnaryOp("-".into(), Box::new(expr)),
+                expr => expr,
+            };
+            left = Expr::BinOp(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.current() {
+            Some(Token::Ident(name)) => {
+                let n = name.clone();
+                self.advance();
+                // Hex byte strings: X'DEADBEEF'
+                if let (Some(Token::String(text)), true) = (self.current(), n.eq_ignore_ascii_case("X")) {
+                    let bytes = binary::parse_hex(text)?;
+                    self.advance();
+                    return Ok(Expr::Literal(Value::Bytes(bytes)));
+                }
+                // Typed literals: DATE '2026-01-01', DECIMAL '0.10', ...
+                if let (Some(Token::String(text)), Ok(ty)) = (self.current(), DataType::from_name(&n)) {
+                    let value = match ty {
+                        DataType::Decimal(..) => Some(Decimal::parse(text).map(Value::Decimal)),
+                        DataType::Json => Some(Json::parse(text).map(Value::Json)),
+                        ty @ (DataType::Bytes | DataType::Uuid) => Some(ty.coerce(Value::String(text.clone()))),
+                        ty if ty.is_temporal() => Some(temporal::parse(&ty, text)),
+                        _ => None,
+                    };
+                    if let Some(value) = value {
+                        self.advance();
+                        return Ok(Expr::Literal(value?));
+                    }
+                }
+                if n.eq_ignore_ascii_case("ARRAY") && matches!(self.current(), Some(Token::LBracket)) {
+                    return self.parse_array();
+                }
+                if matches!(self.current(), Some(Token::LParen)) {
+                    self.advance();
+                    // EXTRACT(field FROM expr) is DATE_PART's arguments in SQL's order
+                    if n.eq_ignore_ascii_case("EXTRACT") && matches!(self.peek(1), Some(Token::From)) {
+                        let field = self.expect_ident("date part")?;
+                        self.advance();
+                        let expr = self.parse_expr()?;
+                        self.expect(Token::RParen)?;
+                        return Ok(Expr::FuncCall("EXTRACT".into(), vec![Expr::Literal(Value::String(field.to_lowercase())), expr]));
+                    }
+                    // CAST(expr AS type) keeps the type as its name
+                    if n.eq_ignore_ascii_case("CAST") {
+                        let expr = self.parse_expr()?;
+                        self.expect(Token::As)?;
+                        let ty = self.parse_data_type()?;
+                        self.expect(Token::RParen)?;
+                        return Ok(Expr::FuncCall("CAST".into(), vec![expr, Expr::Literal(Value::String(ty.to_string()))]));
+                    }
+                    let args = if matches!(self.current(), Some(Token::Star)) {
+                        self.advance();
+                        vec![Expr::Column("*".into())]
+                    } else {
+                        self.parse_expr_list()?
+                    };
+                    self.expect(Token::RParen)?;
+                    return Ok(Expr::FuncCall(n.to_uppercase(), args));
+                }
+                match n.to_uppercase().as_str() {
+                    "NULL" => Ok(Expr::Literal(Value::Null)),
+                    "TRUE" => Ok(Expr::Literal(Value::Bool(true))),
+                    "FALSE" => Ok(Expr::Literal(Value::Bool(false))),
+                    _ => Ok(Expr::Column(n)),
+                }
+            }
+            Some(Token::Number(n)) => {
+                let num = n.clone();
+                self.advance();
+                if num.contains('.') {
+                    Ok(Expr::Literal(Value::Float(num.parse().map_err(|_| format!("Invalid number: {}", num))?)))
+                } else {
+                    Ok(Expr::Literal(Value::Int(num.parse().map_err(|_| format!("Invalid number: {}", num))?)))
+                }
+            }
+            Some(Token::String(s)) => {
+                let s = s.clone();
+                self.advance();
+                Ok(Expr::Literal(Value::String(s)))
+            }
+            Some(Token::LParen) => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::LBracket) => self.parse_array(),
+            // {'name': value, ...}
+            Some(Token::LBrace) => {
+                self.advance();
+                let mut args = Vec::new();
+                while !matches!(self.current(), Some(Token::RBrace)) {
+                    if !args.is_empty() {
+                        self.expect(Token::Comma)?;
+                    }
+                    match self.current() {
+                        Some(Token::String(name) | Token::Ident(name)) => args.push(Expr::Literal(Value::String(name.clone()))),
+                        _ => return Err("Expected field name".into()),
+                    }
+                    self.advance();
+                    self.expect(Token::Colon)?;
+                    args.push(self.parse_expr()?);
+                }
+                self.advance();
+                Ok(Expr::FuncCall("STRUCT".into(), args))
+            }
+            _ => Err("Expected expression".into()),
+        }
+    }
+
+    /// `[a, b, ...]`, the elements of an array.
+    fn parse_array(&mut self) -> Result<Expr, String> {
+        self.expect(Token::LBracket)?;
+        let elements = if matches!(self.current(), Some(Token::RBracket)) { Vec::new() } else { self.parse_expr_list()? };
+        self.expect(Token::RBracket)?;
+        Ok(Expr::FuncCall("ARRAY".into(), elements))
+    }
+}
+
+pub fn parse(sql: &str) -> Result<Query, String> {
+    let tokens = tokenize(sql);
+    let mut parser = Parser::new(tokens);
+    let query = parser.parse_query()?;
+    parser.expect_end()?;
+    Ok(query)
+}
+
+/// The single character a COPY option gives; `'\t'` is a tab.
+fn copy_char(option: &str, text: &str) -> Result<char, String> {
+    let mut chars = text.chars();
+    match (text, chars.next(), chars.next()) {
+        ("\\t", _, _) => Ok('\t'),
+        (_, Some(c), None) => Ok(c),
+        _ => Err(format!("COPY {} must be a single character", option)),
+    }
+}
+
+pub fn parse_statement(sql: &str) -> Result<Statement, String> {
+    let tokens = tokenize(sql);
+    let mut parser = Parser::new(tokens);
+    let stmt = parser.parse_statement()?;
+    parser.expect_end()?;
+    Ok(stmt)
+}
+
+// ============================================================================
+// Grouping
+// ============================================================================
+
+const AGGREGATES: [&str; 7] = ["COUNT", "SUM", "AVG", "MIN", "MAX", "JSON_AGG", "ARRAY_AGG"];
+
+fn is_aggregate(expr: &Expr) -> bool {
+    matches!(expr, Expr::FuncCall(name, _) if AGGREGATES.contains(&name.as_str()))
+}
+
+fn contains_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::FuncCall(name, args) => {
+            AGGREGATES.contains(&name.as_str()) || name == "GROUPING" || args.iter().any(contains_aggregate)
+        }
+        Expr::UnaryOp(_, e) => contains_aggregate(e),
+        Expr::BinOp(l, _, r) => contains_aggregate(l) || contains_aggregate(r),
+        _ => false,
+    }
+}
+
+fn collect_aggregates(expr: &Expr, out: &mut Vec<Expr>) {
+    match expr {
+        e if is_aggregate(e) && !out.contains(e) => out.push(e.clone()),
+        e if is_aggregate(e) => {}
+        Expr::FuncCall(_, args) => args.iter().for_each(|a| collect_aggregates(a, out)),
+        Expr::UnaryOp(_, e) => collect_aggregates(e, out),
+        Expr::BinOp(l, _, r) => {
+            collect_aggregates(l, out);
+            collect_aggregates(r, out);
+        }
+        _ => {}
+    }
+}
+
+/// Hash key for a group: the grouping set and the key values, which hash
+/// and compare as `Value` does.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct GroupKey(usize, Vec<Value>);
+
+enum Accumulator {
+    Count(i64),
+    /// Running sum, `None` until the first non-NULL input. A sum that
+    /// overflows becomes NULL and stays NULL.
+    Sum(Option<Value>),
+    /// Running sum and count. Integers are summed as floats; decimals stay
+    /// exact.
+    Avg(Value, i64),
+    Min(Value),
+    Max(Value),
+    /// Every input in row order, NULLs as JSON null.
+    JsonAgg(Vec<Json>),
+    /// Every input in row order, NULLs included.
+    ArrayAgg(Vec<Value>),
+}
+
+impl Accumulator {
+    fn new(func: &str) -> Self {
+        match func {
+            "COUNT" => Accumulator::Count(0),
+            "SUM" => Accumulator::Sum(None),
+            "AVG" => Accumulator::Avg(Value::Null, 0),
+            "MIN" => Accumulator::Min(Value::Null),
+            "JSON_AGG" => Accumulator::JsonAgg(Vec::new()),
+            "ARRAY_AGG" => Accumulator::ArrayAgg(Vec::new()),
+            _ => Accumulator::Max(Value::Null),
+        }
+    }
+
+    /// `None` is the row marker for COUNT(*).
+    fn update(&mut self, db: &Database, value: Option<&Value>) {
+        match (&mut *self, value) {
+            (Accumulator::JsonAgg(items), Some(v)) => return items.push(Json::from_value(v)),
+            (Accumulator::ArrayAgg(items), Some(v)) => return items.push(v.clone()),
+            _ => {}
+        }
+        let v = match value {
+            None => {
+                if let Accumulator::Count(n) = self {
+                    *n += 1;
+                }
+                return;
+            }
+            Some(Value::Null) => return,
+            Some(v) => v,
+        };
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum(total) => {
+                *total = Some(match total {
+                    Some(t) => db.apply_binop(t, "+", v),
+                    None => v.clone(),
+                });
+            }
+            Accumulator::Avg(sum, n) => {
+                let v = match v {
+                    Value::Int(i) => Value::Float(*i as f64),
+                    Value::Float(_) | Value::Decimal(_) => v.clone(),
+                    _ => return,
+                };
+                *sum = if *sum == Value::Null { v } else { db.apply_binop(sum, "+", &v) };
+                *n += 1;
+            }
+            Accumulator::Min(m) => {
+                if *m == Value::Null || v < m {
+                    *m = v.clone();
+                }
+            }
+            Accumulator::Max(m) => {
+                if *m == Value::Null || v > m {
+                    *m = v.clone();
+                }
+            }
+            Accumulator::JsonAgg(_) | Accumulator::ArrayAgg(_) => {}
+        }
+    }
+
+    /// Folds in the partial result for a later run of rows.
+    fn merge(&mut self, db: &Database, other: Accumulator) {
+        match (self, other) {
+            (Accumulator::Count(a), Accumulator::Count(b)) => *a += b,
+            (Accumulator::Avg(sum, n), Accumulator::Avg(s, m)) => {
+                *sum = if *sum == Value::Null { s } else if s == Value::Null { sum.clone() } else { db.apply_binop(sum, "+", &s) };
+                *n += m;
+            }
+            (Accumulator::JsonAgg(items), Accumulator::JsonAgg(more)) => items.extend(more),
+            (Accumulator::ArrayAgg(items), Accumulator::ArrayAgg(more)) => items.extend(more),
+            (Accumulator::Sum(total), Accumulator::Sum(Some(v))) => {
+                *total = Some(match total {
+                    Some(t) => db.apply_binop(t, "+", &v),
+                    None => v,
+                });
+            }
+            (acc, Accumulator::Min(v) | Accumulator::Max(v)) => acc.update(db, Some(&v)),
+            _ => {}
+        }
+    }
+
+    fn finish(&self, db: &Database) -> Value {
+        match self {
+            Accumulator::Count(n) => Value::Int(*n),
+            Accumulator::Avg(_, 0) => Value::Null,
+            Accumulator::Avg(sum, n) => db.apply_binop(sum, "/", &Value::Int(*n)),
+            Accumulator::Sum(v) => v.clone().unwrap_or(Value::Null),
+            Accumulator::Min(v) | Accumulator::Max(v) => v.clone(),
+            Accumulator::JsonAgg(items) if items.is_empty() => Value::Null,
+            Accumulator::JsonAgg(items) => Value::Json(Json::Array(items.clone())),
+            Accumulator::ArrayAgg(items) if items.is_empty() => Value::Null,
+            Accumulator::ArrayAgg(items) => Value::Array(items.clone()),
+        }
+    }
+}
+
+/// Expands GROUP BY items into grouping sets: the cross product of the sets
+/// produced by each item, with duplicate expressions removed inside a set.
+fn expand_grouping_sets(items: &[GroupByItem]) -> Vec<Vec<Expr>> {
+    let mut sets: Vec<Vec<Expr>> = vec![Vec::new()];
+    for item in items {
+        let item_sets = item_grouping_sets(item);
+        let mut next = Vec::new();
+        for left in &sets {
+            for right in &item_sets {
+                let mut combined = left.clone();
+                for e in right {
+                    if !combined.contains(e) {
+                        combined.push(e.clone());
+                    }
+                }
+                next.push(combined);
+            }
+        }
+        sets = next;
+    }
+    sets
+}
+
+fn item_grouping_sets(item: &GroupByItem) -> Vec<Vec<Expr>> {
+    match item {
+        GroupByItem::Expr(e) => vec![vec![e.clone()]],
+        GroupByItem::Set(exprs) => vec![exprs.clone()],
+        GroupByItem::Rollup(exprs) => (0..=exprs.len()).rev().map(|n| exprs[..n].to_vec()).collect(),
+        GroupByItem::Cube(exprs) => {
+            // Largest sets first, in the same order as a bitmask countdown
+            let n = exprs.len();
+            (0..1usize << n)
+                .rev()
+                .map(|mask| (0..n).filter(|i| mask & (1 << (n - 1 - i)) != 0).map(|i| exprs[i].clone()).collect())
+                .collect()
+        }
+        GroupByItem::GroupingSets(elems) => elems.iter().flat_map(item_grouping_sets).collect(),
+    }
+}
+
+// ============================================================================
+// Query Executor
+// ============================================================================
+
+/// Tables live in one of two layouts: `tables` holds row tables, `columnar`
+/// holds column-oriented ones. Table names are unique across both. Each
+/// table sits in an `Arc`, so transaction snapshots and MVCC versions share
+/// it; `Arc::make_mut` copies it on the first change while it is shared.
+///
+/// `threads` caps the worker threads a query uses for scans, filters, joins,
+/// aggregation, sorting and projection; it defaults to the available
+/// parallelism, and 1 runs everything on the calling thread. The workers
+/// are kept in a pool and reused from query to query.
+///
+/// A database from `open` is backed by a file and a write-ahead log: every
+/// successful change made through `execute_statement`, `add_table`,
+/// `add_columnar_table` or `set_columnar` is appended to the log, synced as
+/// `sync_policy` says, before the call returns. Once the log passes
+/// `checkpoint_bytes` it is folded into the file. Direct edits to `tables`
+/// or `columnar` are not logged; they reach the file at the next checkpoint.
+pub struct Database {
+    pub tables: HashMap<String, Arc<Table>>,
+    pub columnar: HashMap<String, Arc<ColumnarTable>>,
+    pub threads: usize,
+    pub sync_policy: SyncPolicy,
+    pub checkpoint_bytes: u64,
+    /// Statistics gathered by ANALYZE, by table name.
+    pub statistics: HashMap<String, TableStats>,
+    path: Option<PathBuf>,
+    wal: Option<Wal>,
+    txn: Option<TxnState>,
+    pool: Arc<parallel::Pool>,
+}
+
+/// Tables as they were at BEGIN or at a savepoint, with how much of the
+/// log had been queued by then.
+struct Snapshot {
+    tables: HashMap<String, Arc<Table>>,
+    columnar: HashMap<String, Arc<ColumnarTable>>,
+    logged: usize,
+}
+
+struct TxnState {
+    begin: Snapshot,
+    savepoints: Vec<(String, Snapshot)>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            tables: HashMap::new(),
+            columnar: HashMap::new(),
+            threads: parallel::default_threads(),
+            sync_policy: SyncPolicy::default(),
+            checkpoint_bytes: 4 << 20,
+            statistics: HashMap::new(),
+            path: None,
+            wal: None,
+            txn: None,
+            pool: Arc::default(),
+        }
+    }
+
+    /// Opens the database file at `path`, creating an empty one if it does
+    /// not exist, and replays the committed part of its write-ahead log.
+    /// Fails if the file is not a database or a page checksum does not match.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut db = Database::new();
+        let generation = if path.exists() {
+            let contents = storage::read_file(&path)?;
+            for mut table in contents.tables {
+                table.reindex();
+                db.tables.insert(table.name.clone(), Arc::new(table));
+            }
+            for table in contents.columnar {
+                db.columnar.insert(table.name.clone(), Arc::new(table));
+            }
+            contents.generation
+        } else {
+            // A log left over from an earlier file at this path is not ours
+            let _ = std::fs::remove_file(wal::wal_path(&path));
+            storage::write_file(&path, 0, &[], &[])?;
+            0
+        };
+        let (log, records) = Wal::open(&path, generation)?;
+        for record in records {
+            db.replay(record)?;
+        }
+        db.wal = Some(log);
+        db.path = Some(path);
+        Ok(db)
+    }
+
+    /// The backing file, if the database came from `open`.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Writes every table to the backing file under a new generation and
+    /// empties the log; a no-op for in-memory databases.
+    pub fn checkpoint(&mut self) -> Result<(), String> {
+        if self.txn.is_some() {
+            return Err("Cannot checkpoint inside a transaction".into());
+        }
+        let (Some(path), Some(log)) = (&self.path, &mut self.wal) else { return Ok(()) };
+        let generation = log.generation() + 1;
+        let mut tables: Vec<&Table> = self.tables.values().map(|t| &**t).collect();
+        let mut columnar: Vec<&ColumnarTable> = self.columnar.values().map(|t| &**t).collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        columnar.sort_by(|a, b| a.name.cmp(&b.name));
+        storage::write_file(path, generation, &tables, &columnar)?;
+        log.reset(generation)
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.txn.is_some()
+    }
+
+    /// Starts a transaction whose statements commit or roll back together.
+    /// Use `transaction` for a handle that rolls back when dropped.
+    pub fn begin(&mut self) -> Result<(), String> {
+        if self.txn.is_some() {
+            return Err("A transaction is already in progress".into());
+        }
+        self.txn = Some(TxnState { begin: self.snapshot(), savepoints: Vec::new() });
+        Ok(())
+    }
+
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, String> {
+        self.begin()?;
+        Ok(Transaction::new(self))
+    }
+
+    /// Checks deferred foreign keys and makes the transaction's changes
+    /// durable. If a deferred check fails the whole transaction is rolled
+    /// back.
+    pub fn commit(&mut self) -> Result<(), String> {
+        if self.txn.is_none() {
+            return Err("No transaction in progress".into());
+        }
+        if let Err(e) = self.check_deferred() {
+            self.rollback()?;
+            return Err(format!("{}; transaction rolled back", e));
+        }
+        self.txn = None;
+        self.commit_log()
+    }
+
+    pub fn rollback(&mut self) -> Result<(), String> {
+        let txn = self.txn.take().ok_or("No transaction in progress")?;
+        self.restore(txn.begin);
+        Ok(())
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> Result<(), String> {
+        if self.txn.is_none() {
+            return Err("SAVEPOINT can only be used in a transaction".into());
+        }
+        let snapshot = self.snapshot();
+        self.txn.as_mut().unwrap().savepoints.push((name.to_string(), snapshot));
+        Ok(())
+    }
+
+    /// Undoes everything since the savepoint, which stays defined.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), String> {
+        let txn = self.txn.as_mut().ok_or("ROLLBACK TO SAVEPOINT can only be used in a transaction")?;
+        let i = txn.savepoints.iter().rposition(|(n, _)| n == name).ok_or(format!("Savepoint not found: {}", name))?;
+        txn.savepoints.truncate(i + 1);
+        let snapshot = txn.savepoints.pop().unwrap().1;
+        let kept = Snapshot { tables: snapshot.tables.clone(), columnar: snapshot.columnar.clone(), logged: snapshot.logged };
+        self.restore(snapshot);
+        self.txn.as_mut().unwrap().savepoints.push((name.to_string(), kept));
+        Ok(())
+    }
+
+    /// Forgets the savepoint and any made after it, keeping their changes.
+    pub fn release(&mut self, name: &str) -> Result<(), String> {
+        let txn = self.txn.as_mut().ok_or("RELEASE SAVEPOINT can only be used in a transaction")?;
+        let i = txn.savepoints.iter().rposition(|(n, _)| n == name).ok_or(format!("Savepoint not found: {}", name))?;
+        txn.savepoints.truncate(i);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            tables: self.tables.clone(),
+            columnar: self.columnar.clone(),
+            logged: self.wal.as_ref().map_or(0, |log| log.pending.len()),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.tables = snapshot.tables;
+        self.columnar = snapshot.columnar;
+        if let Some(log) = &mut self.wal {
+            log.noted.clear();
+            log.pending.truncate(snapshot.logged);
+        }
+    }
+
+    /// Checks every deferred foreign key against the current contents.
+    fn check_deferred(&self) -> Result<(), String> {
+        for t in self.stored_tables() {
+            for (constraint, fk) in t.foreign_keys().filter(|(_, fk)| fk.deferred) {
+                let parents: HashSet<GroupKey> = self.stored(&fk.ref_table).map(|p| p.keys(&fk.ref_columns).into_iter().flatten().collect()).unwrap_or_default();
+                self.check_fk_keys(constraint, fk, t.keys(&fk.columns).into_iter().flatten(), &parents)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every constraint of the named tables against their full
+    /// contents, along with the foreign keys pointing into them.
+    fn check_tables(&self, names: &HashSet<&str>) -> Result<(), String> {
+        for t in self.stored_tables() {
+            let named = names.contains(t.name());
+            if named {
+                self.validate_rows(&t.schema(), &|_, _| HashSet::new(), &t.rows())?;
+            }
+            for (constraint, fk) in t.foreign_keys() {
+                if named || names.contains(fk.ref_table.as_str()) {
+                    let parent = self.stored(&fk.ref_table).map_err(|_| format!("Constraint {}: table not found: {}", constraint.name, fk.ref_table))?;
+                    let parents: HashSet<GroupKey> = parent.keys(&fk.ref_columns).into_iter().flatten().collect();
+                    self.check_fk_keys(constraint, fk, t.keys(&fk.columns).into_iter().flatten(), &parents)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deferred foreign keys wait for COMMIT inside a transaction.
+    fn skip_check(&self, fk: &ForeignKey) -> bool {
+        fk.deferred && self.txn.is_some()
+    }
+
+    fn log(&mut self, effect: Effect) {
+        if let Some(log) = &mut self.wal {
+            log.noted.push(effect);
+        }
+    }
+
+    fn logging(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    /// Turns the finished statement's effects into records and writes the
+    /// pending records to the log as one committed unit, unless a
+    /// transaction is open; then they wait for its COMMIT.
+    fn commit_log(&mut self) -> Result<(), String> {
+        let Some(log) = &mut self.wal else { return Ok(()) };
+        let effects = std::mem::take(&mut log.noted);
+        let records: Vec<Record> = effects.into_iter().filter_map(|e| self.log_record(e)).collect();
+        let log = self.wal.as_mut().unwrap();
+        log.pending.extend(records);
+        if self.txn.is_some() {
+            return Ok(());
+        }
+        let records = std::mem::take(&mut log.pending);
+        log.commit(&records, self.sync_policy)?;
+        if log.len() > self.checkpoint_bytes {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn log_record(&self, effect: Effect) -> Option<Record> {
+        let image = |name: &str| match (self.tables.get(name), self.columnar.get(name)) {
+            (Some(t), _) => Some((Table::clone(t), false)),
+            (_, Some(t)) => Some((t.to_table(), true)),
+            _ => None,
+        };
+        match effect {
+            Effect::Insert(name, rows) => Some(Record::Insert(name, rows)),
+            Effect::Update(name, rows) => Some(Record::Update(name, rows)),
+            Effect::Delete(name, positions) => Some(Record::Delete(name, positions)),
+            Effect::Table(name) => image(&name).map(|(t, columnar)| Record::Table(t, columnar)),
+            Effect::Drop(name) => Some(Record::Drop(name)),
+        }
+    }
+
+    /// Applies a logged change during recovery.
+    fn replay(&mut self, record: Record) -> Result<(), String> {
+        match record {
+            Record::Insert(name, rows) => {
+                if let Some(t) = self.tables.get_mut(&name).map(Arc::make_mut) {
+                    t.append(rows);
+                } else if let Some(t) = self.columnar.get_mut(&name).map(Arc::make_mut) {
+                    for row in &rows {
+                        t.push_row(row)?;
+                    }
+                }
+            }
+            Record::Update(name, rows) => {
+                let len = self.stored(&name).map_or(0, |t| t.len());
+                if let Some((i, _)) = rows.iter().find(|(i, _)| *i >= len) {
+                    return Err(format!("Corrupt log: no row {} in {}", i, name));
+                }
+                if let Some(t) = self.tables.get_mut(&name).map(Arc::make_mut) {
+                    t.update(rows.into_iter().collect());
+                } else if let Some(t) = self.columnar.get_mut(&name).map(Arc::make_mut) {
+                    for (i, row) in &rows {
+                        t.set_row(*i, row)?;
+                    }
+                }
+            }
+            Record::Delete(name, positions) => {
+                let len = self.stored(&name).map_or(0, |t| t.len());
+                if let Some(i) = positions.iter().find(|&&i| i >= len) {
+                    return Err(format!("Corrupt log: no row {} in {}", i, name));
+                }
+                let deleted: HashSet<usize> = positions.into_iter().collect();
+                if let Some(t) = self.tables.get_mut(&name).map(Arc::make_mut) {
+                    t.delete(&deleted);
+                } else if let Some(t) = self.columnar.get_mut(&name).map(Arc::make_mut) {
+                    let keep: Vec<bool> = (0..t.len()).map(|i| !deleted.contains(&i)).collect();
+                    t.retain(&keep);
+                }
+            }
+            Record::Table(mut table, columnar) => {
+                table.reindex();
+                self.tables.remove(&table.name);
+                self.columnar.remove(&table.name);
+                if columnar {
+                    self.columnar.insert(table.name.clone(), Arc::new(ColumnarTable::from_table(&table)?));
+                } else {
+                    self.tables.insert(table.name.clone(), Arc::new(table));
+                }
+            }
+            Record::Drop(name) => {
+                self.tables.remove(&name);
+                self.columnar.remove(&name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_table(&self, name: &str) -> bool {
+        self.tables.contains_key(name) || self.columnar.contains_key(name)
+    }
+
+    /// Adds a column-oriented table, checking it exactly like `add_table`.
+    pub fn add_columnar_table(&mut self, table: ColumnarTable) -> Result<(), String> {
+        let name = table.name.clone();
+        if self.tables.contains_key(&name) {
+            return Err(format!("Table already exists: {}", name));
+        }
+        self.insert_table(table.to_table())?;
+        self.tables.remove(&name);
+        self.columnar.insert(name.clone(), Arc::new(table));
+        self.log(Effect::Table(name));
+        self.commit_log()
+    }
+
+    /// Moves a table between the row and columnar layouts.
+    pub fn set_columnar(&mut self, name: &str, columnar: bool) -> Result<(), String> {
+        self.convert_table(name, columnar)?;
+        self.log(Effect::Table(name.to_string()));
+        self.commit_log()
+    }
+
+    fn convert_table(&mut self, name: &str, columnar: bool) -> Result<(), String> {
+        if columnar {
+            if let Some(table) = self.tables.get(name) {
+                if let Some(index) = table.indexes.first() {
+                    return Err(format!("Cannot make {} columnar: drop index {} first", name, index.name));
+                }
+                let converted = ColumnarTable::from_table(table)?;
+                self.tables.remove(name);
+                self.columnar.insert(name.to_string(), Arc::new(converted));
+            }
+        } else if let Some(table) = self.columnar.remove(name) {
+            self.tables.insert(name.to_string(), Arc::new(table.to_table()));
+        }
+        if self.has_table(name) { Ok(()) } else { Err(format!("Table not found: {}", name)) }
+    }
+
+    fn source(&self, name: &str) -> Result<Source<'_>, String> {
+        if let Some(t) = self.tables.get(name) {
+            Ok(Source::Rows(t))
+        } else if let Some(t) = self.columnar.get(name) {
+            Ok(Source::Columnar(t))
+        } else {
+            Err(format!("Table not found: {}", name))
+        }
+    }
+
+    /// Adds a table, coercing its rows to the declared column types and
+    /// checking every constraint.
+    pub fn add_table(&mut self, table: Table) -> Result<(), String> {
+        let name = table.name.clone();
+        self.insert_table(table)?;
+        self.log(Effect::Table(name));
+        self.commit_log()
+    }
+
+    fn insert_table(&mut self, mut table: Table) -> Result<(), String> {
+        let rows = std::mem::take(&mut table.rows);
+        let mut checked = Vec::with_capacity(rows.len());
+        for mut row in rows {
+            for col in &table.columns {
+                if let Some(v) = row.data.remove(&col.name) {
+                    row.data.insert(col.name.clone(), table.coerce(&col.name, v)?);
+                }
+            }
+            checked.push(row);
+        }
+        self.validate_rows(&table, &|_, _| HashSet::new(), &checked)?;
+        table.rows = checked;
+        table.reindex();
+
+        let name = table.name.clone();
+        let previous = self.tables.insert(name.clone(), Arc::new(table));
+        if let Err(e) = self.resolve_foreign_keys(&name).and_then(|_| self.check_foreign_keys(&HashMap::new(), Some(&name))) {
+            match previous {
+                Some(t) => self.tables.insert(name, t),
+                None => self.tables.remove(&name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn execute(&self, query: &Query) -> Result<ResultSet, String> {
+        let collations = self.collations(query)?;
+        let query = &*collations.resolve(query);
+        let Plan { sources, grouped, scan: index_scan, joins } = self.plan(query)?;
+        // With joins, rows also carry `table.col` keys so same-named columns
+        // from different tables stay distinct
+        let qualify = sources.len() > 1;
+        let mut filtered = false;
+        let sorted = index_scan.as_ref().is_some_and(|s| s.order.is_some());
+        let mut rows = match (sources[0], &index_scan) {
+            (Source::Columnar(table), _) if !qualify => {
+                filtered = true;
+                self.scan_columnar(table, query)
+            }
+            (_, Some(index_scan)) if index_scan.covering => {
+                filtered = true;
+                self.scan_index(index_scan, query.where_clause.as_ref())
+            }
+            (Source::Rows(table), _) if !qualify => {
+                filtered = true;
+                let positions = index_scan.as_ref().map(IndexScan::positions);
+                self.scan_rows(table, positions.as_deref(), query.where_clause.as_ref())
+            }
+            _ => self.join_input(sources[joins.first], joins.restore.then_some(joins.first), joins.filter.as_ref()),
+        };
+
+        // Apply JOINs
+        for step in &joins.steps {
+            let tag = joins.restore.then_some(step.source);
+            let right = || self.join_input(sources[step.source], tag, step.filter.as_ref());
+            rows = match &step.strategy {
+                JoinPlan::Index(table, lookup) => self.index_join(rows, table, lookup, &step.on, tag),
+                JoinPlan::Hash { keys, build_left } => self.join_rows(rows, &right(), &step.on, keys, *build_left),
+                JoinPlan::NestedLoop => self.join_rows(rows, &right(), &step.on, &[], false),
+                JoinPlan::Unnest(unnest) => self.unnest_join(rows, unnest, &step.on),
+            };
+        }
+        if joins.restore {
+            rows = self.restore_join_order(rows, sources.len());
+        }
+        filtered |= joins.optimized;
+
+        // Apply WHERE clause
+        if let (Some(where_expr), false) = (&query.where_clause, filtered) {
+            let morsels = self.pool.map_owned(self.threads, parallel::into_morsels(rows), |mut morsel| {
+                morsel.retain(|row| self.eval_expr(where_expr, row).is_true());
+                morsel
+            });
+            rows = morsels.into_iter().flatten().collect();
+        }
+
+        // Apply GROUP BY (and aggregates over the whole input)
+        if grouped {
+            rows = self.apply_group_by(&rows, query, &collations)?;
+        }
+
+        // Apply ORDER BY, unless an index scan already produced that order
+        if !query.order_by.is_empty() && !sorted {
+            let cmp = |a: &Row, b: &Row| {
+                for (col, is_asc, collation) in &query.order_by {
+                    let av = lookup(a, col).unwrap_or(&Value::Null);
+                    let bv = lookup(b, col).unwrap_or(&Value::Null);
+                    let c = collation.unwrap_or_else(|| collations.column(col));
+                    let cmp = c.key(av).cmp(&c.key(bv));
+                    if cmp != std::cmp::Ordering::Equal {
+                        return if *is_asc { cmp } else { cmp.reverse() };
+                    }
+                }
+                std::cmp::Ordering::Equal
+            };
+            rows = self.pool.sort_by(self.threads, rows, &cmp);
+        }
+
+        // Apply LIMIT, after DISTINCT when there is one
+        if let Some(l) = query.limit.filter(|_| !query.distinct) {
+            rows.truncate(l);
+        }
+
+        // Apply SELECT projection; grouped rows already hold the outputs by
+        // name and `*` expands to every source column in declared order
+        let mut columns = Vec::new();
+        for (name, expr) in query.select_cols.iter().zip(&query.select_exprs) {
+            if name == "*" {
+                for table in &sources {
+                    columns.extend(table.columns().iter().map(|c| ResultColumn { name: c.name.clone(), data_type: c.data_type.clone() }));
+                }
+            } else {
+                columns.push(ResultColumn { name: name.clone(), data_type: infer_type(expr, &sources) });
+            }
+        }
+        let mut result = ResultSet::new(columns);
+        let width = result.columns.len();
+        let project = |row: &Row| {
+            let mut values = Vec::with_capacity(width);
+            for (name, expr) in query.select_cols.iter().zip(&query.select_exprs) {
+                if name == "*" {
+                    for table in &sources {
+                        for c in table.columns() {
+                            let key = if qualify { format!("{}.{}", table.name(), c.name) } else { c.name.clone() };
+                            values.push(row.data.get(&key).cloned().unwrap_or(Value::Null));
+                        }
+                    }
+                } else if grouped {
+                    values.push(lookup(row, name).cloned().unwrap_or(Value::Null));
+                } else {
+                    values.push(self.eval_expr(expr, row));
+                }
+            }
+            values
+        };
+        let morsels = self.pool.map_morsels(self.threads, rows.len(), |range| rows[range].iter().map(project).collect::<Vec<_>>());
+        result.rows = morsels.into_iter().flatten().collect();
+        // DISTINCT keeps the first of each set of equal rows, in order,
+        // comparing each output under its collation
+        if query.distinct {
+            let mut keys = Vec::with_capacity(width);
+            for (name, expr) in query.select_cols.iter().zip(&query.select_exprs) {
+                if name == "*" {
+                    keys.extend(sources.iter().flat_map(|table| table.columns().iter().map(|c| c.collation)));
+                } else {
+                    keys.push(collations.of(expr));
+                }
+            }
+            let mut seen = HashSet::new();
+            result.rows.retain(|row| seen.insert(row.iter().zip(&keys).map(|(v, c)| c.key(v).into_owned()).collect::<Vec<_>>()));
+            if let Some(l) = query.limit {
+                result.rows.truncate(l);
+            }
+        }
+        refine_types(&mut result);
+        Ok(result)
+    }
+
+    /// Collations of the columns of the tables `query` reads; fails when
+    /// it reads a plain column that joined tables collate differently.
+    fn collations(&self, query: &Query) -> Result<Collations, String> {
+        let mut collations = Collations::default();
+        for name in std::iter::once(&query.from_table).chain(query.joins.iter().map(|j| &j.table)) {
+            if let Ok(source) = self.source(name) {
+                collations.add(source.name(), source.columns());
+            }
+        }
+        referenced_columns(query).iter().try_for_each(|name| collations.check(name))?;
+        Ok(collations)
+    }
+
+    /// Decides how `execute` reads the query's tables: the index for a lone
+    /// row table; with joins, the order to join in (by cost when every table
+    /// has statistics, otherwise as written) and for each join an index
+    /// lookup, a hash join or a nested loop.
+    fn plan<'a>(&'a self, query: &'a Query) -> Result<Plan<'a>, String> {
+        let mut sources = vec![match &query.from_unnest {
+            Some(unnest) => Source::Unnest(unnest),
+            None => self.source(&query.from_table)?,
+        }];
+        for join in &query.joins {
+            sources.push(match &join.unnest {
+                Some(unnest) => Source::Unnest(unnest),
+                None => self.source(&join.table)?,
+            });
+        }
+        let grouped = !query.group_by.is_empty() || query.select_exprs.iter().any(contains_aggregate);
+        let scan = match sources[..] {
+            [Source::Rows(table)] => index::plan_scan(self, table, query, !grouped),
+            _ => None,
+        };
+        if let Some(joins) = optimizer::plan_joins(self, query, &sources) {
+            return Ok(Plan { sources, grouped, scan, joins });
+        }
+        let mut steps = Vec::new();
+        for (i, (join, join_table)) in query.joins.iter().zip(&sources[1..]).enumerate() {
+            if let Source::Unnest(unnest) = join_table {
+                steps.push(JoinStep { source: i + 1, on: join.on.clone(), filter: None, strategy: JoinPlan::Unnest(unnest), rows: None });
+                continue;
+            }
+            let keys = equi_join_keys(&join.on, &sources[..=i], *join_table);
+            let lookup = match join_table {
+                Source::Rows(table) => index::plan_lookup(table, &keys).map(|lookup| JoinPlan::Index(table, lookup)),
+                Source::Columnar(_) | Source::Unnest(_) => None,
+            };
+            let strategy = match lookup {
+                Some(lookup) => lookup,
+                None if keys.is_empty() => JoinPlan::NestedLoop,
+                None => JoinPlan::Hash { keys, build_left: false },
+            };
+            steps.push(JoinStep { source: i + 1, on: join.on.clone(), filter: None, strategy, rows: None });
+        }
+        let joins = Joins { first: 0, filter: None, steps, optimized: false, restore: false };
+        Ok(Plan { sources, grouped, scan, joins })
+    }
+
+    /// Scans a columnar table in batches of `BATCH_SIZE` rows: WHERE runs as
+    /// a compiled kernel over the column vectors, narrowing a selection
+    /// vector, and only the selected rows are materialized, with just the
+    /// columns the query references.
+    fn scan_columnar(&self, table: &ColumnarTable, query: &Query) -> Vec<Row> {
+        let needed: Vec<usize> = match query_columns(query) {
+            None => (0..table.columns.len()).collect(),
+            Some(names) => (0..table.columns.len())
+                .filter(|&i| names.iter().any(|n| columnar_name_matches(table, i, n) || field_path(n, &table.name, &table.columns[i].name).is_some()))
+                .collect(),
+        };
+        let filter = query.where_clause.as_ref().map(|e| Kernel::compile(e, table));
+        let morsels = self.pool.map_morsels(self.threads, table.len(), |morsel| {
+            let mut rows = Vec::new();
+            for start in morsel.clone().step_by(BATCH_SIZE) {
+                let mut sel: Vec<usize> = (start..morsel.end.min(start + BATCH_SIZE)).collect();
+                if let Some(ref kernel) = filter {
+                    sel = kernel.filter(self, table, sel);
+                }
+                for index in sel {
+                    let data = needed.iter().map(|&c| (table.columns[c].name.clone(), table.get(index, c))).collect();
+                    rows.push(Row { data });
+                }
+            }
+            rows
+        });
+        morsels.into_iter().flatten().collect()
+    }
+
+    /// Scans a row table, copying out only the rows that pass `filter`.
+    /// Given index `positions`, visits just those rows, in that order.
+    fn scan_rows(&self, table: &Table, positions: Option<&[usize]>, filter: Option<&Expr>) -> Vec<Row> {
+        let len = positions.map_or(table.rows.len(), |p| p.len());
+        let morsels = self.pool.map_morsels(self.threads, len, |morsel| {
+            morsel
+                .map(|i| &table.rows[positions.map_or(i, |p| p[i])])
+                .filter(|row| filter.is_none_or(|e| self.eval_expr(e, row).is_true()))
+                .cloned()
+                .collect::<Vec<_>>()
+        });
+        morsels.into_iter().flatten().collect()
+    }
+
+    /// Index-only scan: the rows the index selects, built from the values it
+    /// holds, that pass `filter`.
+    fn scan_index(&self, scan: &IndexScan, filter: Option<&Expr>) -> Vec<Row> {
+        let positions = scan.positions();
+        let morsels = self.pool.map_morsels(self.threads, positions.len(), |morsel| {
+            positions[morsel]
+                .iter()
+                .map(|&p| scan.index.covered_row(p))
+                .filter(|row| filter.is_none_or(|e| self.eval_expr(e, row).is_true()))
+                .collect::<Vec<_>>()
+        });
+        morsels.into_iter().flatten().collect()
+    }
+
+    /// Rows of a joined source, qualified, narrowed by `filter`, and
+    /// tagged with their positions under `tag` when the join order is to be
+    /// restored.
+    fn join_input(&self, source: Source, tag: Option<usize>, filter: Option<&Expr>) -> Vec<Row> {
+        let mut rows = match source {
+            Source::Unnest(unnest) => self.unnest(&Row { data: HashMap::new() }, unnest),
+            _ => scan(source, true),
+        };
+        if let Some(tag) = tag {
+            for (i, row) in rows.iter_mut().enumerate() {
+                row.data.insert(position_key(tag), Value::Int(i as i64));
+            }
+        }
+        if let Some(filter) = filter {
+            let morsels = self.pool.map_owned(self.threads, parallel::into_morsels(rows), |mut morsel| {
+                morsel.retain(|row| self.eval_expr(filter, row).is_true());
+                morsel
+            });
+            rows = morsels.into_iter().flatten().collect();
+        }
+        rows
+    }
+
+    /// The rows UNNEST makes from `row`: one per element of its value, under
+    /// both the plain and the qualified column name.
+    fn unnest(&self, row: &Row, unnest: &Unnest) -> Vec<Row> {
+        let name = &unnest.column.name;
+        let elements = composite::elements(self.eval_expr(&unnest.expr, row));
+        elements.into_iter().map(|v| Row { data: HashMap::from([(format!("{}.{}", name, name), v.clone()), (name.clone(), v)]) }).collect()
+    }
+
+    /// Joins each left row with the rows UNNEST makes from it, keeping the
+    /// pairs that satisfy `on`; rows whose value has no elements drop out.
+    fn unnest_join(&self, left: Vec<Row>, unnest: &Unnest, on: &Expr) -> Vec<Row> {
+        let morsels = self.pool.map_owned(self.threads, parallel::into_morsels(left), |morsel| {
+            let mut out = Vec::new();
+            for l in morsel {
+                for r in self.unnest(&l, unnest) {
+                    let mut merged = l.clone();
+                    merged.data.extend(r.data);
+                    if self.eval_expr(on, &merged).is_true() {
+                        out.push(merged);
+                    }
+                }
+            }
+            out
+        });
+        morsels.into_iter().flatten().collect()
+    }
+
+    /// Sorts joined rows by their sources' positions, in textual order, which
+    /// is the order joining as written produces, and drops the positions.
+    fn restore_join_order(&self, rows: Vec<Row>, sources: usize) -> Vec<Row> {
+        let keys: Vec<String> = (0..sources).map(position_key).collect();
+        let position = |row: &Row, key: &String| match row.data.get(key) {
+            Some(Value::Int(i)) => *i,
+            _ => unreachable!("joined rows carry every position"),
+        };
+        let cmp = |a: &Row, b: &Row| keys.iter().map(|k| position(a, k).cmp(&position(b, k))).find(|o| o.is_ne()).unwrap_or(std::cmp::Ordering::Equal);
+        let mut rows = self.pool.sort_by(self.threads, rows, &cmp);
+        for row in &mut rows {
+            for key in &keys {
+                row.data.remove(key);
+            }
+        }
+        rows
+    }
+
+    /// Index nested-loop join: each left row looks up its candidate rows of
+    /// `table` in the index, in table order, and each pair is checked
+    /// against the full condition. Joined rows carry their position under
+    /// `tag` if given.
+    fn index_join(&self, left: Vec<Row>, table: &Table, lookup: &IndexLookup, on: &Expr, tag: Option<usize>) -> Vec<Row> {
+        let morsels = self.pool.map_morsels(self.threads, left.len(), |morsel| {
+            let mut out = Vec::new();
+            for l in &left[morsel] {
+                for i in lookup.positions(self, l) {
+                    let mut merged = l.clone();
+                    merged.data.extend(qualified(&table.rows[i], &table.name).data);
+                    if let Some(tag) = tag {
+                        merged.data.insert(position_key(tag), Value::Int(i as i64));
+                    }
+                    if self.eval_expr(on, &merged).is_true() {
+                        out.push(merged);
+                    }
+                }
+            }
+            out
+        });
+        morsels.into_iter().flatten().collect()
+    }
+
+    /// Joins `left` with `right` on `on`, keeping the nested-loop output
+    /// order. With equality `keys` this is a hash join: right rows are keyed
+    /// in parallel and indexed, then left morsels probe in parallel, and
+    /// each candidate pair is checked against the full condition. With
+    /// `build_left` the left rows are indexed and right morsels probe, so
+    /// the output follows the right rows instead.
+    fn join_rows(&self, left: Vec<Row>, right: &[Row], on: &Expr, keys: &[(Expr, Expr)], build_left: bool) -> Vec<Row> {
+        let matches = |l: &Row, r: &Row| {
+            let mut merged = l.clone();
+            merged.data.extend(r.data.clone());
+            self.eval_expr(on, &merged).is_true().then_some(merged)
+        };
+        let morsels = if keys.is_empty() {
+            self.pool.map_morsels(self.threads, left.len(), |morsel| {
+                left[morsel].iter().flat_map(|l| right.iter().filter_map(move |r| matches(l, r))).collect::<Vec<_>>()
+            })
+        } else if build_left {
+            let index = self.hash_rows(&left, keys, false);
+            self.pool.map_morsels(self.threads, right.len(), |morsel| {
+                let mut out = Vec::new();
+                for r in &right[morsel] {
+                    let candidates = self.join_key(r, keys, true).and_then(|key| index.get(&key));
+                    out.extend(candidates.into_iter().flatten().filter_map(|&i| matches(&left[i], r)));
+                }
+                out
+            })
+        } else {
+            let index = self.hash_rows(right, keys, true);
+            self.pool.map_morsels(self.threads, left.len(), |morsel| {
+                let mut out = Vec::new();
+                for l in &left[morsel] {
+                    let candidates = self.join_key(l, keys, false).and_then(|key| index.get(&key));
+                    out.extend(candidates.into_iter().flatten().filter_map(|&i| matches(l, &right[i])));
+                }
+                out
+            })
+        };
+        morsels.into_iter().flatten().collect()
+    }
+
+    /// Positions of `rows` by their hash key on the `joined` side of
+    /// `keys`, keyed in parallel; rows with a NULL key part are left out.
+    fn hash_rows(&self, rows: &[Row], keys: &[(Expr, Expr)], joined: bool) -> HashMap<Vec<Value>, Vec<usize>> {
+        let row_keys = self.pool.map_morsels(self.threads, rows.len(), |morsel| {
+            rows[morsel].iter().map(|r| self.join_key(r, keys, joined)).collect::<Vec<_>>()
+        });
+        let mut index: HashMap<Vec<Value>, Vec<usize>> = HashMap::new();
+        for (i, key) in row_keys.into_iter().flatten().enumerate() {
+            if let Some(key) = key {
+                index.entry(key).or_default().push(i);
+            }
+        }
+        index
+    }
+
+    /// Hash key of the earlier or `joined` side of an equi-join, each part
+    /// under the collation of its equality; `None` if any part is NULL,
+    /// since such a row cannot satisfy the equality.
+    fn join_key(&self, row: &Row, keys: &[(Expr, Expr)], joined: bool) -> Option<Vec<Value>> {
+        let part = |(l, r): &(Expr, Expr)| {
+            let collation = collation::of_comparison(l, "=", r).unwrap_or_default();
+            let v = self.eval_expr(if joined { r } else { l }, row);
+            Some(collation.into_key(v)).filter(|v| !holds_null(v))
+        };
+        keys.iter().map(part).collect()
+    }
+
+    /// Runs any statement. DML validates and evaluates every affected row
+    /// before touching the table, so a failing statement changes nothing.
+    pub fn execute_statement(&mut self, stmt: &Statement) -> Result<StatementResult, String> {
+        let control = match stmt {
+            Statement::Begin => Some(self.begin()),
+            Statement::Commit => Some(self.commit()),
+            Statement::Rollback => Some(self.rollback()),
+            Statement::Savepoint(name) => Some(self.savepoint(name)),
+            Statement::RollbackTo(name) => Some(self.rollback_to(name)),
+            Statement::Release(name) => Some(self.release(name)),
+            _ => None,
+        };
+        if let Some(result) = control {
+            return result.map(|_| StatementResult::empty());
+        }
+        // COPY runs as the statements it stands for
+        match stmt {
+            Statement::CopyFrom { table, path, options } => return Ok(StatementResult { rows_affected: self.load_csv(table, path, options)?, rows: ResultSet::default() }),
+            Statement::CopyTo { query, path, options } => return Ok(StatementResult { rows_affected: self.write_csv(query, path, options)?, rows: ResultSet::default() }),
+            _ => {}
+        }
+        let logged = self.wal.as_ref().map_or(0, |log| log.noted.len());
+        let result = self.run_statement(stmt);
+        match &result {
+            Ok(_) => {
+                for effect in self.schema_effects(stmt) {
+                    self.log(effect);
+                }
+            }
+            Err(_) => {
+                if let Some(log) = &mut self.wal {
+                    log.noted.truncate(logged);
+                }
+            }
+        }
+        if result.is_ok() && !matches!(stmt, Statement::Select(_) | Statement::Explain(_) | Statement::Analyze(_)) {
+            self.commit_log()?;
+        }
+        result
+    }
+
+    /// Log entries for a schema change: the tables whose definitions it
+    /// changed, including foreign keys elsewhere that follow a rename.
+    fn schema_effects(&self, stmt: &Statement) -> Vec<Effect> {
+        match stmt {
+            Statement::CreateTable { name, .. } => vec![Effect::Table(name.clone())],
+            Statement::DropTable { name, .. } => vec![Effect::Drop(name.clone())],
+            Statement::CreateIndex { table, .. } => vec![Effect::Table(table.clone())],
+            Statement::AlterTable { name, action } => {
+                let (mut effects, target) = match action {
+                    AlterAction::RenameTable(new_name) => (vec![Effect::Drop(name.clone())], new_name),
+                    _ => (Vec::new(), name),
+                };
+                effects.push(Effect::Table(target.clone()));
+                for (child, _) in self.referencing(target) {
+                    if child != *target {
+                        effects.push(Effect::Table(child));
+                    }
+                }
+                effects
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn run_statement(&mut self, stmt: &Statement) -> Result<StatementResult, String> {
+        match stmt {
+            Statement::Select(query) => {
+                let rows = self.execute(query)?;
+                Ok(StatementResult { rows_affected: rows.len(), rows })
+            }
+            Statement::Explain(query) => Ok(StatementResult { rows_affected: 0, rows: self.explain(query)? }),
+            Statement::Analyze(table) => {
+                self.analyze(table.as_deref())?;
+                Ok(StatementResult::empty())
+            }
+            Statement::Insert(insert) => self.execute_insert(insert),
+            Statement::Update(update) => self.execute_update(update),
+            Statement::Delete(delete) => self.execute_delete(delete),
+            Statement::CreateTable { name, columns, constraints, if_not_exists, columnar } => {
+                if self.has_table(name) {
+                    return if *if_not_exists { Ok(StatementResult::empty()) } else { Err(format!("Table already exists: {}", name)) };
+                }
+                for (i, col) in columns.iter().enumerate() {
+                    if columns[..i].iter().any(|c| c.name == col.name) {
+                        return Err(format!("Duplicate column: {}", col.name));
+                    }
+                }
+                let table = Table { constraints: constraints.clone(), ..Table::new(name, columns.clone()) };
+                self.insert_table(table)?;
+                if *columnar {
+                    self.convert_table(name, true)?;
+                }
+                Ok(StatementResult::empty())
+            }
+            Statement::DropTable { name, if_exists } => {
+                if let Some((child, constraint)) = self.referencing(name).into_iter().find(|(child, _)| child != name) {
+                    return Err(format!("Cannot drop table {}: constraint {} on {} references it", name, constraint.name, child));
+                }
+                if self.tables.remove(name).is_none() && self.columnar.remove(name).is_none() && !*if_exists {
+                    return Err(format!("Table not found: {}", name));
+                }
+                self.statistics.remove(name);
+                Ok(StatementResult::empty())
+            }
+            Statement::AlterTable { name, action } => self.alter_table(name, action),
+            Statement::CreateIndex { name, table, columns, unique, kind, include, if_not_exists } => {
+                if self.tables.values().any(|t| t.indexes.iter().any(|i| i.name == *name)) {
+                    return if *if_not_exists { Ok(StatementResult::empty()) } else { Err(format!("Index already exists: {}", name)) };
+                }
+                if self.columnar.contains_key(table) {
+                    return Err(format!("Cannot index columnar table {}", table));
+                }
+                let t = self.tables.get(table).ok_or(format!("Table not found: {}", table))?;
+                for col in columns.iter().chain(include) {
+                    t.column(col).ok_or(format!("Column not found: {}.{}", table, col))?;
+                }
+                let mut index = Index::new(name, columns.clone(), *unique);
+                index.kind = *kind;
+                index.include = include.clone();
+                if *unique {
+                    let keyed = Table { name: t.name.clone(), columns: t.columns.clone(), indexes: vec![index.clone()], ..Table::default() };
+                    self.validate_rows(&keyed, &|_, _| HashSet::new(), &t.rows)?;
+                }
+                let t = self.tables.get_mut(table).map(Arc::make_mut).unwrap();
+                t.indexes.push(index);
+                t.reindex();
+                Ok(StatementResult::empty())
+            }
+            Statement::DropIndex { name, if_exists } => {
+                match self.tables.values_mut().find(|t| t.indexes.iter().any(|i| i.name == *name)) {
+                    Some(t) => {
+                        let t = Arc::make_mut(t);
+                        t.indexes.retain(|i| i.name != *name);
+                        // Logged here: afterwards nothing tells which table held it
+                        let table = t.name.clone();
+                        self.log(Effect::Table(table));
+                    }
+                    None if *if_exists => {}
+                    None => return Err(format!("Index not found: {}", name)),
+                }
+                Ok(StatementResult::empty())
+            }
+            _ => Err("Transaction commands are handled by execute_statement".into()),
+        }
+    }
+
+    /// ALTER TABLE. A columnar table is altered in row form and its vectors
+    /// rebuilt; if either step fails it is left as it was.
+    fn alter_table(&mut self, name: &str, action: &AlterAction) -> Result<StatementResult, String> {
+        let Some(original) = self.columnar.remove(name) else { return self.execute_alter(name, action) };
+        self.tables.insert(name.to_string(), Arc::new(original.to_table()));
+        let result = self.execute_alter(name, action);
+        let target = match (action, &result) {
+            (AlterAction::RenameTable(new_name), Ok(_)) => new_name.as_str(),
+            _ => name,
+        };
+        let table = self.tables.remove(target).ok_or(format!("Table not found: {}", target))?;
+        match result.and_then(|r| ColumnarTable::from_table(&table).map(|c| (r, c))) {
+            Ok((result, columnar)) => {
+                self.columnar.insert(target.to_string(), Arc::new(columnar));
+                Ok(result)
+            }
+            Err(e) => {
+                self.columnar.insert(name.to_string(), original);
+                Err(e)
+            }
+        }
+    }
+
+    fn execute_alter(&mut self, name: &str, action: &AlterAction) -> Result<StatementResult, String> {
+        if let AlterAction::RenameTable(new_name) = action {
+            if self.has_table(new_name) {
+                return Err(format!("Table already exists: {}", new_name));
+            }
+            let mut table = self.tables.remove(name).ok_or(format!("Table not found: {}", name))?;
+            Arc::make_mut(&mut table).name = new_name.clone();
+            self.tables.insert(new_name.clone(), table);
+            if let Some(stats) = self.statistics.remove(name) {
+                self.statistics.insert(new_name.clone(), stats);
+            }
+            for constraints in self.referencing_mut(name) {
+                for c in constraints {
+                    if let ConstraintKind::ForeignKey(fk) = &mut c.kind {
+                        if fk.ref_table == name {
+                            fk.ref_table = new_name.clone();
+                        }
+                    }
+                }
+            }
+            return Ok(StatementResult::empty());
+        }
+
+        if let AlterAction::AddColumn(col, constraints) = action {
+            // Build the altered table aside and validate it before swapping it in
+            let table = self.tables.get(name).ok_or(format!("Table not found: {}", name))?;
+            if table.column(&col.name).is_some() {
+                return Err(format!("Column already exists: {}.{}", name, col.name));
+            }
+            let mut altered = Table { rows: Vec::new(), ..Table::clone(table) };
+            altered.columns.push(col.clone());
+            altered.constraints.extend(constraints.iter().cloned());
+            let mut rows = table.rows.clone();
+            let mut next_id = table.next_identity(&col.name);
+            for row in &mut rows {
+                let value = if col.identity.is_some() {
+                    next_id += 1;
+                    Value::Int(next_id - 1)
+                } else {
+                    match &col.default {
+                        Some(expr) => col.data_type.coerce(self.eval_expr(expr, &Row { data: HashMap::new() }))?,
+                        None => Value::Null,
+                    }
+                };
+                row.data.insert(col.name.clone(), value);
+            }
+            self.validate_rows(&altered, &|_, _| HashSet::new(), &rows)?;
+            altered.rows = rows;
+            altered.reindex();
+            let previous = self.tables.insert(name.to_string(), Arc::new(altered));
+            if let Err(e) = self.resolve_foreign_keys(name).and_then(|_| self.check_foreign_keys(&HashMap::new(), Some(name))) {
+                self.tables.insert(name.to_string(), previous.unwrap());
+                return Err(e);
+            }
+            return Ok(StatementResult::empty());
+        }
+
+        let column = match action {
+            AlterAction::DropColumn(col) | AlterAction::RenameColumn(col, _) => col,
+            _ => unreachable!(),
+        };
+        let referenced = self
+            .referencing(name)
+            .into_iter()
+            .find(|(_, c)| matches!(&c.kind, ConstraintKind::ForeignKey(fk) if fk.ref_columns.contains(column)));
+        if let (AlterAction::DropColumn(_), Some((child, constraint))) = (action, &referenced) {
+            return Err(format!("Cannot drop column {}.{}: constraint {} on {} references it", name, column, constraint.name, child));
+        }
+        if let AlterAction::RenameColumn(from, to) = action {
+            for constraints in self.referencing_mut(name) {
+                for c in constraints {
+                    if let ConstraintKind::ForeignKey(fk) = &mut c.kind {
+                        if fk.ref_table == name {
+                            fk.ref_columns.iter_mut().filter(|c| *c == from).for_each(|c| *c = to.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        let table = self.tables.get_mut(name).map(Arc::make_mut).ok_or(format!("Table not found: {}", name))?;
+        match action {
+            AlterAction::AddColumn(..) => unreachable!(),
+            AlterAction::DropColumn(col) => {
+                let pos = table.columns.iter().position(|c| &c.name == col).ok_or(format!("Column not found: {}.{}", name, col))?;
+                table.columns.remove(pos);
+                table.constraints.retain(|c| !c.columns().contains(col));
+                table.indexes.retain(|i| !i.columns.contains(col));
+                for row in &mut table.rows {
+                    row.data.remove(col);
+                }
+            }
+            AlterAction::RenameColumn(from, to) => {
+                if table.column(to).is_some() {
+                    return Err(format!("Column already exists: {}.{}", name, to));
+                }
+                let col = table.columns.iter_mut().find(|c| &c.name == from).ok_or(format!("Column not found: {}.{}", name, from))?;
+                col.name = to.clone();
+                for constraint in &mut table.constraints {
+                    match &mut constraint.kind {
+                        ConstraintKind::PrimaryKey(cols) | ConstraintKind::Unique(cols) => {
+                            cols.iter_mut().filter(|c| *c == from).for_each(|c| *c = to.clone());
+                        }
+                        ConstraintKind::Check(expr) => rename_column(expr, from, to),
+                        ConstraintKind::ForeignKey(fk) => {
+                            fk.columns.iter_mut().filter(|c| *c == from).for_each(|c| *c = to.clone());
+                        }
+                    }
+                }
+                for index in &mut table.indexes {
+                    index.columns.iter_mut().filter(|c| *c == from).for_each(|c| *c = to.clone());
+                }
+                for row in &mut table.rows {
+                    if let Some(v) = row.data.remove(from) {
+                        row.data.insert(to.clone(), v);
+                    }
+                }
+            }
+            AlterAction::RenameTable(_) => unreachable!(),
+        }
+        Ok(StatementResult::empty())
+    }
+
+    fn execute_insert(&mut self, insert: &Insert) -> Result<StatementResult, String> {
+        let stored = self.stored(&insert.table)?;
+        let schema = stored.schema();
+        let table = &*schema;
+        let columns = if insert.columns.is_empty() {
+            table.columns.iter().map(|c| c.name.clone()).collect()
+        } else {
+            insert.columns.clone()
+        };
+
+        let values: Vec<Vec<Value>> = match &insert.source {
+            InsertSource::Values(rows) => {
+                let empty = Row { data: HashMap::new() };
+                rows.iter().map(|exprs| exprs.iter().map(|e| self.eval_expr(e, &empty)).collect()).collect()
+            }
+            InsertSource::Query(query) => self.execute(query)?.rows,
+        };
+
+        for col in &table.columns {
+            if col.identity == Some(Identity::Always) && columns.contains(&col.name) {
+                return Err(format!("Cannot insert into identity column {}.{}", table.name, col.name));
+            }
+        }
+
+        let mut next_ids: HashMap<&str, i64> = table
+            .columns
+            .iter()
+            .filter(|c| c.identity.is_some())
+            .map(|c| (c.name.as_str(), stored.next_identity(&c.name)))
+            .collect();
+        let empty = Row { data: HashMap::new() };
+        let mut new_rows = Vec::with_capacity(values.len());
+        for vals in values {
+            if vals.len() != columns.len() {
+                return Err(format!("INSERT has {} values for {} columns", vals.len(), columns.len()));
+            }
+            let mut row = Row { data: HashMap::new() };
+            for (col, v) in columns.iter().zip(vals) {
+                row.data.insert(col.clone(), table.coerce(col, v)?);
+            }
+            for col in &table.columns {
+                if let Some(next) = next_ids.get_mut(col.name.as_str()) {
+                    match row.data.get(&col.name) {
+                        Some(Value::Int(i)) => *next = (*next).max(i + 1),
+                        Some(_) => {}
+                        None => {
+                            row.data.insert(col.name.clone(), Value::Int(*next));
+                            *next += 1;
+                        }
+                    }
+                } else if !row.data.contains_key(&col.name) {
+                    let value = match &col.default {
+                        Some(expr) => table.coerce(&col.name, self.eval_expr(expr, &empty))?,
+                        None => Value::Null,
+                    };
+                    row.data.insert(col.name.clone(), value);
+                }
+            }
+            new_rows.push(row);
+        }
+
+        self.validate_rows(table, &|cols, keys| self.taken_keys(&Staged::new(), &insert.table, cols, keys, &HashSet::new()), &new_rows)?;
+        for (constraint, fk) in table.foreign_keys().filter(|(_, fk)| !self.skip_check(fk)) {
+            let mut parents: HashSet<GroupKey> = self.stored(&fk.ref_table).map(|p| p.keys(&fk.ref_columns).into_iter().flatten().collect()).unwrap_or_default();
+            if fk.ref_table == table.name {
+                parents.extend(new_rows.iter().filter_map(|r| key_of(r, &fk.ref_columns)));
+            }
+            self.check_fk_keys(constraint, fk, new_rows.iter().filter_map(|r| key_of(r, &fk.columns)), &parents)?;
+        }
+
+        let returned = self.eval_returning(table, &insert.returning, &new_rows);
+        let count = new_rows.len();
+        if self.logging() {
+            self.log(Effect::Insert(insert.table.clone(), new_rows.clone()));
+        }
+        match self.tables.get_mut(&insert.table).map(Arc::make_mut) {
+            Some(t) => t.append(new_rows),
+            None => self.columnar.get_mut(&insert.table).map(Arc::make_mut).unwrap().append(&new_rows)?,
+        }
+        Ok(StatementResult { rows_affected: count, rows: returned })
+    }
+
+    fn execute_update(&mut self, update: &Update) -> Result<StatementResult, String> {
+        let stored = self.stored(&update.table)?;
+        let schema = stored.schema();
+        let table = &*schema;
+        for (col, _) in &update.assignments {
+            if table.column(col).and_then(|c| c.identity.as_ref()) == Some(&Identity::Always) {
+                return Err(format!("Cannot update identity column {}.{}", table.name, col));
+            }
+        }
+        let where_clause = update.where_clause.as_ref().map(|cond| table.collated(cond));
+        let mut pairs = Vec::new();
+        let mut changes = Changes::default();
+        for i in self.matching(stored, where_clause.as_ref()) {
+            let row = stored.row(i);
+            // Every assignment sees the row as it was before the update
+            let mut new_row = row.clone();
+            for (col, expr) in &update.assignments {
+                new_row.data.insert(col.clone(), table.coerce(col, self.eval_expr(expr, &row))?);
+            }
+            changes.updated.insert(i, new_row.clone());
+            pairs.push((row, new_row));
+        }
+
+        let updated: Vec<Row> = pairs.iter().map(|(_, new)| new.clone()).collect();
+        let mut staged = Staged::new();
+        let changed: HashSet<usize> = changes.updated.keys().copied().collect();
+        self.validate_rows(table, &|cols, keys| self.taken_keys(&staged, &update.table, cols, keys, &changed), &updated)?;
+        staged.insert(update.table.clone(), changes);
+        self.cascade_update(&update.table, &pairs, &mut staged, 0)?;
+        self.check_foreign_keys(&staged, None)?;
+
+        let returned = self.eval_returning(table, &update.returning, &updated);
+        self.commit_staged(staged)?;
+        Ok(StatementResult { rows_affected: updated.len(), rows: returned })
+    }
+
+    fn execute_delete(&mut self, delete: &Delete) -> Result<StatementResult, String> {
+        let stored = self.stored(&delete.table)?;
+        let schema = stored.schema();
+        let table = &*schema;
+        let where_clause = delete.where_clause.as_ref().map(|cond| table.collated(cond));
+        let deleted: HashSet<usize> = self.matching(stored, where_clause.as_ref()).into_iter().collect();
+        let mut removed: Vec<(usize, Row)> = deleted.iter().map(|&i| (i, stored.row(i))).collect();
+        removed.sort_by_key(|(i, _)| *i);
+        let removed: Vec<Row> = removed.into_iter().map(|(_, row)| row).collect();
+
+        let mut staged = HashMap::from([(delete.table.clone(), Changes { deleted, ..Changes::default() })]);
+        self.cascade_delete(&delete.table, &removed, &mut staged, 0)?;
+        self.check_foreign_keys(&staged, None)?;
+
+        let returned = self.eval_returning(table, &delete.returning, &removed);
+        self.commit_staged(staged)?;
+        Ok(StatementResult { rows_affected: removed.len(), rows: returned })
+    }
+
+    /// Positions of the rows of `table` where `cond` is TRUE, in table
+    /// order; a row table reads them through an index when one narrows
+    /// `cond`, a columnar table runs it as a compiled kernel over its
+    /// vectors.
+    fn matching(&self, table: Stored, cond: Option<&Expr>) -> Vec<usize> {
+        match (table, cond) {
+            (_, None) => (0..table.len()).collect(),
+            (Stored::Rows(t), Some(cond)) => {
+                // Narrowed by an index as a SELECT with the same WHERE would be
+                let query = Query {
+                    select_cols: vec!["*".into()],
+                    select_exprs: Vec::new(),
+                    distinct: false,
+                    from_table: t.name.clone(),
+                    from_unnest: None,
+                    joins: Vec::new(),
+                    where_clause: Some(cond.clone()),
+                    group_by: Vec::new(),
+                    order_by: Vec::new(),
+                    limit: None,
+                };
+                let candidates = index::plan_scan(self, t, &query, false).map_or_else(|| (0..t.rows.len()).collect(), |scan| scan.positions());
+                candidates.into_iter().filter(|&i| self.eval_expr(cond, &t.rows[i]).is_true()).collect()
+            }
+            (Stored::Columnar(t), Some(cond)) => Kernel::compile(cond, t).filter(self, t, (0..t.len()).collect()),
+        }
+    }
+
+    fn stored(&self, name: &str) -> Result<Stored<'_>, String> {
+        match (self.tables.get(name), self.columnar.get(name)) {
+            (Some(t), _) => Ok(Stored::Rows(t)),
+            (_, Some(t)) => Ok(Stored::Columnar(t)),
+            _ => Err(format!("Table not found: {}", name)),
+        }
+    }
+
+    /// The constraints of every table, row or columnar, with a foreign key
+    /// to `table`. Other tables are left shared.
+    fn referencing_mut<'a>(&'a mut self, table: &'a str) -> impl Iterator<Item = &'a mut Vec<Constraint>> + 'a {
+        let refers = move |constraints: &[Constraint]| foreign_keys(constraints).any(|(_, fk)| fk.ref_table == table);
+        let rows = self.tables.values_mut().filter(move |t| refers(&t.constraints)).map(|t| &mut Arc::make_mut(t).constraints);
+        rows.chain(self.columnar.values_mut().filter(move |t| refers(&t.constraints)).map(|t| &mut Arc::make_mut(t).constraints))
+    }
+
+    fn stored_tables(&self) -> impl Iterator<Item = Stored<'_>> {
+        self.tables.values().map(|t| Stored::Rows(t)).chain(self.columnar.values().map(|t| Stored::Columnar(t)))
+    }
+
+    // ------------------------------------------------------------------------
+    // Referential integrity
+    //
+    // UPDATE and DELETE stage their changes to every table they touch, by
+    // row position, apply ON DELETE / ON UPDATE actions to the staged view,
+    // then check every affected foreign key before anything is written back.
+    // ------------------------------------------------------------------------
+
+    /// Keys over `cols` of the rows `table` holds once `staged` is applied,
+    /// with their positions. Rows with a NULL in the key are left out.
+    fn staged_keys(&self, staged: &Staged, table: &str, cols: &[String]) -> Vec<(usize, GroupKey)> {
+        let Ok(stored) = self.stored(table) else { return Vec::new() };
+        let changes = staged.get(table);
+        let keys = stored.keys(cols).into_iter().enumerate().filter(|(i, _)| !changes.is_some_and(|c| c.deleted.contains(i)));
+        keys.filter_map(|(i, key)| match changes.and_then(|c| c.updated.get(&i)) {
+            Some(row) => key_of(row, cols).map(|k| (i, k)),
+            None => key.map(|k| (i, k)),
+        })
+        .collect()
+    }
+
+    /// Which of the collated `keys` over `cols` rows of `table` hold once
+    /// `staged` is applied, leaving out the rows at `except`. An index over
+    /// exactly `cols` looks up just these keys among the stored rows;
+    /// without one every row's key is read.
+    fn taken_keys(&self, staged: &Staged, table: &str, cols: &[String], keys: &[GroupKey], except: &HashSet<usize>) -> HashSet<GroupKey> {
+        let Ok(stored) = self.stored(table) else { return HashSet::new() };
+        let schema = stored.schema();
+        let wanted: HashSet<&GroupKey> = keys.iter().collect();
+        let collated = |key| schema.collated_key(cols, key);
+        let index = match stored {
+            Stored::Rows(t) => t.indexes.iter().find(|i| i.columns == cols),
+            Stored::Columnar(_) => None,
+        };
+        let Some(index) = index else {
+            let held = self.staged_keys(staged, table, cols).into_iter().filter(|(i, _)| !except.contains(i));
+            return held.map(|(_, k)| collated(k)).filter(|k| wanted.contains(k)).collect();
+        };
+        let changes = staged.get(table);
+        let moved = |i: &usize| except.contains(i) || changes.is_some_and(|c| c.deleted.contains(i) || c.updated.contains_key(i));
+        let held = |key: &&GroupKey| index.get(&key.1).iter().any(|i| !moved(i));
+        let mut taken: HashSet<GroupKey> = keys.iter().filter(held).cloned().collect();
+        // Rows already staged hold their new keys
+        for (i, row) in changes.iter().flat_map(|c| c.updated.iter().filter(|(i, _)| !c.deleted.contains(i))) {
+            if let Some(key) = key_of(row, cols).filter(|_| !except.contains(i)).map(collated) {
+                if wanted.contains(&key) {
+                    taken.insert(key);
+                }
+            }
+        }
+        taken
+    }
+
+    fn staged_row(&self, staged: &Staged, table: &str, i: usize) -> Row {
+        match staged.get(table).and_then(|c| c.updated.get(&i)) {
+            Some(row) => row.clone(),
+            None => self.stored(table).map_or(Row { data: HashMap::new() }, |t| t.row(i)),
+        }
+    }
+
+    /// Writes staged changes into the tables: row tables change the staged
+    /// rows and their index entries, columnar tables their vectors. Each
+    /// changed row is logged with its position.
+    fn commit_staged(&mut self, staged: Staged) -> Result<(), String> {
+        for (name, changes) in staged {
+            if changes.updated.is_empty() && changes.deleted.is_empty() {
+                continue;
+            }
+            if self.logging() {
+                for effect in Effect::rows(&name, &changes.updated, &changes.deleted) {
+                    self.log(effect);
+                }
+            }
+            if let Some(table) = self.tables.get_mut(&name).map(Arc::make_mut) {
+                table.update(changes.updated);
+                if !changes.deleted.is_empty() {
+                    table.delete(&changes.deleted);
+                }
+            } else if let Some(table) = self.columnar.get_mut(&name).map(Arc::make_mut) {
+                for (i, row) in &changes.updated {
+                    table.set_row(*i, row)?;
+                }
+                if !changes.deleted.is_empty() {
+                    let keep: Vec<bool> = (0..table.len()).map(|i| !changes.deleted.contains(&i)).collect();
+                    table.retain(&keep);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Foreign keys (with their owning table) that reference `table`.
+    fn referencing(&self, table: &str) -> Vec<(String, Constraint)> {
+        let mut out = Vec::new();
+        for t in self.stored_tables() {
+            for (constraint, fk) in t.foreign_keys() {
+                if fk.ref_table == table {
+                    out.push((t.name().to_string(), constraint.clone()));
+                }
+            }
+        }
+        out.sort_by(|a, b| (&a.0, &a.1.name).cmp(&(&b.0, &b.1.name)));
+        out
+    }
+
+    /// Fills in omitted referenced columns with the parent's primary key and
+    /// checks that every foreign key of `table` targets a unique key.
+    fn resolve_foreign_keys(&mut self, table: &str) -> Result<(), String> {
+        let mut constraints = self.tables[table].constraints.clone();
+        for c in &mut constraints {
+            let ConstraintKind::ForeignKey(fk) = &mut c.kind else { continue };
+            let parent = self.stored(&fk.ref_table).map_err(|_| format!("Constraint {}: table not found: {}", c.name, fk.ref_table))?.schema();
+            if fk.ref_columns.is_empty() {
+                fk.ref_columns = parent
+                    .primary_key()
+                    .ok_or(format!("Constraint {}: table {} has no primary key", c.name, fk.ref_table))?
+                    .to_vec();
+            }
+            if fk.ref_columns.len() != fk.columns.len() {
+                return Err(format!("Constraint {}: column count does not match referenced key", c.name));
+            }
+            if !parent.is_unique_key(&fk.ref_columns) {
+                return Err(format!(
+                    "Constraint {}: {}({}) is not a primary key or unique constraint",
+                    c.name,
+                    fk.ref_table,
+                    fk.ref_columns.join(", ")
+                ));
+            }
+        }
+        self.tables.get_mut(table).map(Arc::make_mut).unwrap().constraints = constraints;
+        Ok(())
+    }
+
+    /// Checks every foreign key whose child or parent table is staged, or
+    /// whose child is `only` when given.
+    fn check_foreign_keys(&self, staged: &Staged, only: Option<&str>) -> Result<(), String> {
+        for t in self.stored_tables() {
+            for (constraint, fk) in t.foreign_keys().filter(|(_, fk)| !self.skip_check(fk)) {
+                let affected = match only {
+                    Some(name) => t.name() == name,
+                    None => staged.contains_key(t.name()) || staged.contains_key(&fk.ref_table),
+                };
+                if affected {
+                    let parents: HashSet<GroupKey> = self.staged_keys(staged, &fk.ref_table, &fk.ref_columns).into_iter().map(|(_, k)| k).collect();
+                    let keys = self.staged_keys(staged, t.name(), &fk.columns).into_iter().map(|(_, k)| k);
+                    self.check_fk_keys(constraint, fk, keys, &parents)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a (non-NULL) child key has no matching parent key.
+    fn check_fk_keys(&self, constraint: &Constraint, fk: &ForeignKey, keys: impl IntoIterator<Item = GroupKey>, parents: &HashSet<GroupKey>) -> Result<(), String> {
+        for key in keys {
+            if !parents.contains(&key) {
+                let values: Vec<String> = key.1.iter().map(|v| v.to_string()).collect();
+                return Err(format!(
+                    "Constraint {} violated: key ({})=({}) is not present in table {}",
+                    constraint.name,
+                    fk.columns.join(", "),
+                    values.join(", "),
+                    fk.ref_table
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies ON DELETE actions for `removed` rows of `table`.
+    fn cascade_delete(&self, table: &str, removed: &[Row], staged: &mut Staged, depth: usize) -> Result<(), String> {
+        if depth > MAX_CASCADE_DEPTH {
+            return Err("Referential actions nested too deeply".into());
+        }
+        for (child, constraint) in self.referencing(table) {
+            let ConstraintKind::ForeignKey(fk) = &constraint.kind else { continue };
+            // Keys that no longer exist in the parent after the delete
+            let remaining: HashSet<GroupKey> = self.staged_keys(staged, table, &fk.ref_columns).into_iter().map(|(_, k)| k).collect();
+            let gone: HashSet<GroupKey> = removed
+                .iter()
+                .filter_map(|r| key_of(r, &fk.ref_columns))
+                .filter(|k| !remaining.contains(k))
+                .collect();
+            if gone.is_empty() {
+                continue;
+            }
+            let hits: Vec<usize> = self.staged_keys(staged, &child, &fk.columns).into_iter().filter(|(_, k)| gone.contains(k)).map(|(i, _)| i).collect();
+            if hits.is_empty() {
+                continue;
+            }
+            match fk.on_delete {
+                RefAction::NoAction => {}
+                RefAction::Restrict => {
+                    return Err(format!("Constraint {} violated: {} rows still reference deleted {} rows", constraint.name, child, table));
+                }
+                RefAction::Cascade => {
+                    let deleted: Vec<Row> = hits.iter().map(|&i| self.staged_row(staged, &child, i)).collect();
+                    let changes = staged.entry(child.clone()).or_default();
+                    for i in hits {
+                        changes.updated.remove(&i);
+                        changes.deleted.insert(i);
+                    }
+                    self.cascade_delete(&child, &deleted, staged, depth + 1)?;
+                }
+                RefAction::SetNull => self.set_null(&child, fk, &hits, staged, depth)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies ON UPDATE actions for `(old, new)` row pairs of `table`.
+    fn cascade_update(&self, table: &str, changes: &[(Row, Row)], staged: &mut Staged, depth: usize) -> Result<(), String> {
+        if depth > MAX_CASCADE_DEPTH {
+            return Err("Referential actions nested too deeply".into());
+        }
+        for (child, constraint) in self.referencing(table) {
+            let ConstraintKind::ForeignKey(fk) = &constraint.kind else { continue };
+            let remaining: HashSet<GroupKey> = self.staged_keys(staged, table, &fk.ref_columns).into_iter().map(|(_, k)| k).collect();
+            let mut moved: HashMap<GroupKey, Vec<Value>> = HashMap::new();
+            for (old, new) in changes {
+                if let Some(old_key) = key_of(old, &fk.ref_columns) {
+                    if !remaining.contains(&old_key) {
+                        let new_key = fk.ref_columns.iter().map(|c| new.data.get(c).cloned().unwrap_or(Value::Null)).collect();
+                        moved.insert(old_key, new_key);
+                    }
+                }
+            }
+            if moved.is_empty() {
+                continue;
+            }
+            let hits: Vec<(usize, GroupKey)> = self.staged_keys(staged, &child, &fk.columns).into_iter().filter(|(_, k)| moved.contains_key(k)).collect();
+            if hits.is_empty() {
+                continue;
+            }
+            match fk.on_update {
+                RefAction::NoAction => {}
+                RefAction::Restrict => {
+                    return Err(format!("Constraint {} violated: {} rows still reference updated {} keys", constraint.name, child, table));
+                }
+                RefAction::Cascade => {
+                    let updates = hits.into_iter().map(|(i, key)| {
+                        let old = self.staged_row(staged, &child, i);
+                        let mut new = old.clone();
+                        for (col, v) in fk.columns.iter().zip(&moved[&key]) {
+                            new.data.insert(col.clone(), v.clone());
+                        }
+                        (i, old, new)
+                    });
+                    self.stage_updated(&child, updates.collect(), staged, depth)?;
+                }
+                RefAction::SetNull => {
+                    let hits: Vec<usize> = hits.into_iter().map(|(i, _)| i).collect();
+                    self.set_null(&child, fk, &hits, staged, depth)?;
+                }
             }
-            _ => Err("Expected expression".into()),
         }
+        Ok(())
     }
-}
-
-pub fn parse(sql: &str) -> Result<Query, String> {
-    let tokens = tokenize(sql);
-    let mut parser = Parser::new(tokens);
-    parser.parse_query()
-}
-
-// ============================================================================
-// Query Executor
-// ============================================================================
 
-pub struct Database {
-    pub tables: HashMap<String, Table>,
-}
-
-impl Database {
-    pub fn new() -> Self {
-        Database { tables: HashMap::new() }
+    fn set_null(&self, child: &str, fk: &ForeignKey, hits: &[usize], staged: &mut Staged, depth: usize) -> Result<(), String> {
+        let updates = hits.iter().map(|&i| {
+            let old = self.staged_row(staged, child, i);
+            let mut new = old.clone();
+            for col in &fk.columns {
+                new.data.insert(col.clone(), Value::Null);
+            }
+            (i, old, new)
+        });
+        self.stage_updated(child, updates.collect(), staged, depth)
     }
 
-    pub fn add_table(&mut self, table: Table) {
-        self.tables.insert(table.name.clone(), table);
+    /// Validates rows changed by a referential action, stages them and
+    /// propagates the change to tables that reference `child` in turn. The
+    /// new values come from the parent, so they are coerced to the child's
+    /// column types first.
+    fn stage_updated(&self, child: &str, updates: Vec<(usize, Row, Row)>, staged: &mut Staged, depth: usize) -> Result<(), String> {
+        let schema = self.stored(child)?.schema();
+        let table = &*schema;
+        let mut positions = Vec::with_capacity(updates.len());
+        let mut pairs = Vec::with_capacity(updates.len());
+        for (i, old, mut new) in updates {
+            for (col, v) in new.data.iter_mut() {
+                if table.column(col).is_some() {
+                    *v = table.coerce(col, std::mem::replace(v, Value::Null))?;
+                }
+            }
+            positions.push(i);
+            pairs.push((old, new));
+        }
+        let updated: Vec<Row> = pairs.iter().map(|(_, new)| new.clone()).collect();
+        let changed: HashSet<usize> = positions.iter().copied().collect();
+        self.validate_rows(table, &|cols, keys| self.taken_keys(staged, child, cols, keys, &changed), &updated)?;
+        let changes = staged.entry(child.to_string()).or_default();
+        for (i, row) in positions.into_iter().zip(&updated) {
+            changes.updated.insert(i, row.clone());
+        }
+        self.cascade_update(child, &pairs, staged, depth + 1)
     }
 
-    pub fn execute(&self, query: &Query) -> Result<Vec<Row>, String> {
-        let mut rows = self.tables
-            .get(&query.from_table)
-            .ok_or(format!("Table not found: {}", query.from_table))?
-            .rows
-            .clone();
-
-        // Apply WHERE clause
-        if let Some(ref where_expr) = query.where_clause {
-            rows.retain(|row| self.eval_expr(where_expr, row).is_true());
+    /// Checks `new_rows` against the table's NOT NULL, CHECK, PRIMARY KEY and
+    /// UNIQUE constraints. `taken` gives which of the collated keys, over the
+    /// columns asked for, the rows they will sit beside already hold.
+    fn validate_rows(&self, table: &Table, taken: &Taken, new_rows: &[Row]) -> Result<(), String> {
+        let pk = table.primary_key().unwrap_or(&[]);
+        for row in new_rows {
+            for col in &table.columns {
+                let is_null = matches!(row.data.get(&col.name), None | Some(Value::Null));
+                if is_null && (col.not_null || pk.contains(&col.name)) {
+                    return Err(format!("NOT NULL constraint violated: {}.{} is NULL", table.name, col.name));
+                }
+            }
+            for constraint in &table.constraints {
+                if let ConstraintKind::Check(expr) = &constraint.kind {
+                    // Only FALSE fails a CHECK; NULL (unknown) passes
+                    if self.eval_expr(expr, row) == Value::Bool(false) {
+                        return Err(format!("CHECK constraint {} violated by ({})", constraint.name, expr));
+                    }
+                }
+            }
         }
 
-        // Apply JOINs
-        for join in &query.joins {
-            let join_table = self.tables.get(&join.table).ok_or(format!("Table not found: {}", join.table))?;
-            let mut new_rows = Vec::new();
-            for left in &rows {
-                for right in &join_table.rows {
-                    let mut merged = left.clone();
-                    merged.data.extend(right.data.clone());
-                    if self.eval_expr(&join.on, &merged).is_true() {
-                        new_rows.push(merged);
+        let unique_keys = table
+            .constraints
+            .iter()
+            .filter_map(|c| match &c.kind {
+                ConstraintKind::PrimaryKey(cols) | ConstraintKind::Unique(cols) => Some((&c.name, cols)),
+                ConstraintKind::Check(_) | ConstraintKind::ForeignKey(_) => None,
+            })
+            .chain(table.indexes.iter().filter(|i| i.unique).map(|i| (&i.name, &i.columns)));
+        for (name, cols) in unique_keys {
+            // NULLs never collide in a UNIQUE constraint; text collides
+            // under its column's collation
+            let keys: Vec<Option<GroupKey>> = new_rows.iter().map(|r| key_of(r, cols).map(|k| table.collated_key(cols, k))).collect();
+            let mut seen = taken(cols, &keys.iter().flatten().cloned().collect::<Vec<_>>());
+            for (row, key) in new_rows.iter().zip(keys) {
+                if let Some(key) = key {
+                    if !seen.insert(key) {
+                        let values: Vec<String> = cols.iter().map(|c| row.data.get(c).map_or("NULL".into(), |v| v.to_string())).collect();
+                        return Err(format!(
+                            "Constraint {} violated: duplicate key ({})=({})",
+                            name,
+                            cols.join(", "),
+                            values.join(", ")
+                        ));
                     }
                 }
             }
-            rows = new_rows;
         }
+        Ok(())
+    }
 
-        // Apply GROUP BY
-        if !query.group_by.is_empty() {
-            rows = self.apply_group_by(&rows, &query.group_by);
+    fn eval_returning(&self, table: &Table, returning: &[(String, Expr)], rows: &[Row]) -> ResultSet {
+        if returning.is_empty() {
+            return ResultSet::default();
+        }
+        let mut columns = Vec::new();
+        for (name, expr) in returning {
+            if name == "*" {
+                columns.extend(table.columns.iter().map(|c| ResultColumn { name: c.name.clone(), data_type: c.data_type.clone() }));
+            } else {
+                columns.push(ResultColumn { name: name.clone(), data_type: infer_type(expr, &[Source::Rows(table)]) });
+            }
+        }
+        let mut result = ResultSet::new(columns);
+        for row in rows {
+            let mut values = Vec::new();
+            for (name, expr) in returning {
+                if name == "*" {
+                    values.extend(table.columns.iter().map(|c| row.data.get(&c.name).cloned().unwrap_or(Value::Null)));
+                } else {
+                    values.push(self.eval_expr(expr, row));
+                }
+            }
+            result.rows.push(values);
         }
+        refine_types(&mut result);
+        result
+    }
 
-        // Apply ORDER BY
-        for (col, is_asc) in query.order_by.iter().rev() {
-            rows.sort_by(|a, b| {
-                let av = a.data.get(col).unwrap_or(&Value::Null);
-                let bv = b.data.get(col).unwrap_or(&Value::Null);
-                let cmp = self.compare_values(av, bv);
-                if *is_asc { cmp } else { cmp.reverse() }
-            });
+    /// Computes every grouping set in a single pass over `rows`. Each output
+    /// row holds the select outputs by name plus the grouping expressions, so
+    /// ORDER BY can refer to either.
+    fn apply_group_by(&self, rows: &[Row], query: &Query, collations: &Collations) -> Result<Vec<Row>, String> {
+        let items: Vec<GroupByItem> = query
+            .group_by
+            .iter()
+            .map(|item| self.resolve_ordinals(item, query))
+            .collect::<Result<_, _>>()?;
+        let sets = expand_grouping_sets(&items);
+
+        // Distinct grouping expressions across all sets, and each set as indices
+        let mut keys: Vec<Expr> = Vec::new();
+        for set in &sets {
+            for e in set {
+                if !keys.contains(e) {
+                    keys.push(e.clone());
+                }
+            }
         }
+        let set_masks: Vec<Vec<bool>> = sets.iter().map(|set| keys.iter().map(|k| set.contains(k)).collect()).collect();
+        // Groups are keyed by the keys' collation keys; the first row's
+        // values stand for the group
+        let key_collations: Vec<Collation> = keys.iter().map(|k| collations.of(k)).collect();
 
-        // Apply SELECT projection
-        let selected_cols: Vec<String> = if query.select_cols.contains(&"*".to_string()) {
-            rows.get(0).map(|r| r.data.keys().cloned().collect()).unwrap_or_default()
-        } else {
-            query.select_cols.clone()
-        };
+        let mut aggregates = Vec::new();
+        for expr in &query.select_exprs {
+            if matches!(expr, Expr::Column(c) if c == "*") {
+                return Err("SELECT * is not allowed with GROUP BY".into());
+            }
+            collect_aggregates(expr, &mut aggregates);
+        }
 
-        rows = rows
-            .into_iter()
-            .map(|row| {
-                let mut new_row = Row { data: HashMap::new() };
-                for col in &selected_cols {
-                    new_row.data.insert(col.clone(), row.data.get(col).cloned().unwrap_or(Value::Null));
+        // Each morsel aggregates into its own groups, in first-appearance order
+        let partials = self.pool.map_morsels(self.threads, rows.len(), |morsel| {
+            let mut index: HashMap<GroupKey, usize> = HashMap::new();
+            let mut groups: Vec<(GroupKey, Vec<Value>, Vec<Accumulator>)> = Vec::new();
+            for row in &rows[morsel] {
+                let key_values: Vec<Value> = keys.iter().map(|k| self.eval_expr(k, row)).collect();
+                let agg_inputs: Vec<Option<Value>> = aggregates
+                    .iter()
+                    .map(|agg| match agg {
+                        Expr::FuncCall(_, args) => match args.first() {
+                            Some(Expr::Column(c)) if c == "*" => None,
+                            Some(arg) => Some(self.eval_expr(arg, row)),
+                            None => None,
+                        },
+                        _ => None,
+                    })
+                    .collect();
+                for (set_id, mask) in set_masks.iter().enumerate() {
+                    let values: Vec<Value> = key_values
+                        .iter()
+                        .zip(mask)
+                        .map(|(v, in_set)| if *in_set { v.clone() } else { Value::Null })
+                        .collect();
+                    let key = GroupKey(set_id, values.iter().zip(&key_collations).map(|(v, c)| c.key(v).into_owned()).collect());
+                    let slot = *index.entry(key.clone()).or_insert_with(|| {
+                        groups.push((key, values, aggregates.iter().map(|a| Accumulator::new(func_name(a))).collect()));
+                        groups.len() - 1
+                    });
+                    for (acc, input) in groups[slot].2.iter_mut().zip(&agg_inputs) {
+                        acc.update(self, input.as_ref());
+                    }
                 }
-                new_row
-            })
-            .collect();
+            }
+            groups
+        });
 
-        // Apply LIMIT
-        if let Some(l) = query.limit {
-            rows.truncate(l);
+        // Merging in morsel order keeps groups in first-appearance order
+        let mut index: HashMap<GroupKey, usize> = HashMap::new();
+        let mut groups: Vec<(GroupKey, Vec<Value>, Vec<Accumulator>)> = Vec::new();
+        for (key, values, accs) in partials.into_iter().flatten() {
+            match index.get(&key) {
+                Some(&slot) => {
+                    for (acc, partial) in groups[slot].2.iter_mut().zip(accs) {
+                        acc.merge(self, partial);
+                    }
+                }
+                None => {
+                    index.insert(key.clone(), groups.len());
+                    groups.push((key, values, accs));
+                }
+            }
+        }
+
+        // The empty grouping set yields a row even when there is no input
+        for (set_id, mask) in set_masks.iter().enumerate() {
+            if mask.iter().all(|in_set| !in_set) && !groups.iter().any(|(k, _, _)| k.0 == set_id) {
+                let key = GroupKey(set_id, vec![Value::Null; keys.len()]);
+                groups.push((key, vec![Value::Null; keys.len()], aggregates.iter().map(|a| Accumulator::new(func_name(a))).collect()));
+            }
         }
 
-        Ok(rows)
+        let mut result = Vec::new();
+        for (key, values, accs) in &groups {
+            let ctx = GroupContext {
+                keys: &keys,
+                values,
+                in_set: &set_masks[key.0],
+                aggregates: &aggregates,
+                results: accs.iter().map(|a| a.finish(self)).collect(),
+            };
+            let mut out = Row { data: HashMap::new() };
+            for (k, v) in keys.iter().zip(values) {
+                out.data.insert(k.to_string(), v.clone());
+            }
+            for (name, expr) in query.select_cols.iter().zip(&query.select_exprs) {
+                out.data.insert(name.clone(), self.eval_grouped(expr, &ctx)?);
+            }
+            result.push(out);
+        }
+        Ok(result)
     }
 
-    fn apply_group_by(&self, rows: &[Row], group_cols: &[String]) -> Vec<Row> {
-        let mut groups: HashMap<Vec<Value>, Vec<Row>> = HashMap::new();
-        for row in rows {
-            let key: Vec<Value> = group_cols
-                .iter()
-                .map(|col| row.data.get(col).cloned().unwrap_or(Value::Null))
-                .collect();
-            groups.entry(key).or_insert_with(Vec::new).push(row.clone());
+    fn resolve_ordinals(&self, item: &GroupByItem, query: &Query) -> Result<GroupByItem, String> {
+        let resolve = |e: &Expr| -> Result<Expr, String> {
+            match e {
+                Expr::Literal(Value::Int(n)) => {
+                    let expr = usize::try_from(*n)
+                        .ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|i| query.select_exprs.get(i))
+                        .ok_or(format!("GROUP BY position {} is not in select list", n))?;
+                    if contains_aggregate(expr) {
+                        return Err(format!("GROUP BY position {} refers to an aggregate", n));
+                    }
+                    Ok(expr.clone())
+                }
+                e => Ok(e.clone()),
+            }
+        };
+        let resolve_all = |exprs: &[Expr]| exprs.iter().map(resolve).collect::<Result<Vec<_>, _>>();
+        Ok(match item {
+            GroupByItem::Expr(e) => GroupByItem::Expr(resolve(e)?),
+            GroupByItem::Set(exprs) => GroupByItem::Set(resolve_all(exprs)?),
+            GroupByItem::Rollup(exprs) => GroupByItem::Rollup(resolve_all(exprs)?),
+            GroupByItem::Cube(exprs) => GroupByItem::Cube(resolve_all(exprs)?),
+            GroupByItem::GroupingSets(elems) => GroupByItem::GroupingSets(
+                elems.iter().map(|e| self.resolve_ordinals(e, query)).collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    fn eval_grouped(&self, expr: &Expr, ctx: &GroupContext) -> Result<Value, String> {
+        if let Some(i) = ctx.keys.iter().position(|k| k == expr) {
+            return Ok(ctx.values[i].clone());
         }
-        groups
-            .into_iter()
-            .map(|(key, group)| {
-                let mut result = Row { data: HashMap::new() };
-                for (i, col) in group_cols.iter().enumerate() {
-                    result.data.insert(col.clone(), key[i].clone());
+        if let Some(i) = ctx.aggregates.iter().position(|a| a == expr) {
+            return Ok(ctx.results[i].clone());
+        }
+        match expr {
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Column(name) => Err(format!("Column {} must appear in GROUP BY or an aggregate", name)),
+            Expr::UnaryOp(op, e) => Ok(self.apply_unary(op, &self.eval_grouped(e, ctx)?)),
+            Expr::BinOp(l, op, r) => {
+                let lv = self.eval_grouped(l, ctx)?;
+                let rv = self.eval_grouped(r, ctx)?;
+                Ok(match collation::of_comparison(l, op, r) {
+                    Some(c) => self.apply_binop(&c.key(&lv), op, &c.key(&rv)),
+                    None => self.apply_binop(&lv, op, &rv),
+                })
+            }
+            Expr::FuncCall(name, args) if name == "GROUPING" => {
+                // One bit per argument, first argument most significant; a set
+                // bit means the expression is rolled up in this row
+                let mut bits = 0i64;
+                for arg in args {
+                    let i = ctx
+                        .keys
+                        .iter()
+                        .position(|k| k == arg)
+                        .ok_or(format!("GROUPING argument {} is not a grouping expression", arg))?;
+                    bits = (bits << 1) | i64::from(!ctx.in_set[i]);
                 }
-                result
-            })
-            .collect()
+                Ok(Value::Int(bits))
+            }
+            Expr::FuncCall(name, args) => {
+                let values = args.iter().map(|a| self.eval_grouped(a, ctx)).collect::<Result<Vec<_>, _>>()?;
+                Ok(self.call_function(name, &values))
+            }
+        }
     }
 
     fn eval_expr(&self, expr: &Expr, row: &Row) -> Value {
         match expr {
-            Expr::Column(name) => row.data.get(name).cloned().unwrap_or(Value::Null),
+            Expr::Column(name) => lookup(row, name).cloned().unwrap_or(Value::Null),
             Expr::Literal(v) => v.clone(),
+            Expr::UnaryOp(op, e) => self.apply_unary(op, &self.eval_expr(e, row)),
             Expr::BinOp(left, op, right) => {
                 let lv = self.eval_expr(left, row);
                 let rv = self.eval_expr(right, row);
-                self.apply_binop(&lv, op, &rv)
+                match collation::of_comparison(left, op, right) {
+                    Some(c) => self.apply_binop(&c.key(&lv), op, &c.key(&rv)),
+                    None => self.apply_binop(&lv, op, &rv),
+                }
             }
-            Expr::FuncCall(_, _) => Value::Null,
+            Expr::FuncCall(name, _) if AGGREGATES.contains(&name.as_str()) || name == "GROUPING" => Value::Null,
+            Expr::FuncCall(name, args) => {
+                let values: Vec<Value> = args.iter().map(|a| self.eval_expr(a, row)).collect();
+                self.call_function(name, &values)
+            }
+        }
+    }
+
+    fn call_function(&self, name: &str, args: &[Value]) -> Value {
+        match (name, args) {
+            ("COLLATE", [v, _]) => v.clone(),
+            ("COALESCE", _) => args.iter().find(|v| **v != Value::Null).cloned().unwrap_or(Value::Null),
+            ("UPPER", [Value::String(s)]) => Value::String(s.to_uppercase()),
+            ("LOWER", [Value::String(s)]) => Value::String(s.to_lowercase()),
+            ("LENGTH", [Value::String(s)]) => Value::Int(s.chars().count() as i64),
+            ("LENGTH", [Value::Bytes(b)]) => Value::Int(b.len() as i64),
+            ("ABS", [Value::Int(i)]) => Value::Int(i.abs()),
+            ("ABS", [Value::Float(f)]) => Value::Float(f.abs()),
+            ("ABS", [Value::Decimal(d)]) => Value::Decimal(d.abs()),
+            ("ROUND", [x, rest @ ..]) if rest.len() <= 2 => {
+                let mode = match rest.get(1) {
+                    None => Ok(RoundingMode::HalfUp),
+                    Some(Value::String(name)) => RoundingMode::from_name(name),
+                    Some(_) => Err(String::new()),
+                };
+                mode.ok().and_then(|mode| decimal::round(x, rest.first(), mode)).unwrap_or(Value::Null)
+            }
+            ("TRUNC", [x, rest @ ..]) if rest.len() <= 1 => decimal::round(x, rest.first(), RoundingMode::Down).unwrap_or(Value::Null),
+            ("NOW", []) => Value::TimestampTz(Timestamp::now()),
+            ("DATE_TRUNC", [Value::String(field), v]) => temporal::date_trunc(field, v).unwrap_or(Value::Null),
+            ("EXTRACT" | "DATE_PART", [Value::String(field), v]) => temporal::date_part(field, v).unwrap_or(Value::Null),
+            ("STRFTIME", [v, Value::String(format)]) => temporal::strftime(v, format).map_or(Value::Null, Value::String),
+            ("CAST", [v, Value::String(ty)]) => {
+                let ty = Parser::new(tokenize(ty)).parse_data_type();
+                ty.and_then(|ty| ty.cast(v.clone())).unwrap_or(Value::Null)
+            }
+            ("JSON_EXTRACT", [doc, Value::String(path)]) => json::extract(doc, path).unwrap_or(Value::Null),
+            ("JSON_ARRAY_LENGTH", [doc]) => json::array_length(doc, None).unwrap_or(Value::Null),
+            ("JSON_ARRAY_LENGTH", [doc, Value::String(path)]) => json::array_length(doc, Some(path)).unwrap_or(Value::Null),
+            ("JSON_OBJECT", _) => json::object(args).unwrap_or(Value::Null),
+            ("ARRAY", _) => Value::Array(args.to_vec()),
+            ("STRUCT", _) => composite::build_struct(args).unwrap_or(Value::Null),
+            ("ELEMENT_AT", [v, index]) => composite::element_at(v, index).unwrap_or(Value::Null),
+            ("STRUCT_EXTRACT", [v, Value::String(name)]) => composite::field(v, name).cloned().unwrap_or(Value::Null),
+            ("ARRAY_CONTAINS", [array, v]) => composite::contains(self, array, v).unwrap_or(Value::Null),
+            ("CARDINALITY", [array]) => composite::cardinality(array).unwrap_or(Value::Null),
+            ("GEN_RANDOM_UUID", []) => Value::Uuid(Uuid::random()),
+            ("ENCODE", [v, Value::String(format)]) => binary::encode(v, format).unwrap_or(Value::Null),
+            ("DECODE", [Value::String(text), Value::String(format)]) => binary::decode(text, format).unwrap_or(Value::Null),
+            _ => Value::Null,
+        }
+    }
+
+    fn apply_unary(&self, op: &str, v: &Value) -> Value {
+        match (op, v) {
+            ("NOT", Value::Bool(b)) => Value::Bool(!b),
+            ("-", Value::Int(i)) => i.checked_neg().map_or(Value::Null, Value::Int),
+            ("-", Value::Float(f)) => Value::Float(-f),
+            ("-", Value::Decimal(d)) => d.checked_neg().map_or(Value::Null, Value::Decimal),
+            ("-", Value::Interval(i)) => i.checked_neg().map_or(Value::Null, Value::Interval),
+            (_, Value::Json(doc)) if doc.is_scalar() => self.apply_unary(op, &doc.to_value()),
+            _ => Value::Null,
         }
     }
 
     fn apply_binop(&self, left: &Value, op: &str, right: &Value) -> Value {
         match (left, right) {
+            _ if json::is_access(op) => json::access(left, op, right).unwrap_or(Value::Null),
+            // JSON scalars act as the SQL value they hold
+            (Value::Json(_), _) | (_, Value::Json(_)) => match (&*json::unwrap(left), &*json::unwrap(right)) {
+                (Value::Json(a), Value::Json(b)) => match op {
+                    "=" => Value::Bool(a == b),
+                    "!=" => Value::Bool(a != b),
+                    _ => Value::Null,
+                },
+                (Value::Json(_), _) | (_, Value::Json(_)) => Value::Null,
+                (l, r) => self.apply_binop(l, op, r),
+            },
+            // Numbers compare exactly across types, as they sort
+            (Value::Int(_) | Value::Float(_) | Value::Decimal(_), Value::Int(_) | Value::Float(_) | Value::Decimal(_))
+                if matches!(op, "=" | "!=" | "<" | ">" | "<=" | ">=") => order::compare(left, op, right),
             (Value::Int(a), Value::Int(b)) => match op {
+                "+" => a.checked_add(*b).map_or(Value::Null, Value::Int),
+                "-" => a.checked_sub(*b).map_or(Value::Null, Value::Int),
+                "*" => a.checked_mul(*b).map_or(Value::Null, Value::Int),
+                "/" => a.checked_div(*b).map_or(Value::Null, Value::Int),
+                "%" => a.checked_rem(*b).map_or(Value::Null, Value::Int),
+                _ => Value::Null,
+            },
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                let (a, b) = (left.as_f64().unwrap(), right.as_f64().unwrap());
+                match op {
+                    "+" => Value::Float(a + b),
+                    "-" => Value::Float(a - b),
+                    "*" => Value::Float(a * b),
+                    "/" => Value::Float(a / b),
+                    "%" => Value::Float(a % b),
+                    _ => Value::Null,
+                }
+            }
+            (Value::Int(_) | Value::Float(_) | Value::Decimal(_), Value::Int(_) | Value::Float(_) | Value::Decimal(_)) => {
+                decimal::binop(left, op, right).unwrap_or(Value::Null)
+            }
+            (Value::String(a), Value::String(b)) => match op {
                 "=" => Value::Bool(a == b),
                 "!=" => Value::Bool(a != b),
                 "<" => Value::Bool(a < b),
                 ">" => Value::Bool(a > b),
                 "<=" => Value::Bool(a <= b),
                 ">=" => Value::Bool(a >= b),
-                _ => Value::Null,
-            },
-            (Value::String(a), Value::String(b)) => match op {
-                "=" => Value::Bool(a == b),
-                "!=" => Value::Bool(a != b),
+                "LIKE" => Value::Bool(collation::like(a, b)),
                 _ => Value::Null,
             },
             (Value::Bool(a), Value::Bool(b)) => match op {
                 "AND" => Value::Bool(*a && *b),
                 "OR" => Value::Bool(*a || *b),
+                "=" => Value::Bool(a == b),
+                "!=" => Value::Bool(a != b),
                 _ => Value::Null,
             },
-            _ => Value::Null,
+            (Value::Array(_), Value::Array(_)) | (Value::Struct(_), Value::Struct(_)) => composite::binop(self, left, op, right).unwrap_or(Value::Null),
+            (Value::Bytes(_) | Value::Uuid(_), _) | (_, Value::Bytes(_) | Value::Uuid(_)) => binary::binop(left, op, right).unwrap_or(Value::Null),
+            _ => temporal::binop(left, op, right).unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// How `execute` runs a query, decided before any row is read.
+struct Plan<'a> {
+    /// The FROM table, then each joined table in textual order.
+    sources: Vec<Source<'a>>,
+    grouped: bool,
+    /// The index serving a lone row table's WHERE clause or ORDER BY.
+    scan: Option<IndexScan<'a>>,
+    joins: Joins<'a>,
+}
+
+/// The order the tables of a query with joins are read in.
+struct Joins<'a> {
+    /// The source read first.
+    first: usize,
+    /// Conditions on the first source alone, applied as it is read.
+    filter: Option<Expr>,
+    steps: Vec<JoinStep<'a>>,
+    /// Planned by cost: the filters and join conditions apply the whole
+    /// WHERE clause.
+    optimized: bool,
+    /// Sources are joined out of textual order, or a hash join builds on
+    /// the left: rows carry their source positions so `execute` can put
+    /// them back in the textual plan's order.
+    restore: bool,
+}
+
+struct JoinStep<'a> {
+    source: usize,
+    on: Expr,
+    /// Conditions on this source alone, applied to its rows before joining.
+    filter: Option<Expr>,
+    strategy: JoinPlan<'a>,
+    /// Estimated rows after the join, for plans made by cost.
+    rows: Option<f64>,
+}
+
+enum JoinPlan<'a> {
+    /// Index nested-loop join into the joined table.
+    Index(&'a Table, IndexLookup<'a>),
+    /// Hash join on these equalities, building the hash table over the
+    /// joined table or, with `build_left`, over the rows joined so far.
+    Hash { keys: Vec<(Expr, Expr)>, build_left: bool },
+    NestedLoop,
+    /// Each row joined so far with the rows UNNEST makes from it.
+    Unnest(&'a Unnest),
+}
+
+/// Whether a value is NULL or a composite holding one, which equals nothing
+/// and so can't be a hash-join key.
+fn holds_null(v: &Value) -> bool {
+    match v {
+        Value::Array(items) => items.iter().any(holds_null),
+        Value::Struct(fields) => fields.iter().any(|(_, v)| holds_null(v)),
+        v => *v == Value::Null,
+    }
+}
+
+/// Equality terms of a join condition usable as hash keys: `(left, right)`
+/// pairs where one side reads only earlier tables and the other only the
+/// joined table. Self-joins under one name fall back to a nested loop.
+fn equi_join_keys(on: &Expr, left: &[Source], right: Source) -> Vec<(Expr, Expr)> {
+    if left.iter().any(|s| s.name() == right.name()) {
+        return Vec::new();
+    }
+    let owns = |s: &Source, name: &str| match name.rsplit_once('.') {
+        Some((t, c)) => t == s.name() && s.columns().iter().any(|col| col.name == c),
+        None => s.columns().iter().any(|col| col.name == name),
+    };
+    // Some(true) for the joined table, Some(false) for earlier tables
+    let side = |e: &Expr| {
+        let cols = expr_columns(e);
+        let in_right = |c: &String| owns(&right, c);
+        let in_left = |c: &String| left.iter().any(|s| owns(s, c)) && !in_right(c);
+        if cols.is_empty() || contains_aggregate(e) {
+            None
+        } else if cols.iter().all(in_right) {
+            Some(true)
+        } else if cols.iter().all(in_left) {
+            Some(false)
+        } else {
+            None
+        }
+    };
+    let mut terms = Vec::new();
+    conjuncts(on, &mut terms);
+    terms
+        .into_iter()
+        .filter_map(|t| match t {
+            Expr::BinOp(l, op, r) if op == "=" => match (side(l), side(r)) {
+                (Some(false), Some(true)) => Some(((**l).clone(), (**r).clone())),
+                (Some(true), Some(false)) => Some(((**r).clone(), (**l).clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// The terms of a chain of ANDs.
+fn conjuncts<'a>(e: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match e {
+        Expr::BinOp(l, op, r) if op == "AND" => {
+            conjuncts(l, out);
+            conjuncts(r, out);
+        }
+        e => out.push(e),
+    }
+}
+
+/// A table read by a query, in either storage layout.
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    Rows(&'a Table),
+    Columnar(&'a ColumnarTable),
+    /// Rows computed from a value; joins to them are planned as written.
+    Unnest(&'a Unnest),
+}
+
+impl Source<'_> {
+    fn name(&self) -> &str {
+        match self {
+            Source::Rows(t) => &t.name,
+            Source::Columnar(t) => &t.name,
+            Source::Unnest(u) => &u.column.name,
+        }
+    }
+
+    fn columns(&self) -> &[Column] {
+        match self {
+            Source::Rows(t) => &t.columns,
+            Source::Columnar(t) => &t.columns,
+            Source::Unnest(u) => std::slice::from_ref(&u.column),
+        }
+    }
+}
+
+/// Whether `name` (plain or `table.col`) refers to column `i` of `table`.
+fn columnar_name_matches(table: &ColumnarTable, i: usize, name: &str) -> bool {
+    let col = &table.columns[i].name;
+    col == name || name.rsplit_once('.').is_some_and(|(t, c)| c == col && t == table.name)
+}
+
+/// A stored table in either layout, as data changes read it.
+#[derive(Clone, Copy)]
+enum Stored<'a> {
+    Rows(&'a Table),
+    Columnar(&'a ColumnarTable),
+}
+
+impl<'a> Stored<'a> {
+    fn name(&self) -> &'a str {
+        match self {
+            Stored::Rows(t) => &t.name,
+            Stored::Columnar(t) => &t.name,
+        }
+    }
+
+    fn constraints(&self) -> &'a [Constraint] {
+        match self {
+            Stored::Rows(t) => &t.constraints,
+            Stored::Columnar(t) => &t.constraints,
+        }
+    }
+
+    fn foreign_keys(&self) -> impl Iterator<Item = (&'a Constraint, &'a ForeignKey)> {
+        foreign_keys(self.constraints())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Stored::Rows(t) => t.rows.len(),
+            Stored::Columnar(t) => t.len(),
+        }
+    }
+
+    /// The table's definition; a columnar table's without its data.
+    fn schema(&self) -> Cow<'a, Table> {
+        match self {
+            Stored::Rows(t) => Cow::Borrowed(t),
+            Stored::Columnar(t) => Cow::Owned(Table { name: t.name.clone(), columns: t.columns.clone(), constraints: t.constraints.clone(), ..Table::default() }),
+        }
+    }
+
+    fn row(&self, i: usize) -> Row {
+        match self {
+            Stored::Rows(t) => t.rows[i].clone(),
+            Stored::Columnar(t) => t.row(i),
+        }
+    }
+
+    fn rows(&self) -> Cow<'a, [Row]> {
+        match self {
+            Stored::Rows(t) => Cow::Borrowed(&t.rows),
+            Stored::Columnar(t) => Cow::Owned(t.to_table().rows),
+        }
+    }
+
+    /// Every row's key over `cols`, as `key_of` gives it; a columnar table
+    /// reads just those columns.
+    fn keys(&self, cols: &[String]) -> Vec<Option<GroupKey>> {
+        match self {
+            Stored::Rows(t) => t.rows.iter().map(|r| key_of(r, cols)).collect(),
+            Stored::Columnar(t) => {
+                let Some(at) = cols.iter().map(|c| t.column_index(c)).collect::<Option<Vec<usize>>>() else { return vec![None; t.len()] };
+                (0..t.len())
+                    .map(|i| {
+                        let key: Vec<Value> = at.iter().map(|&c| t.get(i, c)).collect();
+                        if key.contains(&Value::Null) { None } else { Some(GroupKey(0, key)) }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Next value for an identity column: one past the largest present.
+    fn next_identity(&self, col: &str) -> i64 {
+        match self {
+            Stored::Rows(t) => t.next_identity(col),
+            Stored::Columnar(t) => {
+                let ids = t.column_index(col).into_iter().flat_map(|c| (0..t.len()).map(move |i| t.get(i, c)));
+                ids.filter_map(|v| if let Value::Int(i) = v { Some(i) } else { None }).max().unwrap_or(0) + 1
+            }
+        }
+    }
+}
+
+/// A statement's pending changes to one table, by row position.
+#[derive(Debug, Default)]
+struct Changes {
+    updated: HashMap<usize, Row>,
+    deleted: HashSet<usize>,
+}
+
+/// Pending changes to the tables a statement modifies, keyed by table name.
+/// They are written back only once every constraint has been checked.
+type Staged = HashMap<String, Changes>;
+
+/// Which of the given keys, over the given columns, rows already hold; how
+/// `validate_rows` sees the rows that new ones will sit beside.
+type Taken<'a> = dyn Fn(&[String], &[GroupKey]) -> HashSet<GroupKey> + 'a;
+
+/// Bound on chained ON DELETE / ON UPDATE actions, which can cycle.
+const MAX_CASCADE_DEPTH: usize = 32;
+
+fn foreign_keys(constraints: &[Constraint]) -> impl Iterator<Item = (&Constraint, &ForeignKey)> {
+    constraints.iter().filter_map(|c| match &c.kind {
+        ConstraintKind::ForeignKey(fk) => Some((c, fk)),
+        _ => None,
+    })
+}
+
+/// Key of `row` over `cols`, or `None` if any part is NULL (MATCH SIMPLE).
+fn key_of(row: &Row, cols: &[String]) -> Option<GroupKey> {
+    let key: Vec<Value> = cols.iter().map(|c| row.data.get(c).cloned().unwrap_or(Value::Null)).collect();
+    if key.contains(&Value::Null) { None } else { Some(GroupKey(0, key)) }
+}
+
+struct GroupContext<'a> {
+    keys: &'a [Expr],
+    values: &'a [Value],
+    in_set: &'a [bool],
+    aggregates: &'a [Expr],
+    results: Vec<Value>,
+}
+
+fn func_name(expr: &Expr) -> &str {
+    match expr {
+        Expr::FuncCall(name, _) => name,
+        _ => "",
+    }
+}
+
+/// Column names a query reads from its tables, as written; `None` when it
+/// selects `*`.
+fn query_columns(query: &Query) -> Option<Vec<String>> {
+    if query.select_cols.iter().any(|c| c == "*") {
+        return None;
+    }
+    Some(referenced_columns(query))
+}
+
+/// Column names a query mentions, as written, with `*` for the wildcard.
+fn referenced_columns(query: &Query) -> Vec<String> {
+    let mut names: Vec<String> = query.select_exprs.iter().flat_map(expr_columns).collect();
+    names.extend(query.where_clause.iter().flat_map(expr_columns));
+    names.extend(query.order_by.iter().map(|(c, _, _)| c.clone()));
+    for item in &query.group_by {
+        names.extend(item_grouping_sets(item).iter().flatten().flat_map(expr_columns));
+    }
+    for join in &query.joins {
+        names.extend(expr_columns(&join.on));
+        names.extend(join.unnest.iter().flat_map(|u| expr_columns(&u.expr)));
+    }
+    names
+}
+
+/// Column names referenced by an expression, in order of first appearance.
+fn expr_columns(expr: &Expr) -> Vec<String> {
+    fn walk(expr: &Expr, out: &mut Vec<String>) {
+        match expr {
+            Expr::Column(name) if !out.contains(name) => out.push(name.clone()),
+            Expr::UnaryOp(_, e) => walk(e, out),
+            Expr::BinOp(l, _, r) => {
+                walk(l, out);
+                walk(r, out);
+            }
+            Expr::FuncCall(_, args) => args.iter().for_each(|a| walk(a, out)),
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    walk(expr, &mut out);
+    out
+}
+
+fn rename_column(expr: &mut Expr, from: &str, to: &str) {
+    match expr {
+        Expr::Column(name) if name == from => *name = to.to_string(),
+        Expr::UnaryOp(_, e) => rename_column(e, from, to),
+        Expr::BinOp(l, _, r) => {
+            rename_column(l, from, to);
+            rename_column(r, from, to);
+        }
+        Expr::FuncCall(_, args) => args.iter_mut().for_each(|a| rename_column(a, from, to)),
+        _ => {}
+    }
+}
+
+/// Rows of `table`, with `table.col` keys added alongside the plain ones
+/// when `qualify` is set.
+fn scan(source: Source, qualify: bool) -> Vec<Row> {
+    let rows = match source {
+        Source::Rows(table) => table.rows.clone(),
+        Source::Columnar(table) => (0..table.len()).map(|i| table.row(i)).collect(),
+        Source::Unnest(_) => unreachable!("UNNEST rows are computed by Database::unnest"),
+    };
+    if !qualify {
+        return rows;
+    }
+    rows.iter().map(|row| qualified(row, source.name())).collect()
+}
+
+/// Key under which a joined row carries its position in source `source`;
+/// no column can have this name.
+fn position_key(source: usize) -> String {
+    format!("#{}", source)
+}
+
+/// `row` with each column also under its `table.col` name.
+fn qualified(row: &Row, table: &str) -> Row {
+    let mut data = row.data.clone();
+    for (k, v) in &row.data {
+        data.insert(format!("{}.{}", table, k), v.clone());
+    }
+    Row { data }
+}
+
+/// Static type of an expression over the given source tables; `Any` when it
+/// cannot be known without looking at values.
+fn infer_type(expr: &Expr, sources: &[Source]) -> DataType {
+    match expr {
+        Expr::Column(name) => {
+            let (table, col) = match name.rsplit_once('.') {
+                Some((t, c)) => (Some(t), c),
+                None => (None, name.as_str()),
+            };
+            let column = sources.iter().filter(|t| table.is_none_or(|name| t.name() == name)).find_map(|t| t.columns().iter().find(|c| c.name == col));
+            // Otherwise a struct field, `s.f` or `t.s.f`
+            let field = || {
+                sources.iter().find_map(|t| t.columns().iter().find_map(|c| Some(field_type(&c.data_type, &field_path(name, t.name(), &c.name)?))))
+            };
+            column.map(|c| c.data_type.clone()).or_else(field).unwrap_or(DataType::Any)
         }
+        Expr::Literal(v) => v.data_type(),
+        Expr::UnaryOp(op, _) if op == "NOT" => DataType::Bool,
+        Expr::UnaryOp(_, e) => infer_type(e, sources),
+        Expr::BinOp(l, op, r) => match op.as_str() {
+            "=" | "!=" | "<" | ">" | "<=" | ">=" | "LIKE" | "AND" | "OR" => DataType::Bool,
+            "->" | "#>" => DataType::Json,
+            "->>" | "#>>" => DataType::String,
+            _ => match (infer_type(l, sources), infer_type(r, sources)) {
+                (DataType::Int, DataType::Int) => DataType::Int,
+                (DataType::Int | DataType::Float, DataType::Int | DataType::Float) => DataType::Float,
+                (l, r) => decimal::result_type(&l, op, &r).unwrap_or_else(|| temporal::result_type(&l, op, &r)),
+            },
+        },
+        Expr::FuncCall(name, args) => match name.as_str() {
+            "COUNT" | "GROUPING" | "LENGTH" => DataType::Int,
+            "AVG" => match args.first().map(|a| infer_type(a, sources)) {
+                Some(DataType::Decimal(_, scale)) => DataType::Decimal(MAX_PRECISION, (scale + DIVISION_SCALE).min(MAX_PRECISION)),
+                _ => DataType::Float,
+            },
+            "ROUND" | "TRUNC" => match (args.first().map(|a| infer_type(a, sources)), args.get(1)) {
+                (Some(DataType::Decimal(..)), Some(Expr::Literal(Value::Int(places)))) => DataType::Decimal(MAX_PRECISION, (*places).clamp(0, 38) as u8),
+                (Some(DataType::Decimal(..)), None) => DataType::Decimal(MAX_PRECISION, 0),
+                (Some(ty), _) => ty,
+                (None, _) => DataType::Any,
+            },
+            "UPPER" | "LOWER" | "STRFTIME" | "ENCODE" => DataType::String,
+            "DECODE" => DataType::Bytes,
+            "GEN_RANDOM_UUID" => DataType::Uuid,
+            "NOW" => DataType::TimestampTz,
+            "CAST" => match args.get(1) {
+                Some(Expr::Literal(Value::String(ty))) => Parser::new(tokenize(ty)).parse_data_type().unwrap_or(DataType::Any),
+                _ => DataType::Any,
+            },
+            "JSON_EXTRACT" | "JSON_OBJECT" | "JSON_AGG" => DataType::Json,
+            "JSON_ARRAY_LENGTH" | "CARDINALITY" => DataType::Int,
+            "ARRAY_CONTAINS" => DataType::Bool,
+            "ARRAY" => DataType::Array(Box::new(args.first().map_or(DataType::Any, |a| infer_type(a, sources)))),
+            "ARRAY_AGG" => DataType::Array(Box::new(args.first().map_or(DataType::Any, |a| infer_type(a, sources)))),
+            "STRUCT" => {
+                let fields = args.chunks(2).map(|pair| match pair {
+                    [Expr::Literal(Value::String(name)), v] => Some((name.clone(), infer_type(v, sources))),
+                    _ => None,
+                });
+                fields.collect::<Option<_>>().map_or(DataType::Any, DataType::Struct)
+            }
+            "ELEMENT_AT" => match args.iter().map(|a| infer_type(a, sources)).collect::<Vec<_>>().as_slice() {
+                [DataType::Array(element), _] => (**element).clone(),
+                [ty @ DataType::Struct(_), _] => match &args[1] {
+                    Expr::Literal(Value::String(name)) => field_type(ty, &[name.as_str()]),
+                    _ => DataType::Any,
+                },
+                _ => DataType::Any,
+            },
+            "STRUCT_EXTRACT" => match (args.first().map(|a| infer_type(a, sources)), args.get(1)) {
+                (Some(ty), Some(Expr::Literal(Value::String(name)))) => field_type(&ty, &[name.as_str()]),
+                _ => DataType::Any,
+            },
+            "COLLATE" => args.first().map_or(DataType::Any, |a| infer_type(a, sources)),
+            "DATE_TRUNC" => args.get(1).map_or(DataType::Any, |a| infer_type(a, sources)),
+            "EXTRACT" | "DATE_PART" => match args.first() {
+                Some(Expr::Literal(Value::String(field))) if field.eq_ignore_ascii_case("epoch") => DataType::Float,
+                _ => DataType::Int,
+            },
+            "SUM" | "MIN" | "MAX" | "ABS" | "COALESCE" => {
+                args.first().map_or(DataType::Any, |a| infer_type(a, sources))
+            }
+            _ => DataType::Any,
+        },
     }
+}
 
-    fn compare_values(&self, a: &Value, b: &Value) -> std::cmp::Ordering {
-        use std::cmp::Ordering;
-        match (a, b) {
-            (Value::Int(x), Value::Int(y)) => x.cmp(y),
-            (Value::String(x), Value::String(y)) => x.cmp(y),
-            (Value::Float(x), Value::Float(y)) => {
-                if x < y { Ordering::Less } else if x > y { Ordering::Greater } else { Ordering::Equal }
+/// Replaces `Any` column types with the type of the column's first non-NULL
+/// value, so untyped tables still report useful types.
+fn refine_types(result: &mut ResultSet) {
+    for (i, col) in result.columns.iter_mut().enumerate() {
+        if col.data_type == DataType::Any {
+            if let Some(v) = result.rows.iter().map(|r| &r[i]).find(|v| **v != Value::Null) {
+                col.data_type = v.data_type();
             }
-            _ => Ordering::Equal,
         }
     }
 }
 
+/// Resolves a possibly qualified column name; `users.id` falls back to `id`.
+/// A name may also reach into a struct column, `s.f` or `users.s.f`.
+fn lookup<'a>(row: &'a Row, name: &str) -> Option<&'a Value> {
+    row.data
+        .get(name)
+        .or_else(|| field(row, name))
+        .or_else(|| field(row, name.split_once('.')?.1))
+        .or_else(|| name.rsplit_once('.').and_then(|(_, col)| row.data.get(col)))
+}
+
+/// The struct field `name` reaches from the first of its prefixes that is a
+/// struct column of `row`.
+fn field<'a>(row: &'a Row, name: &str) -> Option<&'a Value> {
+    name.match_indices('.').find_map(|(i, _)| {
+        let mut v = row.data.get(&name[..i]).filter(|v| matches!(v, Value::Struct(_)))?;
+        for f in name[i + 1..].split('.') {
+            v = composite::field(v, f)?;
+        }
+        Some(v)
+    })
+}
+
+/// The fields `name` reads inside column `col` of `table`: `s.a.b` and
+/// `t.s.a.b` both give `[a, b]`. `None` if `name` is not a field of `col`.
+pub(crate) fn field_path<'n>(name: &'n str, table: &str, col: &str) -> Option<Vec<&'n str>> {
+    let unqualified = name.strip_prefix(table).and_then(|rest| rest.strip_prefix('.'));
+    [Some(name), unqualified].into_iter().flatten().find_map(|name| Some(name.strip_prefix(col)?.strip_prefix('.')?.split('.').collect()))
+}
+
+/// Type of the field at `path` inside a struct type.
+fn field_type(ty: &DataType, path: &[&str]) -> DataType {
+    match (ty, path) {
+        (ty, []) => ty.clone(),
+        (DataType::Struct(fields), [f, rest @ ..]) => fields.iter().find(|(name, _)| name == f).map_or(DataType::Any, |(_, ty)| field_type(ty, rest)),
+        _ => DataType::Any,
+    }
+}
+
 impl Value {
     fn is_true(&self) -> bool {
         matches!(self, Value::Bool(true))
     }
+
+    /// Type of this value; NULL has no type of its own.
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Value::Int(_) => DataType::Int,
+            Value::Float(_) => DataType::Float,
+            Value::Decimal(d) => DataType::Decimal(MAX_PRECISION, d.scale()),
+            Value::String(_) => DataType::String,
+            Value::Bool(_) => DataType::Bool,
+            Value::Date(_) => DataType::Date,
+            Value::Time(_) => DataType::Time,
+            Value::Timestamp(_) => DataType::Timestamp,
+            Value::TimestampTz(_) => DataType::TimestampTz,
+            Value::Interval(_) => DataType::Interval,
+            Value::Json(_) => DataType::Json,
+            Value::Array(items) => composite::array_type(items),
+            Value::Struct(fields) => DataType::Struct(fields.iter().map(|(name, v)| (name.clone(), v.data_type())).collect()),
+            Value::Bytes(_) => DataType::Bytes,
+            Value::Uuid(_) => DataType::Uuid,
+            Value::Null => DataType::Any,
+        }
+    }
+
+    /// The value as text without SQL quoting, as CAST(... AS TEXT) gives it.
+    fn text(&self) -> String {
+        match self {
+            Value::String(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::Decimal(d) => d.to_string(),
+            Value::Date(d) => d.to_string(),
+            Value::Time(t) => t.to_string(),
+            Value::Timestamp(t) => t.to_string(),
+            Value::TimestampTz(t) => format!("{}+00", t),
+            Value::Interval(i) => i.to_string(),
+            Value::Json(j) => j.to_string(),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.text()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Value::Struct(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, v)| format!("{}: {}", name, v.text())).collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            Value::Bytes(b) => format!("\\x{}", binary::hex(b)),
+            Value::Uuid(u) => u.to_string(),
+            v => v.to_string(),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            Value::Decimal(d) => Some(d.to_f64()),
+            _ => None,
+        }
+    }
 }
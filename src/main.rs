@@ -16,6 +16,7 @@ fn main() {
             row(vec![("id", int(3)), ("name", string("Carol")), ("age", int(35)), ("dept", string("Engineering"))]),
             row(vec![("id", int(4)), ("name", string("David")), ("age", int(28)), ("dept", string("Sales"))]),
         ],
+        ..Default::default()
     };
 
     let orders = Table {
@@ -27,10 +28,11 @@ fn main() {
             row(vec![("order_id", int(103)), ("user_id", int(1)), ("amount", int(700))]),
             row(vec![("order_id", int(104)), ("user_id", int(3)), ("amount", int(450))]),
         ],
+        ..Default::default()
     };
 
-    db.add_table(users);
-    db.add_table(orders);
+    db.add_table(users).unwrap();
+    db.add_table(orders).unwrap();
 
     // Test 1: Simple SELECT
     test_query(&db, "SELECT id, name FROM users");
@@ -56,6 +58,9 @@ fn main() {
     // Test 8: Multiple conditions
     test_query(&db, "SELECT name, age FROM users WHERE age > 25 AND dept = 'Sales'");
 
+    // Test 9: Subtotals with ROLLUP
+    test_query(&db, "SELECT dept, COUNT(*) AS headcount, GROUPING(dept) AS is_total FROM users GROUP BY ROLLUP(dept)");
+
     println!("\n=== All tests completed! ===");
 }
 
@@ -64,16 +69,14 @@ fn test_query(db: &Database, sql: &str) {
     match parse(sql) {
         Ok(query) => {
             match db.execute(&query) {
-                Ok(rows) => {
-                    if rows.is_empty() {
+                Ok(result) => {
+                    println!("  {}", result.column_names().join(" | "));
+                    if result.is_empty() {
                         println!("  (no results)");
-                    } else {
-                        let cols: Vec<&String> = rows[0].data.keys().collect();
-                        println!("  {}", cols.join(" | "));
-                        for row in rows {
-                            let vals: Vec<String> = cols.iter().map(|c| format_value(row.data.get(*c))).collect();
-                            println!("  {}", vals.join(" | "));
-                        }
+                    }
+                    for row in result.iter() {
+                        let vals: Vec<String> = row.values().iter().map(|v| format_value(Some(v))).collect();
+                        println!("  {}", vals.join(" | "));
                     }
                 }
                 Err(e) => println!("  ERROR: {}", e),
@@ -87,8 +90,22 @@ fn format_value(v: Option<&Value>) -> String {
     match v {
         Some(Value::Int(i)) => i.to_string(),
         Some(Value::Float(f)) => f.to_string(),
+        Some(Value::Decimal(d)) => d.to_string(),
         Some(Value::String(s)) => s.clone(),
         Some(Value::Bool(b)) => b.to_string(),
+        Some(Value::Date(d)) => d.to_string(),
+        Some(Value::Time(t)) => t.to_string(),
+        Some(Value::Timestamp(t)) => t.to_string(),
+        Some(Value::TimestampTz(t)) => format!("{}+00", t),
+        Some(Value::Interval(i)) => i.to_string(),
+        Some(Value::Json(j)) => j.to_string(),
+        Some(Value::Array(items)) => format!("[{}]", items.iter().map(|v| format_value(Some(v))).collect::<Vec<_>>().join(", ")),
+        Some(Value::Struct(fields)) => {
+            let fields: Vec<String> = fields.iter().map(|(name, v)| format!("{}: {}", name, format_value(Some(v)))).collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        Some(Value::Bytes(b)) => format!("\\x{}", b.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        Some(Value::Uuid(u)) => u.to_string(),
         Some(Value::Null) | None => "NULL".into(),
     }
 }
@@ -136,7 +153,21 @@ mod tests {
     #[test]
     fn test_parse_group_by() {
         let q = parse("SELECT dept FROM users GROUP BY dept").unwrap();
-        assert_eq!(q.group_by, vec!["dept"]);
+        assert_eq!(q.group_by, vec![GroupByItem::Expr(Expr::Column("dept".into()))]);
+    }
+
+    #[test]
+    fn test_parse_grouping_sets() {
+        let q = parse("SELECT dept, age FROM users GROUP BY GROUPING SETS ((dept, age), dept, ()), ROLLUP(age)").unwrap();
+        assert_eq!(q.group_by.len(), 2);
+        match &q.group_by[0] {
+            GroupByItem::GroupingSets(elems) => {
+                assert_eq!(elems.len(), 3);
+                assert_eq!(elems[2], GroupByItem::Set(vec![]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(q.group_by[1], GroupByItem::Rollup(vec![Expr::Column("age".into())]));
     }
 
     #[test]
@@ -162,8 +193,9 @@ mod tests {
                 row(vec![("id", int(1)), ("name", string("Alice"))]),
                 row(vec![("id", int(2)), ("name", string("Bob"))]),
             ],
+            ..Default::default()
         };
-        db.add_table(users);
+        db.add_table(users).unwrap();
 
         let q = parse("SELECT * FROM users").unwrap();
         let result = db.execute(&q).unwrap();
@@ -180,8 +212,9 @@ mod tests {
                 row(vec![("id", int(1)), ("age", int(25))]),
                 row(vec![("id", int(2)), ("age", int(35))]),
             ],
+            ..Default::default()
         };
-        db.add_table(users);
+        db.add_table(users).unwrap();
 
         let q = parse("SELECT * FROM users WHERE age > 30").unwrap();
         let result = db.execute(&q).unwrap();
@@ -198,12 +231,13 @@ mod tests {
                 row(vec![("name", string("Alice")), ("age", int(30))]),
                 row(vec![("name", string("Bob")), ("age", int(25))]),
             ],
+            ..Default::default()
         };
-        db.add_table(users);
+        db.add_table(users).unwrap();
 
         let q = parse("SELECT * FROM users ORDER BY age ASC").unwrap();
         let result = db.execute(&q).unwrap();
-        if let Value::Int(age) = result[0].data.get("age").unwrap() {
+        if let Value::Int(age) = result.get(0, "age").unwrap() {
             assert_eq!(*age, 25);
         }
     }
@@ -219,14 +253,2021 @@ mod tests {
                 row(vec![("id", int(2))]),
                 row(vec![("id", int(3))]),
             ],
+            ..Default::default()
         };
-        db.add_table(users);
+        db.add_table(users).unwrap();
 
         let q = parse("SELECT * FROM users LIMIT 2").unwrap();
         let result = db.execute(&q).unwrap();
         assert_eq!(result.len(), 2);
     }
 
+    fn sales_db() -> Database {
+        let mut db = Database::new();
+        db.add_table(Table {
+            name: "sales".into(),
+            columns: vec!["region".into(), "product".into(), "amount".into()],
+            rows: vec![
+                row(vec![("region", string("East")), ("product", string("A")), ("amount", int(10))]),
+                row(vec![("region", string("East")), ("product", string("B")), ("amount", int(20))]),
+                row(vec![("region", string("West")), ("product", string("A")), ("amount", int(5))]),
+            ],
+            ..Default::default()
+        })
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_execute_rollup() {
+        let db = sales_db();
+        let q = parse(
+            "SELECT region, product, SUM(amount) AS total, GROUPING(region, product) AS g \
+             FROM sales GROUP BY ROLLUP(region, product)",
+        )
+        .unwrap();
+        let result = db.execute(&q).unwrap();
+        // 3 detail rows, 2 region subtotals, 1 grand total
+        assert_eq!(result.len(), 6);
+        let grand: Vec<ResultRow> = result.iter().filter(|r| r["g"] == int(3)).collect();
+        assert_eq!(grand.len(), 1);
+        assert_eq!(grand[0]["total"], int(35));
+        assert_eq!(grand[0]["region"], Value::Null);
+        let east = result
+            .iter()
+            .find(|r| r["g"] == int(1) && r["region"] == string("East"))
+            .unwrap();
+        assert_eq!(east["total"], int(30));
+    }
+
+    #[test]
+    fn test_execute_cube_and_grouping_sets() {
+        let db = sales_db();
+        let q = parse("SELECT region, product, COUNT(*) FROM sales GROUP BY CUBE(region, product)").unwrap();
+        // 3 (region, product) + 2 region + 2 product + 1 total
+        assert_eq!(db.execute(&q).unwrap().len(), 8);
+
+        let q = parse("SELECT region, product, COUNT(*) AS n FROM sales GROUP BY GROUPING SETS (region, product)").unwrap();
+        let result = db.execute(&q).unwrap();
+        assert_eq!(result.len(), 4);
+        let a = result.iter().find(|r| r["product"] == string("A")).unwrap();
+        assert_eq!(a["n"], int(2));
+        assert_eq!(a["region"], Value::Null);
+    }
+
+    #[test]
+    fn test_execute_group_by_ordinal_and_expression() {
+        let db = sales_db();
+        let q = parse("SELECT amount / 10 AS bucket, COUNT(*) AS n FROM sales GROUP BY 1 ORDER BY bucket").unwrap();
+        let result = db.execute(&q).unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.row(0).unwrap()["bucket"], int(0));
+        assert_eq!(result.row(0).unwrap()["n"], int(1));
+
+        let q = parse("SELECT COUNT(*) AS n FROM sales WHERE amount > 100").unwrap();
+        let result = db.execute(&q).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result.row(0).unwrap()["n"], int(0));
+
+        assert!(db.execute(&parse("SELECT region, amount FROM sales GROUP BY region").unwrap()).is_err());
+
+        // An overflowed sum stays NULL rather than restarting at the next value
+        let mut db = Database::new();
+        db.execute_statement(&parse_statement("CREATE TABLE big (n INT)").unwrap()).unwrap();
+        db.execute_statement(&parse_statement("INSERT INTO big VALUES (9223372036854775807), (1), (5)").unwrap()).unwrap();
+        let result = db.execute(&parse("SELECT SUM(n) AS total FROM big").unwrap()).unwrap();
+        assert_eq!(result.row(0).unwrap()["total"], Value::Null);
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        assert!(matches!(parse_statement("SELECT * FROM t").unwrap(), Statement::Select(_)));
+        match parse_statement("INSERT INTO t (a, b) VALUES (1, 'x'), (2, 'y') RETURNING a").unwrap() {
+            Statement::Insert(ins) => {
+                assert_eq!(ins.columns, vec!["a", "b"]);
+                assert!(matches!(ins.source, InsertSource::Values(ref rows) if rows.len() == 2));
+                assert_eq!(ins.returning.len(), 1);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(parse_statement("UPDATE t SET a = a + 1 WHERE b = 'x'").unwrap(), Statement::Update(_)));
+        assert!(matches!(parse_statement("DELETE FROM t").unwrap(), Statement::Delete(_)));
+        assert!(parse_statement("DELETE t").is_err());
+    }
+
+    #[test]
+    fn test_execute_dml() {
+        let mut db = sales_db();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+
+        let res = run(&mut db, "INSERT INTO sales (region, amount) VALUES ('North', 7), ('North', 8) RETURNING region, amount * 2 AS double");
+        assert_eq!(res.rows_affected, 2);
+        assert_eq!(res.rows.row(1).unwrap()["double"], int(16));
+        assert_eq!(db.tables["sales"].rows[3].data["product"], Value::Null);
+
+        let res = run(&mut db, "UPDATE sales SET amount = amount + 1 WHERE region = 'North'");
+        assert_eq!(res.rows_affected, 2);
+        assert!(res.rows.is_empty());
+
+        let res = run(&mut db, "DELETE FROM sales WHERE amount < 10 RETURNING *");
+        assert_eq!(res.rows_affected, 3);
+        assert_eq!(res.rows.len(), 3);
+        assert_eq!(db.tables["sales"].rows.len(), 2);
+
+        let res = run(&mut db, "INSERT INTO sales SELECT region, product, amount FROM sales WHERE amount > 15");
+        assert_eq!(res.rows_affected, 1);
+        assert_eq!(db.tables["sales"].rows.len(), 3);
+
+        let bad = parse_statement("INSERT INTO sales (region) VALUES ('a'), ('b', 1)").unwrap();
+        assert!(db.execute_statement(&bad).is_err());
+        assert_eq!(db.tables["sales"].rows.len(), 3);
+    }
+
+    #[test]
+    fn test_create_table_typed_insert() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+
+        run(&mut db, "CREATE TABLE items (id INT, name VARCHAR(40), price FLOAT, active BOOLEAN)").unwrap();
+        assert_eq!(db.tables["items"].columns[2], Column::new("price", DataType::Float));
+        assert!(run(&mut db, "CREATE TABLE items (id INT)").is_err());
+        run(&mut db, "CREATE TABLE IF NOT EXISTS items (id INT)").unwrap();
+        assert!(parse_statement("CREATE TABLE bad (id WIDGET)").is_err());
+
+        run(&mut db, "INSERT INTO items VALUES (1, 'pen', 2, TRUE), (2.0, 'cup', 3.5, NULL)").unwrap();
+        let rows = &db.tables["items"].rows;
+        assert_eq!(rows[0].data["price"], Value::Float(2.0));
+        assert_eq!(rows[1].data["id"], int(2));
+
+        assert!(run(&mut db, "INSERT INTO items (id, name) VALUES ('three', 'x')").is_err());
+        assert!(run(&mut db, "INSERT INTO items (id, nope) VALUES (3, 'x')").is_err());
+        assert!(run(&mut db, "UPDATE items SET active = 1").is_err());
+        assert_eq!(db.tables["items"].rows.len(), 2);
+    }
+
+    #[test]
+    fn test_alter_and_drop_table() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+
+        run(&mut db, "CREATE TABLE t (a INT, b TEXT)").unwrap();
+        run(&mut db, "INSERT INTO t VALUES (1, 'x')").unwrap();
+        run(&mut db, "ALTER TABLE t ADD COLUMN c FLOAT").unwrap();
+        assert_eq!(db.tables["t"].rows[0].data["c"], Value::Null);
+        run(&mut db, "ALTER TABLE t DROP COLUMN b").unwrap();
+        run(&mut db, "ALTER TABLE t RENAME COLUMN a TO id").unwrap();
+        let names: Vec<&str> = db.tables["t"].columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["id", "c"]);
+        assert_eq!(db.tables["t"].rows[0].data["id"], int(1));
+        assert!(!db.tables["t"].rows[0].data.contains_key("b"));
+
+        run(&mut db, "ALTER TABLE t RENAME TO u").unwrap();
+        assert!(db.tables.contains_key("u") && !db.tables.contains_key("t"));
+        assert!(run(&mut db, "DROP TABLE t").is_err());
+        run(&mut db, "DROP TABLE IF EXISTS t").unwrap();
+        run(&mut db, "DROP TABLE u").unwrap();
+        assert!(db.tables.is_empty());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+
+        run(
+            &mut db,
+            "CREATE TABLE accounts (id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY, email TEXT NOT NULL UNIQUE, \
+             region TEXT DEFAULT 'east', handle TEXT, balance INT DEFAULT 0 CONSTRAINT positive CHECK (balance >= 0), \
+             UNIQUE (region, handle))",
+        )
+        .unwrap();
+        let names: Vec<&str> = db.tables["accounts"].constraints.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["accounts_pkey", "accounts_email_key", "positive", "accounts_region_handle_key"]);
+
+        let res = run(&mut db, "INSERT INTO accounts (email, handle) VALUES ('a@x', 'al'), ('b@x', NULL) RETURNING id, region, balance").unwrap();
+        assert_eq!(res.rows.row(1).unwrap()["id"], int(2));
+        assert_eq!(res.rows.row(0).unwrap()["region"], string("east"));
+        assert_eq!(res.rows.row(0).unwrap()["balance"], int(0));
+
+        let err = run(&mut db, "INSERT INTO accounts (email) VALUES ('a@x')").unwrap_err();
+        assert!(err.contains("accounts_email_key"), "{}", err);
+        let err = run(&mut db, "INSERT INTO accounts (email, balance) VALUES ('c@x', -5)").unwrap_err();
+        assert!(err.contains("positive"), "{}", err);
+        let err = run(&mut db, "INSERT INTO accounts (handle) VALUES ('zz')").unwrap_err();
+        assert!(err.contains("accounts.email"), "{}", err);
+        let err = run(&mut db, "INSERT INTO accounts (email, handle) VALUES ('c@x', 'al')").unwrap_err();
+        assert!(err.contains("accounts_region_handle_key"), "{}", err);
+        assert!(run(&mut db, "INSERT INTO accounts (id, email) VALUES (9, 'c@x')").is_err());
+
+        // Composite UNIQUE ignores rows with a NULL in the key
+        run(&mut db, "INSERT INTO accounts (email, handle) VALUES ('c@x', NULL)").unwrap();
+        assert!(run(&mut db, "UPDATE accounts SET email = 'a@x' WHERE email = 'b@x'").is_err());
+        run(&mut db, "UPDATE accounts SET balance = balance + 10").unwrap();
+        assert_eq!(db.tables["accounts"].rows.len(), 3);
+
+        // add_table enforces the same rules
+        let mut dup = Table::new("dup", vec![Column::new("k", DataType::Int)]);
+        dup.constraints.push(Constraint { name: "dup_pkey".into(), kind: ConstraintKind::PrimaryKey(vec!["k".into()]) });
+        dup.rows = vec![row(vec![("k", int(1))]), row(vec![("k", Value::Float(1.0))])];
+        let err = db.add_table(dup).unwrap_err();
+        assert!(err.contains("dup_pkey"), "{}", err);
+    }
+
+    fn fk_db(on_delete: &str, on_update: &str) -> Database {
+        let mut db = Database::new();
+        for sql in [
+            "CREATE TABLE users (id INT PRIMARY KEY, name TEXT)".to_string(),
+            format!(
+                "CREATE TABLE orders (order_id INT PRIMARY KEY, user_id INT REFERENCES users ON DELETE {} ON UPDATE {}, amount INT)",
+                on_delete, on_update
+            ),
+            "INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob')".to_string(),
+            "INSERT INTO orders VALUES (101, 1, 500), (102, 2, 300), (103, 1, 700)".to_string(),
+        ] {
+            db.execute_statement(&parse_statement(&sql).unwrap()).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_foreign_key_checks() {
+        let mut db = fk_db("NO ACTION", "NO ACTION");
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+
+        let err = run(&mut db, "INSERT INTO orders VALUES (104, 9, 10)").unwrap_err();
+        assert!(err.contains("orders_user_id_fkey"), "{}", err);
+        run(&mut db, "INSERT INTO orders VALUES (104, NULL, 10)").unwrap();
+        assert!(run(&mut db, "UPDATE orders SET user_id = 3 WHERE order_id = 101").is_err());
+        assert!(run(&mut db, "DELETE FROM users WHERE id = 1").is_err());
+        assert!(run(&mut db, "UPDATE users SET id = 5 WHERE id = 2").is_err());
+        assert!(run(&mut db, "DROP TABLE users").is_err());
+        assert!(run(&mut db, "ALTER TABLE users DROP COLUMN id").is_err());
+        assert_eq!(db.tables["users"].rows.len(), 2);
+
+        // Renames follow through to the referencing constraint
+        run(&mut db, "ALTER TABLE users RENAME TO people").unwrap();
+        run(&mut db, "ALTER TABLE people RENAME COLUMN id TO person_id").unwrap();
+        assert!(run(&mut db, "INSERT INTO orders VALUES (105, 7, 1)").is_err());
+        run(&mut db, "INSERT INTO orders VALUES (105, 2, 1)").unwrap();
+
+        assert!(run(&mut db, "CREATE TABLE bad (x INT REFERENCES people (name))").is_err());
+        assert!(run(&mut db, "CREATE TABLE bad (x INT REFERENCES nowhere)").is_err());
+        assert!(!db.tables.contains_key("bad"));
+    }
+
+    #[test]
+    fn test_foreign_key_actions() {
+        let mut db = fk_db("CASCADE", "CASCADE");
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        run(&mut db, "UPDATE users SET id = 10 WHERE id = 1");
+        let moved = db.tables["orders"].rows.iter().filter(|r| r.data["user_id"] == int(10)).count();
+        assert_eq!(moved, 2);
+        let res = run(&mut db, "DELETE FROM users WHERE id = 10");
+        assert_eq!(res.rows_affected, 1);
+        assert_eq!(db.tables["orders"].rows.len(), 1);
+
+        let mut db = fk_db("SET NULL", "RESTRICT");
+        run(&mut db, "DELETE FROM users WHERE name = 'Alice'");
+        let orphans = db.tables["orders"].rows.iter().filter(|r| r.data["user_id"] == Value::Null).count();
+        assert_eq!(orphans, 2);
+        assert!(db.execute_statement(&parse_statement("UPDATE users SET id = 3").unwrap()).is_err());
+
+        // Actions over many child rows leave each row's other columns with it
+        let mut db = Database::new();
+        run(&mut db, "CREATE TABLE teams (id INT PRIMARY KEY)");
+        run(&mut db, "CREATE TABLE players (id INT PRIMARY KEY, team INT REFERENCES teams ON UPDATE CASCADE ON DELETE SET NULL, num INT)");
+        run(&mut db, "INSERT INTO teams VALUES (1), (2)");
+        run(&mut db, "INSERT INTO players VALUES (1, 1, 10), (2, 2, 20), (3, 1, 30), (4, 2, 40), (5, 1, 50), (6, 2, 60), (7, 1, 70), (8, 2, 80)");
+        run(&mut db, "UPDATE teams SET id = id + 10");
+        run(&mut db, "DELETE FROM teams WHERE id = 12");
+        for (i, r) in db.tables["players"].rows.iter().enumerate() {
+            let id = i as i64 + 1;
+            let team = if id % 2 == 1 { int(11) } else { Value::Null };
+            assert_eq!((&r.data["id"], &r.data["team"], &r.data["num"]), (&int(id), &team, &int(id * 10)));
+        }
+
+        // Self-reference: deleting a manager cascades down the chain
+        let mut db = Database::new();
+        run(&mut db, "CREATE TABLE staff (id INT PRIMARY KEY, boss INT REFERENCES staff (id) ON DELETE CASCADE)");
+        run(&mut db, "INSERT INTO staff VALUES (1, NULL), (2, 1), (3, 2), (4, NULL)");
+        run(&mut db, "DELETE FROM staff WHERE id = 1");
+        assert_eq!(db.tables["staff"].rows.len(), 1);
+
+        // add_table checks references against existing tables
+        let mut db = fk_db("NO ACTION", "NO ACTION");
+        let mut refunds = Table::new("refunds", vec![Column::new("order_id", DataType::Int)]);
+        refunds.constraints.push(Constraint {
+            name: "refunds_order_fkey".into(),
+            kind: ConstraintKind::ForeignKey(ForeignKey {
+                columns: vec!["order_id".into()],
+                ref_table: "orders".into(),
+                ref_columns: vec![],
+                on_delete: RefAction::Restrict,
+                on_update: RefAction::NoAction,
+                deferred: false,
+            }),
+        });
+        refunds.rows = vec![row(vec![("order_id", int(999))])];
+        assert!(db.add_table(refunds.clone()).unwrap_err().contains("refunds_order_fkey"));
+        refunds.rows = vec![row(vec![("order_id", int(101))])];
+        db.add_table(refunds).unwrap();
+        assert!(db.execute_statement(&parse_statement("DELETE FROM orders WHERE order_id = 101").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_result_set_schema() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        run(&mut db, "CREATE TABLE users (id INT, name TEXT, age INT)");
+        run(&mut db, "CREATE TABLE orders (id INT, user_id INT, amount FLOAT)");
+
+        // Empty results still report their columns, in declared order for *
+        let result = db.execute(&parse("SELECT * FROM users").unwrap()).unwrap();
+        assert!(result.is_empty());
+        assert_eq!(result.column_names(), vec!["id", "name", "age"]);
+        assert_eq!(result.columns[1].data_type, DataType::String);
+
+        run(&mut db, "INSERT INTO users VALUES (1, 'Alice', 30), (2, 'Bob', 25)");
+        run(&mut db, "INSERT INTO orders VALUES (10, 1, 5.5)");
+        let result = db.execute(&parse("SELECT age, name, age > 26 AS senior, COUNT(*) AS n FROM users GROUP BY age, name ORDER BY age").unwrap()).unwrap();
+        assert_eq!(result.column_names(), vec!["age", "name", "senior", "n"]);
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::Int, DataType::String, DataType::Bool, DataType::Int]);
+        assert_eq!(result.rows[0], vec![int(25), string("Bob"), Value::Bool(false), int(1)]);
+        let first = result.row(0).unwrap();
+        assert_eq!(first[1], string("Bob"));
+        assert_eq!(first.get("senior"), Some(&Value::Bool(false)));
+        assert_eq!(result.column_index("users.name"), Some(1));
+
+        // Same-named columns from joined tables stay distinct
+        let result = db.execute(&parse("SELECT * FROM users JOIN orders ON users.id = orders.user_id").unwrap()).unwrap();
+        assert_eq!(result.column_names(), vec!["id", "name", "age", "id", "user_id", "amount"]);
+        assert_eq!(result.rows[0][0], int(1));
+        assert_eq!(result.rows[0][3], int(10));
+        let result = db.execute(&parse("SELECT orders.id, users.id FROM users JOIN orders ON users.id = orders.user_id").unwrap()).unwrap();
+        assert_eq!(result.rows[0], vec![int(10), int(1)]);
+
+        let rows = result.to_rows();
+        assert_eq!(rows[0].data["orders.id"], int(10));
+    }
+
+    #[test]
+    fn test_columnar_tables() {
+        let mut db = fk_db("CASCADE", "NO ACTION");
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+
+        run(&mut db, "CREATE TABLE events (id INT PRIMARY KEY, kind TEXT NOT NULL, score FLOAT) USING COLUMNAR").unwrap();
+        assert!(db.columnar.contains_key("events") && !db.tables.contains_key("events"));
+        run(&mut db, "INSERT INTO events VALUES (1, 'click', 1.5), (2, 'view', NULL), (3, 'click', 4)").unwrap();
+        assert!(run(&mut db, "INSERT INTO events VALUES (1, 'dup', 0)").is_err());
+        assert!(run(&mut db, "CREATE TABLE events (x INT)").is_err());
+
+        let events = &db.columnar["events"];
+        assert_eq!(events.len(), 3);
+        assert_eq!(events.vector(2).null_count(), 1);
+        assert_eq!(events.vector(0).data, ColumnData::Int(vec![1, 2, 3]));
+
+        let result = db.execute(&parse("SELECT kind, SUM(score) AS total FROM events WHERE id > 0 GROUP BY kind ORDER BY kind").unwrap()).unwrap();
+        assert_eq!(result.rows, vec![vec![string("click"), Value::Float(5.5)], vec![string("view"), Value::Null]]);
+        let result = db.execute(&parse("SELECT * FROM events WHERE kind = 'view'").unwrap()).unwrap();
+        assert_eq!(result.column_names(), vec!["id", "kind", "score"]);
+        assert_eq!(result.rows, vec![vec![int(2), string("view"), Value::Null]]);
+
+        // UPDATE and DELETE change the vectors in place
+        run(&mut db, "UPDATE events SET kind = 'tap', score = score * 2 WHERE kind = 'click'").unwrap();
+        assert!(run(&mut db, "UPDATE events SET id = 1 WHERE id = 2").is_err());
+        assert!(run(&mut db, "UPDATE events SET kind = NULL").is_err());
+        let result = db.execute(&parse("SELECT id, kind, score FROM events ORDER BY id").unwrap()).unwrap();
+        assert_eq!(result.rows[2], vec![int(3), string("tap"), Value::Float(8.0)]);
+        assert_eq!(result.rows[1], vec![int(2), string("view"), Value::Null]);
+        run(&mut db, "DELETE FROM events WHERE kind = 'view'").unwrap();
+        assert_eq!(db.columnar["events"].vector(0).data, ColumnData::Int(vec![1, 3]));
+        run(&mut db, "ALTER TABLE events ADD COLUMN hits INT DEFAULT 0").unwrap();
+        assert_eq!(db.columnar["events"].vector(3).data, ColumnData::Int(vec![0, 0]));
+
+        run(&mut db, "CREATE TABLE nodes (id INT PRIMARY KEY, parent INT REFERENCES nodes ON DELETE CASCADE) USING COLUMNAR").unwrap();
+        run(&mut db, "INSERT INTO nodes VALUES (1, NULL), (2, 1), (3, 2)").unwrap();
+        assert!(run(&mut db, "INSERT INTO nodes VALUES (4, 9)").is_err());
+        run(&mut db, "INSERT INTO nodes VALUES (4, 3)").unwrap();
+        run(&mut db, "DELETE FROM nodes WHERE id = 2").unwrap();
+        assert_eq!(db.columnar["nodes"].vector(0).data, ColumnData::Int(vec![1]));
+
+        // Converting a table keeps its rows, constraints and cascades
+        db.set_columnar("orders", true).unwrap();
+        let result = db.execute(&parse("SELECT name, amount FROM users JOIN orders ON users.id = orders.user_id ORDER BY amount").unwrap()).unwrap();
+        assert_eq!(result.rows[0], vec![string("Bob"), int(300)]);
+        run(&mut db, "DELETE FROM users WHERE id = 1").unwrap();
+        assert_eq!(db.columnar["orders"].len(), 1);
+        assert!(run(&mut db, "INSERT INTO orders VALUES (104, 9, 1)").is_err());
+        assert_eq!(db.columnar["orders"].len(), 1);
+        db.set_columnar("users", true).unwrap();
+        assert!(run(&mut db, "UPDATE users SET id = 5 WHERE id = 2").is_err());
+        run(&mut db, "INSERT INTO users VALUES (3, 'Cy')").unwrap();
+        run(&mut db, "INSERT INTO orders VALUES (104, 3, 50)").unwrap();
+        run(&mut db, "DELETE FROM users WHERE id = 3").unwrap();
+        assert_eq!((db.columnar["users"].len(), db.columnar["orders"].len()), (1, 1));
+        db.set_columnar("users", false).unwrap();
+
+        run(&mut db, "ALTER TABLE events RENAME TO clicks").unwrap();
+        assert!(db.columnar.contains_key("clicks"));
+        run(&mut db, "DROP TABLE clicks").unwrap();
+        assert!(!db.has_table("clicks"));
+
+        db.set_columnar("orders", false).unwrap();
+        assert_eq!(db.tables["orders"].rows[0].data["amount"], int(300));
+        let table = ColumnarTable::from_table(&db.tables["users"]).unwrap();
+        assert_eq!(table.to_table().rows[0].data, db.tables["users"].rows[0].data);
+    }
+
+    #[test]
+    fn test_vectorized_filters() {
+        // The same data as a row table and a columnar table, spanning several
+        // batches, must filter identically
+        let columns = vec![
+            Column::new("id", DataType::Int),
+            Column::new("score", DataType::Float),
+            Column::new("tag", DataType::String),
+            Column::new("flag", DataType::Bool),
+            Column::from("misc"),
+        ];
+        let mut rows_table = Table::new("r", columns.clone());
+        for i in 0..(BATCH_SIZE as i64 * 2 + 17) {
+            let null_if = |n: i64, v: Value| if i % n == 0 { Value::Null } else { v };
+            rows_table.rows.push(row(vec![
+                ("id", int(i)),
+                ("score", null_if(7, Value::Float(i as f64 / 4.0))),
+                ("tag", null_if(11, string(["a", "b", "c"][i as usize % 3]))),
+                ("flag", null_if(5, Value::Bool(i % 2 == 0))),
+                ("misc", if i % 3 == 0 { int(i) } else { string("x") }),
+            ]));
+        }
+        let mut db = Database::new();
+        let mut columnar = ColumnarTable::from_table(&rows_table).unwrap();
+        columnar.name = "c".into();
+        db.add_table(rows_table).unwrap();
+        db.add_columnar_table(columnar).unwrap();
+
+        for cond in [
+            "id % 3 = 1 AND score > 100",
+            "score * 2 >= id / 2 OR tag = 'b'",
+            "'b' < tag AND NOT flag",
+            "-id < -4000 OR flag = TRUE",
+            "COALESCE(score, 0.0) < 3 AND LENGTH(tag) = 1",
+            "misc = 'x' AND id + 9223372036854775800 > 0",
+            "misc > 4095 AND COALESCE(id / 0, 1) = 1",
+            "tag != 'a' AND flag != FALSE AND id <= 2.5 * 40",
+        ] {
+            let run = |table: &str| db.execute(&parse(&format!("SELECT id FROM {} WHERE {} ORDER BY id", table, cond)).unwrap()).unwrap().rows;
+            let expected = run("r");
+            assert!(!expected.is_empty(), "{}", cond);
+            assert_eq!(run("c"), expected, "{}", cond);
+        }
+    }
+
+    #[test]
+    fn test_parallel_execution() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        run(&mut db, "CREATE TABLE users (id INT PRIMARY KEY, region TEXT)");
+        run(&mut db, "CREATE TABLE orders (id INT, user_id INT, amount FLOAT)");
+        let users: Vec<String> = (0..20).map(|i| format!("({}, '{}')", i, ["n", "s", "e"][i % 3])).collect();
+        run(&mut db, &format!("INSERT INTO users VALUES {}", users.join(", ")));
+        let orders: Vec<String> = (0..MORSEL_SIZE * 2 + 100)
+            .map(|i| format!("({}, {}, {})", i, if i % 13 == 0 { "NULL".to_string() } else { (i % 61).to_string() }, (i % 97) as f64 / 3.0))
+            .collect();
+        run(&mut db, &format!("INSERT INTO orders VALUES {}", orders.join(", ")));
+
+        let queries = [
+            "SELECT id, amount FROM orders WHERE amount > 20 ORDER BY amount DESC, id",
+            "SELECT user_id, COUNT(*) AS n, SUM(amount) AS total, AVG(amount) AS mean, MIN(amount) AS lo FROM orders GROUP BY user_id ORDER BY user_id",
+            "SELECT region, COUNT(*) AS n FROM orders JOIN users ON orders.user_id = users.id GROUP BY ROLLUP (region) ORDER BY region",
+            "SELECT orders.id, region FROM orders JOIN users ON users.id = orders.user_id AND amount < 5",
+            "SELECT orders.id, region FROM orders JOIN users ON (users.id = orders.user_id) = TRUE AND amount < 5",
+        ];
+        db.threads = 1;
+        let expected: Vec<ResultSet> = queries.iter().map(|q| db.execute(&parse(q).unwrap()).unwrap()).collect();
+        db.threads = 4;
+        for (q, expected) in queries.iter().zip(&expected) {
+            assert!(!expected.is_empty(), "{}", q);
+            assert_eq!(&db.execute(&parse(q).unwrap()).unwrap(), expected, "{}", q);
+        }
+        // Hash and nested-loop joins agree, row order included
+        assert_eq!(expected[3].rows, expected[4].rows);
+        let matched = (0..MORSEL_SIZE * 2 + 100).filter(|i| i % 13 != 0 && i % 61 < 20).count() as i64;
+        let per_region: i64 = expected[2].iter().filter(|r| r["region"] != Value::Null).map(|r| if let Value::Int(n) = r["n"] { n } else { 0 }).sum();
+        assert_eq!(per_region, matched);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = std::env::temp_dir().join(format!("ql-persist-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        run(&mut db, "CREATE TABLE users (id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY, name TEXT NOT NULL, score FLOAT DEFAULT 1.5 CHECK (score >= 0))");
+        run(&mut db, "CREATE TABLE orders (id INT PRIMARY KEY, user_id INT REFERENCES users ON DELETE CASCADE, note TEXT) USING COLUMNAR");
+        let names: Vec<String> = (0..600).map(|i| format!("('user {} {}')", i, "x".repeat(i % 40))).collect();
+        run(&mut db, &format!("INSERT INTO users (name) VALUES {}", names.join(", ")));
+        run(&mut db, "INSERT INTO orders VALUES (1, 1, 'it''s'), (2, 2, NULL)");
+        drop(db);
+
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.path(), Some(path.as_path()));
+        assert_eq!(db.tables["users"].rows.len(), 600);
+        assert!(db.columnar.contains_key("orders"));
+        let result = db.execute(&parse("SELECT name, score FROM users WHERE id = 600").unwrap()).unwrap();
+        assert_eq!(result.rows[0], vec![string(&format!("user 599 {}", "x".repeat(599 % 40))), Value::Float(1.5)]);
+        assert!(db.execute_statement(&parse_statement("INSERT INTO users (name, score) VALUES ('neg', -1)").unwrap()).is_err());
+        assert!(db.execute_statement(&parse_statement("INSERT INTO orders VALUES (3, 999, 'x')").unwrap()).is_err());
+        let res = run(&mut db, "INSERT INTO users (name) VALUES ('next') RETURNING id");
+        assert_eq!(res.rows.rows[0][0], int(601));
+        run(&mut db, "DELETE FROM users WHERE id = 1");
+        drop(db);
+
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.columnar["orders"].len(), 1);
+        assert_eq!(db.columnar["orders"].get(0, 2), Value::Null);
+        // The temporary file is named after the whole file name
+        let neighbour = path.with_extension("tmp");
+        std::fs::write(&neighbour, "keep").unwrap();
+        db.checkpoint().unwrap();
+        drop(db);
+        assert_eq!(std::fs::read_to_string(&neighbour).unwrap(), "keep");
+        std::fs::remove_file(&neighbour).unwrap();
+
+        // A flipped byte in any page is caught by its checksum
+        let mut bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len() % PAGE_SIZE, 0);
+        bytes[PAGE_SIZE + 100] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let err = Database::open(&path).err().unwrap();
+        assert!(err.contains("checksum mismatch on page 1"), "{}", err);
+
+        std::fs::write(&path, vec![0u8; PAGE_SIZE]).unwrap();
+        assert!(Database::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    /// Every table's schema and rows, in a stable order.
+    fn dump(db: &Database) -> String {
+        let mut tables: Vec<Table> = db.tables.values().map(|t| Table::clone(t)).chain(db.columnar.values().map(|t| t.to_table())).collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut out = String::new();
+        for t in &tables {
+            out += &format!("{} {:?} {:?}\n", t.name, t.columns, t.constraints);
+            for r in &t.rows {
+                let values: Vec<String> = t.columns.iter().map(|c| r.data.get(&c.name).unwrap_or(&Value::Null).to_string()).collect();
+                out += &format!("  {}\n", values.join(","));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_wal_recovery() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("ql-wal-{}.db", std::process::id()));
+        let wal = dir.join(format!("ql-wal-{}.db-wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut db = Database::open(&path).unwrap();
+        let main_file = std::fs::read(&path).unwrap();
+        // (log length, state) after each committed change
+        let mut states = vec![(std::fs::metadata(&wal).unwrap().len(), dump(&db))];
+        for sql in [
+            "CREATE TABLE users (id INT PRIMARY KEY, name TEXT)",
+            "CREATE TABLE orders (id INT PRIMARY KEY, user_id INT REFERENCES users ON DELETE CASCADE) USING COLUMNAR",
+            "INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob'), (3, 'Cy')",
+            "INSERT INTO orders VALUES (10, 1), (11, 2), (12, 1)",
+            "UPDATE users SET name = 'Bobby' WHERE id = 2",
+            "DELETE FROM users WHERE id = 1",
+            "ALTER TABLE users ADD COLUMN age INT DEFAULT 30",
+            "ALTER TABLE users RENAME TO people",
+            "DROP TABLE orders",
+        ] {
+            db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+            states.push((std::fs::metadata(&wal).unwrap().len(), dump(&db)));
+        }
+        // A failed statement writes nothing
+        assert!(db.execute_statement(&parse_statement("INSERT INTO people VALUES (2, 'dup', 1)").unwrap()).is_err());
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), states.last().unwrap().0);
+        drop(db);
+        let log = std::fs::read(&wal).unwrap();
+
+        // Crash after every byte of the log, so at and between every write
+        // boundary: recovery must land exactly on the last complete commit
+        let crash = dir.join(format!("ql-wal-crash-{}.db", std::process::id()));
+        let crash_wal = dir.join(format!("ql-wal-crash-{}.db-wal", std::process::id()));
+        for cut in 0..=log.len() {
+            std::fs::write(&crash, &main_file).unwrap();
+            std::fs::write(&crash_wal, &log[..cut]).unwrap();
+            let recovered = Database::open(&crash).unwrap();
+            let expected = states.iter().rev().find(|(len, _)| *len as usize <= cut).map_or(&states[0].1, |(_, s)| s);
+            assert_eq!(&dump(&recovered), expected, "crash after {} of {} log bytes", cut, log.len());
+        }
+
+        // A torn tail is cut off, so later commits are not lost behind it
+        std::fs::write(&crash, &main_file).unwrap();
+        std::fs::write(&crash_wal, &log[..log.len() - 3]).unwrap();
+        let mut db = Database::open(&crash).unwrap();
+        db.execute_statement(&parse_statement("INSERT INTO people VALUES (7, 'Gus', 40)").unwrap()).unwrap();
+        drop(db);
+        let db = Database::open(&crash).unwrap();
+        assert_eq!(db.tables["people"].rows.len(), 3);
+
+        // Checkpointing folds the log into the file; a crash before the log
+        // is reset must not apply the old log twice
+        let mut db = Database::open(&path).unwrap();
+        let before = dump(&db);
+        db.checkpoint().unwrap();
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 24);
+        drop(db);
+        std::fs::write(&wal, &log).unwrap();
+        assert_eq!(dump(&Database::open(&path).unwrap()), before);
+
+        // Small logs checkpoint on their own
+        let mut db = Database::open(&path).unwrap();
+        db.checkpoint_bytes = 100;
+        db.sync_policy = SyncPolicy::Off;
+        db.execute_statement(&parse_statement("INSERT INTO people VALUES (8, 'Hal', 50), (9, 'Ida', 60)").unwrap()).unwrap();
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 24);
+        drop(db);
+        assert_eq!(Database::open(&path).unwrap().tables["people"].rows.len(), 4);
+
+        // A transaction's records hold each statement's own changes, so a
+        // table changed and then inserted into recovers without duplicates
+        let mut db = Database::open(&path).unwrap();
+        for sql in [
+            "BEGIN",
+            "UPDATE people SET age = 41 WHERE id = 8",
+            "INSERT INTO people VALUES (10, 'Jo', 70)",
+            "CREATE TABLE tags (id INT PRIMARY KEY, tag TEXT)",
+            "INSERT INTO tags VALUES (1, 'a')",
+            "ALTER TABLE tags ADD COLUMN hits INT DEFAULT 0",
+            "INSERT INTO tags VALUES (2, 'b', 5)",
+            "CREATE TABLE events (id INT PRIMARY KEY) USING COLUMNAR",
+            "INSERT INTO events VALUES (1)",
+            "DELETE FROM events WHERE id = 1",
+            "INSERT INTO events VALUES (2)",
+            "COMMIT",
+        ] {
+            db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        }
+        let committed = dump(&db);
+        assert!(std::fs::metadata(&wal).unwrap().len() > 24);
+        drop(db);
+        let db = Database::open(&path).unwrap();
+        assert_eq!(dump(&db), committed);
+        assert_eq!(db.tables["people"].rows.len(), 5);
+        assert_eq!(db.tables["tags"].rows.len(), 2);
+        drop(db);
+
+        // UPDATE and DELETE log only the rows they change, by position
+        let mut db = Database::open(&path).unwrap();
+        let values: Vec<String> = (0..200).map(|i| format!("({}, '{}')", i, "x".repeat(50))).collect();
+        for sql in ["CREATE TABLE wide (id INT PRIMARY KEY, pad TEXT)".to_string(), format!("INSERT INTO wide VALUES {}", values.join(", ")), "CREATE INDEX wide_pad ON wide (pad)".to_string()] {
+            db.execute_statement(&parse_statement(&sql).unwrap()).unwrap();
+        }
+        let before = std::fs::metadata(&wal).unwrap().len();
+        for sql in ["UPDATE wide SET pad = 'y' WHERE id = 150", "DELETE FROM wide WHERE id = 3 OR id = 199", "UPDATE wide SET id = id + 1000 WHERE id > 190"] {
+            db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        }
+        let grown = std::fs::metadata(&wal).unwrap().len() - before;
+        assert!(grown < 1000, "{} log bytes", grown);
+        let changed = dump(&db);
+        drop(db);
+        assert_eq!(dump(&Database::open(&path).unwrap()), changed);
+
+        for p in [&path, &wal, &crash, &crash_wal] {
+            std::fs::remove_file(p).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut db = fk_db("NO ACTION", "NO ACTION");
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let count = |db: &Database, table: &str| db.tables[table].rows.len();
+
+        run(&mut db, "BEGIN").unwrap();
+        run(&mut db, "INSERT INTO users VALUES (3, 'Cy')").unwrap();
+        run(&mut db, "CREATE TABLE notes (id INT)").unwrap();
+        // A failing statement undoes only itself
+        assert!(run(&mut db, "INSERT INTO users VALUES (4, 'Di'), (3, 'dup')").is_err());
+        assert!(run(&mut db, "BEGIN TRANSACTION").is_err());
+        run(&mut db, "ROLLBACK").unwrap();
+        assert_eq!(count(&db, "users"), 2);
+        assert!(!db.tables.contains_key("notes"));
+        assert!(run(&mut db, "COMMIT").is_err());
+
+        run(&mut db, "START TRANSACTION").unwrap();
+        run(&mut db, "INSERT INTO users VALUES (3, 'Cy')").unwrap();
+        run(&mut db, "SAVEPOINT a").unwrap();
+        run(&mut db, "INSERT INTO users VALUES (4, 'Di')").unwrap();
+        run(&mut db, "SAVEPOINT b").unwrap();
+        run(&mut db, "DELETE FROM orders").unwrap();
+        run(&mut db, "ROLLBACK TO SAVEPOINT b").unwrap();
+        assert_eq!(count(&db, "orders"), 3);
+        run(&mut db, "UPDATE users SET name = 'Dee' WHERE id = 4").unwrap();
+        run(&mut db, "ROLLBACK TO a").unwrap();
+        assert_eq!(count(&db, "users"), 3);
+        assert!(run(&mut db, "ROLLBACK TO b").is_err());
+        run(&mut db, "INSERT INTO users VALUES (5, 'Ed')").unwrap();
+        run(&mut db, "RELEASE SAVEPOINT a").unwrap();
+        assert!(run(&mut db, "ROLLBACK TO a").is_err());
+        run(&mut db, "COMMIT WORK").unwrap();
+        assert!(!db.in_transaction());
+        assert_eq!(count(&db, "users"), 4);
+        assert!(run(&mut db, "SAVEPOINT x").is_err());
+
+        // Deferred foreign keys are checked at COMMIT
+        run(&mut db, "CREATE TABLE links (user_id INT REFERENCES users DEFERRABLE INITIALLY DEFERRED)").unwrap();
+        assert!(run(&mut db, "INSERT INTO links VALUES (9)").is_err());
+        run(&mut db, "BEGIN").unwrap();
+        run(&mut db, "INSERT INTO links VALUES (9)").unwrap();
+        run(&mut db, "INSERT INTO users VALUES (9, 'Ivy')").unwrap();
+        run(&mut db, "COMMIT").unwrap();
+        run(&mut db, "BEGIN").unwrap();
+        run(&mut db, "INSERT INTO links VALUES (10)").unwrap();
+        run(&mut db, "INSERT INTO users VALUES (11, 'Kit')").unwrap();
+        let err = run(&mut db, "COMMIT").unwrap_err();
+        assert!(err.contains("links_user_id_fkey") && err.contains("rolled back"), "{}", err);
+        assert!(!db.in_transaction());
+        assert_eq!(count(&db, "users"), 5);
+        assert_eq!(count(&db, "links"), 1);
+
+        // The Rust handle rolls back unless committed
+        {
+            let mut tx = db.transaction().unwrap();
+            tx.execute_statement(&parse_statement("DELETE FROM links").unwrap()).unwrap();
+            assert_eq!(tx.tables["links"].rows.len(), 0);
+            assert!(tx.execute_statement(&parse_statement("COMMIT").unwrap()).is_err());
+        }
+        assert_eq!(count(&db, "links"), 1);
+        let mut tx = db.transaction().unwrap();
+        tx.execute_statement(&parse_statement("DELETE FROM links").unwrap()).unwrap();
+        tx.savepoint("s").unwrap();
+        tx.execute_statement(&parse_statement("DROP TABLE links").unwrap()).unwrap();
+        tx.rollback_to("s").unwrap();
+        let res = tx.execute(&parse("SELECT COUNT(*) AS n FROM links").unwrap()).unwrap();
+        assert_eq!(res.rows[0][0], int(0));
+        tx.commit().unwrap();
+        assert_eq!(count(&db, "links"), 0);
+    }
+
+    #[test]
+    fn test_transaction_durability() {
+        let path = std::env::temp_dir().join(format!("ql-txn-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        run(&mut db, "CREATE TABLE t (id INT PRIMARY KEY)");
+        run(&mut db, "BEGIN");
+        run(&mut db, "INSERT INTO t VALUES (1)");
+        run(&mut db, "SAVEPOINT s");
+        run(&mut db, "INSERT INTO t VALUES (2)");
+        run(&mut db, "ROLLBACK TO s");
+        run(&mut db, "INSERT INTO t VALUES (3)");
+        assert!(db.checkpoint().is_err());
+        // Nothing from an open transaction survives a crash
+        assert_eq!(dump(&Database::open(&path).unwrap()), "t [Column { name: \"id\", data_type: Int, not_null: false, default: None, identity: None, collation: Binary }] [Constraint { name: \"t_pkey\", kind: PrimaryKey([\"id\"]) }]\n");
+        run(&mut db, "COMMIT");
+        let ids: Vec<Value> = Database::open(&path).unwrap().tables["t"].rows.iter().map(|r| r.data["id"].clone()).collect();
+        assert_eq!(ids, vec![int(1), int(3)]);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_mvcc_isolation() {
+        let shared = SharedDatabase::new(fk_db("CASCADE", "NO ACTION"));
+        let st = |sql: &str| parse_statement(sql).unwrap();
+        let names = |db: &Database| {
+            let mut names: Vec<String> = db.tables["users"].rows.iter().map(|r| r.data["name"].to_string().replace('\'', "")).collect();
+            names.sort();
+            names
+        };
+
+        // Readers keep their version while writers commit
+        let before = shared.snapshot();
+        shared.execute_statement(&st("UPDATE users SET name = 'Al' WHERE id = 1")).unwrap();
+        assert_eq!(names(&before), ["Alice", "Bob"]);
+        assert_eq!(names(&shared.snapshot()), ["Al", "Bob"]);
+        let res = shared.execute(&parse("SELECT COUNT(*) AS n FROM orders").unwrap()).unwrap();
+        assert_eq!(res.rows[0][0], int(3));
+
+        // Versions and transactions share the tables they do not change
+        let shares = |a: &Database, b: &Database, name: &str| std::sync::Arc::ptr_eq(&a.tables[name], &b.tables[name]);
+        let (latest, mut txn) = (shared.snapshot(), shared.begin());
+        assert!(shares(&before, &latest, "orders") && !shares(&before, &latest, "users"));
+        txn.execute_statement(&st("UPDATE users SET name = 'Al' WHERE id = 2")).unwrap();
+        assert!(shares(txn.database(), &latest, "orders") && !shares(txn.database(), &latest, "users"));
+        txn.rollback().unwrap();
+
+        // Uncommitted changes stay private; different rows merge
+        let mut t1 = shared.begin();
+        let mut t2 = shared.begin();
+        t1.execute_statement(&st("UPDATE users SET name = 'A' WHERE id = 1")).unwrap();
+        t2.execute_statement(&st("INSERT INTO users VALUES (3, 'Cy')")).unwrap();
+        assert_eq!(names(t1.database()), ["A", "Bob"]);
+        assert_eq!(names(&shared.snapshot()), ["Al", "Bob"]);
+        t1.commit().unwrap();
+        assert_eq!(names(t2.database()), ["Al", "Bob", "Cy"]);
+        t2.commit().unwrap();
+        assert_eq!(names(&shared.snapshot()), ["A", "Bob", "Cy"]);
+
+        // First committer wins on the same row, including through cascades
+        let mut t1 = shared.begin();
+        let mut t2 = shared.begin();
+        t1.execute_statement(&st("UPDATE orders SET amount = 1 WHERE order_id = 101")).unwrap();
+        t2.execute_statement(&st("DELETE FROM users WHERE id = 1")).unwrap();
+        t1.commit().unwrap();
+        let err = t2.commit().unwrap_err();
+        assert!(err.contains("Could not serialize access: orders"), "{}", err);
+        assert_eq!(shared.snapshot().tables["orders"].rows.len(), 3);
+
+        // Write skew: each keeps one user on call, together they remove both
+        shared.execute_statement(&st("CREATE TABLE on_call (id INT PRIMARY KEY, active BOOL)")).unwrap();
+        shared.execute_statement(&st("INSERT INTO on_call VALUES (1, TRUE), (2, TRUE)")).unwrap();
+        let count = parse("SELECT COUNT(*) AS n FROM on_call WHERE active").unwrap();
+        for (level, skewed) in [(IsolationLevel::Snapshot, true), (IsolationLevel::Serializable, false)] {
+            let mut t1 = shared.begin_with(level);
+            let mut t2 = shared.begin_with(level);
+            assert_eq!(t1.execute(&count).unwrap().rows[0][0], int(2));
+            assert_eq!(t2.execute(&count).unwrap().rows[0][0], int(2));
+            t1.execute_statement(&st("UPDATE on_call SET active = FALSE WHERE id = 1")).unwrap();
+            t2.execute_statement(&st("UPDATE on_call SET active = FALSE WHERE id = 2")).unwrap();
+            t1.commit().unwrap();
+            assert_eq!(t2.commit().is_ok(), skewed);
+            let active = shared.execute(&count).unwrap().rows[0][0].clone();
+            assert_eq!(active, int(if skewed { 0 } else { 1 }));
+            shared.execute_statement(&st("UPDATE on_call SET active = TRUE")).unwrap();
+        }
+
+        // READ COMMITTED sees commits between statements; SNAPSHOT does not
+        let mut rc = shared.begin_with(IsolationLevel::ReadCommitted);
+        let mut si = shared.begin();
+        rc.execute_statement(&st("INSERT INTO users VALUES (4, 'Di')")).unwrap();
+        si.execute(&count).unwrap();
+        shared.execute_statement(&st("INSERT INTO users VALUES (5, 'Ed')")).unwrap();
+        rc.execute_statement(&st("SAVEPOINT s")).unwrap();
+        rc.execute_statement(&st("UPDATE users SET name = 'Eddie' WHERE id = 5")).unwrap();
+        assert_eq!(names(rc.database()), ["A", "Bob", "Cy", "Di", "Eddie"]);
+        rc.rollback_to("s").unwrap();
+        assert!(!si.database().tables["users"].rows.iter().any(|r| r.data["id"] == int(5)));
+        shared.execute_statement(&st("INSERT INTO users VALUES (6, 'Flo')")).unwrap();
+        let err = rc.execute_statement(&st("INSERT INTO users VALUES (6, 'Fay')")).unwrap_err();
+        assert!(err.contains("users_pkey"), "{}", err);
+        rc.commit().unwrap();
+        si.rollback().unwrap();
+        assert_eq!(names(&shared.snapshot()), ["A", "Bob", "Cy", "Di", "Ed", "Flo"]);
+
+        // Constraints are checked again on the merged rows
+        shared.execute_statement(&st("CREATE TABLE emails (id INT PRIMARY KEY, address TEXT UNIQUE)")).unwrap();
+        let mut t1 = shared.begin();
+        let mut t2 = shared.begin();
+        t1.execute_statement(&st("INSERT INTO emails VALUES (1, 'a@x')")).unwrap();
+        t2.execute_statement(&st("INSERT INTO emails VALUES (2, 'a@x')")).unwrap();
+        t1.commit().unwrap();
+        let err = t2.commit().unwrap_err();
+        assert!(err.contains("emails_address_key") && err.contains("rolled back"), "{}", err);
+        assert!(shared.begin().execute_statement(&st("COMMIT")).is_err());
+    }
+
+    #[test]
+    fn test_mvcc_concurrency() {
+        let shared = SharedDatabase::new(Database::new());
+        let st = |sql: &str| parse_statement(sql).unwrap();
+        shared.execute_statement(&st("CREATE TABLE counters (id INT PRIMARY KEY, n INT)")).unwrap();
+        shared.execute_statement(&st("INSERT INTO counters VALUES (1, 0), (2, 0)")).unwrap();
+
+        // Lost updates are caught and retried, and readers never see a torn pair
+        let (threads, increments) = (4, 10);
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                let shared = shared.clone();
+                s.spawn(move || {
+                    for _ in 0..increments {
+                        loop {
+                            let mut txn = shared.begin();
+                            txn.execute_statement(&st("UPDATE counters SET n = n + 1")).unwrap();
+                            if txn.commit().is_ok() {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+            let reader = shared.clone();
+            s.spawn(move || {
+                for _ in 0..50 {
+                    let snapshot = reader.snapshot();
+                    let rows = &snapshot.tables["counters"].rows;
+                    assert_eq!(rows[0].data["n"], rows[1].data["n"]);
+                }
+            });
+        });
+        let res = shared.execute(&parse("SELECT SUM(n) AS total FROM counters").unwrap()).unwrap();
+        assert_eq!(res.rows[0][0], int(2 * threads * increments));
+
+        // History is kept only while an open transaction may conflict with it
+        assert_eq!(shared.history_len(), 0);
+        let mut old = shared.begin();
+        for i in 3..6 {
+            shared.execute_statement(&st(&format!("INSERT INTO counters VALUES ({}, 0)", i))).unwrap();
+        }
+        assert_eq!(shared.history_len(), 3);
+        assert_eq!(old.execute(&parse("SELECT COUNT(*) AS n FROM counters").unwrap()).unwrap().rows[0][0], int(2));
+        drop(old);
+        assert_eq!(shared.history_len(), 0);
+        assert_eq!(shared.gc(), 0);
+
+        // Commits on a file-backed database go through its log
+        let path = std::env::temp_dir().join(format!("ql-mvcc-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let shared = SharedDatabase::new(Database::open(&path).unwrap());
+        shared.execute_statement(&st("CREATE TABLE t (id INT PRIMARY KEY)")).unwrap();
+        let mut txn = shared.begin();
+        txn.execute_statement(&st("INSERT INTO t VALUES (1), (2)")).unwrap();
+        txn.commit().unwrap();
+        shared.begin().execute_statement(&st("INSERT INTO t VALUES (3)")).unwrap();
+        assert_eq!(Database::open(&path).unwrap().tables["t"].rows.len(), 2);
+        drop(shared);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_indexes() {
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let mut plain = Database::new();
+        for sql in [
+            "CREATE TABLE people (id INT PRIMARY KEY, name TEXT NOT NULL, age INT NOT NULL, city TEXT)".to_string(),
+            "CREATE TABLE orders (id INT PRIMARY KEY, person_id INT, total FLOAT)".to_string(),
+        ] {
+            run(&mut plain, &sql).unwrap();
+        }
+        for i in 0..60 {
+            let city = ["'Oslo'", "'Rome'", "NULL", "'Lima'"][i % 4];
+            run(&mut plain, &format!("INSERT INTO people VALUES ({}, 'p{:02}', {}, {})", i, (i * 7) % 60, 20 + (i * 13) % 30, city)).unwrap();
+            run(&mut plain, &format!("INSERT INTO orders VALUES ({}, {}, {}.5)", 100 + i, (i * 11) % 70, i)).unwrap();
+        }
+        let mut indexed = Database::new();
+        indexed.tables = plain.tables.clone();
+        for sql in [
+            "CREATE INDEX people_age ON people (age)",
+            "CREATE INDEX people_city_age ON people (city, age)",
+            "CREATE UNIQUE INDEX people_name ON people (name)",
+            "CREATE INDEX orders_person ON orders (person_id)",
+        ] {
+            run(&mut indexed, sql).unwrap();
+        }
+
+        let queries = [
+            "SELECT * FROM people WHERE age = 33",
+            "SELECT name FROM people WHERE age = 33.0",
+            "SELECT name FROM people WHERE age > 40 AND age <= 45",
+            "SELECT name FROM people WHERE 30 > age",
+            "SELECT name FROM people WHERE age >= 47 OR age = 20",
+            "SELECT name FROM people WHERE city = 'Rome' AND age < 35",
+            "SELECT name FROM people WHERE city = 'Rome' AND age = 21 AND id > 3",
+            "SELECT name FROM people WHERE age = NULL",
+            "SELECT name, age FROM people ORDER BY age",
+            "SELECT name, age FROM people WHERE age < 30 ORDER BY age DESC",
+            "SELECT name FROM people ORDER BY name DESC LIMIT 5",
+            "SELECT name FROM people WHERE city = 'Oslo' ORDER BY age DESC",
+            "SELECT name FROM people WHERE city >= 'Lima' ORDER BY city, age",
+            "SELECT age, COUNT(*) AS n FROM people WHERE age > 40 GROUP BY age ORDER BY age",
+            "SELECT people.name, orders.total FROM people JOIN orders ON people.id = orders.person_id",
+            "SELECT people.name, orders.total FROM orders JOIN people ON orders.person_id = people.id WHERE orders.total > 20",
+            "SELECT people.name FROM orders JOIN people ON people.age = orders.person_id AND people.city = 'Lima'",
+        ];
+        let check = |plain: &Database, indexed: &Database| {
+            for sql in queries {
+                let query = parse(sql).unwrap();
+                assert_eq!(plain.execute(&query).unwrap(), indexed.execute(&query).unwrap(), "{}", sql);
+            }
+        };
+        check(&plain, &indexed);
+
+        // Indexes follow every change; UPDATE and DELETE find their rows through them
+        for sql in [
+            "INSERT INTO people VALUES (60, 'q', 33, 'Rome'), (61, 'r', 41, NULL)",
+            "UPDATE people SET age = age + 1 WHERE city = 'Oslo'",
+            "DELETE FROM people WHERE age > 45 OR id = 2",
+            "INSERT INTO orders SELECT id + 500, id, 1.5 FROM people WHERE age = 34",
+            "UPDATE people SET name = 'moved', city = 'Lima' WHERE age = 34 AND id = 7",
+            "UPDATE people SET age = 30 WHERE city = 'Rome' AND age < 30",
+            "DELETE FROM people WHERE age >= 25 AND age < 28",
+            "DELETE FROM orders WHERE person_id = 11",
+            "UPDATE orders SET person_id = person_id + 1 WHERE person_id > 60",
+        ] {
+            let affected = run(&mut plain, sql).unwrap().rows_affected;
+            assert_eq!(run(&mut indexed, sql).unwrap().rows_affected, affected, "{}", sql);
+            check(&plain, &indexed);
+        }
+
+        // Unique indexes reject duplicates like UNIQUE constraints
+        let err = run(&mut indexed, "INSERT INTO people VALUES (70, 'q', 1, NULL)").unwrap_err();
+        assert!(err.contains("people_name"), "{}", err);
+        assert!(run(&mut indexed, "UPDATE people SET name = 'q' WHERE id = 61").is_err());
+        // An index over a key's columns answers for it, with or without the rows being changed
+        run(&mut indexed, "CREATE INDEX people_id ON people USING HASH (id)").unwrap();
+        for (sql, ok) in [
+            ("INSERT INTO people VALUES (61, 'fresh', 1, NULL)", false),
+            ("UPDATE people SET id = id + 1000 WHERE id >= 60", true),
+            ("UPDATE people SET id = 1061 WHERE id = 1060", false),
+            ("UPDATE people SET id = id - 1000, name = 'q' WHERE id = 1060", true),
+            ("INSERT INTO people VALUES (60, 'fresh', 1, NULL), (1061, 'again', 2, NULL)", false),
+            ("UPDATE people SET name = name, age = age + 1 WHERE id >= 60", true),
+        ] {
+            let result = run(&mut indexed, sql);
+            assert_eq!(result.is_ok(), ok, "{}: {:?}", sql, result);
+            assert_eq!(run(&mut plain, sql).is_ok(), ok, "{}", sql);
+        }
+        run(&mut indexed, "DROP INDEX people_id").unwrap();
+        check(&plain, &indexed);
+        let err = run(&mut indexed, "CREATE UNIQUE INDEX people_city ON people (city)").unwrap_err();
+        assert!(err.contains("duplicate key"), "{}", err);
+        assert!(run(&mut indexed, "CREATE INDEX people_age ON people (name)").is_err());
+        run(&mut indexed, "CREATE INDEX IF NOT EXISTS people_age ON people (name)").unwrap();
+        assert!(run(&mut indexed, "CREATE INDEX bad ON people (height)").is_err());
+        assert!(run(&mut indexed, "CREATE INDEX bad ON nowhere (id)").is_err());
+
+        // Schema changes carry indexes along; rolling back restores them
+        run(&mut indexed, "BEGIN").unwrap();
+        run(&mut indexed, "DROP INDEX people_name").unwrap();
+        run(&mut indexed, "ALTER TABLE people RENAME COLUMN age TO years").unwrap();
+        run(&mut indexed, "ALTER TABLE people DROP COLUMN city").unwrap();
+        let names: Vec<(&str, Vec<String>)> = indexed.tables["people"].indexes.iter().map(|i| (i.name.as_str(), i.columns.clone())).collect();
+        assert_eq!(names, [("people_age", vec!["years".to_string()])]);
+        run(&mut indexed, "ROLLBACK").unwrap();
+        assert_eq!(indexed.tables["people"].indexes.len(), 3);
+        check(&plain, &indexed);
+        assert!(run(&mut indexed, "DROP INDEX missing").is_err());
+        run(&mut indexed, "DROP INDEX IF EXISTS missing").unwrap();
+        assert!(indexed.set_columnar("orders", true).unwrap_err().contains("orders_person"));
+    }
+
+    #[test]
+    fn test_hash_and_covering_indexes() {
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let mut plain = Database::new();
+        run(&mut plain, "CREATE TABLE items (id INT PRIMARY KEY, sku TEXT NOT NULL, qty INT NOT NULL, price FLOAT, note TEXT)").unwrap();
+        for i in 0..50 {
+            let sql = format!("INSERT INTO items VALUES ({}, 's{}', {}, {}.25, 'n{}')", i, i % 20, i % 7, i, i);
+            run(&mut plain, &sql).unwrap();
+        }
+        let mut indexed = Database::new();
+        indexed.tables = plain.tables.clone();
+        run(&mut indexed, "CREATE INDEX items_sku ON items USING HASH (sku) INCLUDE (qty)").unwrap();
+        run(&mut indexed, "CREATE INDEX items_qty ON items USING BTREE (qty) INCLUDE (price)").unwrap();
+        run(&mut indexed, "CREATE UNIQUE INDEX items_note ON items USING HASH (note)").unwrap();
+
+        let explain = |db: &Database, sql: &str| -> Vec<String> {
+            let plan = db.explain(&parse(sql).unwrap()).unwrap();
+            plan.rows.iter().map(|r| if let Value::String(line) = &r[0] { line.clone() } else { panic!("{:?}", r) }).collect()
+        };
+        let cases: [(&str, &[&str]); 6] = [
+            ("SELECT qty FROM items WHERE sku = 's3'", &["Index Only Scan using items_sku (hash) on items", "  Index Cond: sku = 's3'", "Filter: sku = 's3'"]),
+            ("SELECT * FROM items WHERE note = 'n7'", &["Index Scan using items_note (hash) on items", "  Index Cond: note = 'n7'", "Filter: note = 'n7'"]),
+            ("SELECT qty, price FROM items WHERE qty > 4 ORDER BY qty DESC", &["Index Only Scan Backward using items_qty on items", "  Index Cond: qty > 4", "Filter: qty > 4"]),
+            ("SELECT price FROM items WHERE qty >= 2 AND qty < 4 LIMIT 3", &["Index Only Scan using items_qty on items", "  Index Cond: qty >= 2 AND qty < 4", "Filter: qty >= 2 AND qty < 4", "Limit: 3"]),
+            ("SELECT sku, SUM(qty) FROM items GROUP BY sku", &["Index Only Scan using items_sku (hash) on items", "Aggregate"]),
+            ("SELECT id FROM items WHERE sku > 's1' ORDER BY id", &["Seq Scan on items", "Filter: sku > 's1'", "Sort: id ASC"]),
+        ];
+        for (sql, plan) in cases {
+            let query = parse(sql).unwrap();
+            assert_eq!(plain.execute(&query).unwrap(), indexed.execute(&query).unwrap(), "{}", sql);
+            assert_eq!(explain(&indexed, sql), plan, "{}", sql);
+        }
+        let plan = run(&mut plain, "EXPLAIN SELECT qty FROM items WHERE sku = 's3'").unwrap().rows;
+        assert_eq!(plan.columns[0].name, "plan");
+        assert_eq!(plan.rows, [[string("Seq Scan on items")], [string("Filter: sku = 's3'")]]);
+
+        // Joins look up the joined table's hash index on its whole key
+        run(&mut indexed, "CREATE TABLE lines (id INT PRIMARY KEY, note TEXT, sku TEXT)").unwrap();
+        run(&mut indexed, "INSERT INTO lines VALUES (1, 'n3', 's3'), (2, 'n9', 's0'), (3, 'nx', 's3')").unwrap();
+        let join = "SELECT lines.id, items.qty FROM lines JOIN items ON items.note = lines.note";
+        assert_eq!(explain(&indexed, join), ["Seq Scan on lines", "Index Join on items using items_note", "  On: items.note = lines.note"]);
+        let rows = indexed.execute(&parse(join).unwrap()).unwrap().rows;
+        assert_eq!(rows, [[int(1), int(3)], [int(2), int(2)]]);
+        assert_eq!(
+            explain(&indexed, "SELECT lines.id FROM lines JOIN items ON items.qty = lines.id"),
+            ["Seq Scan on lines", "Index Join on items using items_qty", "  On: items.qty = lines.id"]
+        );
+        assert_eq!(
+            explain(&indexed, "SELECT lines.id FROM lines JOIN items ON items.price = lines.id"),
+            ["Seq Scan on lines", "Hash Join on items", "  On: items.price = lines.id"]
+        );
+
+        // Index-only scans read the copies the index holds, never the rows
+        for sql in ["INSERT INTO items VALUES (50, 's3', 6, 0.5, 'n50')", "UPDATE items SET qty = qty + 1 WHERE sku = 's3'", "DELETE FROM items WHERE id < 10"] {
+            run(&mut plain, sql).unwrap();
+            run(&mut indexed, sql).unwrap();
+        }
+        let query = parse("SELECT qty FROM items WHERE sku = 's3'").unwrap();
+        assert_eq!(plain.execute(&query).unwrap(), indexed.execute(&query).unwrap());
+        for row in &mut std::sync::Arc::make_mut(indexed.tables.get_mut("items").unwrap()).rows {
+            row.data.insert("qty".into(), Value::Null);
+        }
+        assert_eq!(plain.execute(&query).unwrap(), indexed.execute(&query).unwrap());
+        assert!(run(&mut indexed, "INSERT INTO items VALUES (60, 's1', 1, 1.0, 'n50')").is_err());
+        assert!(run(&mut indexed, "CREATE INDEX bad ON items (sku) INCLUDE (weight)").is_err());
+        assert!(parse_statement("CREATE INDEX bad ON items USING GIST (sku)").is_err());
+    }
+
+    #[test]
+    fn test_statistics_and_join_order() {
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let mut db = Database::new();
+        run(&mut db, "CREATE TABLE kinds (kind INT PRIMARY KEY, label TEXT)").unwrap();
+        run(&mut db, "CREATE TABLE users (id INT PRIMARY KEY, country TEXT, age INT)").unwrap();
+        run(&mut db, "CREATE TABLE events (id INT PRIMARY KEY, user_id INT, kind INT, score FLOAT)").unwrap();
+        for k in 0..4 {
+            run(&mut db, &format!("INSERT INTO kinds VALUES ({}, 'k{}')", k, k)).unwrap();
+        }
+        for u in 0..100 {
+            let country = if u % 10 == 0 { "NULL".to_string() } else { format!("'c{}'", u % 3) };
+            run(&mut db, &format!("INSERT INTO users VALUES ({}, {}, {})", u, country, 18 + u % 50)).unwrap();
+        }
+        let values: Vec<String> = (0..2000).map(|e| format!("({}, {}, {}, {}.5)", e, (e * 7) % 100, e % 4, e % 13)).collect();
+        run(&mut db, &format!("INSERT INTO events VALUES {}", values.join(", "))).unwrap();
+
+        // ANALYZE gathers counts, distinct values, NULLs, bounds and histograms
+        assert!(run(&mut db, "ANALYZE nowhere").is_err());
+        run(&mut db, "ANALYZE users").unwrap();
+        assert_eq!(db.statistics.keys().collect::<Vec<_>>(), ["users"]);
+        let users = &db.statistics["users"];
+        assert_eq!(users.row_count, 100);
+        let country = &users.columns["country"];
+        assert_eq!((country.distinct, country.null_fraction), (3, 0.1));
+        assert_eq!((country.min.clone(), country.max.clone()), (Some(string("c0")), Some(string("c2"))));
+        let age = &users.columns["age"];
+        assert_eq!(age.histogram.len(), HISTOGRAM_BUCKETS + 1);
+        assert_eq!((age.histogram.first(), age.histogram.last()), (Some(&int(18)), Some(&int(67))));
+        assert!((age.selectivity("<", &int(43)) - 0.5).abs() < 0.05);
+        assert_eq!(country.selectivity("=", &string("c1")), 0.3);
+        assert_eq!(country.selectivity("=", &string("zz")), 0.0);
+
+        let queries = [
+            "SELECT events.id, users.country, kinds.label FROM events JOIN kinds ON events.kind = kinds.kind \
+             JOIN users ON users.id = events.user_id WHERE users.age = 20",
+            "SELECT * FROM kinds JOIN events ON kinds.kind = events.kind JOIN users ON events.user_id = users.id \
+             WHERE kinds.label = 'k2' AND users.country = 'c1' AND events.score > 10",
+            "SELECT users.country, COUNT(*) AS n FROM kinds JOIN users ON users.age > 60 \
+             JOIN events ON events.user_id = users.id AND events.kind = kinds.kind GROUP BY users.country ORDER BY n",
+            "SELECT events.id FROM users JOIN events ON events.user_id = users.id WHERE users.id < 3 ORDER BY events.score LIMIT 7",
+        ];
+        let expected: Vec<ResultSet> = queries.iter().map(|q| db.execute(&parse(q).unwrap()).unwrap()).collect();
+        run(&mut db, "ANALYZE").unwrap();
+        assert_eq!(db.statistics.len(), 3);
+        for (sql, expected) in queries.iter().zip(&expected) {
+            let query = parse(sql).unwrap();
+            assert_eq!(&db.execute(&query).unwrap(), expected, "{}", sql);
+            db.threads = 1;
+            assert_eq!(&db.execute(&query).unwrap(), expected, "{}", sql);
+            db.threads = 4;
+        }
+
+        // The selective table goes first; the large one is joined last,
+        // probing a hash table built over the few rows joined before it
+        let plan: Vec<String> = db.explain(&parse(queries[0]).unwrap()).unwrap().rows.iter().map(|r| r[0].to_string()).collect();
+        assert_eq!(
+            plan,
+            [
+                "'Seq Scan on users'",
+                "'  Filter: users.age = 20'",
+                "'Nested Loop Join on kinds'",
+                "'  On: TRUE'",
+                "'  Estimated Rows: 8'",
+                "'Hash Join on events (build left)'",
+                "'  On: events.kind = kinds.kind AND users.id = events.user_id'",
+                "'  Estimated Rows: 40'",
+            ]
+        );
+
+        // Ambiguous unqualified names keep the written order
+        run(&mut db, "CREATE TABLE tags (id INT, label TEXT)").unwrap();
+        run(&mut db, "INSERT INTO tags VALUES (1, 'a'), (2, 'b')").unwrap();
+        run(&mut db, "ANALYZE tags").unwrap();
+        let plan = db.explain(&parse("SELECT label FROM kinds JOIN tags ON tags.id = kinds.kind").unwrap()).unwrap();
+        assert!(!plan.rows.iter().any(|r| r[0].to_string().contains("Estimated")), "{:?}", plan.rows);
+        run(&mut db, "DROP TABLE tags").unwrap();
+        assert!(!db.statistics.contains_key("tags"));
+        run(&mut db, "ALTER TABLE kinds RENAME TO categories").unwrap();
+        assert!(db.statistics.contains_key("categories"));
+    }
+
+    #[test]
+    fn test_greedy_join_order() {
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        let mut db = Database::new();
+        let n = MAX_DP_TABLES + 1;
+        for t in 0..n {
+            run(&mut db, &format!("CREATE TABLE t{} (k{} INT, v{} INT)", t, t, t));
+            let rows: Vec<String> = (0..(t + 2) * 2).map(|i| format!("({}, {})", i % (t + 2), i)).collect();
+            run(&mut db, &format!("INSERT INTO t{} VALUES {}", t, rows.join(", ")));
+        }
+        let joins: Vec<String> = (1..n).map(|t| format!("JOIN t{} ON k{} = k{} AND v{} < 40", t, t, t - 1, t)).collect();
+        let query = parse(&format!("SELECT * FROM t0 {} WHERE k0 < 2 ORDER BY v1", joins.join(" "))).unwrap();
+        let expected = db.execute(&query).unwrap();
+        assert!(!expected.rows.is_empty());
+        run(&mut db, "ANALYZE");
+        assert_eq!(db.execute(&query).unwrap(), expected);
+        let plan = db.explain(&query).unwrap();
+        assert_eq!(plan.rows.iter().filter(|r| r[0].to_string().contains("Estimated Rows")).count(), n - 1);
+    }
+
+    #[test]
+    fn test_index_persistence() {
+        let path = std::env::temp_dir().join(format!("ql-index-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap()).unwrap();
+        let query = parse("SELECT id FROM t WHERE v >= 2 ORDER BY v").unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        run(&mut db, "CREATE TABLE t (id INT PRIMARY KEY, v INT NOT NULL)");
+        run(&mut db, "CREATE UNIQUE INDEX t_v ON t (v)");
+        run(&mut db, "CREATE INDEX t_id ON t USING HASH (id) INCLUDE (v)");
+        run(&mut db, "INSERT INTO t VALUES (1, 3), (2, 1), (3, 2)");
+        let expected = db.execute(&query).unwrap();
+        // From the log, then from the file
+        for _ in 0..2 {
+            let mut reopened = Database::open(&path).unwrap();
+            assert_eq!(
+                format!("{:?}", reopened.tables["t"].indexes),
+                "[Index { name: \"t_v\", columns: [\"v\"], unique: true, kind: BTree, include: [], .. }, \
+                 Index { name: \"t_id\", columns: [\"id\"], unique: false, kind: Hash, include: [\"v\"], .. }]"
+            );
+            assert_eq!(reopened.execute(&query).unwrap(), expected);
+            assert!(reopened.execute_statement(&parse_statement("INSERT INTO t VALUES (4, 2)").unwrap()).is_err());
+            db.checkpoint().unwrap();
+        }
+        run(&mut db, "DROP INDEX t_v");
+        run(&mut db, "DROP INDEX t_id");
+        assert!(Database::open(&path).unwrap().tables["t"].indexes.is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_date_time_types() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let one = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows[0][0].clone();
+        let ids = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows.iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+
+        run(&mut db, "CREATE TABLE events (id INT PRIMARY KEY, day DATE NOT NULL, at TIMESTAMP, seen TIMESTAMPTZ, took INTERVAL)").unwrap();
+        run(
+            &mut db,
+            "INSERT INTO events VALUES \
+             (1, '2026-01-31', '2026-01-31 23:30:00', '2026-01-31 23:30:00+02', '90 minutes'), \
+             (2, DATE '2024-02-29', TIMESTAMP '2024-02-29 08:00:00.25', '2024-02-29T08:00:00Z', INTERVAL '1 day 2 hours'), \
+             (3, '2026-03-01', '2026-03-01', NULL, NULL)",
+        )
+        .unwrap();
+        let rows = &db.tables["events"].rows;
+        assert_eq!(rows[0].data["day"], Value::Date(Date::from_ymd(2026, 1, 31).unwrap()));
+        assert_eq!(rows[0].data["seen"].to_string(), "TIMESTAMPTZ '2026-01-31 21:30:00+00'");
+        assert_eq!(rows[1].data["took"], Value::Interval(Interval { months: 0, days: 1, micros: 2 * 3_600_000_000 }));
+        assert_eq!(rows[2].data["at"].to_string(), "TIMESTAMP '2026-03-01 00:00:00'");
+        assert_eq!(run(&mut db, "INSERT INTO events (id, day) VALUES (4, '2026-02-30')").unwrap_err(), "Column events.day: Invalid date: '2026-02-30'");
+        assert!(parse("SELECT TIME '24:00' FROM events").is_err());
+        // Dates stop where their microseconds would overflow, about 292,000 years out
+        assert!(run(&mut db, "INSERT INTO events (id, day) VALUES (4, '5000000-01-01')").unwrap_err().contains("Invalid date"));
+        run(&mut db, "INSERT INTO events (id, day, at) VALUES (4, '290000-01-01', '290000-01-01 12:00')").unwrap();
+        assert_eq!(ids(&db, "SELECT id FROM events ORDER BY day DESC, at"), vec![int(4), int(3), int(1), int(2)]);
+        assert_eq!(one(&db, "SELECT day + 200000000 FROM events"), Value::Null);
+        run(&mut db, "DELETE FROM events WHERE id = 4").unwrap();
+
+        // Comparisons, with text read as the other side's type
+        assert_eq!(ids(&db, "SELECT id FROM events WHERE day >= '2026-01-01' ORDER BY day"), vec![int(1), int(3)]);
+        assert_eq!(ids(&db, "SELECT id FROM events WHERE seen < TIMESTAMP '2026-01-31 22:00:00'"), vec![int(1), int(2)]);
+        assert_eq!(one(&db, "SELECT DATE '2026-03-01' = TIMESTAMP '2026-03-01 00:00:00' FROM events"), Value::Bool(true));
+        assert_eq!(one(&db, "SELECT INTERVAL '1 month' = INTERVAL '30 days' FROM events"), Value::Bool(true));
+
+        // Arithmetic
+        let cases = [
+            ("DATE '2026-01-31' + INTERVAL '1 month'", "TIMESTAMP '2026-02-28 00:00:00'"),
+            ("DATE '2026-03-01' - DATE '2024-02-29'", "731"),
+            ("day + 1", "DATE '2026-02-01'"),
+            ("TIMESTAMP '2026-03-01 00:00:00' - at", "INTERVAL '28 days 00:30:00'"),
+            ("seen - INTERVAL '1 year 2 hours'", "TIMESTAMPTZ '2025-01-31 19:30:00+00'"),
+            ("TIME '23:00' + INTERVAL '2 hours'", "TIME '01:00:00'"),
+            ("INTERVAL '1 hour' * 1.5", "INTERVAL '01:30:00'"),
+            ("-INTERVAL '1 day'", "INTERVAL '-1 day'"),
+            ("DATE '2026-10-18' + TIME '09:15'", "TIMESTAMP '2026-10-18 09:15:00'"),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(one(&db, &format!("SELECT {} FROM events WHERE id = 1", expr)).to_string(), expected, "{}", expr);
+        }
+
+        // Functions
+        let cases = [
+            ("EXTRACT(YEAR FROM day)", int(2024)),
+            ("DATE_PART('dow', DATE '2026-10-18')", int(0)),
+            ("EXTRACT(week FROM DATE '2027-01-01')", int(53)),
+            ("EXTRACT(epoch FROM took)", Value::Float(93_600.0)),
+            ("EXTRACT(hour FROM at)", int(8)),
+            ("DATE_TRUNC('month', at)", Value::Timestamp(Timestamp::parse("2024-02-01").unwrap())),
+            ("DATE_TRUNC('week', DATE '2026-10-18')", Value::Date(Date::parse("2026-10-12").unwrap())),
+            ("STRFTIME(at, '%a %d %b %Y %H:%M:%S.%f')", string("Thu 29 Feb 2024 08:00:00.250000")),
+            ("NOW() > TIMESTAMPTZ '2026-01-01 00:00:00'", Value::Bool(true)),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(one(&db, &format!("SELECT {} FROM events WHERE id = 2", expr)), expected, "{}", expr);
+        }
+        let result = db.execute(&parse("SELECT day + 1, at - at, NOW(), EXTRACT(epoch FROM took) FROM events").unwrap()).unwrap();
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::Date, DataType::Interval, DataType::TimestampTz, DataType::Float]);
+
+        // An index over a date column orders rows and serves text bounds
+        let query = parse("SELECT id FROM events WHERE day > '2025-01-01' ORDER BY day DESC").unwrap();
+        let expected = db.execute(&query).unwrap();
+        run(&mut db, "CREATE INDEX events_day ON events (day)").unwrap();
+        assert_eq!(db.execute(&query).unwrap(), expected);
+        let plan: Vec<Value> = db.explain(&query).unwrap().rows.into_iter().map(|r| r[0].clone()).collect();
+        assert_eq!(plan[..2], [string("Index Scan Backward using events_day on events"), string("  Index Cond: day > DATE '2025-01-01'")]);
+
+        // Values survive the file and the log
+        let path = std::env::temp_dir().join(format!("ql-temporal-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file_db = Database::open(&path).unwrap();
+        run(&mut file_db, "CREATE TABLE t (d DATE, t TIME, ts TIMESTAMP, tz TIMESTAMPTZ, i INTERVAL)").unwrap();
+        run(&mut file_db, "INSERT INTO t VALUES ('1969-07-20', '20:17:40', '2000-01-01 00:00:00.5', '2026-10-18 12:00-07:00', '-1 year 3 days')").unwrap();
+        let expected = dump(&file_db);
+        // From the log, then from the file
+        for _ in 0..2 {
+            assert_eq!(dump(&Database::open(&path).unwrap()), expected);
+            file_db.checkpoint().unwrap();
+        }
+        drop(file_db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_decimals() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let one = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows[0][0].clone();
+        let dec = |s: &str| Value::Decimal(Decimal::parse(s).unwrap());
+
+        run(&mut db, "CREATE TABLE orders (id INT PRIMARY KEY, amount DECIMAL(10, 2) NOT NULL, rate NUMERIC(5,4))").unwrap();
+        assert_eq!(db.tables["orders"].columns[1].data_type, DataType::Decimal(10, 2));
+        assert!(parse_statement("CREATE TABLE bad (x DECIMAL(2, 3))").is_err());
+        assert!(parse_statement("CREATE TABLE bad (x DECIMAL(39))").is_err());
+
+        // Values are rounded to the column's scale, halves away from zero
+        run(&mut db, "INSERT INTO orders VALUES (1, 19.99, 0.0825), (2, 0.1, NULL), (3, 0.2, NULL), (4, DECIMAL '1234.565', 1)").unwrap();
+        let rows = &db.tables["orders"].rows;
+        assert_eq!(rows[0].data["amount"].to_string(), "DECIMAL '19.99'");
+        assert_eq!(rows[1].data["amount"].to_string(), "DECIMAL '0.10'");
+        assert_eq!(rows[3].data["amount"], dec("1234.57"));
+        assert_eq!(rows[3].data["rate"].to_string(), "DECIMAL '1.0000'");
+        let err = run(&mut db, "INSERT INTO orders VALUES (5, 100000000, NULL)").unwrap_err();
+        assert!(err.ends_with("Value 100000000 out of range for DECIMAL(10,2)"), "{}", err);
+        assert_eq!(dec("1.0"), dec("1.00"));
+        // Too far apart in scale to compare at one scale: the larger magnitude wins
+        let (big, tiny) = (dec("10000000000000000000"), dec("0.00000000000000000001"));
+        assert_eq!(big.cmp(&tiny), std::cmp::Ordering::Greater);
+        assert!(tiny < big && dec("-10000000000000000000") < tiny);
+        assert!(dec("-0.00000000000000000001") > dec("-10000000000000000000"));
+
+        // Exact arithmetic, with INT and FLOAT operands read as decimals
+        assert_eq!(one(&db, "SELECT 0.1 + 0.2 = 0.3 FROM orders"), Value::Bool(false));
+        assert_eq!(one(&db, "SELECT DECIMAL '0.1' + 0.2 = 0.3 FROM orders"), Value::Bool(true));
+        let cases = [
+            ("amount + 0.2", "0.30"),
+            ("amount * 3 - 0.3", "0.00"),
+            ("DECIMAL '1' / 3", "0.333333"),
+            ("DECIMAL '2' / 3", "0.666667"),
+            ("DECIMAL '10.5' % 4", "2.5"),
+            ("-amount", "-0.10"),
+            ("ROUND(DECIMAL '2.5')", "3"),
+            ("ROUND(DECIMAL '2.5', 0, 'HALF_EVEN')", "2"),
+            ("ROUND(DECIMAL '-2.5', 0, 'floor')", "-3"),
+            ("ROUND(DECIMAL '1.001', 2, 'CEILING')", "1.01"),
+            ("TRUNC(DECIMAL '-1.99', 1)", "-1.9"),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(one(&db, &format!("SELECT {} FROM orders WHERE id = 2", expr)), dec(expected), "{}", expr);
+        }
+        assert_eq!(one(&db, "SELECT amount * rate FROM orders WHERE id = 1").to_string(), "DECIMAL '1.649175'");
+        assert_eq!(one(&db, "SELECT ROUND(2.675, 2) FROM orders"), Value::Float(2.68));
+        assert_eq!(one(&db, "SELECT ROUND(1250, -2, 'HALF_EVEN') FROM orders"), int(1200));
+        assert_eq!(one(&db, "SELECT ROUND(amount, 0, 'SIDEWAYS') FROM orders"), Value::Null);
+        assert_eq!(one(&db, "SELECT DECIMAL '1' / 0 FROM orders"), Value::Null);
+
+        // Exact aggregates
+        let result = db.execute(&parse("SELECT SUM(amount), AVG(amount), MAX(amount), AVG(id) FROM orders").unwrap()).unwrap();
+        assert_eq!(result.rows[0], vec![dec("1254.86"), dec("313.715"), dec("1234.57"), Value::Float(2.5)]);
+        assert_eq!(result.rows[0][1].to_string(), "DECIMAL '313.71500000'");
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::Decimal(10, 2), DataType::Decimal(38, 8), DataType::Decimal(10, 2), DataType::Float]);
+
+        // Ordering, comparisons and indexes across numeric types
+        let ids = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows.iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&db, "SELECT id FROM orders WHERE amount > 0.1 ORDER BY amount DESC"), vec![int(4), int(1), int(3)]);
+        run(&mut db, "CREATE UNIQUE INDEX orders_amount ON orders (amount)").unwrap();
+        assert_eq!(ids(&db, "SELECT id FROM orders WHERE amount = 0.2"), vec![int(3)]);
+        assert_eq!(ids(&db, "SELECT id FROM orders WHERE amount <= 19.99 ORDER BY amount"), vec![int(2), int(3), int(1)]);
+        assert!(run(&mut db, "INSERT INTO orders VALUES (6, DECIMAL '0.100', NULL)").is_err());
+
+        // Values survive the file
+        let path = std::env::temp_dir().join(format!("ql-decimal-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file_db = Database::open(&path).unwrap();
+        run(&mut file_db, "CREATE TABLE t (d DECIMAL(38, 10))").unwrap();
+        run(&mut file_db, "INSERT INTO t VALUES (DECIMAL '-1234567890123456789012345678.0123456789')").unwrap();
+        file_db.checkpoint().unwrap();
+        assert_eq!(dump(&Database::open(&path).unwrap()), dump(&file_db));
+        drop(file_db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_json() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let one = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows[0][0].clone();
+        let doc = |s: &str| Value::Json(Json::parse(s).unwrap());
+
+        run(&mut db, "CREATE TABLE events (id INT PRIMARY KEY, payload JSON)").unwrap();
+        run(
+            &mut db,
+            r#"INSERT INTO events VALUES
+                (1, '{"user": {"name": "ann", "age": 31}, "tags": ["a", "b"], "n": 1.5}'),
+                (2, '{"user": {"name": "bob", "age": 25}, "tags": [], "n": 2}'),
+                (3, '{"user": {"name": "ann", "age": 31}, "tags": ["c"], "n": null}'),
+                (4, NULL)"#,
+        )
+        .unwrap();
+        assert_eq!(db.tables["events"].rows[1].data["payload"].to_string(), r#"JSON '{"user":{"name":"bob","age":25},"tags":[],"n":2}'"#);
+        let err = run(&mut db, "INSERT INTO events VALUES (5, '{\"a\": }')").unwrap_err();
+        assert!(err.starts_with("Column events.payload: Invalid JSON"), "{}", err);
+        assert_eq!(Json::parse(r#"{"a": 1, "a": [true, null, "\u00e9\n"]}"#).unwrap().to_string(), r#"{"a":[true,null,"é\n"]}"#);
+        assert!(Json::parse("01").is_err() && Json::parse("[1,]").is_err() && Json::parse("1 2").is_err());
+
+        // Path operators and functions
+        let cases = [
+            ("payload->'user'", doc(r#"{"name":"ann","age":31}"#)),
+            ("payload->'user'->'name'", doc(r#""ann""#)),
+            ("payload->'user'->>'name'", Value::String("ann".into())),
+            ("payload->'tags'->-1", doc(r#""b""#)),
+            ("payload->'tags'->>5", Value::Null),
+            ("payload#>'{user,age}'", doc("31")),
+            ("payload#>>'{tags,0}'", Value::String("a".into())),
+            ("payload->>'tags'", Value::String(r#"["a","b"]"#.into())),
+            ("JSON_EXTRACT(payload, '$.user.age')", doc("31")),
+            ("JSON_EXTRACT(payload, '$.tags[1]')", doc(r#""b""#)),
+            ("JSON_ARRAY_LENGTH(payload->'tags')", int(2)),
+            ("JSON_ARRAY_LENGTH(payload, '$.tags')", int(2)),
+            ("JSON_ARRAY_LENGTH(payload, '$.user')", Value::Null),
+            ("JSON_OBJECT('id', id, 'n', payload->'n', 'on', TRUE)", doc(r#"{"id":1,"n":1.5,"on":true}"#)),
+            ("'[1, [2, 3]]'->1->0", doc("2")),
+            ("-payload->'n'", Value::Float(-1.5)),
+            ("payload->'n' + 1", Value::Float(2.5)),
+            ("CAST(payload->>'n' AS DECIMAL(4, 2))", Value::Decimal(Decimal::parse("1.50").unwrap())),
+            ("CAST(payload->'user'->>'age' AS INT) * 2", int(62)),
+            ("CAST(payload AS TEXT)", Value::String(r#"{"user":{"name":"ann","age":31},"tags":["a","b"],"n":1.5}"#.into())),
+            ("CAST('yes' AS BOOL)", Value::Bool(true)),
+            ("CAST(2.5 AS INT)", int(3)),
+            ("CAST('x' AS INT)", Value::Null),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(one(&db, &format!("SELECT {} FROM events WHERE id = 1", expr)), expected, "{}", expr);
+        }
+        assert_eq!(parse("SELECT CAST(a->>'x' AS DECIMAL(4,2)) FROM t").unwrap().select_cols[0], "CAST(a ->> 'x' AS DECIMAL(4,2))");
+
+        // Extracted scalars compare, group and sort as SQL values
+        let ids = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows.iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&db, "SELECT id FROM events WHERE payload->'user'->'age' > 30"), vec![int(1), int(3)]);
+        assert_eq!(ids(&db, "SELECT id FROM events WHERE payload->'user'->'name' = 'bob'"), vec![int(2)]);
+        assert_eq!(ids(&db, "SELECT id FROM events WHERE payload->'n' = 2"), vec![int(2)]);
+        assert_eq!(ids(&db, "SELECT id FROM events WHERE payload->'user' = JSON '{\"age\": 31, \"name\": \"ann\"}'"), Vec::<Value>::new());
+        assert_eq!(one(&db, "SELECT MIN(payload->'user'->'age') FROM events"), doc("25"));
+        let result = db
+            .execute(&parse("SELECT payload->'user'->>'name' AS name, COUNT(*), JSON_AGG(payload->'n') FROM events WHERE id < 4 GROUP BY payload->'user'->>'name'").unwrap())
+            .unwrap();
+        assert_eq!(result.rows, vec![
+            vec![Value::String("ann".into()), int(2), doc("[1.5, null]")],
+            vec![Value::String("bob".into()), int(1), doc("[2]")],
+        ]);
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::String, DataType::Int, DataType::Json]);
+        run(&mut db, "CREATE TABLE ages (age INT, label TEXT)").unwrap();
+        run(&mut db, "INSERT INTO ages VALUES (25, 'young'), (31, 'older')").unwrap();
+        let sql = "SELECT events.id, ages.label FROM events JOIN ages ON events.payload->'user'->'age' = ages.age ORDER BY events.id";
+        assert_eq!(ids(&db, sql), vec![int(1), int(2), int(3)]);
+
+        // Columnar tables evaluate the operators too, and documents survive the file
+        run(&mut db, "CREATE TABLE docs (d JSON) USING COLUMNAR").unwrap();
+        run(&mut db, r#"INSERT INTO docs VALUES ('{"k": [10, 20]}'), ('{"k": [30]}')"#).unwrap();
+        assert_eq!(ids(&db, "SELECT d->'k'->>0 FROM docs WHERE JSON_ARRAY_LENGTH(d->'k') = 1"), vec![Value::String("30".into())]);
+        let path = std::env::temp_dir().join(format!("ql-json-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file_db = Database::open(&path).unwrap();
+        run(&mut file_db, "CREATE TABLE t (d JSONB)").unwrap();
+        run(&mut file_db, r#"INSERT INTO t VALUES ('{"pi": 3.0, "s": "it''s \"q\""}')"#).unwrap();
+        file_db.checkpoint().unwrap();
+        assert_eq!(dump(&Database::open(&path).unwrap()), dump(&file_db));
+        drop(file_db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_arrays_and_structs() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let one = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows[0][0].clone();
+        let rows = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows;
+        let strs = |items: &[&str]| Value::Array(items.iter().map(|s| string(s)).collect());
+
+        run(&mut db, "CREATE TABLE posts (id INT PRIMARY KEY, tags TEXT[], author STRUCT(name TEXT, age INT))").unwrap();
+        run(
+            &mut db,
+            "INSERT INTO posts VALUES
+                (1, ARRAY['rust', 'sql'], {'name': 'ann', 'age': 31}),
+                (2, ['sql'], {'age': 25, 'name': 'bob'}),
+                (3, [], {'name': 'cy'}),
+                (4, NULL, NULL)",
+        )
+        .unwrap();
+        assert_eq!(db.tables["posts"].rows[1].data["author"].to_string(), "{'name': 'bob', 'age': 25}");
+        assert_eq!(db.tables["posts"].rows[2].data["author"].to_string(), "{'name': 'cy', 'age': NULL}");
+        let err = run(&mut db, "INSERT INTO posts VALUES (5, [1], NULL)").unwrap_err();
+        assert!(err.starts_with("Column posts.tags: Expected TEXT"), "{}", err);
+        let err = run(&mut db, "INSERT INTO posts VALUES (5, NULL, {'nick': 'x'})").unwrap_err();
+        assert!(err.starts_with("Column posts.author: Unknown field nick"), "{}", err);
+
+        // Element and field access, and the array functions
+        let cases = [
+            ("tags[1]", string("rust")),
+            ("tags[-1]", string("sql")),
+            ("tags[3]", Value::Null),
+            ("author.name", string("ann")),
+            ("posts.author.age + 1", int(32)),
+            ("author['age']", int(31)),
+            ("STRUCT_EXTRACT(author, 'name')", string("ann")),
+            ("CARDINALITY(tags)", int(2)),
+            ("ARRAY_CONTAINS(tags, 'sql')", Value::Bool(true)),
+            ("ARRAY_CONTAINS(tags, 'go')", Value::Bool(false)),
+            ("[1, 2] = ARRAY[1, 2]", Value::Bool(true)),
+            ("[1, 2] < [1, 3]", Value::Bool(true)),
+            ("[1] < [1, 0]", Value::Bool(true)),
+            ("[1, NULL] = [1, 2]", Value::Null),
+            ("[[1, 2], [3]][1][2]", int(2)),
+            ("CAST(tags AS TEXT)", string("[rust, sql]")),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(one(&db, &format!("SELECT {} FROM posts WHERE id = 1", expr)), expected, "{}", expr);
+        }
+        assert_eq!(parse("SELECT a[1], {'k': [1, 2]} FROM t").unwrap().select_cols, vec!["a[1]", "{'k': ARRAY[1, 2]}"]);
+        assert_eq!(rows(&db, "SELECT id FROM posts WHERE author.age < 30"), vec![vec![int(2)]]);
+        assert_eq!(rows(&db, "SELECT id FROM posts WHERE ARRAY_CONTAINS(tags, 'sql') ORDER BY id"), vec![vec![int(1)], vec![int(2)]]);
+        let result = db.execute(&parse("SELECT tags, author.name, tags[1] FROM posts WHERE id = 1").unwrap()).unwrap();
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::Array(Box::new(DataType::String)), DataType::String, DataType::String]);
+
+        // ARRAY_AGG keeps NULLs; arrays group and join by value
+        assert_eq!(one(&db, "SELECT ARRAY_AGG(author.name) FROM posts"), Value::Array(vec![string("ann"), string("bob"), string("cy"), Value::Null]));
+        assert_eq!(one(&db, "SELECT ARRAY_AGG(id) FROM posts WHERE id > 9"), Value::Null);
+        assert_eq!(rows(&db, "SELECT CARDINALITY(tags), COUNT(*) FROM posts WHERE id < 4 GROUP BY tags ORDER BY id").len(), 3);
+        run(&mut db, "CREATE TABLE topics (tags TEXT[], title TEXT)").unwrap();
+        run(&mut db, "INSERT INTO topics VALUES (['sql'], 'databases'), (['rust', 'sql'], 'systems')").unwrap();
+        let sql = "SELECT posts.id, topics.title FROM posts JOIN topics ON posts.tags = topics.tags ORDER BY posts.id";
+        assert_eq!(rows(&db, sql), vec![vec![int(1), string("systems")], vec![int(2), string("databases")]]);
+
+        // UNNEST produces a row per element, alone or joined to the rows before it
+        assert_eq!(rows(&db, "SELECT x * 10 FROM UNNEST([1, 2, 3]) AS x WHERE x > 1"), vec![vec![int(20)], vec![int(30)]]);
+        assert_eq!(rows(&db, "SELECT p.name FROM UNNEST([{'name': 'a'}, {'name': 'b'}]) p"), vec![vec![string("a")], vec![string("b")]]);
+        let sql = "SELECT id, tag FROM posts JOIN UNNEST(tags) AS tag ORDER BY tag";
+        assert_eq!(rows(&db, sql), vec![vec![int(1), string("rust")], vec![int(1), string("sql")], vec![int(2), string("sql")]]);
+        let sql = "SELECT tag, COUNT(*) FROM posts JOIN UNNEST(posts.tags) AS tag ON tag != 'rust' GROUP BY tag";
+        assert_eq!(rows(&db, sql), vec![vec![string("sql"), int(2)]]);
+        let sql = "SELECT topics.title, t FROM posts JOIN UNNEST(tags) AS t JOIN topics ON ARRAY_CONTAINS(topics.tags, t) WHERE posts.id = 2";
+        assert_eq!(rows(&db, sql), vec![vec![string("databases"), string("sql")], vec![string("systems"), string("sql")]]);
+        let plan: Vec<Value> = db.explain(&parse("SELECT tag FROM posts JOIN UNNEST(tags) AS tag").unwrap()).unwrap().rows.into_iter().map(|r| r[0].clone()).collect();
+        assert_eq!(plan, vec![string("Seq Scan on posts"), string("Unnest Join on tags AS tag"), string("  On: TRUE")]);
+
+        // Columnar tables read fields too, and composites survive the file
+        run(&mut db, "CREATE TABLE c (s STRUCT(k INT, v TEXT[])) USING COLUMNAR").unwrap();
+        run(&mut db, "INSERT INTO c VALUES ({'k': 1, 'v': ['a']}), ({'k': 2, 'v': []})").unwrap();
+        assert_eq!(rows(&db, "SELECT s.v FROM c WHERE s.k = 1"), vec![vec![strs(&["a"])]]);
+        let path = std::env::temp_dir().join(format!("ql-composite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file_db = Database::open(&path).unwrap();
+        run(&mut file_db, "CREATE TABLE t (a INT[], s STRUCT(n TEXT, xs FLOAT[]))").unwrap();
+        run(&mut file_db, "INSERT INTO t VALUES ([1, NULL], {'n': 'x', 'xs': [1.5]})").unwrap();
+        file_db.checkpoint().unwrap();
+        let reopened = Database::open(&path).unwrap();
+        assert_eq!(dump(&reopened), dump(&file_db));
+        assert_eq!(reopened.tables["t"].columns[1].data_type.to_string(), "STRUCT(n TEXT, xs FLOAT[])");
+        drop(file_db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_bytes_and_uuids() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let one = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows[0][0].clone();
+        let rows = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows;
+        let uuid = |s: &str| Value::Uuid(Uuid::parse(s).unwrap());
+
+        run(&mut db, "CREATE TABLE files (id UUID PRIMARY KEY DEFAULT GEN_RANDOM_UUID(), name TEXT, hash BYTEA)").unwrap();
+        run(&mut db, "INSERT INTO files (name, hash) VALUES ('a', X'DEADBEEF'), ('b', '\\x00ff'), ('c', 'hi'), ('d', X'DEADBEEF')").unwrap();
+        run(&mut db, "INSERT INTO files VALUES ('A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11', 'e', X'')").unwrap();
+        let ids: Vec<Value> = db.tables["files"].rows.iter().map(|r| r.data["id"].clone()).collect();
+        assert!(ids[..4].iter().all(|id| matches!(id, Value::Uuid(u) if u.0[6] >> 4 == 4 && u.0[8] >> 6 == 2)));
+        assert_eq!(ids.iter().map(|id| id.to_string()).collect::<std::collections::HashSet<_>>().len(), 5);
+        assert_eq!(db.tables["files"].rows[1].data["hash"], Value::Bytes(vec![0, 255]));
+        assert_eq!(db.tables["files"].rows[4].data["id"].to_string(), "UUID 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'");
+        assert_eq!(db.tables["files"].rows[0].data["hash"].to_string(), "X'DEADBEEF'");
+        let err = run(&mut db, "INSERT INTO files VALUES ('a0eebc99', 'f', NULL)").unwrap_err();
+        assert!(err.starts_with("Column files.id: Invalid UUID"), "{}", err);
+        assert!(parse("SELECT X'ABC' FROM t").is_err());
+
+        // Functions, casts and comparisons
+        let cases = [
+            ("ENCODE(hash, 'hex')", string("deadbeef")),
+            ("ENCODE(hash, 'base64')", string("3q2+7w==")),
+            ("ENCODE('hello', 'base64')", string("aGVsbG8=")),
+            ("DECODE('aGVsbG8=', 'base64')", Value::Bytes(b"hello".to_vec())),
+            ("DECODE('aGVsbA==', 'BASE64')", Value::Bytes(b"hell".to_vec())),
+            ("DECODE('00ff', 'hex')", Value::Bytes(vec![0, 255])),
+            ("DECODE('abc', 'base64')", Value::Null),
+            ("DECODE('zz', 'hex')", Value::Null),
+            ("LENGTH(hash)", int(4)),
+            ("CAST(hash AS TEXT)", string("\\xdeadbeef")),
+            ("hash = X'deadbeef'", Value::Bool(true)),
+            ("X'00' < X'0000'", Value::Bool(true)),
+            ("X'FF' > X'00FF'", Value::Bool(true)),
+            ("UUID 'a0eebc999c0b4ef8bb6d6bb9bd380a11' = '{a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11}'", Value::Bool(true)),
+            ("CAST(CAST(UUID 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11' AS BYTEA) AS UUID)", uuid("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11")),
+            ("CAST(X'01' AS UUID)", Value::Null),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(one(&db, &format!("SELECT {} FROM files WHERE name = 'a'", expr)), expected, "{}", expr);
+        }
+        assert_eq!(rows(&db, "SELECT name FROM files WHERE id = 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'"), vec![vec![string("e")]]);
+        assert_eq!(rows(&db, "SELECT name FROM files ORDER BY hash, name"), vec![vec![string("e")], vec![string("b")], vec![string("c")], vec![string("a")], vec![string("d")]]);
+        let types: Vec<DataType> = db.execute(&parse("SELECT id, hash, ENCODE(hash, 'hex'), GEN_RANDOM_UUID() FROM files").unwrap()).unwrap().columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::Uuid, DataType::Bytes, DataType::String, DataType::Uuid]);
+
+        // Group and join keys
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM files GROUP BY hash ORDER BY hash").len(), 4);
+        run(&mut db, "CREATE TABLE seen (hash BLOB, at INT)").unwrap();
+        run(&mut db, "INSERT INTO seen VALUES (X'DEADBEEF', 1), (X'00FF', 2), (X'01', 3)").unwrap();
+        let sql = "SELECT files.name, seen.at FROM files JOIN seen ON files.hash = seen.hash ORDER BY files.name";
+        assert_eq!(rows(&db, sql), vec![vec![string("a"), int(1)], vec![string("b"), int(2)], vec![string("d"), int(1)]]);
+        run(&mut db, "CREATE INDEX files_hash ON files (hash)").unwrap();
+        assert_eq!(rows(&db, sql).len(), 3);
+        run(&mut db, "CREATE TABLE owners (file UUID, owner TEXT)").unwrap();
+        run(&mut db, "INSERT INTO owners VALUES ('a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11', 'ann')").unwrap();
+        assert_eq!(rows(&db, "SELECT owner FROM files JOIN owners ON files.id = owners.file"), vec![vec![string("ann")]]);
+
+        // Both survive the file
+        let path = std::env::temp_dir().join(format!("ql-binary-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file_db = Database::open(&path).unwrap();
+        run(&mut file_db, "CREATE TABLE t (id UUID, b BYTEA)").unwrap();
+        run(&mut file_db, "INSERT INTO t VALUES (GEN_RANDOM_UUID(), X'0001FF'), (NULL, X'')").unwrap();
+        file_db.checkpoint().unwrap();
+        assert_eq!(dump(&Database::open(&path).unwrap()), dump(&file_db));
+        drop(file_db);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_value_ordering() {
+        use std::collections::HashSet;
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let rows = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows;
+        let float = Value::Float;
+        let decimal = |s: &str| Value::Decimal(Decimal::parse(s).unwrap());
+
+        // Equal values are equal and hash alike, whatever their type
+        let equal = [
+            (float(-0.0), float(0.0)),
+            (float(f64::NAN), float(-f64::NAN)),
+            (int(1), float(1.0)),
+            (int(2), decimal("2.00")),
+            (float(0.1), decimal("0.1")),
+            (int(9_007_199_254_740_993), decimal("9007199254740993")),
+            (Value::Json(Json::Float(3.0)), int(3)),
+            (Value::Json(Json::Null), Value::Null),
+            (Value::Array(vec![int(1), float(2.0)]), Value::Array(vec![float(1.0), decimal("2")])),
+        ];
+        for (a, b) in equal {
+            assert_eq!(a, b);
+            assert_eq!(HashSet::from([a.clone(), b.clone()]).len(), 1, "{} {}", a, b);
+        }
+        assert_ne!(int(9_007_199_254_740_993), float(9_007_199_254_740_992.0));
+        assert_ne!(float(0.1), decimal("0.1000000000000000055511151231257827"));
+
+        // One order across types: NULL, booleans, numbers (NaN last), text
+        let mut values = vec![
+            string("a"),
+            float(f64::NAN),
+            decimal("2.5"),
+            Value::Null,
+            float(f64::INFINITY),
+            int(-3),
+            Value::Bool(true),
+            float(f64::NEG_INFINITY),
+            Value::Bool(false),
+            float(0.1),
+            int(0),
+        ];
+        values.sort();
+        let text: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        assert_eq!(text, ["NULL", "FALSE", "TRUE", "-inf", "-3", "0", "0.1", "DECIMAL '2.5'", "inf", "NaN", "'a'"]);
+
+        // Grouping, DISTINCT, joins, comparisons and sorting agree
+        run(&mut db, "CREATE TABLE m (k FLOAT, d DECIMAL(4, 2))").unwrap();
+        run(&mut db, "INSERT INTO m VALUES (0.0, 1), (-0.0, 1.00), (0.0 / 0.0, 2), (0.0 / 0.0, NULL), (NULL, 2.5), (1.5, NULL)").unwrap();
+        let groups = rows(&db, "SELECT k, COUNT(*) AS n FROM m GROUP BY k ORDER BY k");
+        assert_eq!(groups.iter().map(|r| r[1].clone()).collect::<Vec<_>>(), vec![int(1), int(2), int(1), int(2)]);
+        assert_eq!(groups[0][0], Value::Null);
+        assert!(matches!(groups[3][0], Value::Float(f) if f.is_nan()));
+        assert_eq!(rows(&db, "SELECT DISTINCT d FROM m"), vec![vec![decimal("1")], vec![decimal("2")], vec![Value::Null], vec![decimal("2.5")]]);
+        assert_eq!(rows(&db, "SELECT DISTINCT k FROM m ORDER BY k LIMIT 2"), vec![vec![Value::Null], vec![float(0.0)]]);
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM m WHERE k = 0.0 / 0.0"), vec![vec![int(2)]]);
+        assert!(matches!(rows(&db, "SELECT MAX(k) FROM m")[0][0], Value::Float(f) if f.is_nan()));
+
+        run(&mut db, "CREATE TABLE n (i INT, label TEXT)").unwrap();
+        run(&mut db, "INSERT INTO n VALUES (0, 'zero'), (1, 'one'), (2, 'two')").unwrap();
+        let sql = "SELECT n.label, m.k FROM m JOIN n ON m.d = n.i ORDER BY label";
+        let labels: Vec<Value> = rows(&db, sql).into_iter().map(|r| r[0].clone()).collect();
+        assert_eq!(labels, vec![string("one"), string("one"), string("two")]);
+        assert_eq!(rows(&db, "SELECT m.k FROM m JOIN n ON m.k = n.i").len(), 2);
+        run(&mut db, "CREATE INDEX m_k ON m (k)").unwrap();
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM m WHERE k = 0"), vec![vec![int(2)]]);
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM m WHERE k > 1"), vec![vec![int(3)]]);
+    }
+
+    #[test]
+    fn test_collations() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let rows = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows;
+        let column = |rows: Vec<Vec<Value>>| rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+
+        // A NOCASE column compares, sorts, groups and dedupes without case
+        run(&mut db, "CREATE TABLE users (id INT, name TEXT COLLATE NOCASE)").unwrap();
+        run(&mut db, "INSERT INTO users VALUES (1, 'Ann'), (2, 'bob'), (3, 'ANN'), (4, 'Bob'), (5, 'carl')").unwrap();
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE name = 'ann'")), vec![int(1), int(3)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE 'BOB' = name")), vec![int(2), int(4)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE name COLLATE BINARY = 'Ann'")), vec![int(1)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE name < 'B'")), vec![int(1), int(3)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users ORDER BY name, id")), vec![int(1), int(3), int(2), int(4), int(5)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users ORDER BY name COLLATE BINARY, id")), vec![int(3), int(1), int(4), int(2), int(5)]);
+        let groups = rows(&db, "SELECT name, COUNT(*) FROM users GROUP BY name");
+        assert_eq!(groups, vec![vec![string("Ann"), int(2)], vec![string("bob"), int(2)], vec![string("carl"), int(1)]]);
+        assert_eq!(column(rows(&db, "SELECT DISTINCT name FROM users")), vec![string("Ann"), string("bob"), string("carl")]);
+        assert_eq!(rows(&db, "SELECT DISTINCT name COLLATE BINARY FROM users").len(), 5);
+
+        // Explicit collations on plain text
+        run(&mut db, "CREATE TABLE one (x INT)").unwrap();
+        run(&mut db, "INSERT INTO one VALUES (1)").unwrap();
+        assert_eq!(rows(&db, "SELECT 'ABC' = 'abc', 'ABC' COLLATE NOCASE = 'abc', 'abc' = 'ABC' COLLATE NOCASE FROM one"), vec![vec![
+            Value::Bool(false),
+            Value::Bool(true),
+            Value::Bool(true)
+        ]]);
+        assert!(parse("SELECT 'a' COLLATE KLINGON FROM one").is_err());
+        assert!(parse_statement("CREATE TABLE bad (n INT COLLATE NOCASE)").is_err());
+
+        // Joins, hash or by index, match under the collation
+        run(&mut db, "CREATE TABLE pets (owner TEXT, pet TEXT)").unwrap();
+        run(&mut db, "INSERT INTO pets VALUES ('ANN', 'cat'), ('BOB', 'dog'), ('dave', 'eel')").unwrap();
+        let sql = "SELECT users.id, pets.pet FROM users JOIN pets ON pets.owner = users.name ORDER BY users.id";
+        let expected = vec![vec![int(1), string("cat")], vec![int(2), string("dog")], vec![int(3), string("cat")], vec![int(4), string("dog")]];
+        assert_eq!(rows(&db, sql), expected);
+        run(&mut db, "CREATE TABLE owners (id INT, name TEXT)").unwrap();
+        let err = db.execute(&parse("SELECT users.id FROM users JOIN owners ON owners.id = users.id WHERE name = 'ann'").unwrap()).unwrap_err();
+        assert!(err.contains("Ambiguous collation for column name"), "{}", err);
+        assert!(db.execute(&parse("SELECT users.id FROM users JOIN owners ON owners.id = users.id WHERE users.name = 'ann'").unwrap()).is_ok());
+        run(&mut db, "CREATE INDEX users_name ON users (name)").unwrap();
+        assert_eq!(rows(&db, sql), expected);
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE name = 'BOB'")), vec![int(2), int(4)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE name COLLATE BINARY = 'bob'")), vec![int(2)]);
+        assert_eq!(column(rows(&db, "SELECT id FROM users WHERE name >= 'b' AND name < 'c'")), vec![int(2), int(4)]);
+
+        // UNIQUE keys collide under the collation; UPDATE and DELETE match under it
+        run(&mut db, "CREATE TABLE tags (tag TEXT COLLATE NOCASE UNIQUE)").unwrap();
+        run(&mut db, "INSERT INTO tags VALUES ('Rust')").unwrap();
+        let err = run(&mut db, "INSERT INTO tags VALUES ('RUST')").unwrap_err();
+        assert!(err.contains("('RUST')"), "{}", err);
+        assert_eq!(run(&mut db, "UPDATE users SET id = id + 10 WHERE name = 'CARL'").unwrap().rows_affected, 1);
+        assert_eq!(run(&mut db, "DELETE FROM users WHERE name = 'bOb'").unwrap().rows_affected, 2);
+
+        // UNICODE equates precomposed letters with letters and combining marks
+        let like = |db: &Database, sql: &str| column(rows(db, &format!("SELECT {} FROM one", sql)))[0].clone();
+        run(&mut db, "CREATE TABLE words (w TEXT COLLATE UNICODE)").unwrap();
+        run(&mut db, "INSERT INTO words VALUES ('caf\u{e9}'), ('cafe\u{301}'), ('Caf\u{e9}')").unwrap();
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM words WHERE w = 'caf\u{e9}'"), vec![vec![int(2)]]);
+        assert_eq!(rows(&db, "SELECT DISTINCT w FROM words").len(), 2);
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM words WHERE w COLLATE NOCASE = 'CAFE\u{301}'"), vec![vec![int(3)]]);
+        // Marks with a known class are put in canonical order; others stay as written
+        let same = |db: &Database, a: &str, b: &str| like(db, &format!("'{}' COLLATE UNICODE = '{}'", a, b));
+        assert_eq!(same(&db, "a\u{301}\u{323}", "a\u{323}\u{301}"), Value::Bool(true));
+        assert_eq!(same(&db, "\u{1ea1}\u{301}", "a\u{301}\u{323}"), Value::Bool(true));
+        assert_eq!(same(&db, "\u{5d0}\u{5b0}\u{591}", "\u{5d0}\u{591}\u{5b0}"), Value::Bool(false));
+        assert_eq!(like(&db, "'\u{5d0}\u{591}' LIKE '_'"), Value::Bool(false));
+
+        // LIKE: % any run, _ one character with its marks, \ escapes
+        assert_eq!(like(&db, "'hello' LIKE 'h%o'"), Value::Bool(true));
+        assert_eq!(like(&db, "'hello' LIKE 'h_e%'"), Value::Bool(false));
+        assert_eq!(like(&db, "'hello' LIKE '%l_o'"), Value::Bool(true));
+        assert_eq!(like(&db, "'hello' LIKE 'H%'"), Value::Bool(false));
+        assert_eq!(like(&db, "'hello' COLLATE NOCASE LIKE 'H%'"), Value::Bool(true));
+        assert_eq!(like(&db, "'50%' LIKE '50\\%'"), Value::Bool(true));
+        assert_eq!(like(&db, "'500' LIKE '50\\%'"), Value::Bool(false));
+        assert_eq!(like(&db, "'cafe\u{301}' LIKE 'caf_'"), Value::Bool(true));
+        assert_eq!(like(&db, "'abc' NOT LIKE 'a%'"), Value::Bool(false));
+        assert_eq!(like(&db, "NULL LIKE 'a%'"), Value::Null);
+        assert_eq!(column(rows(&db, "SELECT w FROM words WHERE w LIKE 'c%' AND w LIKE 'caf\u{e9}'")).len(), 2);
+        run(&mut db, "CREATE TABLE cities (name TEXT COLLATE NOCASE) USING COLUMNAR").unwrap();
+        run(&mut db, "INSERT INTO cities VALUES ('Rome'), ('rotterdam'), ('Paris')").unwrap();
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM cities WHERE name LIKE 'RO%'"), vec![vec![int(2)]]);
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM cities WHERE name = 'ROME'"), vec![vec![int(1)]]);
+
+        // Collations survive the file
+        let path = std::env::temp_dir().join(format!("ql-collation-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file_db = Database::open(&path).unwrap();
+        run(&mut file_db, "CREATE TABLE t (a TEXT COLLATE NOCASE, b TEXT COLLATE UNICODE, c TEXT)").unwrap();
+        run(&mut file_db, "INSERT INTO t VALUES ('X', 'y', 'z')").unwrap();
+        let reopened = Database::open(&path).unwrap();
+        assert_eq!(dump(&reopened), dump(&file_db));
+        assert_eq!(rows(&reopened, "SELECT COUNT(*) FROM t WHERE a = 'x'"), vec![vec![int(1)]]);
+        file_db.checkpoint().unwrap();
+        assert_eq!(dump(&Database::open(&path).unwrap()), dump(&file_db));
+        drop((file_db, reopened));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}-wal", path.display())).unwrap();
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let rows = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows;
+        let dir = std::env::temp_dir();
+        let file = |name: &str, text: &str| {
+            let path = dir.join(format!("ql-{}-{}.csv", name, std::process::id()));
+            std::fs::write(&path, text).unwrap();
+            path
+        };
+
+        // A new table: header detected, types inferred, quoting and NULLs
+        let people = file("people", "id,name,score,active,joined\r\n1,\"Lovelace, Ada\",9.5,true,2024-01-31\n2,\"Say \"\"hi\"\"\",,false,\n\n3,\"two\nlines\",7,TRUE,2023-12-01\n");
+        assert_eq!(db.load_csv("people", &people, &CsvOptions::default()), Ok(3));
+        let types: Vec<DataType> = db.tables["people"].columns.iter().map(|c| c.data_type.clone()).collect();
+        assert_eq!(types, vec![DataType::Int, DataType::String, DataType::Float, DataType::Bool, DataType::Date]);
+        assert_eq!(rows(&db, "SELECT name, score FROM people WHERE id = 2"), vec![vec![string("Say \"hi\""), Value::Null]]);
+        assert_eq!(rows(&db, "SELECT name FROM people WHERE id = 3"), vec![vec![string("two\nlines")]]);
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM people WHERE active AND joined < DATE '2024-01-01'"), vec![vec![int(1)]]);
+
+        // No header: columns are numbered; options change the dialect
+        let plain = file("plain", "1;'a;b'\n2;'it\\'s'\n");
+        let options = CsvOptions { delimiter: ';', quote: '\'', escape: '\\', ..CsvOptions::default() };
+        assert_eq!(db.load_csv("plain", &plain, &options), Ok(2));
+        assert_eq!(rows(&db, "SELECT column1, column2 FROM plain"), vec![vec![int(1), string("a;b")], vec![int(2), string("it's")]]);
+        let words = file("words", "apple\nbanana\n");
+        db.load_csv("words", &words, &CsvOptions { header: Some(false), ..CsvOptions::default() }).unwrap();
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM words"), vec![vec![int(2)]]);
+
+        // An existing table: header columns in any order, fields cast to the column types
+        run(&mut db, "CREATE TABLE scores (id INT PRIMARY KEY, points DECIMAL(5, 1), note TEXT)").unwrap();
+        let scores = file("scores", "note,id,points\nok,1,2.25\n,2,3\n");
+        assert_eq!(run(&mut db, &format!("COPY scores FROM '{}'", scores.display())).unwrap().rows_affected, 2);
+        assert_eq!(rows(&db, "SELECT id, points, note FROM scores ORDER BY id")[0], vec![int(1), Value::Decimal(Decimal::parse("2.3").unwrap()), string("ok")]);
+        let bad = file("bad", "1,x,\n");
+        let err = run(&mut db, &format!("COPY scores FROM '{}' WITH (HEADER FALSE)", bad.display())).unwrap_err();
+        assert!(err.contains("line 1") && err.contains("points"), "{}", err);
+        let dup = file("dup", "5,1,a\n1,2,b\n");
+        assert!(run(&mut db, &format!("COPY scores FROM '{}' (FORMAT CSV, HEADER FALSE)", dup.display())).is_err());
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM scores"), vec![vec![int(2)]]);
+        let ragged = file("ragged", "a,b\n1,2\n3\n");
+        assert_eq!(db.load_csv("ragged", &ragged, &CsvOptions::default()), Err("CSV line 3: expected 2 fields, found 1".into()));
+        assert!(db.load_csv("missing", dir.join("ql-no-such-file.csv"), &CsvOptions::default()).is_err());
+        assert!(parse_statement("COPY t FROM 'x' (DELIMITER ';;')").is_err());
+        assert!(parse_statement("COPY t FROM 'x' (FORMAT JSON)").is_err());
+
+        // Export a table or a query, and read it back unchanged
+        let out = dir.join(format!("ql-out-{}.csv", std::process::id()));
+        assert_eq!(run(&mut db, &format!("COPY people TO '{}'", out.display())).unwrap().rows_affected, 3);
+        db.load_csv("copied", &out, &CsvOptions::default()).unwrap();
+        assert_eq!(rows(&db, "SELECT * FROM copied"), rows(&db, "SELECT * FROM people"));
+        let sql = format!("COPY (SELECT id, name FROM people WHERE id < 3 ORDER BY id) TO '{}' WITH (HEADER FALSE, DELIMITER '\\t', NULL 'NA')", out.display());
+        assert_eq!(run(&mut db, &sql).unwrap().rows_affected, 2);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "1\tLovelace, Ada\n2\t\"Say \"\"hi\"\"\"\n");
+        let empty = parse("SELECT id, name FROM people WHERE id = 0").unwrap();
+        assert_eq!(db.write_csv(&empty, &out, &CsvOptions { null: "NA".into(), ..CsvOptions::default() }), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "id,name\n");
+        run(&mut db, "INSERT INTO scores VALUES (3, NULL, 'NA')").unwrap();
+        db.write_csv(&parse("SELECT * FROM scores WHERE id = 3").unwrap(), &out, &CsvOptions { null: "NA".into(), ..CsvOptions::default() }).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "id,points,note\n3,NA,\"NA\"\n");
+
+        for path in [people, plain, words, scores, bad, dup, ragged, out] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_json_io() {
+        let mut db = Database::new();
+        let run = |db: &mut Database, sql: &str| db.execute_statement(&parse_statement(sql).unwrap());
+        let rows = |db: &Database, sql: &str| db.execute(&parse(sql).unwrap()).unwrap().rows;
+        let dir = std::env::temp_dir();
+        let file = |name: &str, text: &str| {
+            let path = dir.join(format!("ql-{}-{}.json", name, std::process::id()));
+            std::fs::write(&path, text).unwrap();
+            path
+        };
+
+        // NDJSON: nested objects flattened, types inferred across records
+        let events = file(
+            "events",
+            "{\"id\": 1, \"user\": {\"name\": \"ada\", \"geo\": {\"cc\": \"uk\"}}, \"score\": 2, \"at\": \"2024-01-31 10:00:00\", \"tags\": [\"a\"]}\n\n\
+             {\"id\": 2, \"user\": {\"name\": \"bob\"}, \"score\": 2.5, \"extra\": true, \"at\": null}\n\
+             {\"id\": 3, \"score\": null, \"mixed\": 1}\n{\"id\": 4, \"mixed\": \"x\", \"nothing\": null}\n",
+        );
+        assert_eq!(db.load_json("events", &events, &JsonOptions::default()), Ok(4));
+        let columns: Vec<(String, DataType)> = db.tables["events"].columns.iter().map(|c| (c.name.clone(), c.data_type.clone())).collect();
+        let expected = [
+            ("id", DataType::Int),
+            ("user_name", DataType::String),
+            ("user_geo_cc", DataType::String),
+            ("score", DataType::Float),
+            ("at", DataType::Timestamp),
+            ("tags", DataType::Json),
+            ("extra", DataType::Bool),
+            ("mixed", DataType::Json),
+            ("nothing", DataType::Json),
+        ];
+        assert_eq!(columns, expected.map(|(n, ty)| (n.to_string(), ty)));
+        assert_eq!(rows(&db, "SELECT user_name, user_geo_cc, score, extra FROM events WHERE id = 2"), vec![vec![string("bob"), Value::Null, Value::Float(2.5), Value::Bool(true)]]);
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM events WHERE at < TIMESTAMP '2024-02-01 00:00:00'"), vec![vec![int(1)]]);
+        assert_eq!(rows(&db, "SELECT mixed FROM events WHERE id = 4"), vec![vec![Value::Json(Json::String("x".into()))]]);
+
+        // An array of objects, kept nested
+        let nested = Table::from_json("nested", "[{\"a\": {\"b\": 1}, \"c\": {}}, {\"a\": null}]", &JsonOptions { flatten: false, ..JsonOptions::default() }).unwrap();
+        assert_eq!(nested.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(nested.rows[0].data["a"], Value::Json(Json::parse("{\"b\": 1}").unwrap()));
+        let flat = Table::from_json("flat", "[{\"a\": {\"b\": 1}, \"c\": {}}]", &JsonOptions { separator: ".".into(), ..JsonOptions::default() }).unwrap();
+        assert_eq!(flat.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["a.b", "c"]);
+        assert_eq!(Table::from_json("t", "{\"a\": 1}\n[1]\n", &JsonOptions::default()).unwrap_err(), "JSON line 2: expected an object");
+        assert!(Table::from_json("t", "{\"a\": 1}\n{\"a\": \n", &JsonOptions::default()).unwrap_err().starts_with("NDJSON line 2"));
+        assert_eq!(Table::from_json("t", "[{}, 2]", &JsonOptions::default()).unwrap_err(), "JSON element 2: expected an object");
+
+        // An existing table: members by column name, converted to the column types
+        run(&mut db, "CREATE TABLE orders (id INT PRIMARY KEY, total DECIMAL(6, 2), meta JSON, note TEXT DEFAULT 'none')").unwrap();
+        let orders = file("orders", "[{\"total\": 9, \"id\": 1, \"meta\": {\"gift\": true}}, {\"id\": 2, \"total\": 1.5, \"meta\": \"x\"}]");
+        assert_eq!(db.load_json("orders", &orders, &JsonOptions { flatten: false, ..JsonOptions::default() }), Ok(2));
+        assert_eq!(rows(&db, "SELECT meta, note FROM orders WHERE id = 2"), vec![vec![Value::Json(Json::String("x".into())), string("none")]]);
+        let unknown = file("unknown", "{\"id\": 3, \"colour\": \"red\"}\n");
+        assert_eq!(db.load_json("orders", &unknown, &JsonOptions::default()), Err("Column not found: orders.colour".into()));
+        assert!(db.load_json("orders", &orders, &JsonOptions::default()).is_err());
+        assert_eq!(rows(&db, "SELECT COUNT(*) FROM orders"), vec![vec![int(2)]]);
+
+        // Writers keep the query's column order
+        let out = dir.join(format!("ql-out-{}.json", std::process::id()));
+        let query = parse("SELECT note, id, meta, total, ARRAY[id, 2] AS ids FROM orders ORDER BY id").unwrap();
+        assert_eq!(db.write_json(&query, &out, &JsonOptions { lines: true, ..JsonOptions::default() }), Ok(2));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "{\"note\":\"none\",\"id\":1,\"meta\":{\"gift\":true},\"total\":9.0,\"ids\":[1,2]}\n{\"note\":\"none\",\"id\":2,\"meta\":\"x\",\"total\":1.5,\"ids\":[2,2]}\n"
+        );
+        let result = db.execute(&parse("SELECT id, user_name, at FROM events WHERE id < 3 ORDER BY id").unwrap()).unwrap();
+        assert_eq!(result.to_json(&JsonOptions::default()), "[\n{\"id\":1,\"user_name\":\"ada\",\"at\":\"2024-01-31 10:00:00\"},\n{\"id\":2,\"user_name\":\"bob\",\"at\":null}\n]\n");
+        assert_eq!(db.write_json(&parse("SELECT id FROM events WHERE id = 0").unwrap(), &out, &JsonOptions::default()), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "[]\n");
+
+        // Round trip
+        db.write_json(&parse("SELECT * FROM events").unwrap(), &out, &JsonOptions::default()).unwrap();
+        db.load_json("copied", &out, &JsonOptions::default()).unwrap();
+        assert_eq!(rows(&db, "SELECT id, user_name, score, at, extra FROM copied"), rows(&db, "SELECT id, user_name, score, at, extra FROM events"));
+        for path in [events, orders, unknown, out] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
     #[test]
     fn test_execute_join() {
         let mut db = Database::new();
@@ -234,14 +2275,16 @@ mod tests {
             name: "users".into(),
             columns: vec!["id".into(), "name".into()],
             rows: vec![row(vec![("id", int(1)), ("name", string("Alice"))])],
+            ..Default::default()
         };
         let orders = Table {
             name: "orders".into(),
             columns: vec!["user_id".into(), "amount".into()],
             rows: vec![row(vec![("user_id", int(1)), ("amount", int(100))])],
+            ..Default::default()
         };
-        db.add_table(users);
-        db.add_table(orders);
+        db.add_table(users).unwrap();
+        db.add_table(orders).unwrap();
 
         let q = parse("SELECT * FROM users JOIN orders ON users.id = orders.user_id").unwrap();
         let result = db.execute(&q).unwrap();
@@ -0,0 +1,628 @@
+//! Multi-version concurrency control over a database shared between threads.
+//!
+//! Every commit publishes a new immutable version of the database. Readers
+//! query the version that was current when they asked, without locks, while
+//! later commits publish newer ones. Versions share each table through an
+//! `Arc` until a commit changes it, and a writer's private copy of the
+//! version it started from shares them too: a table is copied only when the
+//! writer first changes it. At commit the tables it changed are diffed
+//! against that version, checked against the commits made since, and merged
+//! into the latest version under a lock. Rows of a table with a primary key
+//! are tracked one by one, so writers touching different rows of the same
+//! table do not conflict; any other change claims the whole table.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{
+    key_of, ColumnarTable, ConstraintKind, Database, Effect, GroupKey, InsertSource, Query, ResultSet, Row, Statement,
+    StatementResult, Table, TableStats, Value,
+};
+
+/// How much of other transactions' work a transaction sees, and which
+/// overlaps make its commit fail.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IsolationLevel {
+    /// Each statement sees what was committed before it started. Commits
+    /// never conflict: a row written by two transactions keeps the version
+    /// of the later commit, and a table without a primary key is replaced
+    /// whole.
+    ReadCommitted,
+    /// The whole transaction sees what was committed before it began. Of
+    /// two transactions that change the same row, the first to commit wins
+    /// and the other fails.
+    #[default]
+    Snapshot,
+    /// Snapshot isolation that also fails a transaction when a table it read
+    /// was changed by a commit made after it began, so committed
+    /// transactions behave as if they ran one at a time in commit order.
+    Serializable,
+}
+
+/// What a transaction did to one table.
+#[derive(Clone)]
+enum Change {
+    /// Inserted, updated or deleted rows, by primary key.
+    Rows(HashSet<GroupKey>),
+    /// Created, altered, or changed with no primary key to track rows by.
+    Table,
+    Drop,
+}
+
+impl Change {
+    fn overlaps(&self, other: &Change) -> bool {
+        match (self, other) {
+            (Change::Rows(a), Change::Rows(b)) => !a.is_disjoint(b),
+            _ => true,
+        }
+    }
+}
+
+/// A published commit, kept while an open transaction may conflict with it.
+struct Commit {
+    ts: u64,
+    changes: BTreeMap<String, Change>,
+}
+
+struct State {
+    /// The latest committed state, which owns the write-ahead log if any.
+    db: Database,
+    /// An immutable copy of `db`, handed to readers.
+    latest: Arc<Database>,
+    /// Number of commits so far; a version's timestamp.
+    ts: u64,
+    history: VecDeque<Commit>,
+    /// Start timestamps of open SNAPSHOT and SERIALIZABLE transactions, with
+    /// how many started at each.
+    active: BTreeMap<u64, usize>,
+    isolation: IsolationLevel,
+}
+
+impl State {
+    fn gc(&mut self) -> usize {
+        let oldest = self.active.keys().next().copied().unwrap_or(self.ts);
+        let before = self.history.len();
+        while self.history.front().is_some_and(|c| c.ts <= oldest) {
+            self.history.pop_front();
+        }
+        before - self.history.len()
+    }
+}
+
+/// A database that many threads can read and write at once. Cloning the
+/// handle shares the same database.
+#[derive(Clone)]
+pub struct SharedDatabase {
+    state: Arc<Mutex<State>>,
+}
+
+impl SharedDatabase {
+    /// Shares `db`, rolling back a transaction left open on it. Commits are
+    /// applied to `db`, so a database from `Database::open` keeps logging
+    /// them to its write-ahead log.
+    pub fn new(mut db: Database) -> Self {
+        if db.in_transaction() {
+            let _ = db.rollback();
+        }
+        let latest = Arc::new(version_of(&db));
+        let state = State {
+            db,
+            latest,
+            ts: 0,
+            history: VecDeque::new(),
+            active: BTreeMap::new(),
+            isolation: IsolationLevel::default(),
+        };
+        SharedDatabase { state: Arc::new(Mutex::new(state)) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// The level `begin` and `execute_statement` use.
+    pub fn isolation(&self) -> IsolationLevel {
+        self.lock().isolation
+    }
+
+    pub fn set_isolation(&self, isolation: IsolationLevel) {
+        self.lock().isolation = isolation;
+    }
+
+    /// The latest committed version. It does not change when later commits
+    /// are made, and is freed once the last handle to it is dropped.
+    pub fn snapshot(&self) -> Arc<Database> {
+        self.lock().latest.clone()
+    }
+
+    pub fn execute(&self, query: &Query) -> Result<ResultSet, String> {
+        self.snapshot().execute(query)
+    }
+
+    /// Runs one statement in a transaction of its own.
+    pub fn execute_statement(&self, stmt: &Statement) -> Result<StatementResult, String> {
+        let mut txn = self.begin();
+        let result = txn.execute_statement(stmt)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    pub fn begin(&self) -> SharedTransaction {
+        self.begin_with(self.isolation())
+    }
+
+    pub fn begin_with(&self, isolation: IsolationLevel) -> SharedTransaction {
+        let mut state = self.lock();
+        let (start, base) = (state.ts, state.latest.clone());
+        let registered = isolation != IsolationLevel::ReadCommitted;
+        if registered {
+            *state.active.entry(start).or_default() += 1;
+        }
+        drop(state);
+        SharedTransaction {
+            shared: self.clone(),
+            isolation,
+            registered: registered.then_some(start),
+            start,
+            work: private_copy(&base),
+            base,
+            reads: HashSet::new(),
+            savepoints: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Folds the write-ahead log into the database file, if there is one.
+    pub fn checkpoint(&self) -> Result<(), String> {
+        self.lock().db.checkpoint()
+    }
+
+    /// Drops the commits that no open transaction can conflict with any
+    /// more, returning how many. Runs whenever a transaction ends.
+    pub fn gc(&self) -> usize {
+        self.lock().gc()
+    }
+
+    /// Number of commits kept for conflict checks.
+    pub fn history_len(&self) -> usize {
+        self.lock().history.len()
+    }
+}
+
+/// A transaction on a `SharedDatabase`, from `begin`. Its changes are
+/// visible only to itself until `commit`; dropping it rolls them back.
+pub struct SharedTransaction {
+    shared: SharedDatabase,
+    isolation: IsolationLevel,
+    /// The start timestamp counted in `State::active`.
+    registered: Option<u64>,
+    /// Timestamp of `base`.
+    start: u64,
+    /// The committed version `work` was copied from.
+    base: Arc<Database>,
+    /// `base` plus this transaction's changes, with a transaction open so
+    /// deferred foreign keys wait for the commit.
+    work: Database,
+    /// Tables read, for SERIALIZABLE.
+    reads: HashSet<String>,
+    savepoints: Vec<Savepoint>,
+    finished: bool,
+}
+
+struct Savepoint {
+    name: String,
+    base: Arc<Database>,
+    start: u64,
+    tables: HashMap<String, Arc<Table>>,
+    columnar: HashMap<String, Arc<ColumnarTable>>,
+}
+
+impl SharedTransaction {
+    pub fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+
+    /// Runs a statement inside the transaction. BEGIN, COMMIT and ROLLBACK
+    /// are rejected; use `commit` and `rollback` instead.
+    pub fn execute_statement(&mut self, stmt: &Statement) -> Result<StatementResult, String> {
+        let control = match stmt {
+            Statement::Begin | Statement::Commit | Statement::Rollback => {
+                return Err("Use SharedTransaction::commit or SharedTransaction::rollback to end the transaction".into());
+            }
+            Statement::Savepoint(name) => Some(self.savepoint(name)),
+            Statement::RollbackTo(name) => Some(self.rollback_to(name)),
+            Statement::Release(name) => Some(self.release(name)),
+            _ => None,
+        };
+        if let Some(result) = control {
+            return result.map(|_| StatementResult::empty());
+        }
+        self.refresh()?;
+        self.note_reads(stmt);
+        self.work.execute_statement(stmt)
+    }
+
+    pub fn execute(&mut self, query: &Query) -> Result<ResultSet, String> {
+        self.refresh()?;
+        self.note_reads(&Statement::Select(query.clone()));
+        self.work.execute(query)
+    }
+
+    /// The data as this transaction sees it, its own changes included.
+    pub fn database(&self) -> &Database {
+        &self.work
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> Result<(), String> {
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            base: self.base.clone(),
+            start: self.start,
+            tables: self.work.tables.clone(),
+            columnar: self.work.columnar.clone(),
+        });
+        Ok(())
+    }
+
+    /// Undoes everything since the savepoint, which stays defined.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), String> {
+        let i = self.find_savepoint(name)?;
+        self.savepoints.truncate(i + 1);
+        let savepoint = &self.savepoints[i];
+        self.work.tables = savepoint.tables.clone();
+        self.work.columnar = savepoint.columnar.clone();
+        self.base = savepoint.base.clone();
+        self.start = savepoint.start;
+        Ok(())
+    }
+
+    /// Forgets the savepoint and any made after it, keeping their changes.
+    pub fn release(&mut self, name: &str) -> Result<(), String> {
+        let i = self.find_savepoint(name)?;
+        self.savepoints.truncate(i);
+        Ok(())
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize, String> {
+        self.savepoints.iter().rposition(|s| s.name == name).ok_or(format!("Savepoint not found: {}", name))
+    }
+
+    /// Checks deferred foreign keys, then checks the changes against the
+    /// commits made since the transaction began and merges them into the
+    /// latest version. If anything fails nothing is applied.
+    pub fn commit(mut self) -> Result<(), String> {
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+        let result = self.publish(&mut state);
+        self.finish(&mut state);
+        result
+    }
+
+    pub fn rollback(mut self) -> Result<(), String> {
+        let shared = self.shared.clone();
+        self.finish(&mut shared.lock());
+        Ok(())
+    }
+
+    fn finish(&mut self, state: &mut State) {
+        self.finished = true;
+        if let Some(start) = self.registered.take() {
+            let count = state.active.get_mut(&start).expect("open transactions are registered");
+            *count -= 1;
+            if *count == 0 {
+                state.active.remove(&start);
+            }
+        }
+        state.gc();
+    }
+
+    fn publish(&mut self, state: &mut State) -> Result<(), String> {
+        self.work.commit()?;
+        // Statistics only steer plans, so the latest ANALYZE simply wins
+        let analyzed: Vec<(String, TableStats)> = self
+            .work
+            .statistics
+            .iter()
+            .filter(|(name, stats)| self.base.statistics.get(*name) != Some(stats))
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect();
+        if !analyzed.is_empty() {
+            state.db.statistics.extend(analyzed);
+            state.latest = Arc::new(version_of(&state.db));
+        }
+        let changes = diff(&self.base, &self.work);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        if self.isolation != IsolationLevel::ReadCommitted {
+            for commit in state.history.iter().filter(|c| c.ts > self.start) {
+                for (name, theirs) in &commit.changes {
+                    if changes.get(name).is_some_and(|mine| mine.overlaps(theirs)) {
+                        return Err(format!("Could not serialize access: {} was changed by a concurrent transaction", name));
+                    }
+                    if self.isolation == IsolationLevel::Serializable && self.reads.contains(name) {
+                        return Err(format!(
+                            "Could not serialize access: {} was read before a concurrent transaction changed it",
+                            name
+                        ));
+                    }
+                }
+            }
+        }
+
+        let db = &mut state.db;
+        db.begin()?;
+        let names: HashSet<&str> = changes.keys().map(String::as_str).collect();
+        if let Err(e) = apply(db, &self.base, &self.work, &changes).and_then(|_| db.check_tables(&names)) {
+            db.rollback()?;
+            return Err(format!("{}; transaction rolled back", e));
+        }
+        let logged = db.commit();
+        state.ts += 1;
+        state.latest = Arc::new(version_of(&state.db));
+        state.history.push_back(Commit { ts: state.ts, changes });
+        logged
+    }
+
+    /// Under READ COMMITTED, moves the transaction onto the latest commit
+    /// before a statement, carrying its own changes over.
+    fn refresh(&mut self) -> Result<(), String> {
+        if self.isolation != IsolationLevel::ReadCommitted {
+            return Ok(());
+        }
+        let (ts, latest) = {
+            let state = self.shared.lock();
+            (state.ts, state.latest.clone())
+        };
+        if ts == self.start {
+            return Ok(());
+        }
+        let mut work = private_copy(&latest);
+        apply(&mut work, &self.base, &self.work, &diff(&self.base, &self.work))?;
+        self.work = work;
+        self.base = latest;
+        self.start = ts;
+        Ok(())
+    }
+
+    /// Records the tables a statement reads, including those at the other
+    /// end of foreign keys that it checks or cascades through.
+    fn note_reads(&mut self, stmt: &Statement) {
+        if self.isolation != IsolationLevel::Serializable {
+            return;
+        }
+        let mut names: HashSet<String> = HashSet::new();
+        match stmt {
+            Statement::Select(query) | Statement::CopyTo { query, .. } => query_tables(query, &mut names),
+            Statement::CopyFrom { table, .. } => {
+                names.insert(table.clone());
+            }
+            Statement::Insert(insert) => {
+                names.insert(insert.table.clone());
+                if let InsertSource::Query(query) = &insert.source {
+                    query_tables(query, &mut names);
+                }
+            }
+            Statement::Update(update) => {
+                names.insert(update.table.clone());
+            }
+            Statement::Delete(delete) => {
+                names.insert(delete.table.clone());
+            }
+            Statement::DropTable { name, .. }
+            | Statement::AlterTable { name, .. }
+            | Statement::CreateIndex { table: name, .. } => {
+                names.insert(name.clone());
+            }
+            Statement::CreateTable { constraints, .. } => {
+                for c in constraints {
+                    if let ConstraintKind::ForeignKey(fk) = &c.kind {
+                        names.insert(fk.ref_table.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        if !matches!(stmt, Statement::Select(_)) {
+            let links: Vec<(&String, &String)> = self
+                .work
+                .tables
+                .values()
+                .map(|t| (&t.name, &t.constraints))
+                .chain(self.work.columnar.values().map(|t| (&t.name, &t.constraints)))
+                .flat_map(|(name, constraints)| {
+                    constraints.iter().filter_map(move |c| match &c.kind {
+                        ConstraintKind::ForeignKey(fk) => Some((name, &fk.ref_table)),
+                        _ => None,
+                    })
+                })
+                .collect();
+            loop {
+                let before = names.len();
+                for (child, parent) in &links {
+                    if names.contains(*child) || names.contains(*parent) {
+                        names.insert((*child).clone());
+                        names.insert((*parent).clone());
+                    }
+                }
+                if names.len() == before {
+                    break;
+                }
+            }
+        }
+        self.reads.extend(names);
+    }
+}
+
+impl Drop for SharedTransaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            let shared = self.shared.clone();
+            self.finish(&mut shared.lock());
+        }
+    }
+}
+
+fn query_tables(query: &Query, names: &mut HashSet<String>) {
+    if query.from_unnest.is_none() {
+        names.insert(query.from_table.clone());
+    }
+    names.extend(query.joins.iter().filter(|j| j.unnest.is_none()).map(|j| j.table.clone()));
+}
+
+/// A read-only version of `db`, sharing its tables.
+fn version_of(db: &Database) -> Database {
+    Database {
+        tables: db.tables.clone(),
+        columnar: db.columnar.clone(),
+        threads: db.threads,
+        statistics: db.statistics.clone(),
+        pool: db.pool.clone(),
+        ..Database::new()
+    }
+}
+
+fn private_copy(db: &Database) -> Database {
+    let mut copy = version_of(db);
+    copy.begin().expect("a new database has no open transaction");
+    copy
+}
+
+/// How `work` differs from `base`, table by table. Tables `work` still
+/// shares with `base` are unchanged and not compared.
+fn diff(base: &Database, work: &Database) -> BTreeMap<String, Change> {
+    let names: BTreeSet<&String> =
+        base.tables.keys().chain(base.columnar.keys()).chain(work.tables.keys()).chain(work.columnar.keys()).collect();
+    let mut changes = BTreeMap::new();
+    for name in names {
+        let shared = match (base.tables.get(name), work.tables.get(name), base.columnar.get(name), work.columnar.get(name)) {
+            (Some(old), Some(new), ..) => Arc::ptr_eq(old, new),
+            (None, None, Some(old), Some(new)) => Arc::ptr_eq(old, new),
+            _ => false,
+        };
+        if shared {
+            continue;
+        }
+        let change = match (base.tables.get(name), work.tables.get(name)) {
+            (Some(old), Some(new)) => table_change(old, new),
+            _ => match (base.columnar.get(name), work.columnar.get(name)) {
+                (Some(old), Some(new)) => (!same_table(&old.to_table(), &new.to_table())).then_some(Change::Table),
+                _ if !work.has_table(name) => Some(Change::Drop),
+                _ => Some(Change::Table),
+            },
+        };
+        if let Some(change) = change {
+            changes.insert(name.clone(), change);
+        }
+    }
+    changes
+}
+
+fn table_change(old: &Table, new: &Table) -> Option<Change> {
+    if old.columns != new.columns || old.constraints != new.constraints || old.indexes != new.indexes {
+        return Some(Change::Table);
+    }
+    let Some(pk) = old.primary_key() else {
+        return (!same_table(old, new)).then_some(Change::Table);
+    };
+    let index = |t: &Table| -> HashMap<GroupKey, HashMap<String, Value>> {
+        t.rows.iter().filter_map(|r| key_of(r, pk).map(|k| (k, r.data.clone()))).collect()
+    };
+    let (before, after) = (index(old), index(new));
+    let mut keys: HashSet<GroupKey> =
+        after.iter().filter(|(k, data)| before.get(*k) != Some(*data)).map(|(k, _)| k.clone()).collect();
+    keys.extend(before.keys().filter(|k| !after.contains_key(*k)).cloned());
+    (!keys.is_empty()).then_some(Change::Rows(keys))
+}
+
+fn same_table(a: &Table, b: &Table) -> bool {
+    a.columns == b.columns
+        && a.constraints == b.constraints
+        && a.indexes == b.indexes
+        && a.rows.len() == b.rows.len()
+        && a.rows.iter().zip(&b.rows).all(|(x, y)| x.data == y.data)
+}
+
+/// Carries `changes`, made on top of `base`, from `from` over to `db`,
+/// logging each. Fails if a row the change inserted has since been
+/// inserted by someone else.
+fn apply(db: &mut Database, base: &Database, from: &Database, changes: &BTreeMap<String, Change>) -> Result<(), String> {
+    let logging = db.logging();
+    for (name, change) in changes {
+        let source = from.tables.get(name);
+        let target = db.tables.get_mut(name);
+        match (change, source, target) {
+            (Change::Rows(keys), Some(source), Some(target))
+                if target.columns == source.columns
+                    && target.constraints == source.constraints
+                    && target.indexes == source.indexes =>
+            {
+                let target = Arc::make_mut(target);
+                let pk = source.primary_key().expect("rows are tracked by primary key").to_vec();
+                let existed: HashSet<GroupKey> =
+                    base.tables.get(name).map(|t| t.rows.iter().filter_map(|r| key_of(r, &pk)).collect()).unwrap_or_default();
+                let mut updated: HashMap<GroupKey, &Row> =
+                    source.rows.iter().filter_map(|r| key_of(r, &pk).filter(|k| keys.contains(k)).map(|k| (k, r))).collect();
+                let present: HashSet<GroupKey> = target.rows.iter().filter_map(|r| key_of(r, &pk)).collect();
+                if let Some(key) = updated.keys().find(|k| !existed.contains(*k) && present.contains(*k)) {
+                    return Err(duplicate_key(source, &pk, key));
+                }
+                let mut replaced = HashMap::new();
+                let mut deleted = HashSet::new();
+                for (i, row) in target.rows.iter().enumerate() {
+                    let Some(key) = key_of(row, &pk).filter(|k| keys.contains(k)) else { continue };
+                    match updated.remove(&key) {
+                        Some(row) => {
+                            replaced.insert(i, row.clone());
+                        }
+                        None => {
+                            deleted.insert(i);
+                        }
+                    }
+                }
+                let inserted: Vec<Row> = source.rows.iter().filter(|r| key_of(r, &pk).is_some_and(|k| updated.contains_key(&k))).cloned().collect();
+                // Logged row by row, in the order they are applied
+                let mut effects = Vec::new();
+                if logging {
+                    effects = Effect::rows(name, &replaced, &deleted);
+                    if !inserted.is_empty() {
+                        effects.push(Effect::Insert(name.clone(), inserted.clone()));
+                    }
+                }
+                target.update(replaced);
+                target.delete(&deleted);
+                target.append(inserted);
+                for effect in effects {
+                    db.log(effect);
+                }
+            }
+            (Change::Drop, ..) => {
+                db.tables.remove(name);
+                db.columnar.remove(name);
+                db.log(Effect::Drop(name.clone()));
+            }
+            _ => {
+                db.tables.remove(name);
+                db.columnar.remove(name);
+                if let Some(table) = source {
+                    db.tables.insert(name.clone(), table.clone());
+                } else if let Some(table) = from.columnar.get(name) {
+                    db.columnar.insert(name.clone(), table.clone());
+                }
+                db.log(Effect::Table(name.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn duplicate_key(table: &Table, pk: &[String], key: &GroupKey) -> String {
+    let constraint = table.constraints.iter().find(|c| matches!(c.kind, ConstraintKind::PrimaryKey(_)));
+    let values: Vec<String> = key.1.iter().map(|v| v.to_string()).collect();
+    format!(
+        "Constraint {} violated: duplicate key ({})=({})",
+        constraint.map_or(table.name.as_str(), |c| c.name.as_str()),
+        pk.join(", "),
+        values.join(", ")
+    )
+}
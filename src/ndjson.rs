@@ -0,0 +1,249 @@
+//! JSON and NDJSON import and export: `Table::from_json` and
+//! `Database::load_json` read a JSON array of objects, or newline-delimited
+//! JSON with one object per line, into a table; `ResultSet::to_json` and
+//! `Database::write_json` write query results out in either form.
+//!
+//! Each object is a row. Nested objects are spread over columns named by
+//! their path (`{"user": {"id": 1}}` gives a `user_id` column), or kept as
+//! JSON values when not flattening. Columns appear in the order their keys
+//! are first seen, and a key missing from an object is NULL there.
+//!
+//! A new table's column types are inferred across every record: INT, FLOAT
+//! when integers and other numbers mix, BOOL, and for strings DATE,
+//! TIMESTAMP or TIMESTAMPTZ when every one reads as such, else TEXT. Arrays,
+//! values of mixed kinds, and columns that are always null are JSON.
+//!
+//! Output objects hold a row's values under its column names, in column
+//! order. Arrays and structs are written as JSON arrays and objects, dates
+//! and times as text, and non-finite floats as null.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{temporal, Column, Database, DataType, Expr, Insert, InsertSource, Json, Query, ResultSet, Row, Statement, Table, Value};
+
+/// How JSON is read into tables and written from results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonOptions {
+    /// Write one object per line (NDJSON) rather than one array. Reading
+    /// tells the two apart by themselves.
+    pub lines: bool,
+    /// Spread nested objects over columns rather than keeping them as JSON.
+    pub flatten: bool,
+    /// Joins the keys of a nested path into a column name.
+    pub separator: String,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions { lines: false, flatten: true, separator: "_".into() }
+    }
+}
+
+/// The members of one input object, flattened, and where it was read.
+struct Record {
+    place: String,
+    members: Vec<(String, Json)>,
+}
+
+impl Table {
+    /// A table named `name` holding the objects of JSON or NDJSON `text`,
+    /// with columns and types inferred from every object.
+    pub fn from_json(name: &str, text: &str, options: &JsonOptions) -> Result<Table, String> {
+        let records = records(text, options)?;
+        let mut columns: Vec<(String, Option<DataType>)> = Vec::new();
+        for record in &records {
+            for (key, doc) in &record.members {
+                let ty = kind(doc);
+                match columns.iter_mut().find(|(n, _)| n == key) {
+                    Some((_, current)) => *current = merge(current.take(), ty),
+                    None => columns.push((key.clone(), ty)),
+                }
+            }
+        }
+        let columns: Vec<Column> = columns
+            .into_iter()
+            .map(|(n, ty)| {
+                let ty = match ty {
+                    Some(DataType::String) => text_type(&records, &n),
+                    Some(ty) => ty,
+                    None => DataType::Json,
+                };
+                Column::new(&n, ty)
+            })
+            .collect();
+
+        let mut table = Table::new(name, columns);
+        for record in records {
+            let mut row = Row { data: Default::default() };
+            for (key, doc) in record.members {
+                let column = table.column(&key).unwrap();
+                let value = value(&column.data_type, doc).map_err(|e| format!("JSON {}: {}: {}", record.place, key, e))?;
+                row.data.insert(key, value);
+            }
+            table.rows.push(row);
+        }
+        Ok(table)
+    }
+}
+
+impl ResultSet {
+    /// The rows as JSON objects, in an array or one per line as `options`
+    /// says, with members in column order.
+    pub fn to_json(&self, options: &JsonOptions) -> String {
+        let objects = self.rows.iter().map(|row| {
+            let members = self.columns.iter().zip(row).map(|(c, v)| (c.name.clone(), to_json(v))).collect();
+            Json::Object(members).to_string()
+        });
+        let objects: Vec<String> = objects.collect();
+        match (options.lines, objects.is_empty()) {
+            (true, true) => String::new(),
+            (true, false) => objects.join("\n") + "\n",
+            (false, true) => "[]\n".into(),
+            (false, false) => format!("[\n{}\n]\n", objects.join(",\n")),
+        }
+    }
+}
+
+impl Database {
+    /// Loads the JSON or NDJSON file at `path` into table `name`. An
+    /// existing table takes the objects' members by column name in one
+    /// INSERT, which converts them to the column types as it converts any
+    /// value; otherwise the table is created as `Table::from_json` builds it.
+    /// Returns the number of rows loaded.
+    pub fn load_json(&mut self, name: &str, path: impl AsRef<Path>, options: &JsonOptions) -> Result<usize, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let Some(existing) = self.tables.get(name).map(|t| &t.columns).or_else(|| self.columnar.get(name).map(|t| &t.columns)).cloned() else {
+            let table = Table::from_json(name, &text, options)?;
+            let count = table.rows.len();
+            self.add_table(table)?;
+            return Ok(count);
+        };
+
+        let records = records(&text, options)?;
+        let mut names: Vec<String> = Vec::new();
+        for (key, _) in records.iter().flat_map(|r| &r.members) {
+            if !names.contains(key) {
+                existing.iter().find(|c| c.name == *key).ok_or(format!("Column not found: {}.{}", name, key))?;
+                names.push(key.clone());
+            }
+        }
+        let mut rows = Vec::with_capacity(records.len());
+        for mut record in records {
+            let row = names.iter().map(|n| {
+                let doc = record.members.iter().position(|(k, _)| k == n).map_or(Json::Null, |i| record.members.swap_remove(i).1);
+                match (&existing.iter().find(|c| c.name == *n).unwrap().data_type, doc) {
+                    (_, Json::Null) => Expr::Literal(Value::Null),
+                    (DataType::Json, doc) => Expr::Literal(Value::Json(doc)),
+                    (_, doc) => Expr::Literal(doc.to_value()),
+                }
+            });
+            rows.push(row.collect());
+        }
+        let insert = Insert { table: name.into(), columns: names, source: InsertSource::Values(rows), returning: Vec::new() };
+        Ok(self.execute_statement(&Statement::Insert(insert))?.rows_affected)
+    }
+
+    /// Writes the result of `query` to a JSON or NDJSON file at `path`,
+    /// replacing any file there. Returns the number of rows written.
+    pub fn write_json(&self, query: &Query, path: impl AsRef<Path>, options: &JsonOptions) -> Result<usize, String> {
+        let path = path.as_ref();
+        let result = self.execute(query)?;
+        fs::write(path, result.to_json(options)).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        Ok(result.len())
+    }
+}
+
+/// The objects of `text`: one JSON array of them, or one per line.
+fn records(text: &str, options: &JsonOptions) -> Result<Vec<Record>, String> {
+    let docs: Vec<(String, Json)> = if text.trim_start().starts_with('[') {
+        match Json::parse(text)? {
+            Json::Array(items) => items.into_iter().enumerate().map(|(i, doc)| (format!("element {}", i + 1), doc)).collect(),
+            _ => unreachable!("a document starting with [ is an array"),
+        }
+    } else {
+        let lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        lines.map(|(i, line)| Json::parse(line).map(|doc| (format!("line {}", i + 1), doc)).map_err(|e| format!("NDJSON line {}: {}", i + 1, e))).collect::<Result<_, _>>()?
+    };
+    docs.into_iter()
+        .map(|(place, doc)| match doc {
+            Json::Object(members) => {
+                let mut flat = Vec::with_capacity(members.len());
+                flatten(String::new(), members, options, &mut flat);
+                Ok(Record { place, members: flat })
+            }
+            _ => Err(format!("JSON {}: expected an object", place)),
+        })
+        .collect()
+}
+
+/// Members of an object with nested objects spread under `prefix`. A later
+/// member replaces an earlier one of the same name.
+fn flatten(prefix: String, members: Vec<(String, Json)>, options: &JsonOptions, out: &mut Vec<(String, Json)>) {
+    for (key, doc) in members {
+        let key = prefix.clone() + &key;
+        match doc {
+            Json::Object(inner) if options.flatten && !inner.is_empty() => flatten(key + &options.separator, inner, options, out),
+            doc => match out.iter_mut().find(|(k, _)| *k == key) {
+                Some(member) => member.1 = doc,
+                None => out.push((key, doc)),
+            },
+        }
+    }
+}
+
+/// The column type a single JSON value suggests; `None` for null.
+fn kind(doc: &Json) -> Option<DataType> {
+    match doc {
+        Json::Null => None,
+        Json::Bool(_) => Some(DataType::Bool),
+        Json::Int(_) => Some(DataType::Int),
+        Json::Float(_) => Some(DataType::Float),
+        Json::String(_) => Some(DataType::String),
+        Json::Array(_) | Json::Object(_) => Some(DataType::Json),
+    }
+}
+
+/// The type of a column holding values of both types.
+fn merge(a: Option<DataType>, b: Option<DataType>) -> Option<DataType> {
+    match (a, b) {
+        (None, ty) | (ty, None) => ty,
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(DataType::Int | DataType::Float), Some(DataType::Int | DataType::Float)) => Some(DataType::Float),
+        _ => Some(DataType::Json),
+    }
+}
+
+/// The type of a column of strings: a date or time type every one of them
+/// reads as, or TEXT.
+fn text_type(records: &[Record], column: &str) -> DataType {
+    let strings: Vec<&str> = records
+        .iter()
+        .flat_map(|r| &r.members)
+        .filter_map(|(k, doc)| match doc {
+            Json::String(s) if k == column => Some(s.as_str()),
+            _ => None,
+        })
+        .collect();
+    let candidates = [DataType::Date, DataType::Timestamp, DataType::TimestampTz];
+    candidates.into_iter().find(|ty| strings.iter().all(|s| temporal::parse(ty, s).is_ok())).unwrap_or(DataType::String)
+}
+
+/// A member's value in a column of type `ty`.
+fn value(ty: &DataType, doc: Json) -> Result<Value, String> {
+    match (ty, doc) {
+        (_, Json::Null) => Ok(Value::Null),
+        (DataType::Json, doc) => Ok(Value::Json(doc)),
+        (ty, doc) => ty.coerce(doc.to_value()),
+    }
+}
+
+/// A result value as JSON, arrays and structs included.
+fn to_json(v: &Value) -> Json {
+    match v {
+        Value::Array(items) => Json::Array(items.iter().map(to_json).collect()),
+        Value::Struct(fields) => Json::Object(fields.iter().map(|(name, v)| (name.clone(), to_json(v))).collect()),
+        v => Json::from_value(v),
+    }
+}
@@ -0,0 +1,256 @@
+//! Cost-based join ordering.
+//!
+//! When every table of a query has statistics (see `stats`), the conditions
+//! of all ON clauses and the WHERE clause are pooled, and the tables are
+//! joined in the order that keeps the estimated intermediate results
+//! smallest: over every left-deep order by dynamic programming for up to
+//! `MAX_DP_TABLES` tables, and greedily beyond. Each condition is checked as
+//! soon as the tables it reads are joined, and one that reads a single table
+//! narrows that table before the join. A hash join builds its table on
+//! whichever side is estimated smaller.
+//!
+//! Only inner joins exist, so any order yields the same rows; `execute`
+//! puts them back in the order the textual plan produces. Queries where an
+//! unqualified column name belongs to more than one table keep the textual
+//! order, since which table such a name reads depends on the join order.
+
+use std::collections::HashMap;
+
+use crate::index::{self, is_constant};
+use crate::stats::DEFAULT_SELECTIVITY;
+use crate::{
+    conjuncts, equi_join_keys, expr_columns, referenced_columns, ColumnStats, Database, Expr, JoinPlan, JoinStep, Joins, Query, Row, Source,
+    TableStats, Value,
+};
+
+/// Largest number of tables whose join orders are all compared.
+pub const MAX_DP_TABLES: usize = 8;
+
+/// A condition and the sources (as a bit set) it reads.
+struct Term {
+    expr: Expr,
+    tables: u64,
+}
+
+struct Estimator<'q, 'a> {
+    db: &'q Database,
+    sources: &'q [Source<'a>],
+    stats: Vec<&'q TableStats>,
+    terms: Vec<Term>,
+    /// Estimated rows of each source after its own conditions.
+    base: Vec<f64>,
+}
+
+/// One join considered by the optimizer: `right` joined to the sources in
+/// `left`.
+struct Step<'a> {
+    on: Option<Expr>,
+    strategy: JoinPlan<'a>,
+    cost: f64,
+    rows: f64,
+}
+
+/// Plans the joins of `query` by cost; `None` when some table has no
+/// statistics or a column name is ambiguous, leaving the textual order.
+pub(crate) fn plan_joins<'a>(db: &Database, query: &Query, sources: &[Source<'a>]) -> Option<Joins<'a>> {
+    let n = sources.len();
+    if !(2..=64).contains(&n) || sources.iter().any(|s| matches!(s, Source::Unnest(_))) || (1..n).any(|i| sources[..i].iter().any(|s| s.name() == sources[i].name())) {
+        return None;
+    }
+    let stats = sources.iter().map(|s| db.statistics.get(s.name())).collect::<Option<Vec<_>>>()?;
+    for name in referenced_columns(query) {
+        owner(sources, &name).ok()?;
+    }
+    let mut exprs = Vec::new();
+    for join in &query.joins {
+        conjuncts(&join.on, &mut exprs);
+    }
+    if let Some(filter) = &query.where_clause {
+        conjuncts(filter, &mut exprs);
+    }
+    let terms: Vec<Term> = exprs
+        .into_iter()
+        .map(|e| {
+            let tables = expr_columns(e).iter().filter_map(|c| owner(sources, c).ok().flatten()).fold(0, |m, i| m | 1 << i);
+            Term { expr: e.clone(), tables }
+        })
+        .collect();
+    let mut est = Estimator { db, sources, stats, terms, base: Vec::new() };
+    est.base = (0..n)
+        .map(|i| {
+            let own = est.terms.iter().filter(|t| t.tables == 1 << i);
+            len(sources[i]) as f64 * own.map(|t| est.selectivity(&t.expr)).product::<f64>()
+        })
+        .collect();
+
+    let textual: Vec<usize> = (0..n).collect();
+    let order = if n <= MAX_DP_TABLES { est.dynamic() } else { est.greedy() };
+    // Keep the textual order unless another is clearly cheaper
+    let order = if est.cost(&order) < est.cost(&textual) * (1.0 - 1e-9) { order } else { textual };
+
+    let own_filter = |i: usize| and_all(est.terms.iter().filter(|t| t.tables == 1 << i).map(|t| t.expr.clone()));
+    let first = order[0];
+    let first_filter = and_all(est.terms.iter().filter(|t| t.tables & !(1 << first) == 0).map(|t| t.expr.clone()));
+    let mut steps = Vec::new();
+    let mut left = 1u64 << first;
+    for &i in &order[1..] {
+        let step = est.step(left, i);
+        let (on, filter) = match step.strategy {
+            // An index join reads the table's rows only through the index
+            JoinPlan::Index(..) => (and_all(step.on.into_iter().chain(own_filter(i))), None),
+            _ => (step.on, own_filter(i)),
+        };
+        let on = on.unwrap_or(Expr::Literal(Value::Bool(true)));
+        steps.push(JoinStep { source: i, on, filter, strategy: step.strategy, rows: Some(step.rows) });
+        left |= 1 << i;
+    }
+    let restore = order.iter().enumerate().any(|(k, &i)| k != i)
+        || steps.iter().any(|s| matches!(s.strategy, JoinPlan::Hash { build_left: true, .. }));
+    Some(Joins { first, filter: first_filter, steps, optimized: true, restore })
+}
+
+impl<'a> Estimator<'_, 'a> {
+    /// Cheapest left-deep order, comparing every order of every subset.
+    fn dynamic(&self) -> Vec<usize> {
+        let n = self.sources.len();
+        let mut best: Vec<Option<(f64, Vec<usize>)>> = vec![None; 1 << n];
+        for i in 0..n {
+            best[1 << i] = Some((0.0, vec![i]));
+        }
+        // Every proper subset of a set is a smaller number, so it is done first
+        for set in 1..1usize << n {
+            if set.count_ones() < 2 {
+                continue;
+            }
+            for i in (0..n).filter(|i| set & 1 << i != 0) {
+                let rest = set & !(1 << i);
+                let Some((cost, order)) = &best[rest] else { continue };
+                let total = cost + self.step(rest as u64, i).cost;
+                if best[set].as_ref().is_none_or(|(c, _)| total < *c) {
+                    let mut order = order.clone();
+                    order.push(i);
+                    best[set] = Some((total, order));
+                }
+            }
+        }
+        best.pop().flatten().expect("the full set has an order").1
+    }
+
+    /// Starts from the smallest table and repeatedly joins the table that
+    /// is cheapest to add.
+    fn greedy(&self) -> Vec<usize> {
+        let n = self.sources.len();
+        let start = (0..n).min_by(|&a, &b| self.base[a].total_cmp(&self.base[b])).unwrap();
+        let mut order = vec![start];
+        let mut left = 1u64 << start;
+        while order.len() < n {
+            let costs = (0..n).filter(|i| left & 1 << i == 0).map(|i| (self.step(left, i).cost, i));
+            let (_, next) = costs.min_by(|a, b| a.0.total_cmp(&b.0)).unwrap();
+            order.push(next);
+            left |= 1 << next;
+        }
+        order
+    }
+
+    fn cost(&self, order: &[usize]) -> f64 {
+        let mut left = 1u64 << order[0];
+        let mut total = 0.0;
+        for &i in &order[1..] {
+            total += self.step(left, i).cost;
+            left |= 1 << i;
+        }
+        total
+    }
+
+    /// Joining source `right` to the set `left`: the conditions that become
+    /// checkable, how the join runs, and what it costs. Costs count rows
+    /// read, compared and produced.
+    fn step(&self, left: u64, right: usize) -> Step<'a> {
+        let joined = left | 1 << right;
+        let new = self.terms.iter().filter(|t| t.tables & 1 << right != 0 && t.tables & !joined == 0 && t.tables != 1 << right);
+        let on = and_all(new.map(|t| t.expr.clone()));
+        let left_sources: Vec<Source> = (0..self.sources.len()).filter(|i| left & 1 << i != 0).map(|i| self.sources[i]).collect();
+        let keys = on.as_ref().map_or(Vec::new(), |on| equi_join_keys(on, &left_sources, self.sources[right]));
+        let (l, r, rows) = (self.rows(left), self.base[right], self.rows(joined));
+        let lookup = match self.sources[right] {
+            Source::Rows(table) => index::plan_lookup(table, &keys).map(|lookup| JoinPlan::Index(table, lookup)),
+            Source::Columnar(_) | Source::Unnest(_) => None,
+        };
+        let (strategy, cost) = match lookup {
+            Some(lookup) => (lookup, l + rows),
+            None if keys.is_empty() => (JoinPlan::NestedLoop, l * r + rows),
+            None => (JoinPlan::Hash { build_left: l < r, keys }, l + len(self.sources[right]) as f64 + rows),
+        };
+        Step { on, strategy, cost, rows }
+    }
+
+    /// Estimated rows of the set of sources joined on every condition
+    /// among them.
+    fn rows(&self, set: u64) -> f64 {
+        let base: f64 = (0..self.sources.len()).filter(|i| set & 1 << i != 0).map(|i| self.base[i]).product();
+        let joins = self.terms.iter().filter(|t| t.tables.count_ones() > 1 && t.tables & !set == 0);
+        (base * joins.map(|t| self.selectivity(&t.expr)).product::<f64>()).max(1.0)
+    }
+
+    /// Estimated fraction of rows passing a condition: from the column's
+    /// statistics for a comparison with a constant, from distinct counts
+    /// for an equality of two tables' columns, and a fixed guess otherwise.
+    fn selectivity(&self, expr: &Expr) -> f64 {
+        let Expr::BinOp(l, op, r) = expr else { return DEFAULT_SELECTIVITY };
+        let flipped = match op.as_str() {
+            "<" => ">",
+            ">" => "<",
+            "<=" => ">=",
+            ">=" => "<=",
+            other => other,
+        };
+        let empty = Row { data: HashMap::new() };
+        match (self.column(l), self.column(r)) {
+            (Some((_, a)), None) if is_constant(r) => a.map_or(DEFAULT_SELECTIVITY, |s| s.selectivity(op, &self.db.eval_expr(r, &empty))),
+            (None, Some((_, b))) if is_constant(l) => b.map_or(DEFAULT_SELECTIVITY, |s| s.selectivity(flipped, &self.db.eval_expr(l, &empty))),
+            (Some((i, Some(a))), Some((j, Some(b)))) if op == "=" && i != j => {
+                (1.0 - a.null_fraction) * (1.0 - b.null_fraction) / a.distinct.max(b.distinct).max(1) as f64
+            }
+            _ => DEFAULT_SELECTIVITY,
+        }
+    }
+
+    /// The source a column reference reads, with that column's statistics.
+    fn column(&self, expr: &Expr) -> Option<(usize, Option<&ColumnStats>)> {
+        let Expr::Column(name) = expr else { return None };
+        let i = owner(self.sources, name).ok()??;
+        let col = name.rsplit_once('.').map_or(name.as_str(), |(_, c)| c);
+        Some((i, self.stats[i].columns.get(col)))
+    }
+}
+
+/// The source a column name reads: a `table.col` name the source of that
+/// name, otherwise the one source with the column. `Ok(None)` for names no
+/// source has, `Err` for names several have.
+fn owner(sources: &[Source], name: &str) -> Result<Option<usize>, ()> {
+    let has = |s: &Source, col: &str| s.columns().iter().any(|c| c.name == col);
+    if let Some((table, col)) = name.rsplit_once('.') {
+        if let Some(i) = sources.iter().position(|s| s.name() == table && has(s, col)) {
+            return Ok(Some(i));
+        }
+    }
+    let col = name.rsplit_once('.').map_or(name, |(_, c)| c);
+    let mut matching = (0..sources.len()).filter(|&i| has(&sources[i], col));
+    match (matching.next(), matching.next()) {
+        (Some(_), Some(_)) => Err(()),
+        (first, None) => Ok(first),
+        (None, Some(_)) => unreachable!(),
+    }
+}
+
+fn len(source: Source) -> usize {
+    match source {
+        Source::Rows(table) => table.rows.len(),
+        Source::Columnar(table) => table.len(),
+        Source::Unnest(_) => unreachable!("joins with UNNEST are planned as written"),
+    }
+}
+
+fn and_all(exprs: impl IntoIterator<Item = Expr>) -> Option<Expr> {
+    exprs.into_iter().reduce(|a, b| Expr::BinOp(Box::new(a), "AND".into(), Box::new(b)))
+}
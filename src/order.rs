@@ -0,0 +1,217 @@
+//! Equality, hashing and the total order of values. GROUP BY, DISTINCT,
+//! hash joins, indexes, statistics and ORDER BY all use them, so they agree
+//! on which values are equal and how values sort.
+//!
+//! Values of different kinds sort in this order:
+//!
+//! 1. NULL
+//! 2. BOOL, FALSE first
+//! 3. numbers: INT, FLOAT and DECIMAL together
+//! 4. TEXT
+//! 5. DATE, TIMESTAMP and TIMESTAMPTZ together
+//! 6. TIME
+//! 7. INTERVAL
+//! 8. JSON arrays and objects
+//! 9. arrays
+//! 10. structs
+//! 11. BYTEA
+//! 12. UUID
+//!
+//! Numbers compare by value across types. A FLOAT counts as the shortest
+//! decimal that converts back to it, as DECIMAL arithmetic reads it, so
+//! `0.1` equals `DECIMAL '0.1'`. -0.0 equals 0.0. NaN equals NaN and sorts
+//! after every other number, infinity included.
+//!
+//! Dates and timestamps compare as instants: a date is its midnight and a
+//! TIMESTAMP is taken as UTC. Intervals compare by length, a month counting
+//! as 30 days. A JSON scalar is the SQL value it holds, JSON null being
+//! NULL, and JSON arrays and objects compare by their text. Arrays compare
+//! element by element, a prefix first. Structs compare by their field
+//! names, then by their values.
+//!
+//! Equal values hash alike. Numbers hash as the float nearest their value,
+//! which every number equal to them shares.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::{decimal, json, temporal, Value};
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (json::unwrap(self), json::unwrap(other));
+        match (&*a, &*b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+            (Value::Int(x), Value::Int(y)) => x.cmp(y),
+            (x @ (Value::Int(_) | Value::Float(_) | Value::Decimal(_)), y @ (Value::Int(_) | Value::Float(_) | Value::Decimal(_))) => compare_numbers(x, y),
+            (Value::String(x), Value::String(y)) => x.cmp(y),
+            (Value::Time(x), Value::Time(y)) => x.cmp(y),
+            (Value::Interval(x), Value::Interval(y)) => x.span().cmp(&y.span()),
+            (Value::Json(x), Value::Json(y)) => x.to_string().cmp(&y.to_string()),
+            (Value::Array(x), Value::Array(y)) => x.cmp(y),
+            (Value::Struct(x), Value::Struct(y)) => {
+                let names = x.iter().map(|(name, _)| name).cmp(y.iter().map(|(name, _)| name));
+                names.then_with(|| x.iter().map(|(_, v)| v).cmp(y.iter().map(|(_, v)| v)))
+            }
+            (Value::Bytes(x), Value::Bytes(y)) => x.cmp(y),
+            (Value::Uuid(x), Value::Uuid(y)) => x.cmp(y),
+            (x, y) => match (temporal::instant(x), temporal::instant(y)) {
+                (Some(i), Some(j)) => i.cmp(&j),
+                _ => rank(x).cmp(&rank(y)),
+            },
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let v = json::unwrap(self);
+        rank(&v).hash(state);
+        match &*v {
+            Value::Int(_) | Value::Float(_) | Value::Decimal(_) => nearest_float(&v).to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Date(_) | Value::Timestamp(_) | Value::TimestampTz(_) => temporal::instant(&v).hash(state),
+            Value::Time(t) => t.hash(state),
+            Value::Interval(i) => i.span().hash(state),
+            Value::Json(j) => j.to_string().hash(state),
+            Value::Array(items) => items.hash(state),
+            Value::Struct(fields) => fields.hash(state),
+            Value::Bytes(b) => b.hash(state),
+            Value::Uuid(u) => u.hash(state),
+            Value::Null => {}
+        }
+    }
+}
+
+/// `left op right` for a comparison operator, by the order above.
+pub(crate) fn compare(left: &Value, op: &str, right: &Value) -> Value {
+    let ord = left.cmp(right);
+    Value::Bool(match op {
+        "=" => ord == Ordering::Equal,
+        "!=" => ord != Ordering::Equal,
+        "<" => ord == Ordering::Less,
+        ">" => ord == Ordering::Greater,
+        "<=" => ord != Ordering::Greater,
+        _ => ord != Ordering::Less,
+    })
+}
+
+/// Position of a value's kind in the order.
+fn rank(v: &Value) -> u8 {
+    match v {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Int(_) | Value::Float(_) | Value::Decimal(_) => 2,
+        Value::String(_) => 3,
+        Value::Date(_) | Value::Timestamp(_) | Value::TimestampTz(_) => 4,
+        Value::Time(_) => 5,
+        Value::Interval(_) => 6,
+        Value::Json(_) => 7,
+        Value::Array(_) => 8,
+        Value::Struct(_) => 9,
+        Value::Bytes(_) => 10,
+        Value::Uuid(_) => 11,
+    }
+}
+
+/// The float nearest a number, with -0.0 as 0.0 and every NaN alike. The
+/// conversions round correctly, so equal numbers share it.
+fn nearest_float(v: &Value) -> f64 {
+    let f = match v {
+        Value::Int(i) => *i as f64,
+        Value::Float(f) if f.is_nan() => f64::NAN,
+        Value::Float(f) => *f,
+        Value::Decimal(d) => d.to_f64(),
+        _ => unreachable!("only numbers have a nearest float"),
+    };
+    if f == 0.0 { 0.0 } else { f }
+}
+
+fn compare_floats(x: f64, y: f64) -> Ordering {
+    match (x.is_nan(), y.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => x.partial_cmp(&y).unwrap(),
+    }
+}
+
+fn compare_numbers(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Float(x), Value::Float(y)) => compare_floats(*x, *y),
+        // NaN and the infinities lie beyond every finite number
+        (Value::Float(x), _) if !x.is_finite() => compare_floats(*x, 0.0),
+        (_, Value::Float(y)) if !y.is_finite() => compare_floats(0.0, *y),
+        (Value::Int(_) | Value::Decimal(_), Value::Int(_) | Value::Decimal(_)) => decimal::to_decimal(a).cmp(&decimal::to_decimal(b)),
+        _ => Exact::of(a).cmp(&Exact::of(b)),
+    }
+}
+
+/// A finite number exactly, in scientific form: its sign, the power of ten
+/// of its first significant digit and its significant digits, without
+/// trailing zeros. Floats of any size fit, unlike in a DECIMAL.
+#[derive(PartialEq, Eq)]
+struct Exact {
+    sign: Ordering,
+    exponent: i32,
+    digits: String,
+}
+
+impl Exact {
+    fn of(v: &Value) -> Exact {
+        let (negative, exponent, digits) = match v {
+            // `{:e}` writes the shortest digits that convert back to `f`
+            Value::Float(f) => {
+                let text = format!("{:e}", f.abs());
+                let (mantissa, exponent) = text.split_once('e').unwrap();
+                (*f < 0.0, exponent.parse().unwrap(), mantissa.replace('.', ""))
+            }
+            _ => {
+                let d = decimal::to_decimal(v).unwrap();
+                let digits = d.digits().unsigned_abs().to_string();
+                (d.digits() < 0, digits.len() as i32 - 1 - d.scale() as i32, digits)
+            }
+        };
+        let digits = digits.trim_end_matches('0').to_string();
+        let sign = match (digits.is_empty(), negative) {
+            (true, _) => Ordering::Equal,
+            (false, true) => Ordering::Less,
+            (false, false) => Ordering::Greater,
+        };
+        Exact { sign, exponent, digits }
+    }
+}
+
+impl PartialOrd for Exact {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Exact {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let magnitude = || (self.exponent, &self.digits).cmp(&(other.exponent, &other.digits));
+        match (self.sign, other.sign) {
+            (Ordering::Equal, Ordering::Equal) => Ordering::Equal,
+            (Ordering::Greater, Ordering::Greater) => magnitude(),
+            (Ordering::Less, Ordering::Less) => magnitude().reverse(),
+            (a, b) => a.cmp(&b),
+        }
+    }
+}
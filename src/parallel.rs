@@ -0,0 +1,206 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// Rows per unit of parallel work. Input is always split at the same
+/// boundaries whatever the thread count, so partial results combine the
+/// same way and query results do not depend on `Database::threads`.
+pub const MORSEL_SIZE: usize = 4096;
+
+/// Number of worker threads to use when none is configured.
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Worker threads kept for the life of a database. They are started the
+/// first time a call needs them, up to one fewer than its thread count
+/// since the calling thread works too, and stop when the pool is dropped.
+#[derive(Default)]
+pub(crate) struct Pool {
+    state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    sender: Option<Sender<Job>>,
+    jobs: Option<Arc<Mutex<Receiver<Job>>>>,
+    workers: usize,
+}
+
+/// Tracks the workers helping with one call. Once the caller has finished
+/// the queue the gate closes: workers that reach it later do nothing, and
+/// the caller waits only for those already inside.
+#[derive(Default)]
+struct Gate {
+    state: Mutex<GateState>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct GateState {
+    closed: bool,
+    active: usize,
+    panicked: bool,
+}
+
+/// The caller's work, with its lifetime erased so a worker can run it.
+struct Work(*const (dyn Fn() + Sync));
+
+// SAFETY: the pointee is `Sync`, and `map_owned` does not return before
+// every worker that passed the gate is done with it.
+unsafe impl Send for Work {}
+
+impl Work {
+    /// # Safety
+    /// The caller that made the pointer must still be waiting for this call.
+    unsafe fn run(&self) {
+        unsafe { (*self.0)() }
+    }
+}
+
+impl Pool {
+    /// Runs `f` on every item, using up to `threads` threads including the
+    /// caller; each takes the next unclaimed item whenever it finishes one.
+    /// Results come back in input order.
+    pub(crate) fn map_owned<T: Send, R: Send>(&self, threads: usize, items: Vec<T>, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+        let helpers = threads.min(items.len()).saturating_sub(1);
+        if helpers == 0 {
+            return items.into_iter().map(f).collect();
+        }
+        let queue = Mutex::new(items.into_iter().enumerate());
+        let results = Mutex::new(Vec::new());
+        let drain = || loop {
+            let next = queue.lock().unwrap().next();
+            let Some((i, item)) = next else { break };
+            let r = f(item);
+            results.lock().unwrap().push((i, r));
+        };
+        let work: &(dyn Fn() + Sync) = &drain;
+        // SAFETY: only erases the lifetime; see `Work`.
+        let work: *const (dyn Fn() + Sync + 'static) = unsafe { std::mem::transmute(work) };
+
+        let gate = Arc::new(Gate::default());
+        let sender = self.workers(helpers);
+        for _ in 0..helpers {
+            let (gate, work) = (gate.clone(), Work(work));
+            let job = move || {
+                {
+                    let mut state = gate.state.lock().unwrap();
+                    if state.closed {
+                        return;
+                    }
+                    state.active += 1;
+                }
+                // SAFETY: the gate was open, so the caller is still waiting
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| unsafe { work.run() }));
+                let mut state = gate.state.lock().unwrap();
+                state.active -= 1;
+                state.panicked |= outcome.is_err();
+                gate.done.notify_all();
+            };
+            sender.send(Box::new(job)).expect("worker threads outlive the pool");
+        }
+
+        let outcome = panic::catch_unwind(AssertUnwindSafe(drain));
+        let mut state = gate.state.lock().unwrap();
+        state.closed = true;
+        while state.active > 0 {
+            state = gate.done.wait(state).unwrap();
+        }
+        if let Err(payload) = outcome {
+            panic::resume_unwind(payload);
+        }
+        assert!(!state.panicked, "worker thread panicked");
+        drop(state);
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, r)| r).collect()
+    }
+
+    /// The job queue, with at least `count` workers serving it.
+    fn workers(&self, count: usize) -> Sender<Job> {
+        let mut state = self.state.lock().unwrap();
+        if state.sender.is_none() {
+            let (sender, receiver) = mpsc::channel();
+            state.sender = Some(sender);
+            state.jobs = Some(Arc::new(Mutex::new(receiver)));
+        }
+        while state.workers < count {
+            let jobs = state.jobs.clone().unwrap();
+            thread::spawn(move || loop {
+                let job = jobs.lock().unwrap().recv();
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                }
+            });
+            state.workers += 1;
+        }
+        state.sender.clone().unwrap()
+    }
+
+    /// Runs `f` on each `MORSEL_SIZE` range of `0..len`, in parallel.
+    pub(crate) fn map_morsels<R: Send>(&self, threads: usize, len: usize, f: impl Fn(Range<usize>) -> R + Sync) -> Vec<R> {
+        let morsels: Vec<Range<usize>> = (0..len).step_by(MORSEL_SIZE).map(|s| s..len.min(s + MORSEL_SIZE)).collect();
+        self.map_owned(threads, morsels, f)
+    }
+
+    /// Stable sort: morsels are sorted in parallel and the sorted runs merged
+    /// pairwise, ties going to the earlier run, which is the order a
+    /// sequential stable sort produces.
+    pub(crate) fn sort_by<T: Send>(&self, threads: usize, mut items: Vec<T>, cmp: &(impl Fn(&T, &T) -> Ordering + Sync)) -> Vec<T> {
+        if threads <= 1 || items.len() <= MORSEL_SIZE {
+            items.sort_by(cmp);
+            return items;
+        }
+        let mut runs = self.map_owned(threads, into_morsels(items), |mut run| {
+            run.sort_by(cmp);
+            run
+        });
+        while runs.len() > 1 {
+            let mut pairs = Vec::new();
+            let mut iter = runs.into_iter();
+            while let Some(a) = iter.next() {
+                pairs.push((a, iter.next()));
+            }
+            runs = self.map_owned(threads, pairs, |(a, b)| match b {
+                Some(b) => merge(a, b, cmp),
+                None => a,
+            });
+        }
+        runs.pop().unwrap_or_default()
+    }
+}
+
+/// Splits `items` into owned morsels of `MORSEL_SIZE`.
+pub(crate) fn into_morsels<T>(mut items: Vec<T>) -> Vec<Vec<T>> {
+    let mut morsels = Vec::new();
+    while items.len() > MORSEL_SIZE {
+        let rest = items.split_off(MORSEL_SIZE);
+        morsels.push(items);
+        items = rest;
+    }
+    if !items.is_empty() {
+        morsels.push(items);
+    }
+    morsels
+}
+
+fn merge<T>(a: Vec<T>, b: Vec<T>, cmp: &impl Fn(&T, &T) -> Ordering) -> Vec<T> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        let next = if cmp(y, x) == Ordering::Less { b.next() } else { a.next() };
+        out.extend(next);
+    }
+    out.extend(a);
+    out.extend(b);
+    out
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::ops::Index;
+
+use crate::{DataType, Row, Value};
+
+/// Name and type of one output column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultColumn {
+    pub name: String,
+    pub data_type: DataType,
+}
+
+/// Query output: an ordered schema plus positional rows. The schema is
+/// reported even when there are no rows.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResultSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Borrowed view of one result row, indexable by position or column name.
+#[derive(Debug, Clone, Copy)]
+pub struct ResultRow<'a> {
+    columns: &'a [ResultColumn],
+    values: &'a [Value],
+}
+
+impl ResultSet {
+    pub fn new(columns: Vec<ResultColumn>) -> Self {
+        ResultSet { columns, rows: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn column_names(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// Position of the first column called `name`. A qualified name such as
+    /// `users.id` also matches an output column named `id`.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        column_index(&self.columns, name)
+    }
+
+    pub fn row(&self, i: usize) -> Option<ResultRow<'_>> {
+        self.rows.get(i).map(|values| ResultRow { columns: &self.columns, values })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ResultRow<'_>> {
+        self.rows.iter().map(|values| ResultRow { columns: &self.columns, values })
+    }
+
+    /// Value at `row` in column `name`.
+    pub fn get(&self, row: usize, name: &str) -> Option<&Value> {
+        self.row(row).and_then(|r| r.get(name))
+    }
+
+    /// Converts to name-keyed rows. Later columns win on duplicate names.
+    pub fn to_rows(&self) -> Vec<Row> {
+        self.rows
+            .iter()
+            .map(|values| Row {
+                data: self.columns.iter().map(|c| c.name.clone()).zip(values.iter().cloned()).collect::<HashMap<_, _>>(),
+            })
+            .collect()
+    }
+}
+
+impl<'a> ResultRow<'a> {
+    pub fn get(&self, name: &str) -> Option<&'a Value> {
+        column_index(self.columns, name).map(|i| &self.values[i])
+    }
+
+    pub fn get_index(&self, i: usize) -> Option<&'a Value> {
+        self.values.get(i)
+    }
+
+    pub fn values(&self) -> &'a [Value] {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Index<usize> for ResultRow<'_> {
+    type Output = Value;
+
+    fn index(&self, i: usize) -> &Value {
+        &self.values[i]
+    }
+}
+
+impl Index<&str> for ResultRow<'_> {
+    type Output = Value;
+
+    fn index(&self, name: &str) -> &Value {
+        self.get(name).unwrap_or_else(|| panic!("No column named {}", name))
+    }
+}
+
+fn column_index(columns: &[ResultColumn], name: &str) -> Option<usize> {
+    columns.iter().position(|c| c.name == name).or_else(|| {
+        let (_, col) = name.rsplit_once('.')?;
+        columns.iter().position(|c| c.name == col)
+    })
+}
@@ -0,0 +1,121 @@
+//! Table statistics gathered by ANALYZE, and the selectivity estimates the
+//! join optimizer builds on them.
+//!
+//! Statistics are a snapshot: they are not kept up to date by later
+//! changes, are not saved in the database file, and only steer planning, so
+//! stale ones make plans slower but never results wrong.
+
+use std::collections::HashMap;
+
+use crate::{Database, Source, Value};
+
+/// Number of buckets in each column's histogram.
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Selectivity assumed for a condition the statistics cannot judge.
+pub(crate) const DEFAULT_SELECTIVITY: f64 = 1.0 / 3.0;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TableStats {
+    pub row_count: usize,
+    pub columns: HashMap<String, ColumnStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColumnStats {
+    /// Number of distinct non-NULL values.
+    pub distinct: usize,
+    pub null_fraction: f64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// Equi-depth histogram: up to `HISTOGRAM_BUCKETS + 1` bounds, from the
+    /// minimum to the maximum, with about as many non-NULL values between
+    /// each neighbouring pair.
+    pub histogram: Vec<Value>,
+}
+
+impl ColumnStats {
+    /// Statistics of one column's values, in key order as indexes sort them.
+    fn collect(mut values: Vec<Value>) -> Self {
+        let rows = values.len();
+        values.retain(|v| *v != Value::Null);
+        values.sort();
+        let n = values.len();
+        let distinct = if n == 0 { 0 } else { 1 + values.windows(2).filter(|w| w[0] != w[1]).count() };
+        let histogram = if n == 0 { Vec::new() } else { (0..=HISTOGRAM_BUCKETS).map(|i| values[i * (n - 1) / HISTOGRAM_BUCKETS].clone()).collect() };
+        ColumnStats {
+            distinct,
+            null_fraction: if rows == 0 { 0.0 } else { (rows - n) as f64 / rows as f64 },
+            min: values.first().cloned(),
+            max: values.last().cloned(),
+            histogram,
+        }
+    }
+
+    /// Estimated fraction of rows for which `column op value` holds.
+    pub fn selectivity(&self, op: &str, value: &Value) -> f64 {
+        let non_null = 1.0 - self.null_fraction;
+        if *value == Value::Null || self.distinct == 0 {
+            return 0.0;
+        }
+        let (Some(min), Some(max)) = (&self.min, &self.max) else { return 0.0 };
+        let outside = value < min || value > max;
+        match op {
+            "=" if outside => 0.0,
+            "=" => non_null / self.distinct as f64,
+            "!=" => non_null * (1.0 - 1.0 / self.distinct as f64),
+            "<" | "<=" => non_null * self.fraction_below(value),
+            ">" | ">=" => non_null * (1.0 - self.fraction_below(value)),
+            _ => DEFAULT_SELECTIVITY,
+        }
+    }
+
+    /// Fraction of the non-NULL values below `value`, read off the
+    /// histogram, interpolating within a bucket of numbers.
+    fn fraction_below(&self, value: &Value) -> f64 {
+        let bounds = &self.histogram;
+        let buckets = (bounds.len() - 1).max(1) as f64;
+        let Some(i) = bounds.iter().rposition(|b| b <= value) else { return 0.0 };
+        if i + 1 >= bounds.len() {
+            return 1.0;
+        }
+        let within = match (bounds[i].as_f64(), bounds[i + 1].as_f64(), value.as_f64()) {
+            (Some(lo), Some(hi), Some(v)) if hi > lo => (v - lo) / (hi - lo),
+            _ => 0.5,
+        };
+        (i as f64 + within) / buckets
+    }
+}
+
+impl Database {
+    /// Gathers statistics for the table `name`, or for every table when
+    /// `None`, replacing any gathered before.
+    pub fn analyze(&mut self, name: Option<&str>) -> Result<(), String> {
+        let names: Vec<String> = match name {
+            Some(name) => vec![self.source(name)?.name().to_string()],
+            None => self.tables.keys().chain(self.columnar.keys()).cloned().collect(),
+        };
+        for name in names {
+            let source = self.source(&name)?;
+            let mut columns = HashMap::new();
+            let row_count = match source {
+                Source::Rows(table) => {
+                    for c in &table.columns {
+                        let values = table.rows.iter().map(|r| r.data.get(&c.name).cloned().unwrap_or(Value::Null)).collect();
+                        columns.insert(c.name.clone(), ColumnStats::collect(values));
+                    }
+                    table.rows.len()
+                }
+                Source::Columnar(table) => {
+                    for (i, c) in table.columns.iter().enumerate() {
+                        columns.insert(c.name.clone(), ColumnStats::collect((0..table.len()).map(|r| table.get(r, i)).collect()));
+                    }
+                    table.len()
+                }
+                Source::Unnest(_) => unreachable!("a named source is a table"),
+            };
+            self.statistics.insert(name, TableStats { row_count, columns });
+        }
+        Ok(())
+    }
+}
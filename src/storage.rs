@@ -0,0 +1,659 @@
+//! Single-file, page-based storage.
+//!
+//! The file is a sequence of `PAGE_SIZE` pages. Every page has the same frame:
+//!
+//! ```text
+//! offset  size  field
+//! 0       1     page kind: 0 header, 1 catalog, 2 data
+//! 1       3     reserved, zero
+//! 4       4     next page in this chain, 0 for the last page
+//! 8       4     payload length
+//! 12      ...   payload, zero padded
+//! 4092    4     CRC-32 of bytes 0..4092
+//! ```
+//!
+//! Page 0 is the header; its payload is the magic `QLDBFILE`, the format
+//! version, the page size, the first catalog page and the file's generation,
+//! which goes up with every checkpoint and ties the write-ahead log to the
+//! file it extends. The catalog is one byte stream spread over a chain of
+//! catalog pages, listing every table with its layout, columns,
+//! constraints, indexes, row count and first data page. Index contents are
+//! rebuilt when the file is read; JSON documents are stored as their text.
+//! Each table's rows form their own stream over a chain of data pages, row
+//! by row with values in declared column order. Integers are little-endian;
+//! strings and lists are prefixed with a `u32` length.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::{
+    Collation, Column, ColumnarTable, Constraint, ConstraintKind, DataType, Date, Decimal, Expr, ForeignKey, Identity, Index, IndexKind, Interval, Json, RefAction, Row, Table,
+    Time, Timestamp, Uuid, Value,
+};
+
+pub const PAGE_SIZE: usize = 4096;
+pub const FORMAT_VERSION: u32 = 1;
+const MAGIC: &[u8; 8] = b"QLDBFILE";
+const FRAME_HEADER: usize = 12;
+const PAYLOAD_SIZE: usize = PAGE_SIZE - FRAME_HEADER - 4;
+
+const HEADER_PAGE: u8 = 0;
+const CATALOG_PAGE: u8 = 1;
+const DATA_PAGE: u8 = 2;
+
+/// Tables as stored on disk, in either layout.
+pub(crate) struct Contents {
+    pub generation: u64,
+    pub tables: Vec<Table>,
+    pub columnar: Vec<ColumnarTable>,
+}
+
+/// Writes the whole database to `path`. The pages go to a temporary file
+/// that then replaces `path`, so a crash mid-write leaves the old file.
+pub(crate) fn write_file(path: &Path, generation: u64, tables: &[&Table], columnar: &[&ColumnarTable]) -> Result<(), String> {
+    let mut pages: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut catalog = Encoder::default();
+    catalog.u32((tables.len() + columnar.len()) as u32);
+    let converted: Vec<Table> = columnar.iter().map(|t| t.to_table()).collect();
+    let entries = tables.iter().map(|t| (*t, false)).chain(converted.iter().map(|t| (t, true)));
+    for (table, is_columnar) in entries {
+        let mut data = Encoder::default();
+        for row in &table.rows {
+            for c in &table.columns {
+                data.value(row.data.get(&c.name).unwrap_or(&Value::Null));
+            }
+        }
+        let first = write_chain(&mut pages, DATA_PAGE, &data.bytes);
+        catalog.str(&table.name);
+        catalog.u8(u8::from(is_columnar));
+        catalog.u32(table.columns.len() as u32);
+        for c in &table.columns {
+            catalog.column(c);
+        }
+        catalog.u32(table.constraints.len() as u32);
+        for c in &table.constraints {
+            catalog.constraint(c);
+        }
+        catalog.u32(table.indexes.len() as u32);
+        for index in &table.indexes {
+            catalog.index(index);
+        }
+        catalog.u64(table.rows.len() as u64);
+        catalog.u32(first);
+    }
+    let catalog_page = write_chain(&mut pages, CATALOG_PAGE, &catalog.bytes);
+
+    let mut header = Encoder::default();
+    header.bytes.extend_from_slice(MAGIC);
+    header.u32(FORMAT_VERSION);
+    header.u32(PAGE_SIZE as u32);
+    header.u32(catalog_page);
+    header.u64(generation);
+    pages[0] = frame(HEADER_PAGE, 0, &header.bytes);
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let io_err = |e: std::io::Error| format!("Cannot write {}: {}", path.display(), e);
+    let mut file = fs::File::create(&tmp).map_err(io_err)?;
+    for page in &pages {
+        file.write_all(page).map_err(io_err)?;
+    }
+    file.sync_all().map_err(io_err)?;
+    fs::rename(&tmp, path).map_err(io_err)?;
+    // The rename only survives a crash once the directory is synced; the
+    // caller resets the log after this returns
+    sync_dir(path).map_err(io_err)
+}
+
+/// Syncs the directory holding `path`, making a rename into it durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories cannot be opened for syncing here; the rename is left to
+/// the file system.
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Reads a database file, verifying each page's checksum as it is used.
+pub(crate) fn read_file(path: &Path) -> Result<Contents, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if bytes.len() < PAGE_SIZE || bytes.len() % PAGE_SIZE != 0 {
+        return Err(format!("Corrupt database file {}: size {} is not a whole number of pages", path.display(), bytes.len()));
+    }
+    let pages: Vec<&[u8]> = bytes.chunks(PAGE_SIZE).collect();
+    let (kind, _, payload) = read_page(&pages, 0)?;
+    let mut header = Decoder::new(payload);
+    if kind != HEADER_PAGE || header.take(MAGIC.len())? != MAGIC {
+        return Err(format!("{} is not a database file", path.display()));
+    }
+    let version = header.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported database file version {} (this build reads version {})", version, FORMAT_VERSION));
+    }
+    if header.u32()? as usize != PAGE_SIZE {
+        return Err("Unsupported database page size".into());
+    }
+    let catalog_page = header.u32()?;
+    let generation = header.u64()?;
+    let catalog_bytes = read_chain(&pages, catalog_page, CATALOG_PAGE)?;
+    let mut catalog = Decoder::new(&catalog_bytes);
+
+    let mut contents = Contents { generation, tables: Vec::new(), columnar: Vec::new() };
+    for _ in 0..catalog.u32()? {
+        let name = catalog.str()?;
+        let is_columnar = catalog.u8()? != 0;
+        let columns = (0..catalog.u32()?).map(|_| catalog.column()).collect::<Result<Vec<_>, _>>()?;
+        let constraints = (0..catalog.u32()?).map(|_| catalog.constraint()).collect::<Result<Vec<_>, _>>()?;
+        let indexes = (0..catalog.u32()?).map(|_| catalog.index()).collect::<Result<Vec<_>, _>>()?;
+        let row_count = catalog.u64()?;
+        let data_bytes = read_chain(&pages, catalog.u32()?, DATA_PAGE)?;
+        let mut data = Decoder::new(&data_bytes);
+        let mut rows = Vec::new();
+        for _ in 0..row_count {
+            let mut row = Row { data: Default::default() };
+            for c in &columns {
+                row.data.insert(c.name.clone(), data.value()?);
+            }
+            rows.push(row);
+        }
+        let table = Table { name, columns, rows, constraints, indexes };
+        if is_columnar {
+            contents.columnar.push(ColumnarTable::from_table(&table)?);
+        } else {
+            contents.tables.push(table);
+        }
+    }
+    Ok(contents)
+}
+
+fn frame(kind: u8, next: u32, payload: &[u8]) -> Vec<u8> {
+    let mut page = vec![0u8; PAGE_SIZE];
+    page[0] = kind;
+    page[4..8].copy_from_slice(&next.to_le_bytes());
+    page[8..12].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    page[FRAME_HEADER..FRAME_HEADER + payload.len()].copy_from_slice(payload);
+    let crc = crc32(&page[..PAGE_SIZE - 4]);
+    page[PAGE_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// Appends `bytes` as a chain of pages and returns the first page number.
+/// An empty stream still takes one page.
+fn write_chain(pages: &mut Vec<Vec<u8>>, kind: u8, bytes: &[u8]) -> u32 {
+    let first = pages.len();
+    let chunks: Vec<&[u8]> = if bytes.is_empty() { vec![&[]] } else { bytes.chunks(PAYLOAD_SIZE).collect() };
+    let count = chunks.len();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let next = if i + 1 < count { (first + i + 1) as u32 } else { 0 };
+        pages.push(frame(kind, next, chunk));
+    }
+    first as u32
+}
+
+fn read_page<'a>(pages: &[&'a [u8]], n: u32) -> Result<(u8, u32, &'a [u8]), String> {
+    let page = pages.get(n as usize).ok_or(format!("Corrupt database file: page {} is past the end", n))?;
+    let stored = u32::from_le_bytes(page[PAGE_SIZE - 4..].try_into().unwrap());
+    if crc32(&page[..PAGE_SIZE - 4]) != stored {
+        return Err(format!("Corrupt database file: checksum mismatch on page {}", n));
+    }
+    let next = u32::from_le_bytes(page[4..8].try_into().unwrap());
+    let len = u32::from_le_bytes(page[8..12].try_into().unwrap()) as usize;
+    if len > PAYLOAD_SIZE {
+        return Err(format!("Corrupt database file: bad payload length on page {}", n));
+    }
+    Ok((page[0], next, &page[FRAME_HEADER..FRAME_HEADER + len]))
+}
+
+fn read_chain(pages: &[&[u8]], first: u32, kind: u8) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut n = first;
+    let mut visited = 0;
+    loop {
+        let (page_kind, next, payload) = read_page(pages, n)?;
+        if page_kind != kind || n == 0 || visited > pages.len() {
+            return Err(format!("Corrupt database file: unexpected page {} in chain", n));
+        }
+        bytes.extend_from_slice(payload);
+        visited += 1;
+        if next == 0 {
+            return Ok(bytes);
+        }
+        n = next;
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[derive(Default)]
+pub(crate) struct Encoder {
+    pub bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn strs(&mut self, list: &[String]) {
+        self.u32(list.len() as u32);
+        for s in list {
+            self.str(s);
+        }
+    }
+
+    pub fn value(&mut self, v: &Value) {
+        match v {
+            Value::Null => self.u8(0),
+            Value::Int(i) => {
+                self.u8(1);
+                self.u64(*i as u64);
+            }
+            Value::Float(f) => {
+                self.u8(2);
+                self.u64(f.to_bits());
+            }
+            Value::String(s) => {
+                self.u8(3);
+                self.str(s);
+            }
+            Value::Bool(b) => {
+                self.u8(4);
+                self.u8(u8::from(*b));
+            }
+            Value::Decimal(d) => {
+                self.u8(10);
+                self.u64(d.digits() as u64);
+                self.u64((d.digits() >> 64) as u64);
+                self.u8(d.scale());
+            }
+            Value::Date(d) => {
+                self.u8(5);
+                self.u32(d.0 as u32);
+            }
+            Value::Time(t) => {
+                self.u8(6);
+                self.u64(t.0 as u64);
+            }
+            Value::Timestamp(t) => {
+                self.u8(7);
+                self.u64(t.0 as u64);
+            }
+            Value::TimestampTz(t) => {
+                self.u8(8);
+                self.u64(t.0 as u64);
+            }
+            Value::Interval(i) => {
+                self.u8(9);
+                self.u32(i.months as u32);
+                self.u32(i.days as u32);
+                self.u64(i.micros as u64);
+            }
+            Value::Json(j) => {
+                self.u8(11);
+                self.str(&j.to_string());
+            }
+            Value::Array(items) => {
+                self.u8(12);
+                self.u32(items.len() as u32);
+                items.iter().for_each(|v| self.value(v));
+            }
+            Value::Bytes(b) => {
+                self.u8(14);
+                self.u32(b.len() as u32);
+                self.bytes.extend_from_slice(b);
+            }
+            Value::Uuid(u) => {
+                self.u8(15);
+                self.bytes.extend_from_slice(&u.0);
+            }
+            Value::Struct(fields) => {
+                self.u8(13);
+                self.u32(fields.len() as u32);
+                for (name, v) in fields {
+                    self.str(name);
+                    self.value(v);
+                }
+            }
+        }
+    }
+
+    pub fn expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Column(name) => {
+                self.u8(0);
+                self.str(name);
+            }
+            Expr::Literal(v) => {
+                self.u8(1);
+                self.value(v);
+            }
+            Expr::UnaryOp(op, e) => {
+                self.u8(2);
+                self.str(op);
+                self.expr(e);
+            }
+            Expr::BinOp(l, op, r) => {
+                self.u8(3);
+                self.expr(l);
+                self.str(op);
+                self.expr(r);
+            }
+            Expr::FuncCall(name, args) => {
+                self.u8(4);
+                self.str(name);
+                self.u32(args.len() as u32);
+                for a in args {
+                    self.expr(a);
+                }
+            }
+        }
+    }
+
+    fn data_type(&mut self, t: &DataType) {
+        self.u8(match t {
+            DataType::Int => 0,
+            DataType::Float => 1,
+            DataType::String => 2,
+            DataType::Bool => 3,
+            DataType::Any => 4,
+            DataType::Date => 5,
+            DataType::Time => 6,
+            DataType::Timestamp => 7,
+            DataType::TimestampTz => 8,
+            DataType::Interval => 9,
+            DataType::Decimal(..) => 10,
+            DataType::Json => 11,
+            DataType::Array(_) => 12,
+            DataType::Struct(_) => 13,
+            DataType::Bytes => 14,
+            DataType::Uuid => 15,
+        });
+        match t {
+            DataType::Decimal(precision, scale) => {
+                self.u8(*precision);
+                self.u8(*scale);
+            }
+            DataType::Array(element) => self.data_type(element),
+            DataType::Struct(fields) => {
+                self.u32(fields.len() as u32);
+                for (name, ty) in fields {
+                    self.str(name);
+                    self.data_type(ty);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn column(&mut self, c: &Column) {
+        self.str(&c.name);
+        self.data_type(&c.data_type);
+        self.u8(u8::from(c.not_null));
+        match &c.default {
+            Some(e) => {
+                self.u8(1);
+                self.expr(e);
+            }
+            None => self.u8(0),
+        }
+        self.u8(match c.identity {
+            None => 0,
+            Some(Identity::Always) => 1,
+            Some(Identity::ByDefault) => 2,
+        });
+        self.u8(match c.collation {
+            Collation::Binary => 0,
+            Collation::NoCase => 1,
+            Collation::Unicode => 2,
+        });
+    }
+
+    pub fn constraint(&mut self, c: &Constraint) {
+        self.str(&c.name);
+        match &c.kind {
+            ConstraintKind::PrimaryKey(cols) => {
+                self.u8(0);
+                self.strs(cols);
+            }
+            ConstraintKind::Unique(cols) => {
+                self.u8(1);
+                self.strs(cols);
+            }
+            ConstraintKind::Check(e) => {
+                self.u8(2);
+                self.expr(e);
+            }
+            ConstraintKind::ForeignKey(fk) => {
+                self.u8(3);
+                self.strs(&fk.columns);
+                self.str(&fk.ref_table);
+                self.strs(&fk.ref_columns);
+                self.u8(ref_action_tag(&fk.on_delete));
+                self.u8(ref_action_tag(&fk.on_update));
+                self.u8(u8::from(fk.deferred));
+            }
+        }
+    }
+
+    pub fn index(&mut self, index: &Index) {
+        self.str(&index.name);
+        self.strs(&index.columns);
+        self.u8(u8::from(index.unique));
+        self.u8(match index.kind {
+            IndexKind::BTree => 0,
+            IndexKind::Hash => 1,
+        });
+        self.strs(&index.include);
+    }
+}
+
+fn ref_action_tag(a: &RefAction) -> u8 {
+    match a {
+        RefAction::NoAction => 0,
+        RefAction::Restrict => 1,
+        RefAction::Cascade => 2,
+        RefAction::SetNull => 3,
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes, pos: 0 }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len()).ok_or("Corrupt database file: unexpected end of data")?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn str(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "Corrupt database file: invalid UTF-8".to_string())
+    }
+
+    fn strs(&mut self) -> Result<Vec<String>, String> {
+        (0..self.u32()?).map(|_| self.str()).collect()
+    }
+
+    pub fn value(&mut self) -> Result<Value, String> {
+        Ok(match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Int(self.u64()? as i64),
+            2 => Value::Float(f64::from_bits(self.u64()?)),
+            3 => Value::String(self.str()?),
+            4 => Value::Bool(self.u8()? != 0),
+            5 => Value::Date(Date(self.u32()? as i32)),
+            6 => Value::Time(Time(self.u64()? as i64)),
+            7 => Value::Timestamp(Timestamp(self.u64()? as i64)),
+            8 => Value::TimestampTz(Timestamp(self.u64()? as i64)),
+            9 => Value::Interval(Interval { months: self.u32()? as i32, days: self.u32()? as i32, micros: self.u64()? as i64 }),
+            10 => {
+                let digits = self.u64()? as i128 | (self.u64()? as i128) << 64;
+                Value::Decimal(Decimal::new(digits, self.u8()?).ok_or("Corrupt database file: invalid decimal")?)
+            }
+            11 => Value::Json(Json::parse(&self.str()?).map_err(|e| format!("Corrupt database file: {}", e))?),
+            12 => Value::Array((0..self.u32()?).map(|_| self.value()).collect::<Result<_, _>>()?),
+            13 => Value::Struct((0..self.u32()?).map(|_| Ok((self.str()?, self.value()?))).collect::<Result<_, String>>()?),
+            14 => {
+                let len = self.u32()? as usize;
+                Value::Bytes(self.take(len)?.to_vec())
+            }
+            15 => Value::Uuid(Uuid(self.take(16)?.try_into().unwrap())),
+            tag => return Err(format!("Corrupt database file: unknown value tag {}", tag)),
+        })
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, String> {
+        Ok(match self.u8()? {
+            0 => Expr::Column(self.str()?),
+            1 => Expr::Literal(self.value()?),
+            2 => {
+                let op = self.str()?;
+                Expr::UnaryOp(op, Box::new(self.expr()?))
+            }
+            3 => {
+                let l = self.expr()?;
+                let op = self.str()?;
+                Expr::BinOp(Box::new(l), op, Box::new(self.expr()?))
+            }
+            4 => {
+                let name = self.str()?;
+                let args = (0..self.u32()?).map(|_| self.expr()).collect::<Result<_, _>>()?;
+                Expr::FuncCall(name, args)
+            }
+            tag => return Err(format!("Corrupt database file: unknown expression tag {}", tag)),
+        })
+    }
+
+    fn data_type(&mut self) -> Result<DataType, String> {
+        Ok(match self.u8()? {
+            0 => DataType::Int,
+            1 => DataType::Float,
+            2 => DataType::String,
+            3 => DataType::Bool,
+            4 => DataType::Any,
+            5 => DataType::Date,
+            6 => DataType::Time,
+            7 => DataType::Timestamp,
+            8 => DataType::TimestampTz,
+            9 => DataType::Interval,
+            10 => DataType::Decimal(self.u8()?, self.u8()?),
+            11 => DataType::Json,
+            12 => DataType::Array(Box::new(self.data_type()?)),
+            13 => DataType::Struct((0..self.u32()?).map(|_| Ok((self.str()?, self.data_type()?))).collect::<Result<_, String>>()?),
+            14 => DataType::Bytes,
+            15 => DataType::Uuid,
+            tag => return Err(format!("Corrupt database file: unknown type tag {}", tag)),
+        })
+    }
+
+    pub fn column(&mut self) -> Result<Column, String> {
+        let name = self.str()?;
+        let data_type = self.data_type()?;
+        let not_null = self.u8()? != 0;
+        let default = if self.u8()? != 0 { Some(self.expr()?) } else { None };
+        let identity = match self.u8()? {
+            0 => None,
+            1 => Some(Identity::Always),
+            2 => Some(Identity::ByDefault),
+            tag => return Err(format!("Corrupt database file: unknown identity {}", tag)),
+        };
+        let collation = match self.u8()? {
+            0 => Collation::Binary,
+            1 => Collation::NoCase,
+            2 => Collation::Unicode,
+            tag => return Err(format!("Corrupt database file: unknown collation {}", tag)),
+        };
+        Ok(Column { name, data_type, not_null, default, identity, collation })
+    }
+
+    pub fn constraint(&mut self) -> Result<Constraint, String> {
+        let name = self.str()?;
+        let kind = match self.u8()? {
+            0 => ConstraintKind::PrimaryKey(self.strs()?),
+            1 => ConstraintKind::Unique(self.strs()?),
+            2 => ConstraintKind::Check(self.expr()?),
+            3 => ConstraintKind::ForeignKey(ForeignKey {
+                columns: self.strs()?,
+                ref_table: self.str()?,
+                ref_columns: self.strs()?,
+                on_delete: self.ref_action()?,
+                on_update: self.ref_action()?,
+                deferred: self.u8()? != 0,
+            }),
+            tag => return Err(format!("Corrupt database file: unknown constraint tag {}", tag)),
+        };
+        Ok(Constraint { name, kind })
+    }
+
+    pub fn index(&mut self) -> Result<Index, String> {
+        let name = self.str()?;
+        let columns = self.strs()?;
+        let mut index = Index::new(&name, columns, self.u8()? != 0);
+        index.kind = match self.u8()? {
+            0 => IndexKind::BTree,
+            1 => IndexKind::Hash,
+            tag => return Err(format!("Corrupt database file: unknown index kind {}", tag)),
+        };
+        index.include = self.strs()?;
+        Ok(index)
+    }
+
+    fn ref_action(&mut self) -> Result<RefAction, String> {
+        Ok(match self.u8()? {
+            0 => RefAction::NoAction,
+            1 => RefAction::Restrict,
+            2 => RefAction::Cascade,
+            3 => RefAction::SetNull,
+            tag => return Err(format!("Corrupt database file: unknown referential action {}", tag)),
+        })
+    }
+}
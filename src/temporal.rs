@@ -0,0 +1,619 @@
+//! Dates, times, timestamps and intervals: their literals, calendar
+//! arithmetic, comparisons and functions.
+//!
+//! A DATE counts days from 1970-01-01 and a TIME microseconds from
+//! midnight; timestamps count microseconds from 1970-01-01 00:00:00. A
+//! TIMESTAMPTZ is an instant kept in UTC: its literals may give an offset,
+//! and it is shown in UTC. An INTERVAL keeps months, days and microseconds
+//! apart, since months and days have no fixed length; intervals compare as
+//! if a month were 30 days.
+
+use std::cmp::Ordering;
+use std::fmt::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{DataType, Value};
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+/// The furthest a date may be from 1970-01-01, about 292,000 years, so that
+/// every instant of it fits in an i64 count of microseconds.
+const MAX_DAYS: i64 = i64::MAX / MICROS_PER_DAY - 1;
+
+const MONTHS: [&str; 12] =
+    ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Days since 1970-01-01.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(pub i32);
+
+/// Microseconds since midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time(pub i64);
+
+/// Microseconds since 1970-01-01 00:00:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl Date {
+    /// The date of a calendar day; `None` if there is no such day or it is
+    /// out of range.
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        Date::from_days(days_from_civil(year, month, day))
+    }
+
+    /// The date `days` after 1970-01-01; `None` if out of range.
+    pub fn from_days(days: i64) -> Option<Date> {
+        (-MAX_DAYS..=MAX_DAYS).contains(&days).then_some(Date(days as i32))
+    }
+
+    /// Year, month and day.
+    pub fn ymd(self) -> (i64, u32, u32) {
+        civil_from_days(self.0 as i64)
+    }
+
+    /// Parses `YYYY-MM-DD`.
+    pub fn parse(text: &str) -> Result<Date, String> {
+        parse_date(text.trim()).ok_or_else(|| format!("Invalid date: '{}'", text))
+    }
+}
+
+impl Time {
+    /// Parses `HH:MM[:SS[.ffffff]]`.
+    pub fn parse(text: &str) -> Result<Time, String> {
+        parse_clock(text.trim(), true).map(Time).ok_or_else(|| format!("Invalid time: '{}'", text))
+    }
+}
+
+impl Timestamp {
+    /// The instant `time` into `date`; `None` if out of range.
+    pub fn new(date: Date, time: Time) -> Option<Timestamp> {
+        (date.0 as i64).checked_mul(MICROS_PER_DAY)?.checked_add(time.0).map(Timestamp)
+    }
+
+    /// The current instant.
+    pub fn now() -> Timestamp {
+        Timestamp(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as i64))
+    }
+
+    pub fn date(self) -> Date {
+        Date(self.0.div_euclid(MICROS_PER_DAY) as i32)
+    }
+
+    pub fn time(self) -> Time {
+        Time(self.0.rem_euclid(MICROS_PER_DAY))
+    }
+
+    /// Parses `YYYY-MM-DD[ HH:MM[:SS[.ffffff]]]`; a `T` may separate the
+    /// date and the time.
+    pub fn parse(text: &str) -> Result<Timestamp, String> {
+        parse_timestamp(text.trim(), false).ok_or_else(|| format!("Invalid timestamp: '{}'", text))
+    }
+
+    /// Parses a timestamp followed by an optional UTC offset (`Z`, `UTC`,
+    /// `+05`, `+05:30` or `-0800`), giving the instant in UTC.
+    pub fn parse_utc(text: &str) -> Result<Timestamp, String> {
+        parse_timestamp(text.trim(), true).ok_or_else(|| format!("Invalid timestamp with time zone: '{}'", text))
+    }
+}
+
+impl Interval {
+    /// Parses a list of quantities such as `1 year 2 months`, `3 days
+    /// 04:05:06` or `-90 minutes`. Fractions carry over to smaller units,
+    /// with a month as 30 days.
+    pub fn parse(text: &str) -> Result<Interval, String> {
+        parse_interval(text).ok_or_else(|| format!("Invalid interval: '{}'", text))
+    }
+
+    pub fn checked_add(self, other: Interval) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_add(other.months)?,
+            days: self.days.checked_add(other.days)?,
+            micros: self.micros.checked_add(other.micros)?,
+        })
+    }
+
+    pub fn checked_neg(self) -> Option<Interval> {
+        Some(Interval { months: self.months.checked_neg()?, days: self.days.checked_neg()?, micros: self.micros.checked_neg()? })
+    }
+
+    /// Length in microseconds with a month counted as 30 days, which is
+    /// how intervals compare.
+    pub(crate) fn span(self) -> i128 {
+        (self.months as i128 * 30 + self.days as i128) * MICROS_PER_DAY as i128 + self.micros as i128
+    }
+
+    fn scale(self, factor: f64) -> Option<Interval> {
+        Interval::default()
+            .add_unit(self.months as f64 * factor, "months")?
+            .add_unit(self.days as f64 * factor, "days")?
+            .add_unit(self.micros as f64 * factor, "microseconds")
+    }
+
+    fn add_unit(self, amount: f64, unit: &str) -> Option<Interval> {
+        let (months, days, micros) = match unit {
+            "year" | "years" | "y" | "yr" | "yrs" => (amount * 12.0, 0.0, 0.0),
+            "month" | "months" | "mon" | "mons" => (amount, 0.0, 0.0),
+            "week" | "weeks" | "w" => (0.0, amount * 7.0, 0.0),
+            "day" | "days" | "d" => (0.0, amount, 0.0),
+            "hour" | "hours" | "h" | "hr" | "hrs" => (0.0, 0.0, amount * MICROS_PER_HOUR as f64),
+            "minute" | "minutes" | "min" | "mins" | "m" => (0.0, 0.0, amount * MICROS_PER_MINUTE as f64),
+            "second" | "seconds" | "sec" | "secs" | "s" => (0.0, 0.0, amount * MICROS_PER_SECOND as f64),
+            "millisecond" | "milliseconds" | "ms" => (0.0, 0.0, amount * 1000.0),
+            "microsecond" | "microseconds" | "us" => (0.0, 0.0, amount),
+            _ => return None,
+        };
+        let whole_months = months.trunc();
+        let days = days + (months - whole_months) * 30.0;
+        let whole_days = days.trunc();
+        let micros = micros + (days - whole_days) * MICROS_PER_DAY as f64;
+        if whole_months.abs() > i32::MAX as f64 || whole_days.abs() > i32::MAX as f64 || micros.abs() > i64::MAX as f64 {
+            return None;
+        }
+        self.checked_add(Interval { months: whole_months as i32, days: whole_days as i32, micros: micros.round() as i64 })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (y, m, d) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", y, m, d)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_clock(f, self.0 as u64)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.date(), self.time())
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        for (n, unit) in [(self.months / 12, "year"), (self.months % 12, "month"), (self.days, "day")] {
+            if n != 0 {
+                parts.push(format!("{} {}{}", n, unit, if n.abs() == 1 { "" } else { "s" }));
+            }
+        }
+        if self.micros != 0 || parts.is_empty() {
+            let mut clock = String::from(if self.micros < 0 { "-" } else { "" });
+            write_clock(&mut clock, self.micros.unsigned_abs())?;
+            parts.push(clock);
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// `HH:MM:SS`, with the fraction of a second when there is one.
+fn write_clock(f: &mut impl Write, micros: u64) -> fmt::Result {
+    let unit = |n: i64| n as u64;
+    let (h, m, s, frac) = (micros / unit(MICROS_PER_HOUR), micros / unit(MICROS_PER_MINUTE) % 60, micros / unit(MICROS_PER_SECOND) % 60, micros % unit(MICROS_PER_SECOND));
+    write!(f, "{:02}:{:02}:{:02}", h, m, s)?;
+    if frac != 0 {
+        write!(f, ".{}", format!("{:06}", frac).trim_end_matches('0'))?;
+    }
+    Ok(())
+}
+
+/// Reads `text` as a value of the date or time type `ty`.
+pub(crate) fn parse(ty: &DataType, text: &str) -> Result<Value, String> {
+    match ty {
+        DataType::Date => Date::parse(text).map(Value::Date),
+        DataType::Time => Time::parse(text).map(Value::Time),
+        DataType::Timestamp => Timestamp::parse(text).map(Value::Timestamp),
+        DataType::TimestampTz => Timestamp::parse_utc(text).map(Value::TimestampTz),
+        DataType::Interval => Interval::parse(text).map(Value::Interval),
+        _ => Err(format!("{} is not a date or time type", ty)),
+    }
+}
+
+/// A text constant compared with a column of date or time type, read as
+/// that type the way comparisons read it.
+pub(crate) fn align(ty: &DataType, value: Value) -> Value {
+    match &value {
+        Value::String(s) if ty.is_temporal() => parse(ty, s).unwrap_or(value),
+        _ => value,
+    }
+}
+
+/// Microseconds since the epoch of a DATE, TIMESTAMP or TIMESTAMPTZ; a date
+/// is its midnight and a TIMESTAMP is taken as UTC.
+pub(crate) fn instant(v: &Value) -> Option<i64> {
+    match v {
+        Value::Date(d) => Timestamp::new(*d, Time(0)).map(|t| t.0),
+        Value::Timestamp(t) | Value::TimestampTz(t) => Some(t.0),
+        _ => None,
+    }
+}
+
+/// Order of two date or time values, `None` unless they are comparable.
+/// Text compared with one of them is read as its type.
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(s), other) if other.data_type().is_temporal() => compare(&parse(&other.data_type(), s).ok()?, other),
+        (other, Value::String(s)) if other.data_type().is_temporal() => compare(other, &parse(&other.data_type(), s).ok()?),
+        (Value::Time(x), Value::Time(y)) => Some(x.cmp(y)),
+        (Value::Interval(x), Value::Interval(y)) => Some(x.span().cmp(&y.span())),
+        _ => Some(instant(a)?.cmp(&instant(b)?)),
+    }
+}
+
+/// Comparison or arithmetic on date and time values; `None` when `op` does
+/// not apply to them or the result is out of range.
+pub(crate) fn binop(left: &Value, op: &str, right: &Value) -> Option<Value> {
+    if matches!(op, "=" | "!=" | "<" | ">" | "<=" | ">=") {
+        let ord = compare(left, right)?;
+        return Some(Value::Bool(match op {
+            "=" => ord == Ordering::Equal,
+            "!=" => ord != Ordering::Equal,
+            "<" => ord == Ordering::Less,
+            ">" => ord == Ordering::Greater,
+            "<=" => ord != Ordering::Greater,
+            _ => ord != Ordering::Less,
+        }));
+    }
+    let signed = |iv: &Interval| if op == "-" { iv.checked_neg() } else { Some(*iv) };
+    Some(match (left, op, right) {
+        (Value::Date(d), "+" | "-", Value::Int(n)) => {
+            let n = if op == "-" { n.checked_neg()? } else { *n };
+            Value::Date(Date::from_days((d.0 as i64).checked_add(n)?)?)
+        }
+        (Value::Int(n), "+", Value::Date(d)) => Value::Date(Date::from_days((d.0 as i64).checked_add(*n)?)?),
+        (Value::Date(a), "-", Value::Date(b)) => Value::Int(a.0 as i64 - b.0 as i64),
+        (Value::Date(d), "+", Value::Time(t)) | (Value::Time(t), "+", Value::Date(d)) => Value::Timestamp(Timestamp::new(*d, *t)?),
+        (Value::Date(_) | Value::Timestamp(_), "+" | "-", Value::Interval(iv)) => Value::Timestamp(Timestamp(shift(instant(left)?, signed(iv)?)?)),
+        (Value::Interval(iv), "+", Value::Date(_) | Value::Timestamp(_)) => Value::Timestamp(Timestamp(shift(instant(right)?, *iv)?)),
+        (Value::TimestampTz(t), "+" | "-", Value::Interval(iv)) => Value::TimestampTz(Timestamp(shift(t.0, signed(iv)?)?)),
+        (Value::Interval(iv), "+", Value::TimestampTz(t)) => Value::TimestampTz(Timestamp(shift(t.0, *iv)?)),
+        (Value::Timestamp(a), "-", Value::Timestamp(b)) | (Value::TimestampTz(a), "-", Value::TimestampTz(b)) => {
+            let micros = a.0.checked_sub(b.0)?;
+            Value::Interval(Interval { months: 0, days: (micros / MICROS_PER_DAY) as i32, micros: micros % MICROS_PER_DAY })
+        }
+        // A time of day wraps around midnight; whole months and days do not move it
+        (Value::Time(t), "+" | "-", Value::Interval(iv)) => Value::Time(Time((t.0 + signed(iv)?.micros % MICROS_PER_DAY).rem_euclid(MICROS_PER_DAY))),
+        (Value::Interval(iv), "+", Value::Time(t)) => Value::Time(Time((t.0 + iv.micros % MICROS_PER_DAY).rem_euclid(MICROS_PER_DAY))),
+        (Value::Time(a), "-", Value::Time(b)) => Value::Interval(Interval { micros: a.0 - b.0, ..Interval::default() }),
+        (Value::Interval(a), "+" | "-", Value::Interval(b)) => Value::Interval(a.checked_add(signed(b)?)?),
+        (Value::Interval(iv), "*", n @ (Value::Int(_) | Value::Float(_))) | (n @ (Value::Int(_) | Value::Float(_)), "*", Value::Interval(iv)) => {
+            Value::Interval(iv.scale(n.as_f64()?)?)
+        }
+        (Value::Interval(iv), "/", n @ (Value::Int(_) | Value::Float(_))) if n.as_f64() != Some(0.0) => Value::Interval(iv.scale(1.0 / n.as_f64()?)?),
+        _ => return None,
+    })
+}
+
+/// Type of `left op right` for date and time operands, `Any` otherwise.
+pub(crate) fn result_type(left: &DataType, op: &str, right: &DataType) -> DataType {
+    use DataType as T;
+    match (left, op, right) {
+        (T::Date, "+" | "-", T::Int) | (T::Int, "+", T::Date) => T::Date,
+        (T::Date, "-", T::Date) => T::Int,
+        (T::Date, "+", T::Time) | (T::Time, "+", T::Date) => T::Timestamp,
+        (T::Date | T::Timestamp, "+" | "-", T::Interval) | (T::Interval, "+", T::Date | T::Timestamp) => T::Timestamp,
+        (T::TimestampTz, "+" | "-", T::Interval) | (T::Interval, "+", T::TimestampTz) => T::TimestampTz,
+        (T::Timestamp, "-", T::Timestamp) | (T::TimestampTz, "-", T::TimestampTz) | (T::Time, "-", T::Time) => T::Interval,
+        (T::Time, "+" | "-", T::Interval) | (T::Interval, "+", T::Time) => T::Time,
+        (T::Interval, "+" | "-", T::Interval) | (T::Interval, "*" | "/", T::Int | T::Float) | (T::Int | T::Float, "*", T::Interval) => T::Interval,
+        _ => T::Any,
+    }
+}
+
+/// `DATE_TRUNC(field, v)`: `v` rounded down to the start of its year,
+/// quarter, month, week (from Monday), day, hour, minute or second.
+pub(crate) fn date_trunc(field: &str, v: &Value) -> Option<Value> {
+    let micros = instant(v)?;
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let (y, m, _) = civil_from_days(days);
+    let start = |y, m| days_from_civil(y, m, 1) * MICROS_PER_DAY;
+    let down = |unit: i64| micros - micros.rem_euclid(unit);
+    let out = match field.to_lowercase().as_str() {
+        "microsecond" | "microseconds" => micros,
+        "millisecond" | "milliseconds" => down(1000),
+        "second" | "seconds" => down(MICROS_PER_SECOND),
+        "minute" | "minutes" => down(MICROS_PER_MINUTE),
+        "hour" | "hours" => down(MICROS_PER_HOUR),
+        "day" | "days" => down(MICROS_PER_DAY),
+        "week" | "weeks" => (days - weekday(days, 1)) * MICROS_PER_DAY,
+        "month" | "months" => start(y, m),
+        "quarter" => start(y, (m - 1) / 3 * 3 + 1),
+        "year" | "years" => start(y, 1),
+        "decade" => start(y - y.rem_euclid(10), 1),
+        _ => return None,
+    };
+    Some(match v {
+        Value::Date(_) => Value::Date(Date(out.div_euclid(MICROS_PER_DAY) as i32)),
+        Value::Timestamp(_) => Value::Timestamp(Timestamp(out)),
+        _ => Value::TimestampTz(Timestamp(out)),
+    })
+}
+
+/// `EXTRACT(field FROM v)` and `DATE_PART(field, v)`. Every field is an
+/// integer except `epoch`, the seconds since 1970 (or in an interval).
+pub(crate) fn date_part(field: &str, v: &Value) -> Option<Value> {
+    let field = field.to_lowercase();
+    let (days, time) = match v {
+        Value::Interval(iv) => return interval_part(&field, iv),
+        Value::Time(t) => (None, t.0),
+        _ => {
+            let micros = instant(v)?;
+            (Some(micros.div_euclid(MICROS_PER_DAY)), micros.rem_euclid(MICROS_PER_DAY))
+        }
+    };
+    let n = match field.as_str() {
+        "epoch" => {
+            let micros = days.map_or(time, |d| d * MICROS_PER_DAY + time);
+            return Some(Value::Float(micros as f64 / MICROS_PER_SECOND as f64));
+        }
+        "hour" | "hours" => time / MICROS_PER_HOUR,
+        "minute" | "minutes" => time / MICROS_PER_MINUTE % 60,
+        "second" | "seconds" => time / MICROS_PER_SECOND % 60,
+        "millisecond" | "milliseconds" => time % MICROS_PER_MINUTE / 1000,
+        "microsecond" | "microseconds" => time % MICROS_PER_MINUTE,
+        _ => {
+            let days = days?;
+            let (y, m, d) = civil_from_days(days);
+            match field.as_str() {
+                "year" | "years" => y,
+                "quarter" => (m as i64 - 1) / 3 + 1,
+                "month" | "months" => m as i64,
+                "day" | "days" => d as i64,
+                "dow" => weekday(days, 0),
+                "isodow" => weekday(days, 1) + 1,
+                "doy" => days - days_from_civil(y, 1, 1) + 1,
+                "week" | "weeks" => iso_week(days),
+                "decade" => y.div_euclid(10),
+                _ => return None,
+            }
+        }
+    };
+    Some(Value::Int(n))
+}
+
+fn interval_part(field: &str, iv: &Interval) -> Option<Value> {
+    let n = match field {
+        "epoch" => return Some(Value::Float(iv.span() as f64 / MICROS_PER_SECOND as f64)),
+        "year" | "years" => iv.months as i64 / 12,
+        "month" | "months" => iv.months as i64 % 12,
+        "day" | "days" => iv.days as i64,
+        "hour" | "hours" => iv.micros / MICROS_PER_HOUR,
+        "minute" | "minutes" => iv.micros / MICROS_PER_MINUTE % 60,
+        "second" | "seconds" => iv.micros / MICROS_PER_SECOND % 60,
+        "millisecond" | "milliseconds" => iv.micros % MICROS_PER_MINUTE / 1000,
+        "microsecond" | "microseconds" => iv.micros % MICROS_PER_MINUTE,
+        _ => return None,
+    };
+    Some(Value::Int(n))
+}
+
+/// `STRFTIME(v, format)`: `v` written out by a C-style format. Supports
+/// `%Y %y %m %d %e %j %B %b %A %a %u %w %H %I %M %S %f %p %F %T %z %Z %%`;
+/// other directives are copied as written. `None` when the format needs a
+/// date and `v` is a TIME.
+pub(crate) fn strftime(v: &Value, format: &str) -> Option<String> {
+    let (days, time) = match v {
+        Value::Time(t) => (None, t.0),
+        _ => {
+            let micros = instant(v)?;
+            (Some(micros.div_euclid(MICROS_PER_DAY)), micros.rem_euclid(MICROS_PER_DAY))
+        }
+    };
+    let zoned = matches!(v, Value::TimestampTz(_));
+    let (h, mi, s) = (time / MICROS_PER_HOUR, time / MICROS_PER_MINUTE % 60, time / MICROS_PER_SECOND % 60);
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let Some(spec) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        let _ = match spec {
+            'H' => write!(out, "{:02}", h),
+            'I' => write!(out, "{:02}", (h + 11) % 12 + 1),
+            'M' => write!(out, "{:02}", mi),
+            'S' => write!(out, "{:02}", s),
+            'f' => write!(out, "{:06}", time % MICROS_PER_SECOND),
+            'p' => write!(out, "{}", if h < 12 { "AM" } else { "PM" }),
+            'T' => write!(out, "{:02}:{:02}:{:02}", h, mi, s),
+            'z' => write!(out, "{}", if zoned { "+0000" } else { "" }),
+            'Z' => write!(out, "{}", if zoned { "UTC" } else { "" }),
+            '%' => write!(out, "%"),
+            'Y' | 'y' | 'm' | 'd' | 'e' | 'j' | 'B' | 'b' | 'A' | 'a' | 'u' | 'w' | 'F' => {
+                let days = days?;
+                let (y, m, d) = civil_from_days(days);
+                match spec {
+                    'Y' => write!(out, "{:04}", y),
+                    'y' => write!(out, "{:02}", y.rem_euclid(100)),
+                    'm' => write!(out, "{:02}", m),
+                    'd' => write!(out, "{:02}", d),
+                    'e' => write!(out, "{:2}", d),
+                    'j' => write!(out, "{:03}", days - days_from_civil(y, 1, 1) + 1),
+                    'B' => write!(out, "{}", MONTHS[m as usize - 1]),
+                    'b' => write!(out, "{}", &MONTHS[m as usize - 1][..3]),
+                    'A' => write!(out, "{}", WEEKDAYS[weekday(days, 0) as usize]),
+                    'a' => write!(out, "{}", &WEEKDAYS[weekday(days, 0) as usize][..3]),
+                    'u' => write!(out, "{}", weekday(days, 1) + 1),
+                    'w' => write!(out, "{}", weekday(days, 0)),
+                    _ => write!(out, "{:04}-{:02}-{:02}", y, m, d),
+                }
+            }
+            other => write!(out, "%{}", other),
+        };
+    }
+    Some(out)
+}
+
+/// Days since the week began on `first` (0 Sunday, 1 Monday).
+fn weekday(days: i64, first: i64) -> i64 {
+    // 1970-01-01 was a Thursday
+    (days + 4 - first).rem_euclid(7)
+}
+
+/// ISO 8601 week number: weeks start on Monday, and week 1 holds the
+/// year's first Thursday.
+fn iso_week(days: i64) -> i64 {
+    let thursday = days - weekday(days, 1) + 3;
+    let (y, _, _) = civil_from_days(thursday);
+    (thursday - days_from_civil(y, 1, 1)) / 7 + 1
+}
+
+/// Moves an instant by an interval: months first, keeping the day of the
+/// month where the target month has it and its last day otherwise, then
+/// days, then the time.
+fn shift(micros: i64, iv: Interval) -> Option<i64> {
+    let (days, time) = (micros.div_euclid(MICROS_PER_DAY), micros.rem_euclid(MICROS_PER_DAY));
+    let days = if iv.months == 0 {
+        days
+    } else {
+        let (y, m, d) = civil_from_days(days);
+        let month = y.checked_mul(12)? + m as i64 - 1 + iv.months as i64;
+        let (y, m) = (month.div_euclid(12), month.rem_euclid(12) as u32 + 1);
+        days_from_civil(y, m, d.min(days_in_month(y, m)))
+    };
+    (days + iv.days as i64).checked_mul(MICROS_PER_DAY)?.checked_add(time)?.checked_add(iv.micros)
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to a day of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Years start in March, so the leap day ends the year
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn digits(s: &str) -> Option<i64> {
+    (!s.is_empty() && s.len() <= 9 && s.bytes().all(|b| b.is_ascii_digit())).then(|| s.parse().unwrap())
+}
+
+fn parse_date(s: &str) -> Option<Date> {
+    let mut parts = s.split('-');
+    let (y, m, d) = (digits(parts.next()?)?, digits(parts.next()?)?, digits(parts.next()?)?);
+    if parts.next().is_some() || m > 12 || d > 31 {
+        return None;
+    }
+    Date::from_ymd(y, m as u32, d as u32)
+}
+
+/// `HH:MM[:SS[.ffffff]]` in microseconds. Hours past 23 are allowed
+/// unless `time_of_day`.
+fn parse_clock(s: &str, time_of_day: bool) -> Option<i64> {
+    let mut parts = s.split(':');
+    let (h, m) = (digits(parts.next()?)?, digits(parts.next()?)?);
+    let (sec, frac) = match parts.next() {
+        Some(p) => match p.split_once('.') {
+            Some((sec, frac)) if frac.len() <= 6 => (digits(sec)?, digits(frac)? * 10i64.pow(6 - frac.len() as u32)),
+            Some(_) => return None,
+            None => (digits(p)?, 0),
+        },
+        None => (0, 0),
+    };
+    if parts.next().is_some() || (time_of_day && h > 23) || m > 59 || sec > 59 {
+        return None;
+    }
+    Some(h * MICROS_PER_HOUR + m * MICROS_PER_MINUTE + sec * MICROS_PER_SECOND + frac)
+}
+
+fn parse_timestamp(s: &str, zoned: bool) -> Option<Timestamp> {
+    let (date, rest) = match s.find([' ', 'T']) {
+        Some(i) => (&s[..i], s[i + 1..].trim()),
+        None => (s, ""),
+    };
+    let date = parse_date(date)?;
+    let (clock, offset) = if zoned { split_zone(rest)? } else { (rest, 0) };
+    let time = if clock.is_empty() { 0 } else { parse_clock(clock, true)? };
+    Timestamp::new(date, Time(time))?.0.checked_sub(offset).map(Timestamp)
+}
+
+/// Splits a UTC offset off the end of a time of day, giving the offset in
+/// microseconds (0 when there is none).
+fn split_zone(s: &str) -> Option<(&str, i64)> {
+    if let Some(clock) = s.strip_suffix('Z').or_else(|| s.strip_suffix("UTC")) {
+        return Some((clock.trim_end(), 0));
+    }
+    let Some(i) = s.rfind(['+', '-']) else { return Some((s, 0)) };
+    let zone = s[i + 1..].replace(':', "");
+    let (h, m) = match zone.len() {
+        2 => (digits(&zone)?, 0),
+        4 => (digits(&zone[..2])?, digits(&zone[2..])?),
+        _ => return None,
+    };
+    if h > 23 || m > 59 {
+        return None;
+    }
+    let sign = if s[i..].starts_with('-') { -1 } else { 1 };
+    Some((s[..i].trim_end(), sign * (h * MICROS_PER_HOUR + m * MICROS_PER_MINUTE)))
+}
+
+fn parse_interval(s: &str) -> Option<Interval> {
+    let mut out = Interval::default();
+    let mut words = s.split_whitespace();
+    let mut any = false;
+    while let Some(word) = words.next() {
+        any = true;
+        let (negative, body) = match word.strip_prefix('-') {
+            Some(body) => (true, body),
+            None => (false, word.strip_prefix('+').unwrap_or(word)),
+        };
+        if body.contains(':') {
+            let micros = parse_clock(body, false)?;
+            out.micros = out.micros.checked_add(if negative { -micros } else { micros })?;
+            continue;
+        }
+        // The unit follows the number, as its own word or attached ("3d")
+        let split = body.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(body.len());
+        let amount: f64 = body[..split].parse().ok()?;
+        let unit = if split < body.len() { &body[split..] } else { words.next()? };
+        out = out.add_unit(if negative { -amount } else { amount }, unit.trim_end_matches(',').to_lowercase().as_str())?;
+    }
+    any.then_some(out)
+}
@@ -0,0 +1,68 @@
+use std::ops::Deref;
+
+use crate::{Database, Query, ResultSet, Statement, StatementResult};
+
+/// An open transaction, from `Database::transaction`. Statements run through
+/// it take effect together at `commit`; dropping it without committing rolls
+/// everything back. The database stays readable through `Deref`.
+pub struct Transaction<'a> {
+    db: &'a mut Database,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a mut Database) -> Self {
+        Transaction { db, finished: false }
+    }
+
+    /// Runs a statement inside the transaction. BEGIN, COMMIT and ROLLBACK
+    /// are rejected; use `commit` and `rollback` instead.
+    pub fn execute_statement(&mut self, stmt: &Statement) -> Result<StatementResult, String> {
+        if matches!(stmt, Statement::Begin | Statement::Commit | Statement::Rollback) {
+            return Err("Use Transaction::commit or Transaction::rollback to end the transaction".into());
+        }
+        self.db.execute_statement(stmt)
+    }
+
+    pub fn execute(&self, query: &Query) -> Result<ResultSet, String> {
+        self.db.execute(query)
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> Result<(), String> {
+        self.db.savepoint(name)
+    }
+
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), String> {
+        self.db.rollback_to(name)
+    }
+
+    pub fn release(&mut self, name: &str) -> Result<(), String> {
+        self.db.release(name)
+    }
+
+    pub fn commit(mut self) -> Result<(), String> {
+        self.finished = true;
+        self.db.commit()
+    }
+
+    pub fn rollback(mut self) -> Result<(), String> {
+        self.finished = true;
+        self.db.rollback()
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.db
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished && self.db.in_transaction() {
+            let _ = self.db.rollback();
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::cmp::Ordering;
+
+use crate::collation::{self, Collation};
+use crate::{columnar_name_matches, field_path, ColumnData, ColumnarTable, Database, Expr, Value, AGGREGATES};
+
+/// Rows evaluated per kernel call when scanning a columnar table.
+pub const BATCH_SIZE: usize = 2048;
+
+/// An expression compiled against one columnar table: column names are
+/// resolved to positions and operators to tags, so evaluating a batch does no
+/// name lookups or string matching.
+#[derive(Debug, Clone)]
+pub(crate) enum Kernel {
+    Column(usize),
+    Const(Value),
+    Not(Box<Kernel>),
+    Neg(Box<Kernel>),
+    Binary(BinaryOp, Box<Kernel>, Box<Kernel>),
+    Call(String, Vec<Kernel>),
+    /// Values replaced by their collation keys, for a comparison made
+    /// under a collation.
+    Collate(Collation, Box<Kernel>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Like,
+    /// JSON path operators, applied value by value.
+    Get,
+    GetText,
+    Path,
+    PathText,
+}
+
+impl BinaryOp {
+    fn from_str(op: &str) -> Option<Self> {
+        Some(match op {
+            "=" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            ">" => BinaryOp::Gt,
+            "<=" => BinaryOp::Le,
+            ">=" => BinaryOp::Ge,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "AND" => BinaryOp::And,
+            "OR" => BinaryOp::Or,
+            "LIKE" => BinaryOp::Like,
+            "->" => BinaryOp::Get,
+            "->>" => BinaryOp::GetText,
+            "#>" => BinaryOp::Path,
+            "#>>" => BinaryOp::PathText,
+            _ => return None,
+        })
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            BinaryOp::Eq => "=",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Le => "<=",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+            BinaryOp::Like => "LIKE",
+            BinaryOp::Get => "->",
+            BinaryOp::GetText => "->>",
+            BinaryOp::Path => "#>",
+            BinaryOp::PathText => "#>>",
+        }
+    }
+
+    fn compares(self, ord: Option<Ordering>) -> bool {
+        match (self, ord) {
+            (BinaryOp::Eq, Some(o)) => o == Ordering::Equal,
+            (BinaryOp::Ne, o) => o != Some(Ordering::Equal),
+            (BinaryOp::Lt, Some(o)) => o == Ordering::Less,
+            (BinaryOp::Gt, Some(o)) => o == Ordering::Greater,
+            (BinaryOp::Le, Some(o)) => o != Ordering::Greater,
+            (BinaryOp::Ge, Some(o)) => o != Ordering::Less,
+            _ => false,
+        }
+    }
+
+    fn is_comparison(self) -> bool {
+        matches!(self, BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge)
+    }
+}
+
+/// Values of one expression for the selected rows of a batch. Typed vectors
+/// carry a validity flag per value; `Const` stands for the same value in
+/// every row.
+enum Vector<'a> {
+    Const(Value),
+    Int(Vec<i64>, Vec<bool>),
+    Float(Vec<f64>, Vec<bool>),
+    Bool(Vec<bool>, Vec<bool>),
+    Str(Vec<&'a str>, Vec<bool>),
+    Values(Vec<Value>),
+}
+
+impl Vector<'_> {
+    fn get(&self, i: usize) -> Value {
+        match self {
+            Vector::Const(v) => v.clone(),
+            Vector::Int(v, valid) if valid[i] => Value::Int(v[i]),
+            Vector::Float(v, valid) if valid[i] => Value::Float(v[i]),
+            Vector::Bool(v, valid) if valid[i] => Value::Bool(v[i]),
+            Vector::Str(v, valid) if valid[i] => Value::String(v[i].to_string()),
+            Vector::Values(v) => v[i].clone(),
+            _ => Value::Null,
+        }
+    }
+
+    fn is_true(&self, i: usize) -> bool {
+        match self {
+            Vector::Bool(v, valid) => valid[i] && v[i],
+            Vector::Const(v) => v.is_true(),
+            Vector::Values(v) => v[i].is_true(),
+            _ => false,
+        }
+    }
+
+    /// Expands a typed constant so kernels only see dense vectors.
+    fn broadcast(self, n: usize) -> Self {
+        match self {
+            Vector::Const(Value::Int(x)) => Vector::Int(vec![x; n], vec![true; n]),
+            Vector::Const(Value::Float(x)) => Vector::Float(vec![x; n], vec![true; n]),
+            Vector::Const(Value::Bool(x)) => Vector::Bool(vec![x; n], vec![true; n]),
+            other => other,
+        }
+    }
+
+    fn as_floats(&self) -> Option<(Vec<f64>, &[bool])> {
+        match self {
+            Vector::Int(v, valid) => Some((v.iter().map(|&x| x as f64).collect(), valid)),
+            Vector::Float(v, valid) => Some((v.clone(), valid)),
+            _ => None,
+        }
+    }
+}
+
+impl Kernel {
+    /// Compiles `expr` for `table`. Unknown columns and operators become NULL
+    /// constants, as they evaluate to NULL row by row.
+    pub(crate) fn compile(expr: &Expr, table: &ColumnarTable) -> Kernel {
+        match expr {
+            Expr::Column(name) => match (0..table.columns.len()).find(|&i| columnar_name_matches(table, i, name)) {
+                Some(i) => Kernel::Column(i),
+                // A struct field reads the column and extracts it
+                None => (0..table.columns.len())
+                    .find_map(|i| Some((i, field_path(name, &table.name, &table.columns[i].name)?)))
+                    .map_or(Kernel::Const(Value::Null), |(i, path)| {
+                        path.into_iter().fold(Kernel::Column(i), |k, f| Kernel::Call("STRUCT_EXTRACT".into(), vec![k, Kernel::Const(Value::String(f.into()))]))
+                    }),
+            },
+            Expr::Literal(v) => Kernel::Const(v.clone()),
+            Expr::UnaryOp(op, e) => match op.as_str() {
+                "NOT" => Kernel::Not(Box::new(Kernel::compile(e, table))),
+                "-" => Kernel::Neg(Box::new(Kernel::compile(e, table))),
+                _ => Kernel::Const(Value::Null),
+            },
+            Expr::BinOp(l, op, r) => match (BinaryOp::from_str(op), collation::of_comparison(l, op, r)) {
+                (Some(op), None) => Kernel::Binary(op, Box::new(Kernel::compile(l, table)), Box::new(Kernel::compile(r, table))),
+                (Some(op), Some(c)) => {
+                    let collate = |e| Box::new(Kernel::Collate(c, Box::new(Kernel::compile(e, table))));
+                    Kernel::Binary(op, collate(l), collate(r))
+                }
+                (None, _) => Kernel::Const(Value::Null),
+            },
+            Expr::FuncCall(name, _) if AGGREGATES.contains(&name.as_str()) || name == "GROUPING" => Kernel::Const(Value::Null),
+            Expr::FuncCall(name, args) => Kernel::Call(name.clone(), args.iter().map(|a| Kernel::compile(a, table)).collect()),
+        }
+    }
+
+    /// Narrows `sel` (row positions in `table`) to the rows where this
+    /// kernel is TRUE. A conjunction filters by each side in turn, so the
+    /// right side only runs on rows the left side kept.
+    pub(crate) fn filter(&self, db: &Database, table: &ColumnarTable, sel: Vec<usize>) -> Vec<usize> {
+        if let Kernel::Binary(BinaryOp::And, l, r) = self {
+            let sel = l.filter(db, table, sel);
+            return if sel.is_empty() { sel } else { r.filter(db, table, sel) };
+        }
+        let result = self.eval(db, table, &sel);
+        sel.into_iter().enumerate().filter(|&(i, _)| result.is_true(i)).map(|(_, row)| row).collect()
+    }
+
+    fn eval<'a>(&self, db: &Database, table: &'a ColumnarTable, sel: &[usize]) -> Vector<'a> {
+        match self {
+            Kernel::Const(v) => Vector::Const(v.clone()),
+            Kernel::Column(c) => gather(table, *c, sel),
+            Kernel::Not(e) => match e.eval(db, table, sel) {
+                Vector::Bool(v, valid) => Vector::Bool(v.into_iter().map(|b| !b).collect(), valid),
+                Vector::Const(v) => Vector::Const(db.apply_unary("NOT", &v)),
+                other => Vector::Values((0..sel.len()).map(|i| db.apply_unary("NOT", &other.get(i))).collect()),
+            },
+            Kernel::Neg(e) => match e.eval(db, table, sel) {
+                Vector::Int(v, mut valid) => {
+                    let out = v.iter().zip(&mut valid).map(|(x, ok)| x.checked_neg().unwrap_or_else(|| { *ok = false; 0 })).collect();
+                    Vector::Int(out, valid)
+                }
+                Vector::Float(v, valid) => Vector::Float(v.into_iter().map(|x| -x).collect(), valid),
+                Vector::Const(v) => Vector::Const(db.apply_unary("-", &v)),
+                other => Vector::Values((0..sel.len()).map(|i| db.apply_unary("-", &other.get(i))).collect()),
+            },
+            Kernel::Binary(op, l, r) => {
+                let lv = l.eval(db, table, sel);
+                let rv = r.eval(db, table, sel);
+                binary(db, *op, lv, rv, sel.len())
+            }
+            Kernel::Call(name, args) => {
+                let args: Vec<Vector> = args.iter().map(|a| a.eval(db, table, sel)).collect();
+                if args.iter().all(|a| matches!(a, Vector::Const(_))) {
+                    let values: Vec<Value> = args.iter().map(|a| a.get(0)).collect();
+                    return Vector::Const(db.call_function(name, &values));
+                }
+                Vector::Values(
+                    (0..sel.len())
+                        .map(|i| {
+                            let values: Vec<Value> = args.iter().map(|a| a.get(i)).collect();
+                            db.call_function(name, &values)
+                        })
+                        .collect(),
+                )
+            }
+            Kernel::Collate(c, e) => match e.eval(db, table, sel) {
+                v if *c == Collation::Binary => v,
+                Vector::Const(v) => Vector::Const(c.into_key(v)),
+                v => Vector::Values((0..sel.len()).map(|i| c.into_key(v.get(i))).collect()),
+            },
+        }
+    }
+}
+
+fn gather<'a>(table: &'a ColumnarTable, col: usize, sel: &[usize]) -> Vector<'a> {
+    let vector = table.vector(col);
+    let valid: Vec<bool> = sel.iter().map(|&i| vector.validity.get(i)).collect();
+    match &vector.data {
+        ColumnData::Int(v) => Vector::Int(sel.iter().map(|&i| v[i]).collect(), valid),
+        ColumnData::Float(v) => Vector::Float(sel.iter().map(|&i| v[i]).collect(), valid),
+        ColumnData::Bool(v) => Vector::Bool(sel.iter().map(|&i| v[i]).collect(), valid),
+        ColumnData::String(v) => Vector::Str(sel.iter().map(|&i| v[i].as_str()).collect(), valid),
+        ColumnData::Any(v) => Vector::Values(sel.iter().map(|&i| v[i].clone()).collect()),
+    }
+}
+
+fn both(a: &[bool], b: &[bool]) -> Vec<bool> {
+    a.iter().zip(b).map(|(x, y)| *x && *y).collect()
+}
+
+/// Applies a binary operator to two vectors with the same semantics as
+/// `Database::apply_binop`: tight loops for matching typed inputs, and the
+/// scalar operator value by value for anything else.
+fn binary<'a>(db: &Database, op: BinaryOp, left: Vector<'a>, right: Vector<'a>, n: usize) -> Vector<'a> {
+    if let (Vector::Const(a), Vector::Const(b)) = (&left, &right) {
+        return Vector::Const(db.apply_binop(a, op.as_str(), b));
+    }
+    if matches!(left, Vector::Const(Value::Null)) || matches!(right, Vector::Const(Value::Null)) {
+        return Vector::Const(Value::Null);
+    }
+    // String constants compare in place against string columns
+    let (left, right) = match (left, right) {
+        (Vector::Const(Value::String(s)), Vector::Str(v, valid)) if op.is_comparison() => {
+            let out = v.iter().map(|x| op.compares(Some(s.as_str().cmp(x)))).collect();
+            return Vector::Bool(out, valid);
+        }
+        (Vector::Str(v, valid), Vector::Const(Value::String(s))) if op.is_comparison() => {
+            let out = v.iter().map(|x| op.compares(Some((*x).cmp(s.as_str())))).collect();
+            return Vector::Bool(out, valid);
+        }
+        (l, r) => (l.broadcast(n), r.broadcast(n)),
+    };
+    match (&left, &right) {
+        (Vector::Int(a, va), Vector::Int(b, vb)) => {
+            let mut valid = both(va, vb);
+            if op.is_comparison() {
+                let out = a.iter().zip(b).map(|(x, y)| op.compares(Some(x.cmp(y)))).collect();
+                return Vector::Bool(out, valid);
+            }
+            let f = match op {
+                BinaryOp::Add => i64::checked_add,
+                BinaryOp::Sub => i64::checked_sub,
+                BinaryOp::Mul => i64::checked_mul,
+                BinaryOp::Div => i64::checked_div,
+                BinaryOp::Rem => i64::checked_rem,
+                _ => return Vector::Const(Value::Null),
+            };
+            let out = a.iter().zip(b).zip(&mut valid).map(|((x, y), ok)| f(*x, *y).unwrap_or_else(|| { *ok = false; 0 })).collect();
+            Vector::Int(out, valid)
+        }
+        (Vector::Int(..) | Vector::Float(..), Vector::Int(..) | Vector::Float(..)) => {
+            let (a, va) = left.as_floats().unwrap();
+            let (b, vb) = right.as_floats().unwrap();
+            let valid = both(va, vb);
+            // Ints and floats compare exactly, NaN included, as `Value` orders them
+            if op.is_comparison() {
+                let out = (0..n).map(|i| op.compares(Some(left.get(i).cmp(&right.get(i))))).collect();
+                return Vector::Bool(out, valid);
+            }
+            let f: fn(f64, f64) -> f64 = match op {
+                BinaryOp::Add => |x, y| x + y,
+                BinaryOp::Sub => |x, y| x - y,
+                BinaryOp::Mul => |x, y| x * y,
+                BinaryOp::Div => |x, y| x / y,
+                BinaryOp::Rem => |x, y| x % y,
+                _ => return Vector::Const(Value::Null),
+            };
+            Vector::Float(a.iter().zip(&b).map(|(x, y)| f(*x, *y)).collect(), valid)
+        }
+        (Vector::Str(a, va), Vector::Str(b, vb)) if op.is_comparison() => {
+            let out = a.iter().zip(b).map(|(x, y)| op.compares(Some(x.cmp(y)))).collect();
+            Vector::Bool(out, both(va, vb))
+        }
+        (Vector::Bool(a, va), Vector::Bool(b, vb)) => {
+            let f: fn(bool, bool) -> bool = match op {
+                BinaryOp::And => |x, y| x && y,
+                BinaryOp::Or => |x, y| x || y,
+                BinaryOp::Eq => |x, y| x == y,
+                BinaryOp::Ne => |x, y| x != y,
+                _ => return Vector::Const(Value::Null),
+            };
+            Vector::Bool(a.iter().zip(b).map(|(x, y)| f(*x, *y)).collect(), both(va, vb))
+        }
+        _ => Vector::Values((0..n).map(|i| db.apply_binop(&left.get(i), op.as_str(), &right.get(i))).collect()),
+    }
+}
@@ -0,0 +1,336 @@
+//! Write-ahead log kept beside the database file, at `<path>-wal`.
+//!
+//! The log starts with a 24-byte header: the magic `QLDBWAL\0`, the log
+//! version, the generation of the database file it applies to, and a CRC-32
+//! of those fields. Records follow, each framed as a `u32` payload length, a
+//! CRC-32 of the payload, and the payload itself, whose first byte is the
+//! record kind.
+//! A statement's records are followed by a commit record and written with a
+//! single write. Recovery replays records up to the last commit and cuts the
+//! log there, so a torn or uncommitted tail is dropped.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::storage::{crc32, Decoder, Encoder};
+use crate::{Row, Table, Value};
+
+const MAGIC: &[u8; 8] = b"QLDBWAL\0";
+const WAL_VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 24;
+
+const INSERT: u8 = 1;
+const UPDATE: u8 = 2;
+const DELETE: u8 = 3;
+const TABLE: u8 = 4;
+const DROP: u8 = 5;
+const COMMIT: u8 = 6;
+
+/// When the log is flushed to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SyncPolicy {
+    /// fsync on every commit: a statement that returned is never lost.
+    #[default]
+    EveryCommit,
+    /// fsync on a commit when the last one is at least this long ago. A
+    /// crash can lose the commits since then, but never part of one.
+    Periodic(Duration),
+    /// Leave flushing to the operating system.
+    Off,
+}
+
+/// A change to record, noted as the statement runs. Row changes carry their
+/// rows; a changed table is read when the statement finishes, so its record
+/// holds the state that statement left behind, not what later statements in
+/// its transaction did.
+#[derive(Debug, Clone)]
+pub(crate) enum Effect {
+    Insert(String, Vec<Row>),
+    Update(String, Vec<(usize, Row)>),
+    Delete(String, Vec<usize>),
+    Table(String),
+    Drop(String),
+}
+
+impl Effect {
+    /// The effects of replacing the rows at the positions `updated` gives
+    /// and then removing those at `deleted`, in position order.
+    pub fn rows(name: &str, updated: &HashMap<usize, Row>, deleted: &HashSet<usize>) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        if !updated.is_empty() {
+            let mut rows: Vec<(usize, Row)> = updated.iter().map(|(&i, row)| (i, row.clone())).collect();
+            rows.sort_by_key(|(i, _)| *i);
+            effects.push(Effect::Update(name.to_string(), rows));
+        }
+        if !deleted.is_empty() {
+            let mut positions: Vec<usize> = deleted.iter().copied().collect();
+            positions.sort_unstable();
+            effects.push(Effect::Delete(name.to_string(), positions));
+        }
+        effects
+    }
+}
+
+/// One logged change, as replayed by recovery.
+#[derive(Debug, Clone)]
+pub(crate) enum Record {
+    /// Rows appended to a table.
+    Insert(String, Vec<Row>),
+    /// Rows replaced, each with its position.
+    Update(String, Vec<(usize, Row)>),
+    /// Positions of removed rows, in ascending order, as they were before
+    /// the removal.
+    Delete(String, Vec<usize>),
+    /// A whole table, schema and rows, and whether it is columnar.
+    Table(Table, bool),
+    Drop(String),
+}
+
+pub(crate) struct Wal {
+    file: File,
+    generation: u64,
+    len: u64,
+    last_sync: Instant,
+    /// Changes noted by the running statement.
+    pub noted: Vec<Effect>,
+    /// Records of finished statements waiting for their transaction to
+    /// commit.
+    pub pending: Vec<Record>,
+}
+
+pub(crate) fn wal_path(db_path: &Path) -> PathBuf {
+    let mut name = db_path.as_os_str().to_owned();
+    name.push("-wal");
+    PathBuf::from(name)
+}
+
+impl Wal {
+    /// Opens the log for a database file of the given generation and returns
+    /// the committed records to replay. A log from another generation was
+    /// already checkpointed into the file, so it is discarded.
+    pub fn open(db_path: &Path, generation: u64) -> Result<(Wal, Vec<Record>), String> {
+        let path = wal_path(db_path);
+        let io_err = |e: std::io::Error| format!("Cannot open {}: {}", path.display(), e);
+        let bytes = if path.exists() { fs::read(&path).map_err(io_err)? } else { Vec::new() };
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).map_err(io_err)?;
+        let mut wal = Wal { file, generation, len: 0, last_sync: Instant::now(), noted: Vec::new(), pending: Vec::new() };
+
+        let header_ok = bytes.len() as u64 >= HEADER_SIZE
+            && &bytes[..8] == MAGIC
+            && u32::from_le_bytes(bytes[8..12].try_into().unwrap()) == WAL_VERSION
+            && u64::from_le_bytes(bytes[12..20].try_into().unwrap()) == generation
+            && crc32(&bytes[..20]) == u32::from_le_bytes(bytes[20..24].try_into().unwrap());
+        if !header_ok {
+            wal.reset(generation)?;
+            return Ok((wal, Vec::new()));
+        }
+
+        let mut records = Vec::new();
+        let mut group = Vec::new();
+        let mut pos = HEADER_SIZE as usize;
+        let mut committed = pos;
+        while let Some((payload, end)) = next_frame(&bytes, pos) {
+            pos = end;
+            let mut d = Decoder::new(payload);
+            match d.u8() {
+                Ok(COMMIT) => {
+                    records.append(&mut group);
+                    committed = pos;
+                }
+                Ok(kind) => match decode_record(kind, &mut d) {
+                    Ok(record) => group.push(record),
+                    Err(_) => break,
+                },
+                Err(_) => break,
+            }
+        }
+        wal.len = committed as u64;
+        wal.file.set_len(wal.len).map_err(io_err)?;
+        wal.file.sync_all().map_err(io_err)?;
+        Ok((wal, records))
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Empties the log and stamps it with a new database generation.
+    pub fn reset(&mut self, generation: u64) -> Result<(), String> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&WAL_VERSION.to_le_bytes());
+        header.extend_from_slice(&generation.to_le_bytes());
+        header.extend_from_slice(&crc32(&header).to_le_bytes());
+        let io_err = |e: std::io::Error| format!("Cannot write log: {}", e);
+        self.file.set_len(0).map_err(io_err)?;
+        self.file.seek(SeekFrom::Start(0)).map_err(io_err)?;
+        self.file.write_all(&header).map_err(io_err)?;
+        self.file.sync_all().map_err(io_err)?;
+        self.generation = generation;
+        self.len = HEADER_SIZE;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Appends `records` and a commit record in one write, then syncs as the
+    /// policy asks.
+    pub fn commit(&mut self, records: &[Record], policy: SyncPolicy) -> Result<(), String> {
+        let mut buf = Vec::new();
+        for record in records {
+            push_frame(&mut buf, &encode_record(record));
+        }
+        push_frame(&mut buf, &[COMMIT]);
+        let io_err = |e: std::io::Error| format!("Cannot write log: {}", e);
+        self.file.seek(SeekFrom::Start(self.len)).map_err(io_err)?;
+        self.file.write_all(&buf).map_err(io_err)?;
+        self.len += buf.len() as u64;
+        let sync = match policy {
+            SyncPolicy::EveryCommit => true,
+            SyncPolicy::Periodic(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::Off => false,
+        };
+        if sync {
+            self.file.sync_data().map_err(io_err)?;
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Wal {
+    fn drop(&mut self) {
+        let _ = self.file.sync_data();
+    }
+}
+
+fn push_frame(buf: &mut Vec<u8>, payload: &[u8]) {
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&crc32(payload).to_le_bytes());
+    buf.extend_from_slice(payload);
+}
+
+/// The payload of the frame at `pos` and the offset after it, or `None` if
+/// the frame is incomplete or fails its checksum.
+fn next_frame(bytes: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(pos..pos + 8)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let end = pos.checked_add(8 + len)?;
+    let payload = bytes.get(pos + 8..end)?;
+    (crc32(payload) == crc).then_some((payload, end))
+}
+
+fn encode_row(e: &mut Encoder, row: &Row) {
+    let mut entries: Vec<(&String, &Value)> = row.data.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    e.u32(entries.len() as u32);
+    for (name, value) in entries {
+        e.str(name);
+        e.value(value);
+    }
+}
+
+fn decode_row(d: &mut Decoder) -> Result<Row, String> {
+    let mut row = Row { data: Default::default() };
+    for _ in 0..d.u32()? {
+        let name = d.str()?;
+        row.data.insert(name, d.value()?);
+    }
+    Ok(row)
+}
+
+fn encode_rows(e: &mut Encoder, rows: &[Row]) {
+    e.u32(rows.len() as u32);
+    for row in rows {
+        encode_row(e, row);
+    }
+}
+
+fn decode_rows(d: &mut Decoder) -> Result<Vec<Row>, String> {
+    (0..d.u32()?).map(|_| decode_row(d)).collect()
+}
+
+fn encode_record(record: &Record) -> Vec<u8> {
+    let mut e = Encoder::default();
+    match record {
+        Record::Insert(name, rows) => {
+            e.u8(INSERT);
+            e.str(name);
+            encode_rows(&mut e, rows);
+        }
+        Record::Update(name, rows) => {
+            e.u8(UPDATE);
+            e.str(name);
+            e.u32(rows.len() as u32);
+            for (position, row) in rows {
+                e.u64(*position as u64);
+                encode_row(&mut e, row);
+            }
+        }
+        Record::Delete(name, positions) => {
+            e.u8(DELETE);
+            e.str(name);
+            e.u32(positions.len() as u32);
+            for position in positions {
+                e.u64(*position as u64);
+            }
+        }
+        Record::Table(table, columnar) => {
+            e.u8(TABLE);
+            e.str(&table.name);
+            e.u8(u8::from(*columnar));
+            e.u32(table.columns.len() as u32);
+            for c in &table.columns {
+                e.column(c);
+            }
+            e.u32(table.constraints.len() as u32);
+            for c in &table.constraints {
+                e.constraint(c);
+            }
+            e.u32(table.indexes.len() as u32);
+            for index in &table.indexes {
+                e.index(index);
+            }
+            encode_rows(&mut e, &table.rows);
+        }
+        Record::Drop(name) => {
+            e.u8(DROP);
+            e.str(name);
+        }
+    }
+    e.bytes
+}
+
+fn decode_record(kind: u8, d: &mut Decoder) -> Result<Record, String> {
+    Ok(match kind {
+        INSERT => Record::Insert(d.str()?, decode_rows(d)?),
+        UPDATE => {
+            let name = d.str()?;
+            let rows = (0..d.u32()?).map(|_| Ok((d.u64()? as usize, decode_row(d)?))).collect::<Result<Vec<_>, String>>()?;
+            Record::Update(name, rows)
+        }
+        DELETE => {
+            let name = d.str()?;
+            let positions = (0..d.u32()?).map(|_| Ok(d.u64()? as usize)).collect::<Result<Vec<_>, String>>()?;
+            Record::Delete(name, positions)
+        }
+        TABLE => {
+            let name = d.str()?;
+            let columnar = d.u8()? != 0;
+            let columns = (0..d.u32()?).map(|_| d.column()).collect::<Result<Vec<_>, _>>()?;
+            let constraints = (0..d.u32()?).map(|_| d.constraint()).collect::<Result<Vec<_>, _>>()?;
+            let indexes = (0..d.u32()?).map(|_| d.index()).collect::<Result<Vec<_>, _>>()?;
+            let rows = decode_rows(d)?;
+            Record::Table(Table { name, columns, rows, constraints, indexes }, columnar)
+        }
+        DROP => Record::Drop(d.str()?),
+        _ => return Err(format!("Unknown log record kind {}", kind)),
+    })
+}